@@ -0,0 +1,99 @@
+use std::f32::consts::PI;
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use imgal::simulation::blob::logistic_metaballs;
+use ndarray::{Array2, ArrayView2, Ix2, arr2};
+
+use cellcast::process::nms::{OverlapCriterion, polygon_nms};
+
+const SHAPE_2D: [usize; 2] = [128, 128];
+const N_RAYS: usize = 32;
+
+/// Create dense star-convex candidates, one per foreground pixel, by marching
+/// rays from each pixel to the object boundary. Candidates are sorted by
+/// descending intensity, as a stand-in for the object probability.
+fn ray_candidates(data: ArrayView2<f64>, threshold: f64) -> (Array2<f32>, Array2<f32>) {
+    let (rows, cols) = data.dim();
+    let mut pixels: Vec<(usize, usize)> = data
+        .indexed_iter()
+        .filter(|&(_, &v)| v > threshold)
+        .map(|(p, _)| p)
+        .collect();
+    pixels.sort_by(|a, b| data[*b].total_cmp(&data[*a]));
+    let angle_step = 2.0 * PI / N_RAYS as f32;
+    let dist = Array2::from_shape_fn((pixels.len(), N_RAYS), |(p, r)| {
+        let (y, x) = pixels[p];
+        let angle = angle_step * r as f32;
+        let mut d = 0.0;
+        loop {
+            let ny = (y as f32 + (d + 0.5) * angle.sin()).round();
+            let nx = (x as f32 + (d + 0.5) * angle.cos()).round();
+            if ny < 0.0 || nx < 0.0 || ny >= rows as f32 || nx >= cols as f32 {
+                break d;
+            }
+            if data[[ny as usize, nx as usize]] <= threshold {
+                break d;
+            }
+            d += 0.5;
+        }
+    });
+    let pnts = Array2::from_shape_fn((pixels.len(), 2), |(p, d)| match d {
+        0 => pixels[p].0 as f32,
+        _ => pixels[p].1 as f32,
+    });
+    (dist, pnts)
+}
+
+fn bench_polygon_nms(c: &mut Criterion) {
+    let centers = arr2(&[
+        [(SHAPE_2D[0] / 4) as f64, SHAPE_2D[1] as f64 / 1.5],
+        [SHAPE_2D[0] as f64 / 1.2, SHAPE_2D[1] as f64 / 1.8],
+        [(SHAPE_2D[0] / 10) as f64, (SHAPE_2D[1] / 12) as f64],
+    ]);
+    let radii = [3.0, 5.0, 7.0];
+    let intensities = [10.0; 3];
+    let falloffs = [2.0; 3];
+    let data = logistic_metaballs(
+        &centers,
+        &radii,
+        &intensities,
+        &falloffs,
+        0.0,
+        &SHAPE_2D,
+        None,
+    )
+    .unwrap();
+    let data = data.into_dimensionality::<Ix2>().unwrap();
+    let (dist, pnts) = ray_candidates(data.view(), 1.0);
+    let n_polys = dist.nrows();
+    let mut group = c.benchmark_group("NMS");
+    group.bench_function("polygon_nms", |b| {
+        b.iter(|| {
+            let _ = polygon_nms(
+                dist.view(),
+                pnts.view(),
+                n_polys,
+                N_RAYS,
+                0.4,
+                OverlapCriterion::Min,
+            );
+        });
+    });
+    // without suppression every overlapping candidate pair is compared
+    group.bench_function("polygon_nms_all_pairs", |b| {
+        b.iter(|| {
+            let _ = polygon_nms(
+                dist.view(),
+                pnts.view(),
+                n_polys,
+                N_RAYS,
+                1.0,
+                OverlapCriterion::Min,
+            );
+        });
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_polygon_nms);
+criterion_main!(benches);
@@ -0,0 +1,206 @@
+use imgal::prelude::*;
+use ndarray::Axis;
+use rand::rngs::ChaCha8Rng;
+
+use crate::CellcastError;
+use crate::augment::pipeline::{AugmentSample, Augmentation, sample_range};
+
+/// The RGB to HED stain matrix (Ruifrok and Johnston, 2001).
+const RGB_FROM_HED: [[f32; 3]; 3] = [[0.65, 0.70, 0.29], [0.07, 0.99, 0.11], [0.27, 0.57, 0.78]];
+
+/// Random hue and saturation jitter for RGB images.
+///
+/// Converts each pixel to HSV, shifts the hue, scales the saturation and
+/// converts back to RGB. The image is expected to hold RGB values in `[0, 1]`
+/// along its channel axis, values are clipped to `[0, 1]`.
+#[derive(Debug, Clone)]
+pub struct HueSaturationJitter {
+    hue: (f32, f32),
+    saturation: (f32, f32),
+}
+
+impl HueSaturationJitter {
+    /// Create a new hue and saturation jitter augmentation.
+    ///
+    /// # Arguments
+    ///
+    /// * `hue`: The `(low, high)` range of the hue shift, as a fraction of a
+    ///   full turn (*e.g.* `(-0.05, 0.05)`).
+    /// * `saturation`: The `(low, high)` range of the saturation factor. If
+    ///   `None` then `saturation = (1.0, 1.0)`.
+    pub fn new(hue: (f32, f32), saturation: Option<(f32, f32)>) -> Self {
+        Self {
+            hue,
+            saturation: saturation.unwrap_or((1.0, 1.0)),
+        }
+    }
+}
+
+impl Augmentation for HueSaturationJitter {
+    fn apply(&self, sample: &mut AugmentSample, rng: &mut ChaCha8Rng) -> Result<(), CellcastError> {
+        let c = rgb_axis(sample)?;
+        let dh = sample_range(rng, self.hue);
+        let ds = sample_range(rng, self.saturation);
+        sample
+            .image
+            .lanes_mut(Axis(c))
+            .into_iter()
+            .for_each(|mut px| {
+                let (h, s, v) = rgb_to_hsv(px[0], px[1], px[2]);
+                let (r, g, b) = hsv_to_rgb((h + dh).rem_euclid(1.0), (s * ds).clamp(0.0, 1.0), v);
+                px[0] = r;
+                px[1] = g;
+                px[2] = b;
+            });
+        Ok(())
+    }
+
+    fn check(&self, sample: &AugmentSample) -> Result<(), CellcastError> {
+        rgb_axis(sample).map(|_| ())
+    }
+}
+
+/// Random H&E stain jitter for RGB images.
+///
+/// Separates each pixel into hematoxylin, eosin and DAB optical densities with
+/// color deconvolution, perturbs each stain with `hed * alpha + beta` and
+/// converts back to RGB (Tellez *et al.*, 2018). The image is expected to hold
+/// RGB values in `[0, 1]` along its channel axis, values are clipped to
+/// `[0, 1]`.
+#[derive(Debug, Clone)]
+pub struct StainJitter {
+    alpha: (f32, f32),
+    beta: (f32, f32),
+}
+
+impl StainJitter {
+    /// Create a new H&E stain jitter augmentation.
+    ///
+    /// # Arguments
+    ///
+    /// * `alpha`: The `(low, high)` range of the per stain multiplicative
+    ///   factor. If `None` then `alpha = (0.95, 1.05)`.
+    /// * `beta`: The `(low, high)` range of the per stain additive offset. If
+    ///   `None` then `beta = (-0.05, 0.05)`.
+    pub fn new(alpha: Option<(f32, f32)>, beta: Option<(f32, f32)>) -> Self {
+        Self {
+            alpha: alpha.unwrap_or((0.95, 1.05)),
+            beta: beta.unwrap_or((-0.05, 0.05)),
+        }
+    }
+}
+
+impl Augmentation for StainJitter {
+    fn apply(&self, sample: &mut AugmentSample, rng: &mut ChaCha8Rng) -> Result<(), CellcastError> {
+        let c = rgb_axis(sample)?;
+        let alpha: [f32; 3] = std::array::from_fn(|_| sample_range(rng, self.alpha));
+        let beta: [f32; 3] = std::array::from_fn(|_| sample_range(rng, self.beta));
+        let hed_from_rgb = invert_3x3(&RGB_FROM_HED);
+        sample
+            .image
+            .lanes_mut(Axis(c))
+            .into_iter()
+            .for_each(|mut px| {
+                // optical density
+                let od: [f32; 3] = std::array::from_fn(|i| -px[i].max(1e-6).ln());
+                let hed: [f32; 3] = std::array::from_fn(|j| {
+                    let v = (0..3).fold(0.0, |acc, i| acc + od[i] * hed_from_rgb[i][j]);
+                    v * alpha[j] + beta[j]
+                });
+                (0..3).for_each(|i| {
+                    let od = (0..3).fold(0.0, |acc, j| acc + hed[j] * RGB_FROM_HED[j][i]);
+                    px[i] = (-od).exp().clamp(0.0, 1.0);
+                });
+            });
+        Ok(())
+    }
+
+    fn check(&self, sample: &AugmentSample) -> Result<(), CellcastError> {
+        rgb_axis(sample).map(|_| ())
+    }
+}
+
+/// Get the channel axis of an RGB sample.
+fn rgb_axis(sample: &AugmentSample) -> Result<usize, CellcastError> {
+    let c = sample
+        .channel_axis
+        .ok_or(CellcastError::Imgal(ImgalError::InvalidGeneric {
+            msg: "Color augmentations require an image with a channel axis.",
+        }))?;
+    let n_ch = sample.image.shape()[c];
+    if n_ch != 3 {
+        return Err(CellcastError::Imgal(
+            ImgalError::InvalidAxisLengthExpected {
+                arr_name: "image",
+                axis_idx: c,
+                expected: 3,
+                got: n_ch,
+            },
+        ));
+    }
+    Ok(c)
+}
+
+/// Convert an RGB value to HSV, all components in `[0, 1]`.
+#[inline]
+fn rgb_to_hsv(r: f32, g: f32, b: f32) -> (f32, f32, f32) {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+    let h = if delta <= 0.0 {
+        0.0
+    } else if max == r {
+        ((g - b) / delta).rem_euclid(6.0) / 6.0
+    } else if max == g {
+        ((b - r) / delta + 2.0) / 6.0
+    } else {
+        ((r - g) / delta + 4.0) / 6.0
+    };
+    let s = if max <= 0.0 { 0.0 } else { delta / max };
+    (h, s, max)
+}
+
+/// Convert an HSV value to RGB, all components in `[0, 1]`.
+#[inline]
+fn hsv_to_rgb(h: f32, s: f32, v: f32) -> (f32, f32, f32) {
+    let h6 = h * 6.0;
+    let sector = h6.floor();
+    let f = h6 - sector;
+    let p = v * (1.0 - s);
+    let q = v * (1.0 - s * f);
+    let t = v * (1.0 - s * (1.0 - f));
+    match sector as i32 % 6 {
+        0 => (v, t, p),
+        1 => (q, v, p),
+        2 => (p, v, t),
+        3 => (p, q, v),
+        4 => (t, p, v),
+        _ => (v, p, q),
+    }
+}
+
+/// Invert a 3x3 matrix.
+fn invert_3x3(m: &[[f32; 3]; 3]) -> [[f32; 3]; 3] {
+    let det = m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+        - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+        + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0]);
+    let cof =
+        |r0: usize, r1: usize, c0: usize, c1: usize| m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0];
+    [
+        [
+            cof(1, 2, 1, 2) / det,
+            -cof(0, 2, 1, 2) / det,
+            cof(0, 1, 1, 2) / det,
+        ],
+        [
+            -cof(1, 2, 0, 2) / det,
+            cof(0, 2, 0, 2) / det,
+            -cof(0, 1, 0, 2) / det,
+        ],
+        [
+            cof(1, 2, 0, 1) / det,
+            -cof(0, 2, 0, 1) / det,
+            cof(0, 1, 0, 1) / det,
+        ],
+    ]
+}
@@ -0,0 +1,269 @@
+use imgal::prelude::*;
+use ndarray::{ArrayD, ArrayViewD, Axis, IxDyn, indices};
+use rand::RngExt;
+use rand::rngs::ChaCha8Rng;
+use rand_distr::{Distribution, Normal};
+
+use crate::CellcastError;
+use crate::augment::pipeline::{AugmentSample, Augmentation};
+
+/// Randomly flip spatial axes.
+///
+/// Each selected spatial axis is flipped with a probability of `0.5`. The image
+/// and label are flipped identically.
+#[derive(Debug, Clone, Default)]
+pub struct RandomFlip {
+    axes: Option<Vec<usize>>,
+}
+
+impl RandomFlip {
+    /// Create a new random flip augmentation.
+    ///
+    /// # Arguments
+    ///
+    /// * `axes`: The spatial axes that can be flipped. If `None` then all
+    ///   spatial axes can be flipped.
+    pub fn new(axes: Option<&[usize]>) -> Self {
+        Self {
+            axes: axes.map(|a| a.to_vec()),
+        }
+    }
+}
+
+impl Augmentation for RandomFlip {
+    fn apply(&self, sample: &mut AugmentSample, rng: &mut ChaCha8Rng) -> Result<(), CellcastError> {
+        let axes = sample.spatial_axes(self.axes.as_deref())?;
+        axes.iter().for_each(|&ax| {
+            if rng.random_bool(0.5) {
+                let img_ax = sample.image_axis(ax);
+                sample.image.invert_axis(Axis(img_ax));
+                sample.label.invert_axis(Axis(ax));
+            }
+        });
+        Ok(())
+    }
+
+    fn check(&self, sample: &AugmentSample) -> Result<(), CellcastError> {
+        sample.spatial_axes(self.axes.as_deref()).map(|_| ())
+    }
+}
+
+/// Randomly rotate by a multiple of 90 degrees.
+///
+/// Rotates the image and label by `k * 90` degrees in the plane of two spatial
+/// axes, where `k` is drawn uniformly from `0..4`. Rotations by 90 and 270
+/// degrees swap the lengths of the two axes.
+#[derive(Debug, Clone, Default)]
+pub struct RandomRot90 {
+    axes: Option<(usize, usize)>,
+}
+
+impl RandomRot90 {
+    /// Create a new random 90 degree rotation augmentation.
+    ///
+    /// # Arguments
+    ///
+    /// * `axes`: The two spatial axes defining the rotation plane. If `None`
+    ///   then the last two spatial axes (*i.e.* row and col) are used.
+    pub fn new(axes: Option<(usize, usize)>) -> Self {
+        Self { axes }
+    }
+
+    /// Validate and get the two spatial axes of the rotation plane.
+    fn rotation_axes(&self, sample: &AugmentSample) -> Result<(usize, usize), CellcastError> {
+        let ndim = sample.spatial_ndim();
+        if ndim < 2 {
+            return Err(CellcastError::Imgal(
+                ImgalError::InvalidParameterValueLess {
+                    param_name: "spatial_ndim",
+                    value: 2,
+                },
+            ));
+        }
+        let (a, b) = self.axes.unwrap_or((ndim - 2, ndim - 1));
+        sample.spatial_axes(Some(&[a, b]))?;
+        if a == b {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "The rotation axes must be different.",
+            }));
+        }
+        Ok((a, b))
+    }
+}
+
+impl Augmentation for RandomRot90 {
+    fn apply(&self, sample: &mut AugmentSample, rng: &mut ChaCha8Rng) -> Result<(), CellcastError> {
+        let (a, b) = self.rotation_axes(sample)?;
+        let k: usize = rng.random_range(0..4);
+        let (img_a, img_b) = (sample.image_axis(a), sample.image_axis(b));
+        (0..k).for_each(|_| {
+            sample.image.swap_axes(img_a, img_b);
+            sample.image.invert_axis(Axis(img_a));
+            sample.label.swap_axes(a, b);
+            sample.label.invert_axis(Axis(a));
+        });
+        Ok(())
+    }
+
+    fn check(&self, sample: &AugmentSample) -> Result<(), CellcastError> {
+        self.rotation_axes(sample).map(|_| ())
+    }
+}
+
+/// Random elastic deformation.
+///
+/// Deforms the image and label with a smooth random displacement field. The
+/// displacements are drawn from a normal distribution on a coarse grid of
+/// control points and linearly interpolated to every pixel/voxel. Images are
+/// resampled with linear interpolation and labels with nearest neighbor
+/// interpolation, preserving label ids.
+#[derive(Debug, Clone)]
+pub struct ElasticDeform {
+    sigma: f32,
+    n_points: usize,
+    axes: Option<Vec<usize>>,
+}
+
+impl ElasticDeform {
+    /// Create a new elastic deformation augmentation.
+    ///
+    /// # Arguments
+    ///
+    /// * `sigma`: The standard deviation of the control point displacements in
+    ///   pixels.
+    /// * `n_points`: The number of control points along each spatial axis. If
+    ///   `None` then `n_points = 3`.
+    /// * `axes`: The spatial axes to deform. If `None` then all spatial axes
+    ///   are deformed.
+    pub fn new(sigma: f32, n_points: Option<usize>, axes: Option<&[usize]>) -> Self {
+        Self {
+            sigma: sigma.max(0.0),
+            n_points: n_points.unwrap_or(3).max(2),
+            axes: axes.map(|a| a.to_vec()),
+        }
+    }
+
+    /// Validate and get the deformed spatial axes.
+    fn deform_axes(&self, sample: &AugmentSample) -> Result<Vec<usize>, CellcastError> {
+        if !(2..=3).contains(&sample.spatial_ndim()) {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "Elastic deformation requires 2D or 3D spatial data.",
+            }));
+        }
+        sample.spatial_axes(self.axes.as_deref())
+    }
+}
+
+impl Augmentation for ElasticDeform {
+    fn apply(&self, sample: &mut AugmentSample, rng: &mut ChaCha8Rng) -> Result<(), CellcastError> {
+        let ndim = sample.spatial_ndim();
+        let axes = self.deform_axes(sample)?;
+        let shape = sample.label.shape().to_vec();
+        let n = self.n_points;
+        // SAFE: sigma is clamped to be non-negative
+        let normal = Normal::new(0.0_f32, self.sigma).unwrap();
+        let grids: Vec<Option<ArrayD<f32>>> = (0..ndim)
+            .map(|ax| {
+                axes.contains(&ax)
+                    .then(|| ArrayD::from_shape_fn(IxDyn(&vec![n; ndim]), |_| normal.sample(rng)))
+            })
+            .collect();
+        // compute the source coordinate of every output position
+        let n_pos: usize = shape.iter().product();
+        let mut src = vec![0.0_f32; n_pos * ndim];
+        let mut grid_coord = [0.0_f32; 3];
+        indices(IxDyn(&shape))
+            .into_iter()
+            .enumerate()
+            .for_each(|(i, idx)| {
+                (0..ndim).for_each(|d| {
+                    grid_coord[d] = if shape[d] > 1 {
+                        idx[d] as f32 * (n - 1) as f32 / (shape[d] - 1) as f32
+                    } else {
+                        0.0
+                    };
+                });
+                (0..ndim).for_each(|d| {
+                    let disp = grids[d]
+                        .as_ref()
+                        .map_or(0.0, |g| interp_linear(&g.view(), &grid_coord[..ndim]));
+                    src[i * ndim + d] = idx[d] as f32 + disp;
+                });
+            });
+        // resample the label with nearest neighbor interpolation
+        let mut near = [0_usize; 3];
+        let label_vals: Vec<u64> = (0..n_pos)
+            .map(|i| {
+                (0..ndim).for_each(|d| {
+                    near[d] = src[i * ndim + d].round().clamp(0.0, (shape[d] - 1) as f32) as usize;
+                });
+                sample.label[IxDyn(&near[..ndim])]
+            })
+            .collect();
+        sample.label = ArrayD::from_shape_vec(IxDyn(&shape), label_vals).unwrap();
+        // resample the image (per channel) with linear interpolation
+        let resample = |data: &ArrayViewD<f32>| -> ArrayD<f32> {
+            let vals: Vec<f32> = (0..n_pos)
+                .map(|i| interp_linear(data, &src[i * ndim..(i + 1) * ndim]))
+                .collect();
+            ArrayD::from_shape_vec(IxDyn(&shape), vals).unwrap()
+        };
+        match sample.channel_axis {
+            Some(c) => {
+                let mut out = ArrayD::<f32>::zeros(sample.image.raw_dim());
+                (0..sample.image.shape()[c]).for_each(|ch| {
+                    let res = resample(&sample.image.index_axis(Axis(c), ch));
+                    out.index_axis_mut(Axis(c), ch).assign(&res);
+                });
+                sample.image = out;
+            }
+            None => {
+                sample.image = resample(&sample.image.view());
+            }
+        }
+        Ok(())
+    }
+
+    fn check(&self, sample: &AugmentSample) -> Result<(), CellcastError> {
+        self.deform_axes(sample).map(|_| ())
+    }
+}
+
+/// Sample an n-dimensional array at a floating point coordinate.
+///
+/// Performs multilinear interpolation of up to 3 dimensions, clamping the
+/// coordinate to the array bounds.
+#[inline]
+fn interp_linear(data: &ArrayViewD<f32>, coord: &[f32]) -> f32 {
+    let ndim = coord.len();
+    let shape = data.shape();
+    let mut lo = [0_usize; 3];
+    let mut hi = [0_usize; 3];
+    let mut frac = [0.0_f32; 3];
+    (0..ndim).for_each(|d| {
+        let max = (shape[d] - 1) as f32;
+        let c = coord[d].clamp(0.0, max);
+        let f = c.floor();
+        lo[d] = f as usize;
+        hi[d] = (lo[d] + 1).min(shape[d] - 1);
+        frac[d] = c - f;
+    });
+    let mut idx = [0_usize; 3];
+    (0..(1_usize << ndim)).fold(0.0, |acc, corner| {
+        let mut w = 1.0;
+        (0..ndim).for_each(|d| {
+            if corner & (1 << d) != 0 {
+                idx[d] = hi[d];
+                w *= frac[d];
+            } else {
+                idx[d] = lo[d];
+                w *= 1.0 - frac[d];
+            }
+        });
+        if w == 0.0 {
+            acc
+        } else {
+            acc + w * data[IxDyn(&idx[..ndim])]
+        }
+    })
+}
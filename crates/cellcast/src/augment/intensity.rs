@@ -0,0 +1,228 @@
+use ndarray::Axis;
+use rand::rngs::ChaCha8Rng;
+use rand_distr::{Distribution, Normal, Poisson};
+
+use crate::CellcastError;
+use crate::augment::pipeline::{AugmentSample, Augmentation, sample_range};
+
+/// Random linear intensity scaling and shifting.
+///
+/// Transforms each image value `x` into `x * a + b`, where `a` and `b` are
+/// drawn uniformly from the scale and shift ranges.
+#[derive(Debug, Clone)]
+pub struct IntensityScale {
+    scale: (f32, f32),
+    shift: (f32, f32),
+}
+
+impl IntensityScale {
+    /// Create a new intensity scaling augmentation.
+    ///
+    /// # Arguments
+    ///
+    /// * `scale`: The `(low, high)` range of the multiplicative factor.
+    /// * `shift`: The `(low, high)` range of the additive offset. If `None`
+    ///   then `shift = (0.0, 0.0)`.
+    pub fn new(scale: (f32, f32), shift: Option<(f32, f32)>) -> Self {
+        Self {
+            scale,
+            shift: shift.unwrap_or((0.0, 0.0)),
+        }
+    }
+}
+
+impl Augmentation for IntensityScale {
+    fn apply(&self, sample: &mut AugmentSample, rng: &mut ChaCha8Rng) -> Result<(), CellcastError> {
+        let a = sample_range(rng, self.scale);
+        let b = sample_range(rng, self.shift);
+        sample.image.mapv_inplace(|v| v * a + b);
+        Ok(())
+    }
+}
+
+/// Random gamma correction.
+///
+/// Normalizes the image to `[0, 1]`, raises each value to the power `gamma`
+/// and rescales the result back to the original intensity range.
+#[derive(Debug, Clone)]
+pub struct IntensityGamma {
+    gamma: (f32, f32),
+}
+
+impl IntensityGamma {
+    /// Create a new gamma correction augmentation.
+    ///
+    /// # Arguments
+    ///
+    /// * `gamma`: The `(low, high)` range of the gamma exponent.
+    pub fn new(gamma: (f32, f32)) -> Self {
+        Self {
+            gamma: (gamma.0.max(f32::EPSILON), gamma.1.max(f32::EPSILON)),
+        }
+    }
+}
+
+impl Augmentation for IntensityGamma {
+    fn apply(&self, sample: &mut AugmentSample, rng: &mut ChaCha8Rng) -> Result<(), CellcastError> {
+        let gamma = sample_range(rng, self.gamma);
+        let (min, max) = sample
+            .image
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(lo, hi), &v| (lo.min(v), hi.max(v)));
+        let range = max - min;
+        if range > 0.0 {
+            sample
+                .image
+                .mapv_inplace(|v| ((v - min) / range).powf(gamma) * range + min);
+        }
+        Ok(())
+    }
+}
+
+/// Random additive Gaussian noise.
+///
+/// Adds zero mean Gaussian noise to the image, with a standard deviation drawn
+/// uniformly from the sigma range.
+#[derive(Debug, Clone)]
+pub struct GaussianNoise {
+    sigma: (f32, f32),
+}
+
+impl GaussianNoise {
+    /// Create a new Gaussian noise augmentation.
+    ///
+    /// # Arguments
+    ///
+    /// * `sigma`: The `(low, high)` range of the noise standard deviation.
+    pub fn new(sigma: (f32, f32)) -> Self {
+        Self {
+            sigma: (sigma.0.max(0.0), sigma.1.max(0.0)),
+        }
+    }
+}
+
+impl Augmentation for GaussianNoise {
+    fn apply(&self, sample: &mut AugmentSample, rng: &mut ChaCha8Rng) -> Result<(), CellcastError> {
+        let sigma = sample_range(rng, self.sigma);
+        // SAFE: sigma is clamped to be non-negative
+        let normal = Normal::new(0.0_f32, sigma).unwrap();
+        sample
+            .image
+            .iter_mut()
+            .for_each(|v| *v += normal.sample(rng));
+        Ok(())
+    }
+}
+
+/// Random Poisson (shot) noise.
+///
+/// Replaces each image value `x` with `P(x * lambda) / lambda`, where `P` is a
+/// Poisson random variable and `lambda` (*i.e.* the number of photons per unit
+/// intensity) is drawn uniformly from the lambda range. Smaller values of
+/// `lambda` produce stronger noise. Non-positive values are left unchanged.
+#[derive(Debug, Clone)]
+pub struct PoissonNoise {
+    lambda: (f32, f32),
+}
+
+impl PoissonNoise {
+    /// Create a new Poisson noise augmentation.
+    ///
+    /// # Arguments
+    ///
+    /// * `lambda`: The `(low, high)` range of the photons per unit intensity.
+    pub fn new(lambda: (f32, f32)) -> Self {
+        Self {
+            lambda: (lambda.0.max(f32::EPSILON), lambda.1.max(f32::EPSILON)),
+        }
+    }
+}
+
+impl Augmentation for PoissonNoise {
+    fn apply(&self, sample: &mut AugmentSample, rng: &mut ChaCha8Rng) -> Result<(), CellcastError> {
+        let lambda = sample_range(rng, self.lambda);
+        sample.image.iter_mut().for_each(|v| {
+            let rate = *v * lambda;
+            if rate > 0.0 && rate.is_finite() {
+                // SAFE: rate is positive and finite
+                let poisson = Poisson::new(rate).unwrap();
+                *v = poisson.sample(rng) / lambda;
+            }
+        });
+        Ok(())
+    }
+}
+
+/// Random Gaussian blur.
+///
+/// Blurs the image along its spatial axes with a Gaussian kernel, with a
+/// standard deviation drawn uniformly from the sigma range. Channels are
+/// blurred independently and image borders are reflected.
+#[derive(Debug, Clone)]
+pub struct GaussianBlur {
+    sigma: (f32, f32),
+}
+
+impl GaussianBlur {
+    /// Create a new Gaussian blur augmentation.
+    ///
+    /// # Arguments
+    ///
+    /// * `sigma`: The `(low, high)` range of the kernel standard deviation in
+    ///   pixels.
+    pub fn new(sigma: (f32, f32)) -> Self {
+        Self {
+            sigma: (sigma.0.max(0.0), sigma.1.max(0.0)),
+        }
+    }
+}
+
+impl Augmentation for GaussianBlur {
+    fn apply(&self, sample: &mut AugmentSample, rng: &mut ChaCha8Rng) -> Result<(), CellcastError> {
+        let sigma = sample_range(rng, self.sigma);
+        if sigma <= 0.0 {
+            return Ok(());
+        }
+        let radius = (3.0 * sigma).ceil() as isize;
+        let mut kernel: Vec<f32> = (-radius..=radius)
+            .map(|i| (-((i * i) as f32) / (2.0 * sigma * sigma)).exp())
+            .collect();
+        let sum: f32 = kernel.iter().sum();
+        kernel.iter_mut().for_each(|k| *k /= sum);
+        // separable convolution along each spatial axis of the image
+        let mut buf: Vec<f32> = Vec::new();
+        (0..sample.spatial_ndim()).for_each(|ax| {
+            let img_ax = sample.image_axis(ax);
+            sample
+                .image
+                .lanes_mut(Axis(img_ax))
+                .into_iter()
+                .for_each(|mut lane| {
+                    let n = lane.len() as isize;
+                    buf.clear();
+                    buf.extend(lane.iter());
+                    lane.iter_mut().enumerate().for_each(|(i, v)| {
+                        *v = kernel.iter().enumerate().fold(0.0, |acc, (k, w)| {
+                            let j = reflect_index(i as isize + k as isize - radius, n);
+                            acc + w * buf[j]
+                        });
+                    });
+                });
+        });
+        Ok(())
+    }
+}
+
+/// Reflect an out of bounds index back into `0..n`.
+#[inline]
+fn reflect_index(mut i: isize, n: isize) -> usize {
+    if n == 1 {
+        return 0;
+    }
+    let period = 2 * (n - 1);
+    i = i.rem_euclid(period);
+    if i >= n {
+        i = period - i;
+    }
+    i as usize
+}
@@ -0,0 +1,17 @@
+//! Data augmentation for microscopy training data.
+//!
+//! This module provides composable and seeded augmentations for image and label
+//! pairs. Geometric augmentations (*e.g.* flips, rotations and elastic
+//! deformations) are applied identically to the image and its label, while
+//! intensity and color augmentations only modify the image. Augmentations
+//! support 2D, 2D with channels and 3D data.
+
+mod color;
+mod geometric;
+mod intensity;
+mod pipeline;
+
+pub use color::{HueSaturationJitter, StainJitter};
+pub use geometric::{ElasticDeform, RandomFlip, RandomRot90};
+pub use intensity::{GaussianBlur, GaussianNoise, IntensityGamma, IntensityScale, PoissonNoise};
+pub use pipeline::{AugmentDataset, AugmentPipeline, AugmentSample, Augmentation};
@@ -0,0 +1,373 @@
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use burn::data::dataset::Dataset;
+use imgal::prelude::*;
+use ndarray::ArrayD;
+use rand::rngs::ChaCha8Rng;
+use rand::{RngExt, SeedableRng};
+
+use crate::CellcastError;
+
+/// An image and label pair for training.
+///
+/// The label holds the instance or semantic labels for each spatial position of
+/// the image. If the image has a channel axis (*e.g.* RGB H&E images), the
+/// label shape is the image shape without the channel axis.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AugmentSample {
+    /// The image data.
+    pub image: ArrayD<f32>,
+    /// The label data with the spatial shape of `image`.
+    pub label: ArrayD<u64>,
+    /// The channel axis of `image`, if any.
+    pub channel_axis: Option<usize>,
+}
+
+impl AugmentSample {
+    /// Create a new image and label pair.
+    ///
+    /// # Arguments
+    ///
+    /// * `image`: The input image, 2D, 2D with a channel axis or 3D.
+    /// * `label`: The label image with the same spatial shape as `image`.
+    /// * `channel_axis`: The channel axis of `image`. If `None` then `image`
+    ///   has no channel axis.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(AugmentSample)`: The image and label pair.
+    /// * `Err(CellcastError)`: If `channel_axis` is out of bounds. If the
+    ///   spatial shapes of `image` and `label` do not match.
+    pub fn new(
+        image: ArrayD<f32>,
+        label: ArrayD<u64>,
+        channel_axis: Option<usize>,
+    ) -> Result<Self, CellcastError> {
+        let mut spatial_shape = image.shape().to_vec();
+        if let Some(ax) = channel_axis {
+            if ax >= image.ndim() {
+                return Err(CellcastError::Imgal(ImgalError::InvalidAxis {
+                    axis_idx: ax,
+                    dim_len: image.ndim(),
+                }));
+            }
+            spatial_shape.remove(ax);
+        }
+        if spatial_shape != label.shape() {
+            return Err(CellcastError::Imgal(ImgalError::MismatchedArrayShapes {
+                a_arr_name: "image",
+                a_shape: spatial_shape,
+                b_arr_name: "label",
+                b_shape: label.shape().to_vec(),
+            }));
+        }
+        Ok(Self {
+            image,
+            label,
+            channel_axis,
+        })
+    }
+
+    /// Get the number of spatial dimensions.
+    pub fn spatial_ndim(&self) -> usize {
+        self.label.ndim()
+    }
+
+    /// Get the image axis of a spatial (*i.e.* label) axis.
+    pub(crate) fn image_axis(&self, spatial_axis: usize) -> usize {
+        match self.channel_axis {
+            Some(c) if spatial_axis >= c => spatial_axis + 1,
+            _ => spatial_axis,
+        }
+    }
+
+    /// Validate spatial axes and return the requested axes or all spatial axes.
+    pub(crate) fn spatial_axes(&self, axes: Option<&[usize]>) -> Result<Vec<usize>, CellcastError> {
+        let ndim = self.spatial_ndim();
+        match axes {
+            Some(a) => {
+                if let Some(&ax) = a.iter().find(|&&ax| ax >= ndim) {
+                    return Err(CellcastError::Imgal(ImgalError::InvalidAxis {
+                        axis_idx: ax,
+                        dim_len: ndim,
+                    }));
+                }
+                Ok(a.to_vec())
+            }
+            None => Ok((0..ndim).collect()),
+        }
+    }
+
+    /// Convert the image and label into standard (row-major) memory layout.
+    pub(crate) fn standardize(&mut self) {
+        if !self.image.is_standard_layout() {
+            self.image = self.image.as_standard_layout().into_owned();
+        }
+        if !self.label.is_standard_layout() {
+            self.label = self.label.as_standard_layout().into_owned();
+        }
+    }
+}
+
+/// A single augmentation step.
+///
+/// An augmentation transforms an image and label pair in place using the given
+/// random number generator. All randomness must be drawn from `rng` so that
+/// results are reproducible for a given seed.
+pub trait Augmentation: Send + Sync {
+    /// Apply the augmentation to an image and label pair.
+    ///
+    /// # Arguments
+    ///
+    /// * `sample`: The image and label pair to augment.
+    /// * `rng`: The random number generator.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())`: If successful.
+    /// * `Err(CellcastError)`: If the augmentation is not compatible with the
+    ///   sample (*e.g.* an invalid axis).
+    fn apply(&self, sample: &mut AugmentSample, rng: &mut ChaCha8Rng) -> Result<(), CellcastError>;
+
+    /// Check that the augmentation is compatible with an image and label pair.
+    ///
+    /// # Description
+    ///
+    /// Checks the sample dimensions, axes and channels without augmenting the
+    /// sample. An augmentation that passes the check must not fail in `apply`
+    /// for the same sample. The default implementation accepts every sample.
+    ///
+    /// # Arguments
+    ///
+    /// * `sample`: The image and label pair to check.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())`: If the augmentation can be applied to `sample`.
+    /// * `Err(CellcastError)`: If the augmentation is not compatible with the
+    ///   sample.
+    fn check(&self, sample: &AugmentSample) -> Result<(), CellcastError> {
+        let _ = sample;
+        Ok(())
+    }
+}
+
+/// A composable and seeded augmentation pipeline.
+///
+/// Steps are applied in the order they were added, each with its own
+/// probability. Pipelines implement [`Augmentation`] and can therefore be
+/// nested.
+pub struct AugmentPipeline {
+    steps: Vec<(Box<dyn Augmentation>, f64)>,
+    seed: u64,
+}
+
+impl AugmentPipeline {
+    /// Create an empty augmentation pipeline.
+    ///
+    /// # Arguments
+    ///
+    /// * `seed`: The base seed used to derive per-item random number
+    ///   generators.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            steps: Vec::new(),
+            seed,
+        }
+    }
+
+    /// Add an augmentation step that is always applied.
+    pub fn with<A: Augmentation + 'static>(self, augmentation: A) -> Self {
+        self.with_prob(augmentation, 1.0)
+    }
+
+    /// Add an augmentation step that is applied with probability `prob`.
+    pub fn with_prob<A: Augmentation + 'static>(mut self, augmentation: A, prob: f64) -> Self {
+        self.steps
+            .push((Box::new(augmentation), prob.clamp(0.0, 1.0)));
+        self
+    }
+
+    /// Get the pipeline's base seed.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Augment a copy of an image and label pair.
+    ///
+    /// # Description
+    ///
+    /// Augments a copy of `sample` with a random number generator derived from
+    /// the pipeline seed, `epoch` and `index`. The same inputs always produce
+    /// the same output.
+    ///
+    /// # Arguments
+    ///
+    /// * `sample`: The image and label pair to augment.
+    /// * `epoch`: The training epoch.
+    /// * `index`: The item index.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(AugmentSample)`: The augmented image and label pair.
+    /// * `Err(CellcastError)`: If an augmentation step fails.
+    pub fn augment(
+        &self,
+        sample: &AugmentSample,
+        epoch: u64,
+        index: u64,
+    ) -> Result<AugmentSample, CellcastError> {
+        let mut rng = item_rng(self.seed, epoch, index);
+        let mut sample = sample.clone();
+        self.apply(&mut sample, &mut rng)?;
+        Ok(sample)
+    }
+}
+
+impl Augmentation for AugmentPipeline {
+    fn apply(&self, sample: &mut AugmentSample, rng: &mut ChaCha8Rng) -> Result<(), CellcastError> {
+        self.steps.iter().try_for_each(|(step, prob)| {
+            // always draw so that the random stream does not depend on which
+            // steps were skipped
+            let draw: f64 = rng.random();
+            if draw < *prob {
+                step.apply(sample, rng)?;
+                sample.standardize();
+            }
+            Ok(())
+        })
+    }
+
+    /// Check every step of the pipeline, regardless of its probability. The
+    /// steps do not change the number of axes or channels of a sample, each
+    /// step is checked against the input sample.
+    fn check(&self, sample: &AugmentSample) -> Result<(), CellcastError> {
+        self.steps
+            .iter()
+            .try_for_each(|(step, _)| step.check(sample))
+    }
+}
+
+/// A dataset wrapper that augments items on access.
+///
+/// Wraps a Burn [`Dataset`] of image and label pairs and augments each item
+/// with an [`AugmentPipeline`]. Items are augmented with a random number
+/// generator derived from the pipeline seed, the current epoch and the item
+/// index, making augmentations reproducible regardless of access order or data
+/// loader threads.
+///
+/// The pipeline is checked against the first item when the dataset is created,
+/// the items of a dataset are expected to share their dimensions and channels.
+/// An item that still fails to augment is returned without augmentation instead
+/// of aborting the data loader, the failure is logged and kept until it is
+/// taken with `take_error`.
+pub struct AugmentDataset<D> {
+    dataset: D,
+    pipeline: AugmentPipeline,
+    epoch: AtomicU64,
+    error: Mutex<Option<(usize, CellcastError)>>,
+}
+
+impl<D> AugmentDataset<D>
+where
+    D: Dataset<AugmentSample>,
+{
+    /// Create a new augmented dataset.
+    ///
+    /// # Arguments
+    ///
+    /// * `dataset`: The source dataset. The first item is loaded to check it
+    ///   against `pipeline`.
+    /// * `pipeline`: The augmentation pipeline applied to each item.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(AugmentDataset)`: The augmented dataset.
+    /// * `Err(CellcastError)`: If an augmentation of `pipeline` is not
+    ///   compatible with the first item of `dataset` (*e.g.* a color
+    ///   augmentation of a single channel image).
+    pub fn new(dataset: D, pipeline: AugmentPipeline) -> Result<Self, CellcastError> {
+        // only the first item is loaded, avoiding a full pass over lazily
+        // loaded datasets
+        if let Some(s) = dataset.get(0) {
+            pipeline.check(&s)?;
+        }
+        Ok(Self {
+            dataset,
+            pipeline,
+            epoch: AtomicU64::new(0),
+            error: Mutex::new(None),
+        })
+    }
+
+    /// Set the current epoch, changing the augmentations drawn for each item.
+    pub fn set_epoch(&self, epoch: u64) {
+        self.epoch.store(epoch, Ordering::Relaxed);
+    }
+
+    /// Take the last augmentation error.
+    ///
+    /// # Returns
+    ///
+    /// * `Some((usize, CellcastError))`: The index of the last item that failed
+    ///   to augment (and was returned without augmentation) and its error.
+    /// * `None`: If every item was augmented since the last call.
+    pub fn take_error(&self) -> Option<(usize, CellcastError)> {
+        self.error.lock().unwrap_or_else(|e| e.into_inner()).take()
+    }
+}
+
+impl<D> Dataset<AugmentSample> for AugmentDataset<D>
+where
+    D: Dataset<AugmentSample>,
+{
+    fn get(&self, index: usize) -> Option<AugmentSample> {
+        let epoch = self.epoch.load(Ordering::Relaxed);
+        // the first item was checked against the pipeline, an item that still
+        // fails is not augmented rather than aborting the data loader
+        self.dataset
+            .get(index)
+            .map(|s| match self.pipeline.augment(&s, epoch, index as u64) {
+                Ok(augmented) => augmented,
+                Err(e) => {
+                    log::error!(
+                        "Failed to augment item {}, it is not augmented: {}",
+                        index,
+                        e
+                    );
+                    *self.error.lock().unwrap_or_else(|e| e.into_inner()) = Some((index, e));
+                    s
+                }
+            })
+    }
+
+    fn len(&self) -> usize {
+        self.dataset.len()
+    }
+}
+
+/// Sample a uniform value from an inclusive `(low, high)` range.
+#[inline]
+pub(crate) fn sample_range(rng: &mut ChaCha8Rng, range: (f32, f32)) -> f32 {
+    if range.0 >= range.1 {
+        range.0
+    } else {
+        rng.random_range(range.0..=range.1)
+    }
+}
+
+/// Create a random number generator for a single item.
+///
+/// The seed, epoch and index are mixed with the SplitMix64 finalizer. The
+/// ChaCha8 generator is portable and value-stable across `rand` releases
+/// (unlike `StdRng`), keeping seeded augmentations reproducible.
+fn item_rng(seed: u64, epoch: u64, index: u64) -> ChaCha8Rng {
+    let mix = |mut z: u64| {
+        z = z.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    };
+    ChaCha8Rng::seed_from_u64(mix(mix(mix(seed) ^ epoch) ^ index))
+}
@@ -0,0 +1,46 @@
+//! Convert PyTorch Cellpose weights to burnpack format.
+//!
+//! Usage:
+//!
+//! ```text
+//! cellpose_to_bpk <weights> <output.bpk> [--preset <name>]
+//! ```
+//!
+//! The network configuration is the `--preset` network (`cyto` or `nuclei`)
+//! or, if not given, the `cyto` network.
+use std::process::ExitCode;
+
+use cellcast::convert::convert_cellpose_weights;
+use cellcast::models::CellposeNetworkConfig;
+
+const USAGE: &str = "Usage: cellpose_to_bpk <weights> <output.bpk> [--preset <name>]";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (weights_path, output_path, preset) = match args.as_slice() {
+        [w, o] => (w, o, "cyto"),
+        [w, o, flag, value] if flag == "--preset" => (w, o, value.as_str()),
+        _ => {
+            eprintln!("{}", USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+    let network = match preset {
+        "cyto" => CellposeNetworkConfig::cyto(),
+        "nuclei" => CellposeNetworkConfig::nuclei(),
+        _ => {
+            eprintln!("{}", USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+    match convert_cellpose_weights(weights_path, output_path, &network) {
+        Ok(n_tensors) => {
+            println!("Converted {} tensors to \"{}\".", n_tensors, output_path);
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Failed to convert \"{}\": {}", weights_path, e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,100 @@
+//! Convert Keras HDF5 StarDist weights to burnpack format.
+//!
+//! Usage:
+//!
+//! ```text
+//! keras_to_bpk <weights.h5> <output.bpk> [--config <config.json> | --preset <name>]
+//!     [--test-input <input.npy> --test-output <output.npy>]
+//! ```
+//!
+//! The network configuration is read from `--config`, the `--preset` network
+//! (`fluo_2d`, `he_2d` or `fluo_3d`) or, if neither is given, the
+//! `config.json` file next to the weights file. The conversion is verified
+//! against the Keras network output if `--test-input` and `--test-output` are
+//! given (*e.g.* the test tensors of a bioimage.io StarDist package). The test
+//! input is expected unnormalized and is normalized with the `1.0` and `99.8`
+//! percentiles before running the network.
+use std::path::Path;
+use std::process::ExitCode;
+
+use cellcast::convert::{KerasReference, convert_keras_weights};
+use cellcast::models::StarDistNetworkConfig;
+
+const USAGE: &str = "Usage: keras_to_bpk <weights.h5> <output.bpk> [--config <config.json> | --preset <name>] [--test-input <input.npy> --test-output <output.npy>]";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [weights_path, output_path, options @ ..] = args.as_slice() else {
+        eprintln!("{}", USAGE);
+        return ExitCode::FAILURE;
+    };
+    let mut option = None;
+    let mut test_input = None;
+    let mut test_output = None;
+    for pair in options.chunks(2) {
+        match pair {
+            [flag, value] if (flag == "--config" || flag == "--preset") && option.is_none() => {
+                option = Some((flag.as_str(), value.as_str()))
+            }
+            [flag, value] if flag == "--test-input" => test_input = Some(value.as_str()),
+            [flag, value] if flag == "--test-output" => test_output = Some(value.as_str()),
+            _ => {
+                eprintln!("{}", USAGE);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    let reference = match (test_input, test_output) {
+        (Some(input), Some(output)) => {
+            match KerasReference::from_npy_files(input, output, Some((1.0, 99.8))) {
+                Ok(reference) => Some(reference),
+                Err(e) => {
+                    eprintln!("Failed to read the test tensors: {}", e);
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+        (None, None) => None,
+        _ => {
+            eprintln!("{}", USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+    let network = match option {
+        Some(("--preset", "fluo_2d")) => Ok(StarDistNetworkConfig::fluo_2d()),
+        Some(("--preset", "he_2d")) => Ok(StarDistNetworkConfig::he_2d()),
+        Some(("--preset", "fluo_3d")) => Ok(StarDistNetworkConfig::fluo_3d()),
+        Some(("--config", config)) => StarDistNetworkConfig::from_config_file(config),
+        None => {
+            let config = Path::new(weights_path).with_file_name("config.json");
+            StarDistNetworkConfig::from_config_file(&config.to_string_lossy())
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+    let result = network.and_then(|network| {
+        convert_keras_weights(weights_path, output_path, &network, reference.as_ref())
+    });
+    match result {
+        Ok(conversion) => {
+            println!(
+                "Converted {} convolution layers to \"{}\".",
+                conversion.n_convs, output_path
+            );
+            match conversion.reference_diff {
+                Some(diff) => println!(
+                    "Verified against the test output (max abs diff: {:e}).",
+                    diff
+                ),
+                None => println!("Not verified, no test input and output given."),
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Failed to convert \"{}\": {}", weights_path, e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,412 @@
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use imgal::prelude::*;
+use ndarray::{ArrayD, Axis, IxDyn};
+use serde_json::Value;
+use zip::ZipArchive;
+
+use crate::CellcastError;
+use crate::config::stardist;
+use crate::convert::read_keras_bytes;
+use crate::networks::stardist::config::StarDistNetworkConfig;
+use crate::networks::stardist::{ConvWeights, onnx};
+use crate::utils::npy::read_npy;
+
+const RDF_FILE: &str = "rdf.yaml";
+const RDF_SUFFIX: &str = "bioimageio.yaml";
+const VALIDATE_ATOL: f32 = 1e-3;
+const VALIDATE_RTOL: f32 = 1e-3;
+
+/// A StarDist model read from a bioimage.io model package.
+#[derive(Debug)]
+pub(crate) struct BioimageioPackage {
+    pub network: StarDistNetworkConfig,
+    pub anisotropy: Option<[f32; 3]>,
+    pub prob_threshold: Option<f64>,
+    pub nms_threshold: Option<f64>,
+    pub percentiles: Option<(f64, f64)>,
+    pub convs: Vec<ConvWeights>,
+    pub test: Option<PackageTest>,
+}
+
+/// The test tensors of a bioimage.io model package.
+///
+/// The input has shape `(...spatial, channels)` and is not preprocessed. The
+/// output has shape `(...spatial / grid, 1 + n_rays)` with the object
+/// probability followed by the ray distances.
+#[derive(Debug)]
+pub(crate) struct PackageTest {
+    pub input: ArrayD<f32>,
+    pub output: ArrayD<f32>,
+}
+
+/// The files of a bioimage.io model package, zipped or unpacked.
+enum PackageFiles {
+    Folder(PathBuf),
+    Zip(ZipArchive<fs::File>),
+}
+
+impl PackageFiles {
+    /// Open a zipped or unpacked model package.
+    fn open(path: &Path) -> Option<Self> {
+        if path.is_dir() {
+            return Some(Self::Folder(path.to_path_buf()));
+        }
+        let file = fs::File::open(path).ok()?;
+        ZipArchive::new(file).ok().map(Self::Zip)
+    }
+
+    /// Find the name of the resource description file of the package.
+    fn rdf_name(&self) -> Option<String> {
+        let names: Vec<String> = match self {
+            Self::Folder(dir) => fs::read_dir(dir)
+                .ok()?
+                .filter_map(|e| e.ok()?.file_name().into_string().ok())
+                .collect(),
+            Self::Zip(archive) => archive.file_names().map(String::from).collect(),
+        };
+        names
+            .iter()
+            .find(|n| n.as_str() == RDF_FILE)
+            .or_else(|| names.iter().find(|n| n.ends_with(RDF_SUFFIX)))
+            .cloned()
+    }
+
+    /// Read a file of the package.
+    fn read(&mut self, name: &str) -> Option<Vec<u8>> {
+        let name = name.trim_start_matches("./");
+        match self {
+            Self::Folder(dir) => fs::read(dir.join(name)).ok(),
+            Self::Zip(archive) => {
+                let mut file = archive.by_name(name).ok()?;
+                let mut bytes = Vec::with_capacity(file.size() as usize);
+                file.read_to_end(&mut bytes).ok()?;
+                Some(bytes)
+            }
+        }
+    }
+}
+
+/// Read a StarDist model from a bioimage.io model package.
+///
+/// # Description
+///
+/// Reads a bioimage.io model zoo package, *i.e.* a zip file (or unpacked
+/// folder) with an `rdf.yaml` resource description file (RDF), in format
+/// version 0.4 or 0.5. The RDF must describe a StarDist model exported by the
+/// reference StarDist: the network configuration and thresholds are read from
+/// the `config.stardist` section, and the single input and output tensors
+/// must have the StarDist spatial axes. The `scale_range` preprocessing sets
+/// the normalization percentiles. The weights are read from the `keras_hdf5`
+/// or `onnx` weights entry, in that order. The test input and output tensors
+/// (`.npy` files) are read if present in the package.
+///
+/// # Arguments
+///
+/// * `path`: The path to the model package zip file or unpacked folder.
+/// * `n_dim`: The expected number of spatial dimensions, 2 or 3.
+///
+/// # Returns
+///
+/// * `Ok(BioimageioPackage)`: The StarDist model of the package.
+/// * `Err(CellcastError)`: If the package or its RDF can not be read. If the
+///   RDF does not describe a StarDist model with `n_dim` dimensions. If the
+///   axes, preprocessing or postprocessing are not supported. If the package
+///   has no supported weights or they do not match the network.
+pub(crate) fn read_package(path: &Path, n_dim: usize) -> Result<BioimageioPackage, CellcastError> {
+    let read_err = || ImgalError::InvalidGeneric {
+        msg: "Failed to read the bioimage.io model package.",
+    };
+    let mut files = PackageFiles::open(path).ok_or_else(read_err)?;
+    let rdf_name = files.rdf_name().ok_or_else(read_err)?;
+    let rdf: Value = files
+        .read(&rdf_name)
+        .and_then(|bytes| serde_yaml_ng::from_slice(&bytes).ok())
+        .ok_or_else(read_err)?;
+    let sd = rdf
+        .get("config")
+        .and_then(|c| c.get("stardist"))
+        .filter(|_| rdf.get("type").is_none_or(|t| t == "model"))
+        .ok_or(ImgalError::InvalidGeneric {
+            msg: "The bioimage.io model package does not describe a StarDist model.",
+        })?;
+    let (network, anisotropy) = stardist::parse_config(
+        sd.get("config").ok_or(ImgalError::InvalidGeneric {
+            msg: "The bioimage.io model package does not describe a StarDist model.",
+        })?,
+        Some(n_dim),
+    )?;
+    let thresholds = sd.get("thresholds");
+    let threshold = |key: &str| thresholds.and_then(|t| t.get(key)).and_then(Value::as_f64);
+    // StarDist models have a single image input and a single output
+    // concatenating the object probability and ray distances
+    let (input, output) = match (tensors(&rdf, "inputs"), tensors(&rdf, "outputs")) {
+        ([input], [output]) => (input, output),
+        _ => {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "The bioimage.io model package inputs and outputs do not match a StarDist model.",
+            }));
+        }
+    };
+    let spatial = if n_dim == 2 { "yx" } else { "zyx" };
+    let input_axes = tensor_axes(input).filter(|a| is_stardist_axes(a, spatial));
+    let output_axes = tensor_axes(output).filter(|a| is_stardist_axes(a, spatial));
+    let (Some(input_axes), Some(output_axes)) = (input_axes, output_axes) else {
+        return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+            msg: "The bioimage.io model package axes are not supported.",
+        }));
+    };
+    let mut percentiles = None;
+    for (id, kwargs) in processing(input, "preprocessing") {
+        match id {
+            "scale_range" => {
+                let kwarg = |key: &str| kwargs.and_then(|k| k.get(key)).and_then(Value::as_f64);
+                percentiles = Some((
+                    kwarg("min_percentile").unwrap_or(0.0),
+                    kwarg("max_percentile").unwrap_or(100.0),
+                ));
+            }
+            "ensure_dtype" => (),
+            _ => {
+                return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                    msg: "The bioimage.io model package preprocessing is not supported.",
+                }));
+            }
+        }
+    }
+    if processing(output, "postprocessing").any(|(id, _)| id != "ensure_dtype") {
+        return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+            msg: "The bioimage.io model package postprocessing is not supported.",
+        }));
+    }
+    let (network, convs) = read_weights(&mut files, &rdf, network)?;
+    let test = read_test(&mut files, &rdf, input, output).and_then(|(i, o)| {
+        Some(PackageTest {
+            input: to_spatial_channels(i, &input_axes, spatial)?,
+            output: to_spatial_channels(o, &output_axes, spatial)?,
+        })
+    });
+    Ok(BioimageioPackage {
+        network,
+        anisotropy,
+        prob_threshold: threshold("prob"),
+        nms_threshold: threshold("nms"),
+        percentiles,
+        convs,
+        test,
+    })
+}
+
+/// Validate the network outputs of a model package test input.
+///
+/// # Description
+///
+/// Compares the object probabilities and ray distances predicted for the
+/// package test input with the package test output. Values match if their
+/// absolute difference is at most `1e-3` plus `1e-3` times the expected value.
+///
+/// # Arguments
+///
+/// * `expected`: The package test output, with shape
+///   `(...spatial, 1 + n_rays)`.
+/// * `prob`: The flat predicted object probabilities.
+/// * `dist`: The flat predicted ray distances.
+/// * `channels_last`: If `true` the ray distances have shape
+///   `(...spatial, n_rays)`, otherwise `(n_rays, ...spatial)`.
+///
+/// # Returns
+///
+/// * `Ok(())`: If the predictions match the test output.
+/// * `Err(CellcastError)`: If the prediction shape or values do not match the
+///   test output.
+pub(crate) fn validate_outputs(
+    expected: &ArrayD<f32>,
+    prob: &[f32],
+    dist: &[f32],
+    channels_last: bool,
+) -> Result<(), CellcastError> {
+    let n = prob.len();
+    let n_channels = expected.shape().last().copied().unwrap_or(0);
+    if n == 0 || expected.len() != n + dist.len() || n_channels * n != expected.len() {
+        return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+            msg: "The bioimage.io model package test output shape does not match the model output.",
+        }));
+    }
+    let n_rays = n_channels - 1;
+    let expected = expected.as_standard_layout();
+    let matches = expected.iter().enumerate().all(|(i, &e)| {
+        let (px, ch) = (i / n_channels, i % n_channels);
+        let got = match ch {
+            0 => prob[px],
+            _ if channels_last => dist[px * n_rays + ch - 1],
+            _ => dist[(ch - 1) * n + px],
+        };
+        (e - got).abs() <= VALIDATE_ATOL + VALIDATE_RTOL * e.abs()
+    });
+    if !matches {
+        return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+            msg: "The model output does not match the bioimage.io model package test output.",
+        }));
+    }
+    Ok(())
+}
+
+/// Get the input or output tensor descriptions of an RDF.
+fn tensors<'a>(rdf: &'a Value, key: &str) -> &'a [Value] {
+    rdf.get(key)
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+}
+
+/// Get the axes of a tensor description as a lowercase string.
+///
+/// Format version 0.4 axes are a string (*e.g.* `"byxc"`) and format version
+/// 0.5 axes are a list of axis descriptions with a `type` and `id`.
+fn tensor_axes(tensor: &Value) -> Option<String> {
+    match tensor.get("axes")? {
+        Value::String(axes) => Some(axes.to_lowercase()),
+        Value::Array(axes) => axes
+            .iter()
+            .map(|axis| {
+                if let Some(id) = axis.as_str() {
+                    return id.chars().next();
+                }
+                match axis.get("type").and_then(Value::as_str)? {
+                    "batch" => Some('b'),
+                    "channel" => Some('c'),
+                    "space" => axis.get("id").and_then(Value::as_str)?.chars().next(),
+                    _ => None,
+                }
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
+/// Check that tensor axes are the `spatial` axes with an optional batch and
+/// channel axis.
+fn is_stardist_axes(axes: &str, spatial: &str) -> bool {
+    let other: String = axes.chars().filter(|&c| c != 'b' && c != 'c').collect();
+    other == spatial && axes.matches('b').count() <= 1 && axes.matches('c').count() <= 1
+}
+
+/// Iterate the `(id, kwargs)` pre- or postprocessing steps of a tensor
+/// description.
+fn processing<'a>(
+    tensor: &'a Value,
+    key: &str,
+) -> impl Iterator<Item = (&'a str, Option<&'a Value>)> {
+    tensor
+        .get(key)
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .map(|step| {
+            // format version 0.4 steps have a "name" and 0.5 steps an "id"
+            let id = step
+                .get("id")
+                .or_else(|| step.get("name"))
+                .and_then(Value::as_str)
+                .unwrap_or_default();
+            (id, step.get("kwargs"))
+        })
+}
+
+/// Read the StarDist network weights of a model package.
+///
+/// # Returns
+///
+/// * `Ok((StarDistNetworkConfig, Vec<ConvWeights>))`: The network
+///   configuration and convolution weights in ONNX graph order.
+/// * `Err(CellcastError)`: If the package has no `keras_hdf5` or `onnx`
+///   weights. If the weights can not be read or do not match `network`.
+fn read_weights(
+    files: &mut PackageFiles,
+    rdf: &Value,
+    network: StarDistNetworkConfig,
+) -> Result<(StarDistNetworkConfig, Vec<ConvWeights>), CellcastError> {
+    let source = |format: &str| {
+        rdf.get("weights")?
+            .get(format)?
+            .get("source")?
+            .as_str()
+            .map(String::from)
+    };
+    let read_err = || ImgalError::InvalidGeneric {
+        msg: "Failed to read the bioimage.io model package weights.",
+    };
+    if let Some(source) = source("keras_hdf5") {
+        let bytes = files.read(&source).ok_or_else(read_err)?;
+        let convs = read_keras_bytes(bytes, network.n_dim)?;
+        return Ok((network, convs));
+    }
+    if let Some(source) = source("onnx") {
+        let bytes = files.read(&source).ok_or_else(read_err)?;
+        let onnx = onnx::read_onnx_bytes(&bytes, network.n_dim, Some(network))?;
+        return Ok((onnx.network, onnx.convs));
+    }
+    Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+        msg: "The bioimage.io model package has no \"keras_hdf5\" or \"onnx\" weights.",
+    }))
+}
+
+/// Read the test input and output tensors of a model package.
+///
+/// Format version 0.4 lists the test tensors in `test_inputs` and
+/// `test_outputs`, format version 0.5 in the `test_tensor` of each tensor
+/// description.
+fn read_test(
+    files: &mut PackageFiles,
+    rdf: &Value,
+    input: &Value,
+    output: &Value,
+) -> Option<(ArrayD<f32>, ArrayD<f32>)> {
+    let source = |tensor: &Value, key: &str| {
+        tensor
+            .get("test_tensor")
+            .and_then(|t| t.get("source"))
+            .or_else(|| rdf.get(key)?.get(0))
+            .and_then(Value::as_str)
+            .map(String::from)
+    };
+    let input = read_npy(&files.read(&source(input, "test_inputs")?)?)?;
+    let output = read_npy(&files.read(&source(output, "test_outputs")?)?)?;
+    Some((input, output))
+}
+
+/// Reorder a tensor to `(...spatial, channels)` axes.
+///
+/// The batch axis (if present) must have a length of 1 and is removed. A
+/// channel axis of length 1 is added if not present.
+fn to_spatial_channels(arr: ArrayD<f32>, axes: &str, spatial: &str) -> Option<ArrayD<f32>> {
+    if arr.ndim() != axes.len() {
+        return None;
+    }
+    let mut arr = arr;
+    let mut axes: Vec<char> = axes.chars().collect();
+    if let Some(b) = axes.iter().position(|&a| a == 'b') {
+        if arr.shape()[b] != 1 {
+            return None;
+        }
+        arr = arr.index_axis_move(Axis(b), 0);
+        axes.remove(b);
+    }
+    if !axes.contains(&'c') {
+        let n = arr.ndim();
+        arr = arr.insert_axis(Axis(n));
+        axes.push('c');
+    }
+    let order: Vec<usize> = spatial
+        .chars()
+        .chain(['c'])
+        .map(|s| axes.iter().position(|&a| a == s))
+        .collect::<Option<Vec<usize>>>()?;
+    Some(
+        arr.permuted_axes(IxDyn(&order))
+            .as_standard_layout()
+            .into_owned(),
+    )
+}
@@ -1,7 +1,10 @@
-//! Model backend and pretrained weight configuration.
+//! Model backend, pretrained weight and model folder configuration.
 //!
 //! This module provides access to crate wide model backend and pretrained
-//! weight configuration.
+//! weight configuration, as well as reading exported model folders and
+//! bioimage.io model packages.
 
 pub(crate) mod backend;
+pub(crate) mod bioimageio;
+pub(crate) mod stardist;
 pub(crate) mod weights;
@@ -0,0 +1,324 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use imgal::prelude::*;
+use serde_json::{Value, json};
+
+use crate::CellcastError;
+use crate::networks::stardist::config::{StarDistBackbone, StarDistNetworkConfig};
+
+const CONFIG_FILE: &str = "config.json";
+const THRESHOLDS_FILE: &str = "thresholds.json";
+const WEIGHTS_FILES: [&str; 2] = ["weights_best.bpk", "weights_last.bpk"];
+
+/// A StarDist model folder exported from the reference StarDist.
+#[derive(Debug)]
+pub(crate) struct StarDistModelFolder {
+    pub network: StarDistNetworkConfig,
+    pub anisotropy: Option<[f32; 3]>,
+    pub prob_threshold: Option<f64>,
+    pub nms_threshold: Option<f64>,
+    pub weights_path: PathBuf,
+}
+
+/// Read a StarDist model folder.
+///
+/// # Description
+///
+/// Reads the network configuration from `config.json`, the optimized
+/// thresholds from `thresholds.json` (if present) and locates the weights
+/// converted to burnpack format. The weights file is `weights_best.bpk`,
+/// `weights_last.bpk` or the only `.bpk` file in the folder, in that order.
+/// Network parameters missing from `config.json` fall back to the preset of
+/// the published pretrained weights with `n_dim` dimensions.
+///
+/// # Arguments
+///
+/// * `path`: The path to the model folder.
+/// * `n_dim`: The expected number of spatial dimensions, 2 or 3.
+///
+/// # Returns
+///
+/// * `Ok(StarDistModelFolder)`: The model folder contents.
+/// * `Err(CellcastError)`: If `config.json` can not be read or parsed. If the
+///   model is not `n_dim` dimensional. If the axes are not supported. If
+///   `thresholds.json` can not be parsed. If no weights file is found.
+pub(crate) fn read_model_folder(
+    path: &Path,
+    n_dim: usize,
+) -> Result<StarDistModelFolder, CellcastError> {
+    let (network, anisotropy) = read_config(&path.join(CONFIG_FILE), Some(n_dim))?;
+    let (prob_threshold, nms_threshold) = match path.join(THRESHOLDS_FILE) {
+        p if p.is_file() => {
+            let thresholds = read_json(&p).ok_or(ImgalError::InvalidGeneric {
+                msg: "Failed to read the StarDist model folder \"thresholds.json\" file.",
+            })?;
+            (
+                thresholds.get("prob").and_then(Value::as_f64),
+                thresholds.get("nms").and_then(Value::as_f64),
+            )
+        }
+        _ => (None, None),
+    };
+    Ok(StarDistModelFolder {
+        network,
+        anisotropy,
+        prob_threshold,
+        nms_threshold,
+        weights_path: find_weights(path)?,
+    })
+}
+
+/// Read the network configuration of a StarDist model folder.
+///
+/// # Description
+///
+/// Reads the network configuration and anisotropy from a StarDist
+/// `config.json` file. Network parameters missing from the file fall back to
+/// the preset of the published pretrained weights with the same number of
+/// dimensions.
+///
+/// # Arguments
+///
+/// * `path`: The path to the `config.json` file.
+/// * `n_dim`: The expected number of spatial dimensions. If `None` then any
+///   2D or 3D model is accepted.
+///
+/// # Returns
+///
+/// * `Ok((StarDistNetworkConfig, Option<[f32; 3]>))`: The network
+///   configuration and anisotropy (if present).
+/// * `Err(CellcastError)`: If the file can not be read or parsed. If the model
+///   does not have `n_dim` dimensions. If the axes or backbone are not
+///   supported.
+pub(crate) fn read_config(
+    path: &Path,
+    n_dim: Option<usize>,
+) -> Result<(StarDistNetworkConfig, Option<[f32; 3]>), CellcastError> {
+    let config = read_json(path).ok_or(ImgalError::InvalidGeneric {
+        msg: "Failed to read the StarDist model folder \"config.json\" file.",
+    })?;
+    parse_config(&config, n_dim)
+}
+
+/// Parse a StarDist network configuration.
+///
+/// # Description
+///
+/// Parses the network configuration and anisotropy from the JSON object of a
+/// StarDist `config.json` file (*e.g.* also embedded in model packages).
+/// Network parameters missing from the object fall back to the preset of the
+/// published pretrained weights with the same number of dimensions.
+///
+/// # Arguments
+///
+/// * `config`: The StarDist configuration object.
+/// * `n_dim`: The expected number of spatial dimensions. If `None` then any
+///   2D or 3D model is accepted.
+///
+/// # Returns
+///
+/// * `Ok((StarDistNetworkConfig, Option<[f32; 3]>))`: The network
+///   configuration and anisotropy (if present).
+/// * `Err(CellcastError)`: If the model does not have `n_dim` dimensions. If
+///   a value is invalid. If the axes or backbone are not supported.
+pub(crate) fn parse_config(
+    config: &Value,
+    n_dim: Option<usize>,
+) -> Result<(StarDistNetworkConfig, Option<[f32; 3]>), CellcastError> {
+    let n_dim = match config.get("n_dim").and_then(Value::as_u64) {
+        Some(d) if n_dim.is_none_or(|n| n as u64 == d) && (2..=3).contains(&d) => d as usize,
+        _ => {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "The StarDist model configuration has the wrong number of dimensions.",
+            }));
+        }
+    };
+    if let Some(axes) = config.get("axes").and_then(Value::as_str) {
+        let spatial: String = axes.chars().filter(|&c| c != 'C').collect();
+        let expected = if n_dim == 2 { "YX" } else { "ZYX" };
+        if spatial != expected {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "The StarDist model configuration axes are not supported.",
+            }));
+        }
+    }
+    let mut network = if n_dim == 2 {
+        StarDistNetworkConfig::fluo_2d()
+    } else {
+        StarDistNetworkConfig::fluo_3d()
+    };
+    for (key, value) in [
+        ("n_channel_in", &mut network.n_channel_in),
+        ("n_rays", &mut network.n_rays),
+        ("unet_n_depth", &mut network.unet_n_depth),
+        ("unet_n_filter_base", &mut network.unet_n_filter_base),
+        ("unet_n_conv_per_depth", &mut network.unet_n_conv_per_depth),
+        ("resnet_n_blocks", &mut network.resnet_n_blocks),
+        ("resnet_n_filter_base", &mut network.resnet_n_filter_base),
+        (
+            "resnet_n_conv_per_block",
+            &mut network.resnet_n_conv_per_block,
+        ),
+        ("net_conv_after_unet", &mut network.net_conv_after_unet),
+    ] {
+        read_usize(config, key, value)?;
+    }
+    for (key, value) in [
+        ("grid", &mut network.grid),
+        ("unet_kernel_size", &mut network.unet_kernel_size),
+        ("unet_pool", &mut network.unet_pool),
+        ("resnet_kernel_size", &mut network.resnet_kernel_size),
+    ] {
+        read_usize_vec(config, key, value)?;
+    }
+    // the reference StarDist stores "n_classes": null for single class models
+    if config.get("n_classes").is_some_and(|v| !v.is_null()) {
+        let mut n_classes = 0;
+        read_usize(config, "n_classes", &mut n_classes)?;
+        network.n_classes = Some(n_classes);
+    }
+    match config.get("backbone").and_then(Value::as_str) {
+        Some("unet") => network.backbone = StarDistBackbone::Unet,
+        Some("resnet") => network.backbone = StarDistBackbone::Resnet,
+        Some(_) => {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "The StarDist model configuration backbone is not supported.",
+            }));
+        }
+        None => (),
+    }
+    network.validate()?;
+    let anisotropy = match config.get("anisotropy") {
+        Some(Value::Array(arr)) => {
+            let arr: Vec<f32> = arr
+                .iter()
+                .filter_map(|v| v.as_f64())
+                .map(|v| v as f32)
+                .collect();
+            if arr.len() != 3 {
+                return Err(CellcastError::Imgal(
+                    ImgalError::InvalidArrayLengthExpected {
+                        arr_name: "anisotropy",
+                        expected: 3,
+                        got: arr.len(),
+                    },
+                ));
+            }
+            Some([arr[0], arr[1], arr[2]])
+        }
+        _ => None,
+    };
+    Ok((network, anisotropy))
+}
+
+/// Create a StarDist network configuration object.
+///
+/// # Description
+///
+/// Creates the JSON object of a StarDist `config.json` file with the network
+/// configuration and anisotropy, readable with `parse_config`.
+///
+/// # Arguments
+///
+/// * `network`: The network configuration.
+/// * `anisotropy`: The anisotropy the model was trained with (if any).
+///
+/// # Returns
+///
+/// * `Value`: The StarDist configuration object.
+pub(crate) fn config_json(network: &StarDistNetworkConfig, anisotropy: Option<[f32; 3]>) -> Value {
+    let backbone = match network.backbone {
+        StarDistBackbone::Unet => "unet",
+        StarDistBackbone::Resnet => "resnet",
+    };
+    let axes = if network.n_dim == 2 { "YXC" } else { "ZYXC" };
+    let mut config = json!({
+        "n_dim": network.n_dim,
+        "axes": axes,
+        "backbone": backbone,
+        "n_channel_in": network.n_channel_in,
+        "n_rays": network.n_rays,
+        "grid": network.grid,
+        "unet_n_depth": network.unet_n_depth,
+        "unet_kernel_size": network.unet_kernel_size,
+        "unet_n_filter_base": network.unet_n_filter_base,
+        "unet_n_conv_per_depth": network.unet_n_conv_per_depth,
+        "unet_pool": network.unet_pool,
+        "resnet_n_blocks": network.resnet_n_blocks,
+        "resnet_kernel_size": network.resnet_kernel_size,
+        "resnet_n_filter_base": network.resnet_n_filter_base,
+        "resnet_n_conv_per_block": network.resnet_n_conv_per_block,
+        "net_conv_after_unet": network.net_conv_after_unet,
+        "n_classes": network.n_classes,
+    });
+    if let Some(anisotropy) = anisotropy {
+        config["anisotropy"] = json!(anisotropy);
+    }
+    config
+}
+
+/// Find the burnpack weights file of a model folder.
+fn find_weights(path: &Path) -> Result<PathBuf, CellcastError> {
+    if let Some(wp) = WEIGHTS_FILES
+        .iter()
+        .map(|f| path.join(f))
+        .find(|p| p.is_file())
+    {
+        return Ok(wp);
+    }
+    let bpk: Vec<PathBuf> = fs::read_dir(path)
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok().map(|e| e.path()))
+                .filter(|p| p.extension().is_some_and(|ext| ext == "bpk"))
+                .collect()
+        })
+        .unwrap_or_default();
+    match bpk.as_slice() {
+        [wp] => Ok(wp.clone()),
+        _ => Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+            msg: "No unique burnpack (.bpk) weights file found in the StarDist model folder.",
+        })),
+    }
+}
+
+/// Read and parse a JSON file.
+fn read_json(path: &Path) -> Option<Value> {
+    let text = fs::read_to_string(path).ok()?;
+    serde_json::from_str(&text).ok()
+}
+
+/// Read an optional `usize` field of a JSON object into `value`.
+fn read_usize(json: &Value, key: &str, value: &mut usize) -> Result<(), CellcastError> {
+    if let Some(v) = json.get(key) {
+        *value = v.as_u64().ok_or(ImgalError::InvalidGeneric {
+            msg: "The StarDist model configuration has an invalid integer value.",
+        })? as usize;
+    }
+    Ok(())
+}
+
+/// Read an optional `usize` array field of a JSON object into `value`.
+///
+/// A single integer is broadcast to all elements of `value`.
+fn read_usize_vec(json: &Value, key: &str, value: &mut Vec<usize>) -> Result<(), CellcastError> {
+    let err = ImgalError::InvalidGeneric {
+        msg: "The StarDist model configuration has an invalid integer array value.",
+    };
+    match json.get(key) {
+        Some(Value::Array(arr)) => {
+            *value = arr
+                .iter()
+                .map(|v| v.as_u64().map(|v| v as usize))
+                .collect::<Option<Vec<usize>>>()
+                .ok_or(err)?;
+        }
+        Some(v) => {
+            let v = v.as_u64().ok_or(err)? as usize;
+            value.iter_mut().for_each(|e| *e = v);
+        }
+        None => (),
+    }
+    Ok(())
+}
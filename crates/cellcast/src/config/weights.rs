@@ -1,4 +1,4 @@
-pub const DEMO_3D_URL: &'static str =
+pub const DEMO_3D_URL: &str =
     "https://github.com/uw-loci/cellcast/raw/refs/heads/main/weights/stardist/stardist_3d_demo.bpk";
-pub const VERSATILE_FLUO_2D_URL: &'static str = "https://github.com/uw-loci/cellcast/raw/refs/tags/cellcast-v0.1.2/weights/stardist/stardist_2d_versatile_fluo.bpk";
-pub const VERSATILE_HE_2D_URL: &'static str = "https://github.com/uw-loci/cellcast/raw/refs/tags/cellcast-v0.2.0/weights/stardist/stardist_2d_versatile_he.bpk";
+pub const VERSATILE_FLUO_2D_URL: &str = "https://github.com/uw-loci/cellcast/raw/refs/tags/cellcast-v0.1.2/weights/stardist/stardist_2d_versatile_fluo.bpk";
+pub const VERSATILE_HE_2D_URL: &str = "https://github.com/uw-loci/cellcast/raw/refs/tags/cellcast-v0.2.0/weights/stardist/stardist_2d_versatile_he.bpk";
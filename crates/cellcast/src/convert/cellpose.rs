@@ -0,0 +1,88 @@
+use std::path::Path;
+
+use burn::module::ParamId;
+use burn_store::pytorch::PytorchReader;
+use burn_store::{BurnpackWriter, TensorSnapshot};
+use imgal::prelude::*;
+
+use crate::CellcastError;
+use crate::config::backend::CpuBackend;
+use crate::networks::cellpose::config::CellposeNetworkConfig;
+use crate::networks::cellpose::unet;
+
+type CpuConfigBackend = CpuBackend<f32, i32>;
+
+/// The parameter name suffixes of the Cellpose network layers.
+const PARAM_SUFFIXES: [&str; 4] = [".weight", ".bias", ".running_mean", ".running_var"];
+
+/// Convert PyTorch Cellpose weights to burnpack format.
+///
+/// # Description
+///
+/// Reads the state dict of a Cellpose model saved by the reference PyTorch
+/// implementation (*e.g.* the `cyto3` or `nuclei` model files) and writes the
+/// network parameters to a burnpack file, keeping the PyTorch parameter names.
+/// Batch norm counters and other non-parameter tensors (*e.g.* the stored
+/// mean diameters) are skipped. The conversion is verified by loading the
+/// written burnpack file into a Cellpose network of `network`.
+///
+/// # Arguments
+///
+/// * `weights_path`: The path to the PyTorch Cellpose weights file.
+/// * `output_path`: The path of the burnpack (`.bpk`) file to write.
+/// * `network`: The network configuration of the weights (*e.g.* the `cyto`
+///   or `nuclei` presets).
+///
+/// # Returns
+///
+/// * `Ok(usize)`: The number of converted tensors.
+/// * `Err(CellcastError)`: If `network` is invalid. If the weights file can not
+///   be read. If the burnpack file can not be written. If the written weights
+///   do not match `network`.
+pub fn convert_cellpose_weights(
+    weights_path: &str,
+    output_path: &str,
+    network: &CellposeNetworkConfig,
+) -> Result<usize, CellcastError> {
+    network.validate()?;
+    let tensors = PytorchReader::new(weights_path)
+        .map_err(|_| ImgalError::InvalidGeneric {
+            msg: "Failed to read the PyTorch Cellpose weights file.",
+        })?
+        .into_tensors();
+    let mut names: Vec<&String> = tensors
+        .keys()
+        .filter(|k| PARAM_SUFFIXES.iter().any(|s| k.ends_with(s)))
+        .collect();
+    names.sort();
+    let mut snapshots = Vec::with_capacity(names.len());
+    for name in names {
+        let data = tensors[name]
+            .to_data()
+            .map_err(|_| ImgalError::InvalidGeneric {
+                msg: "Failed to read a PyTorch Cellpose weights tensor.",
+            })?
+            .convert::<f32>();
+        let path: Vec<String> = name.split('.').map(String::from).collect();
+        snapshots.push(TensorSnapshot::from_data(
+            data,
+            path,
+            vec!["Struct:Model".to_string()],
+            ParamId::new(),
+        ));
+    }
+    let n_tensors = snapshots.len();
+    BurnpackWriter::new(snapshots)
+        .write_to_file(Path::new(output_path))
+        .map_err(|_| {
+            CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "Failed to write the burnpack weights file.",
+            })
+        })?;
+    unet::Model::<CpuConfigBackend>::from_file(
+        Path::new(output_path),
+        &Default::default(),
+        network,
+    )?;
+    Ok(n_tensors)
+}
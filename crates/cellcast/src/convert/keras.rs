@@ -0,0 +1,537 @@
+use std::fs;
+use std::path::Path;
+
+use burn::module::ParamId;
+use burn::prelude::*;
+use burn_store::{BurnpackWriter, TensorSnapshot};
+use hdf5_pure::{AttrValue, File, Group};
+use imgal::image::percentile_normalize;
+use imgal::prelude::*;
+use ndarray::{ArrayD, ArrayViewD, Axis, IxDyn};
+
+use crate::CellcastError;
+use crate::config::backend::CpuBackend;
+use crate::networks::stardist::config::StarDistNetworkConfig;
+use crate::networks::stardist::metadata::{METADATA_KEY, StarDistMetadata};
+use crate::networks::stardist::{ConvWeights, Network3d, StarDistWeights, unet_2d};
+use crate::utils::npy::read_npy;
+
+type CpuConfigBackend = CpuBackend<f32, i32>;
+
+/// The maximum absolute output difference accepted by the round trip check.
+const ROUND_TRIP_TOLERANCE: f32 = 1e-4;
+const REFERENCE_ATOL: f32 = 1e-3;
+const REFERENCE_RTOL: f32 = 1e-3;
+
+/// The result of a Keras weights conversion.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KerasConversion {
+    /// The number of converted convolution layers.
+    pub n_convs: usize,
+    /// The maximum absolute difference between the network outputs of the
+    /// converted weights and the written burnpack file on a synthetic input.
+    pub round_trip_diff: f32,
+    /// The maximum absolute difference between the network outputs of the
+    /// converted weights and the reference output, `None` if no reference
+    /// was given.
+    pub reference_diff: Option<f32>,
+}
+
+/// The output of a Keras StarDist network on a test input.
+///
+/// The input has shape `(...spatial, channels)` and the output has shape
+/// `(...spatial / grid, 1 + n_rays)` with the object probability followed by
+/// the ray distances, as the test tensors of bioimage.io StarDist packages. A
+/// leading batch axis of length 1 is ignored.
+#[derive(Debug, Clone)]
+pub struct KerasReference {
+    pub input: ArrayD<f32>,
+    pub output: ArrayD<f32>,
+    /// The percentiles to normalize the input with, `None` if the input is
+    /// already normalized.
+    pub percentiles: Option<(f64, f64)>,
+}
+
+impl KerasReference {
+    /// Read a Keras reference from NumPy `.npy` files.
+    ///
+    /// # Arguments
+    ///
+    /// * `input_path`: The path to the test input `.npy` file.
+    /// * `output_path`: The path to the test output `.npy` file.
+    /// * `percentiles`: The percentiles to normalize the input with, `None` if
+    ///   the input is already normalized. The test inputs of bioimage.io
+    ///   StarDist packages are not normalized.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(KerasReference)`: The reference input and output.
+    /// * `Err(CellcastError)`: If either file can not be read or is not a
+    ///   supported `.npy` array.
+    pub fn from_npy_files(
+        input_path: &str,
+        output_path: &str,
+        percentiles: Option<(f64, f64)>,
+    ) -> Result<Self, CellcastError> {
+        let read = |path: &str| {
+            fs::read(path)
+                .ok()
+                .and_then(|bytes| read_npy(&bytes))
+                .ok_or(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                    msg: "Failed to read the Keras reference \".npy\" file.",
+                }))
+        };
+        Ok(Self {
+            input: read(input_path)?,
+            output: read(output_path)?,
+            percentiles,
+        })
+    }
+}
+
+/// Convert Keras HDF5 StarDist weights to burnpack format.
+///
+/// # Description
+///
+/// Reads the convolution layers of a Keras HDF5 weights file (*e.g.*
+/// `weights_best.h5`) saved by the reference StarDist, in model layer order
+/// with the named `dist` and `prob` heads last, followed by the
+/// `features_class` and `prob_class` layers of multi-class networks. The
+/// Keras kernels are transposed from the `(...kernel, c_in, c_out)` layout to
+/// the `(c_out, c_in, ...kernel)` layout and written to a burnpack file with
+/// the parameter names of the pretrained networks (*i.e.* `conv2d1` ...
+/// `conv2dN` for 2D and `conv3d1` ... `conv3dN` for 3D networks) and the
+/// network configuration as StarDist metadata.
+///
+/// If a `reference` is given, the conversion is verified by running the
+/// converted network on the reference input and comparing the object
+/// probabilities and ray distances with the reference output. Values match if
+/// their absolute difference is at most `1e-3` plus `1e-3` times the reference
+/// value. Without a reference the conversion is not verified. The written
+/// burnpack file is checked to reproduce the outputs of the converted weights
+/// on a synthetic input, which catches serialization errors but not
+/// conversion errors. The burnpack file is written to a temporary file next
+/// to `output_path`, which replaces `output_path` once both checks pass and
+/// is removed otherwise.
+///
+/// # Arguments
+///
+/// * `weights_path`: The path to the Keras HDF5 (`.h5`) weights file.
+/// * `output_path`: The path of the burnpack (`.bpk`) file to write.
+/// * `network`: The network configuration of the weights (*e.g.* read from
+///   the model folder `config.json` or one of the `fluo_2d`, `he_2d` or
+///   `fluo_3d` presets).
+/// * `reference`: The output of the Keras network on a test input (*e.g.* the
+///   test tensors of a bioimage.io StarDist package). If `None`, the
+///   conversion is not verified.
+///
+/// # Returns
+///
+/// * `Ok(KerasConversion)`: The number of converted layers and the output
+///   differences of the checks.
+/// * `Err(CellcastError)`: If `network` is invalid. If the weights file can not
+///   be read or does not contain StarDist `dist` and `prob` heads. If the
+///   weights do not match `network`. If the reference input does not match
+///   the network input. If the converted network output does not match the
+///   reference output. If the burnpack file can not be written or does not
+///   reproduce the converted weights.
+pub fn convert_keras_weights(
+    weights_path: &str,
+    output_path: &str,
+    network: &StarDistNetworkConfig,
+    reference: Option<&KerasReference>,
+) -> Result<KerasConversion, CellcastError> {
+    network.validate()?;
+    let convs = read_keras_convs(Path::new(weights_path), network.n_dim)?;
+    let reference_diff = reference
+        .map(|r| compare_reference(r, &convs, network))
+        .transpose()?;
+    let output_path = Path::new(output_path);
+    let tmp_path = output_path.with_extension("tmp.bpk");
+    let checked = write_burnpack(&tmp_path, &convs, network)
+        .and_then(|_| check_round_trip(&tmp_path, &convs, network))
+        .and_then(|round_trip_diff| {
+            if round_trip_diff <= ROUND_TRIP_TOLERANCE {
+                Ok(round_trip_diff)
+            } else {
+                Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                    msg: "The written burnpack weights do not reproduce the converted weights outputs.",
+                }))
+            }
+        })
+        .and_then(|round_trip_diff| {
+            fs::rename(&tmp_path, output_path).map_err(|_| {
+                CellcastError::Imgal(ImgalError::InvalidGeneric {
+                    msg: "Failed to write the burnpack weights file.",
+                })
+            })?;
+            Ok(round_trip_diff)
+        });
+    // never leave unchecked weights behind
+    let round_trip_diff = checked.inspect_err(|_| {
+        let _ = fs::remove_file(&tmp_path);
+    })?;
+    Ok(KerasConversion {
+        n_convs: convs.len(),
+        round_trip_diff,
+        reference_diff,
+    })
+}
+
+/// Read the convolution layers of a Keras HDF5 weights file in ONNX graph
+/// order.
+fn read_keras_convs(path: &Path, n_dim: usize) -> Result<Vec<ConvWeights>, CellcastError> {
+    let file = File::open(path).map_err(|_| ImgalError::InvalidGeneric {
+        msg: "Failed to read the Keras HDF5 weights file.",
+    })?;
+    read_keras_file(&file, n_dim)
+}
+
+/// Read the convolution layers of a Keras HDF5 weights file loaded in memory
+/// (*e.g.* read from a model package) in ONNX graph order.
+pub(crate) fn read_keras_bytes(
+    data: Vec<u8>,
+    n_dim: usize,
+) -> Result<Vec<ConvWeights>, CellcastError> {
+    let file = File::from_bytes(data).map_err(|_| ImgalError::InvalidGeneric {
+        msg: "Failed to read the Keras HDF5 weights file.",
+    })?;
+    read_keras_file(&file, n_dim)
+}
+
+/// Read the convolution layers of an open Keras HDF5 weights file in ONNX
+/// graph order.
+fn read_keras_file(file: &File, n_dim: usize) -> Result<Vec<ConvWeights>, CellcastError> {
+    let read_err = || ImgalError::InvalidGeneric {
+        msg: "Failed to read the Keras HDF5 weights file.",
+    };
+    // full model files store the weights in the "model_weights" group
+    let root = file.group("model_weights").unwrap_or_else(|_| file.root());
+    let layer_names = string_attr(&root, "layer_names").ok_or_else(read_err)?;
+    let mut convs = Vec::new();
+    let mut dist = None;
+    let mut prob = None;
+    let mut class_head = Vec::new();
+    for name in layer_names {
+        let layer = root.group(&name).map_err(|_| read_err())?;
+        let weight_names = string_attr(&layer, "weight_names").unwrap_or_default();
+        let find = |suffix: &str| {
+            weight_names
+                .iter()
+                .find(|w| w.trim_end_matches(":0").ends_with(suffix))
+        };
+        // layers without a kernel (e.g. pooling and concatenation) are skipped
+        let Some(kernel_name) = find("kernel") else {
+            continue;
+        };
+        let (kernel, shape) = read_dataset(&layer, kernel_name)?;
+        if shape.len() != n_dim + 2 {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "The Keras HDF5 weights file has a layer that is not a StarDist convolution.",
+            }));
+        }
+        let bias = match find("bias") {
+            Some(bias_name) => {
+                let (bias, bias_shape) = read_dataset(&layer, bias_name)?;
+                Some(TensorData::new(bias, bias_shape))
+            }
+            None => None,
+        };
+        let conv = ConvWeights {
+            weight: transpose_kernel(kernel, &shape),
+            bias,
+        };
+        match name.as_str() {
+            "dist" => dist = Some(conv),
+            "prob" => prob = Some(conv),
+            "features_class" | "prob_class" => class_head.push(conv),
+            _ => convs.push(conv),
+        }
+    }
+    match (dist, prob) {
+        (Some(dist), Some(prob)) => {
+            convs.push(dist);
+            convs.push(prob);
+            convs.extend(class_head);
+            Ok(convs)
+        }
+        _ => Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+            msg: "The Keras HDF5 weights file does not have StarDist \"dist\" and \"prob\" layers.",
+        })),
+    }
+}
+
+/// Read a string array attribute of an HDF5 group.
+fn string_attr(group: &Group, name: &str) -> Option<Vec<String>> {
+    match group.attrs().ok()?.remove(name)? {
+        AttrValue::StringArray(v)
+        | AttrValue::AsciiStringArray(v)
+        | AttrValue::VarLenStringArray(v)
+        | AttrValue::VarLenAsciiStringArray(v)
+        | AttrValue::StringArraySized { values: v, .. }
+        | AttrValue::AsciiStringArraySized { values: v, .. } => Some(v),
+        AttrValue::String(s) | AttrValue::AsciiString(s) => Some(vec![s]),
+        _ => None,
+    }
+}
+
+/// Read an `f32` dataset of an HDF5 group and its shape.
+fn read_dataset(group: &Group, path: &str) -> Result<(Vec<f32>, Vec<usize>), CellcastError> {
+    let err = || ImgalError::InvalidGeneric {
+        msg: "Failed to read a layer weight of the Keras HDF5 weights file.",
+    };
+    let dataset = group.dataset(path).map_err(|_| err())?;
+    let shape = dataset.shape().map_err(|_| err())?;
+    let data = dataset.read_f32().map_err(|_| err())?;
+    Ok((data, shape.into_iter().map(|v| v as usize).collect()))
+}
+
+/// Transpose a Keras `(...kernel, c_in, c_out)` convolution kernel to the
+/// `(c_out, c_in, ...kernel)` layout.
+fn transpose_kernel(kernel: Vec<f32>, shape: &[usize]) -> TensorData {
+    let n = shape.len();
+    let axes: Vec<usize> = [n - 1, n - 2].into_iter().chain(0..n - 2).collect();
+    // SAFE: the dataset length matches its shape
+    let arr = ArrayD::from_shape_vec(IxDyn(shape), kernel)
+        .unwrap()
+        .permuted_axes(IxDyn(&axes));
+    let shape = arr.shape().to_vec();
+    let data: Vec<f32> = arr.iter().copied().collect();
+    TensorData::new(data, shape)
+}
+
+/// Write convolution weights to a burnpack file with ONNX layer names and the
+/// StarDist metadata of `network`.
+fn write_burnpack(
+    path: &Path,
+    convs: &[ConvWeights],
+    network: &StarDistNetworkConfig,
+) -> Result<(), CellcastError> {
+    let n_dim = network.n_dim;
+    let container = format!("Struct:Conv{}d", n_dim);
+    let mut snapshots = Vec::with_capacity(2 * convs.len());
+    for (i, conv) in convs.iter().enumerate() {
+        let layer = format!("conv{}d{}", n_dim, i + 1);
+        let params = [("weight", Some(&conv.weight)), ("bias", conv.bias.as_ref())];
+        for (param, data) in params {
+            if let Some(data) = data {
+                snapshots.push(TensorSnapshot::from_data(
+                    data.clone(),
+                    vec![layer.clone(), param.to_string()],
+                    vec![container.clone()],
+                    ParamId::new(),
+                ));
+            }
+        }
+    }
+    let metadata = StarDistMetadata::new(network.clone()).to_json().to_string();
+    BurnpackWriter::new(snapshots)
+        .with_metadata(METADATA_KEY, &metadata)
+        .write_to_file(path)
+        .map_err(|_| {
+            CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "Failed to write the burnpack weights file.",
+            })
+        })
+}
+
+/// Check that a written burnpack file reproduces the converted convolution
+/// weights.
+///
+/// # Returns
+///
+/// * `Ok(f32)`: The maximum absolute difference between the object
+///   probability, ray distance and class probability (if any) outputs of both
+///   networks on a synthetic input.
+/// * `Err(CellcastError)`: If either network can not be loaded. If the
+///   outputs of both networks can not be read or do not have the same
+///   shapes.
+fn check_round_trip(
+    path: &Path,
+    convs: &[ConvWeights],
+    network: &StarDistNetworkConfig,
+) -> Result<f32, CellcastError> {
+    let device = Default::default();
+    // a smooth synthetic input of at least 32 pixels (8 planes) per axis
+    let shape: Vec<usize> = network
+        .div()
+        .iter()
+        .enumerate()
+        .map(|(i, &d)| {
+            let min: usize = if network.n_dim == 3 && i == 0 { 8 } else { 32 };
+            min.div_ceil(d) * d
+        })
+        .collect();
+    let n_channel_in = if network.n_dim == 3 {
+        1
+    } else {
+        network.n_channel_in
+    };
+    let len = n_channel_in * shape.iter().product::<usize>();
+    let input: Vec<f32> = (0..len).map(|i| (i as f32 * 0.37).sin()).collect();
+    let outputs = if network.n_dim == 2 {
+        let td = TensorData::new(input, [1, n_channel_in, shape[0], shape[1]]);
+        let run = |model: unet_2d::Model<CpuConfigBackend>| {
+            let (prob, dist, class_prob) =
+                model.forward_classes(Tensor::from_data(td.clone(), &device));
+            let class_prob = class_prob.map(|c| c.into_data());
+            [Some(prob.into_data()), Some(dist.into_data()), class_prob]
+        };
+        [
+            run(unet_2d::Model::from_conv_weights(
+                convs, &device, network, false,
+            )?),
+            run(unet_2d::Model::from_file(path, &device, network, false)?),
+        ]
+    } else {
+        let td = TensorData::new(input, [1, 1, shape[0], shape[1], shape[2]]);
+        let run = |model: Network3d<CpuConfigBackend>| {
+            let (prob, dist) = model.forward(Tensor::from_data(td.clone(), &device));
+            [Some(prob.into_data()), Some(dist.into_data()), None]
+        };
+        [
+            run(Network3d::from_weights(
+                StarDistWeights::Convs(convs),
+                &device,
+                network,
+            )?),
+            run(Network3d::from_weights(
+                StarDistWeights::Burnpack(path),
+                &device,
+                network,
+            )?),
+        ]
+    };
+    let [expected, got] = outputs;
+    let mismatch_err = || {
+        CellcastError::Imgal(ImgalError::InvalidGeneric {
+            msg: "The burnpack network outputs do not match the shapes of the Keras network outputs.",
+        })
+    };
+    if expected.iter().flatten().count() != got.iter().flatten().count() {
+        return Err(mismatch_err());
+    }
+    let mut max_abs_diff = 0.0_f32;
+    for (e, g) in expected.iter().flatten().zip(got.iter().flatten()) {
+        let read = |data: &TensorData| {
+            data.to_vec::<f32>().map_err(|_| {
+                CellcastError::Imgal(ImgalError::InvalidGeneric {
+                    msg: "Failed to read the round trip network outputs.",
+                })
+            })
+        };
+        if e.shape != g.shape {
+            return Err(mismatch_err());
+        }
+        let (e, g) = (read(e)?, read(g)?);
+        e.iter()
+            .zip(g.iter())
+            .for_each(|(a, b)| max_abs_diff = max_abs_diff.max((a - b).abs()));
+    }
+    Ok(max_abs_diff)
+}
+
+/// Compare the output of the converted network on the reference input with
+/// the reference output.
+///
+/// # Returns
+///
+/// * `Ok(f32)`: The maximum absolute difference between the object
+///   probability and ray distance outputs and the reference output.
+/// * `Err(CellcastError)`: If the reference input does not match the network
+///   input. If the network output does not match the shape or values of the
+///   reference output.
+fn compare_reference(
+    reference: &KerasReference,
+    convs: &[ConvWeights],
+    network: &StarDistNetworkConfig,
+) -> Result<f32, CellcastError> {
+    let n_dim = network.n_dim;
+    let n_channel_in = if n_dim == 3 { 1 } else { network.n_channel_in };
+    let input = spatial_channels(reference.input.view(), n_dim)
+        .filter(|input| {
+            input.shape()[n_dim] == n_channel_in
+                && input
+                    .shape()
+                    .iter()
+                    .zip(network.div())
+                    .all(|(&s, d)| s > 0 && s % d == 0)
+        })
+        .ok_or(CellcastError::Imgal(ImgalError::InvalidGeneric {
+            msg: "The Keras reference input does not match the network input.",
+        }))?;
+    let input = match reference.percentiles {
+        Some((pmin, pmax)) => {
+            percentile_normalize(input, pmin, pmax, false, Some(n_dim), None, None)?
+                .mapv(|v| v as f32)
+        }
+        None => input.to_owned(),
+    };
+    let shape = input.shape().to_vec();
+    let data: Vec<f32> = input.iter().copied().collect();
+    let device = Default::default();
+    // the 2D ray distances are channels last, the 3D ray distances are
+    // channels first
+    let (prob, dist, channels_last) = if n_dim == 2 {
+        let model =
+            unet_2d::Model::<CpuConfigBackend>::from_conv_weights(convs, &device, network, true)?;
+        let td = TensorData::new(data, [1, shape[0], shape[1], shape[2]]);
+        let (prob, dist) = model.forward(Tensor::from_data(td, &device));
+        (prob.into_data(), dist.into_data(), true)
+    } else {
+        let model = Network3d::<CpuConfigBackend>::from_weights(
+            StarDistWeights::Convs(convs),
+            &device,
+            network,
+        )?;
+        let td = TensorData::new(data, [1, 1, shape[0], shape[1], shape[2]]);
+        let (prob, dist) = model.forward(Tensor::from_data(td, &device));
+        (prob.into_data(), dist.into_data(), false)
+    };
+    let read = |data: TensorData| {
+        data.into_vec::<f32>().map_err(|_| {
+            CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "Failed to read the reference network outputs.",
+            })
+        })
+    };
+    let (prob, dist) = (read(prob)?, read(dist)?);
+    let n = prob.len();
+    let expected = spatial_channels(reference.output.view(), n_dim)
+        .filter(|e| n > 0 && e.len() == n + dist.len() && e.shape()[n_dim] * n == e.len())
+        .ok_or(CellcastError::Imgal(ImgalError::InvalidGeneric {
+            msg: "The Keras reference output shape does not match the network output.",
+        }))?;
+    let n_channels = expected.shape()[n_dim];
+    let n_rays = n_channels - 1;
+    let mut max_abs_diff = 0.0_f32;
+    let mut matches = true;
+    for (i, &e) in expected.iter().enumerate() {
+        let (px, ch) = (i / n_channels, i % n_channels);
+        let got = match ch {
+            0 => prob[px],
+            _ if channels_last => dist[px * n_rays + ch - 1],
+            _ => dist[(ch - 1) * n + px],
+        };
+        let diff = (e - got).abs();
+        max_abs_diff = max_abs_diff.max(diff);
+        matches &= diff <= REFERENCE_ATOL + REFERENCE_RTOL * e.abs();
+    }
+    if !matches {
+        return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+            msg: "The converted network output does not match the Keras reference output.",
+        }));
+    }
+    Ok(max_abs_diff)
+}
+
+/// View a reference tensor with `(...spatial, channels)` axes, removing a
+/// leading batch axis of length 1.
+fn spatial_channels(arr: ArrayViewD<f32>, n_dim: usize) -> Option<ArrayViewD<f32>> {
+    let arr = if arr.ndim() == n_dim + 2 && arr.shape()[0] == 1 {
+        arr.index_axis_move(Axis(0), 0)
+    } else {
+        arr
+    };
+    (arr.ndim() == n_dim + 1).then_some(arr)
+}
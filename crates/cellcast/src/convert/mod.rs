@@ -0,0 +1,15 @@
+//! Weights conversion.
+//!
+//! This module converts StarDist weights trained with the reference
+//! TensorFlow/Keras implementation into the burnpack (`.bpk`) format used by
+//! cellcast models, mapping the layers onto the parameter names of the
+//! pretrained `fluo_2d`, `he_2d` and `fluo_3d` networks. Cellpose weights
+//! saved by the reference PyTorch implementation are converted with their
+//! PyTorch parameter names.
+
+mod cellpose;
+mod keras;
+
+pub use cellpose::convert_cellpose_weights;
+pub(crate) use keras::read_keras_bytes;
+pub use keras::{KerasConversion, KerasReference, convert_keras_weights};
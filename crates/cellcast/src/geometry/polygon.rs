@@ -4,8 +4,8 @@
 //! needed for pre- and post-processing steps.
 
 use std::f32::consts::PI;
+use std::f64::consts::TAU;
 
-use geo::{Area, BooleanOps, LineString, Polygon};
 use ndarray::ArrayView2;
 use rayon::prelude::*;
 
@@ -15,36 +15,167 @@ pub struct Polygon2D {
     pub bbox: (f32, f32, f32, f32),
     /// The polygon area.
     pub area: f32,
-    /// The polygon maximum radius.
-    pub dist: f32,
+    /// The polygon center `(y, x)`.
+    pub center: (f32, f32),
     /// The polygon vertices.
     pub vertices: Vec<(f32, f32)>,
 }
 
-/// Compute the intersection area of two 2-dimensional polygons.
+/// Compute the intersection area of two star-convex polygons.
 ///
 /// # Description
 ///
-/// Computes the intersection (_i.e._ overlapping) area of two 2-dimensional
-/// polygons.
+/// Computes the intersection area of two star-convex polygons built by
+/// [`build_polygons`] with the same number of rays. The boundary of the
+/// intersection is made of the boundary parts of each polygon inside of the
+/// other polygon, the area is then computed with Green's theorem (*i.e.* the
+/// Shoelace formula over these boundary parts). Since the polygon rays share
+/// the same angles, a point is located in the polygon by the ray sector
+/// containing it, without general polygon boolean operations.
 ///
 /// # Arguments
 ///
-/// * `vertices_a`: The vertices of polygon `a`.
-/// * `vertices_b`: The vertices of polygon `b`.
+/// * `a`: The star-convex polygon `a`.
+/// * `b`: The star-convex polygon `b`.
 ///
 /// # Returns
 ///
 /// * `f32`: The intersection area of polygons `a` and `b`.
+pub fn star_convex_intersection(a: &Polygon2D, b: &Polygon2D) -> f32 {
+    if !check_bbox_intersect(&a.bbox, &b.bbox) {
+        return 0.0;
+    }
+    // find the crossings of the polygon edges as positions along each edge,
+    // an edge of "a" can only cross the edges of "b" in the ray sectors of
+    // "b" swept by the edge
+    let n_a = a.vertices.len();
+    let n_b = b.vertices.len();
+    let edges_a = bbox_edges(&a.vertices, &b.bbox);
+    let edges_b = bbox_edges(&b.vertices, &a.bbox);
+    let sectors: Vec<usize> = a
+        .vertices
+        .iter()
+        .map(|&(y, x)| sector((y as f64, x as f64), b))
+        .collect();
+    let center = (b.center.0 as f64, b.center.1 as f64);
+    let mut splits_a: Vec<(usize, f64)> = Vec::new();
+    let mut splits_b: Vec<(usize, f64)> = Vec::new();
+    edges_a.iter().for_each(|&i| {
+        let (p0, p1) = edge(&a.vertices, i);
+        let r = (p1.0 - p0.0, p1.1 - p0.1);
+        let c0 = (p0.0 - center.0, p0.1 - center.1);
+        let c1 = (p1.0 - center.0, p1.1 - center.1);
+        let (s0, s1) = (sectors[i], sectors[(i + 1) % n_a]);
+        // the sector angles increase counter-clockwise in (x, y)
+        let turn = -cross(c0, c1);
+        let (first, n_swept) = if turn > 0.0 {
+            (s0, (s1 + n_b - s0) % n_b + 1)
+        } else if turn < 0.0 {
+            (s1, (s0 + n_b - s1) % n_b + 1)
+        } else if c0.0 * c1.0 + c0.1 * c1.1 >= 0.0 {
+            (s0, 1)
+        } else {
+            // the edge goes through the center of "b"
+            (0, n_b)
+        };
+        // include the neighboring sectors for the rounding of vertex angles
+        (0..(n_swept + 2).min(n_b)).for_each(|o| {
+            let k = (first + n_b - 1 + o) % n_b;
+            let (q0, q1) = edge(&b.vertices, k);
+            let s = (q1.0 - q0.0, q1.1 - q0.1);
+            let denom = cross(r, s);
+            if denom == 0.0 {
+                // parallel edges do not cross, shared edges are resolved when
+                // locating the boundary parts
+                return;
+            }
+            let qp = (q0.0 - p0.0, q0.1 - p0.1);
+            let t = cross(qp, s) / denom;
+            let u = cross(qp, r) / denom;
+            if !(0.0..=1.0).contains(&t) || !(0.0..=1.0).contains(&u) {
+                return;
+            }
+            if t > 0.0 && t < 1.0 {
+                splits_a.push((i, t));
+            }
+            if u > 0.0 && u < 1.0 {
+                splits_b.push((k, u));
+            }
+        });
+    });
+    // a shared boundary part is only counted once, from polygon "a"
+    let inter = boundary_inside(&a.vertices, &edges_a, b, &mut splits_a, true)
+        + boundary_inside(&b.vertices, &edges_b, a, &mut splits_b, false);
+
+    ((inter.abs() / 2.0) as f32).min(a.area.min(b.area))
+}
+
+/// Sum the Shoelace terms of the boundary parts of a polygon inside of a
+/// star-convex polygon.
+///
+/// # Arguments
+///
+/// * `vertices`: The polygon vertices.
+/// * `edges`: The sorted indices of the polygon edges touching the bounding
+///   box of `other`, the other edges are outside of `other`.
+/// * `other`: The star-convex polygon.
+/// * `splits`: The `(edge, position)` crossings of the polygon edges with the
+///   edges of `other`.
+/// * `shared`: If `true`, boundary parts shared with `other` in the same
+///   direction are inside of `other`.
+///
+/// # Returns
+///
+/// * `f64`: The sum of the Shoelace terms of the boundary parts.
+fn boundary_inside(
+    vertices: &[(f32, f32)],
+    edges: &[usize],
+    other: &Polygon2D,
+    splits: &mut [(usize, f64)],
+    shared: bool,
+) -> f64 {
+    splits.sort_unstable_by(|x, y| x.0.cmp(&y.0).then(x.1.total_cmp(&y.1)));
+    let mut splits = splits.iter().peekable();
+    edges.iter().fold(0.0, |mut acc, &i| {
+        let (p0, p1) = edge(vertices, i);
+        let d = (p1.0 - p0.0, p1.1 - p0.1);
+        let at = |t: f64| (p0.0 + t * d.0, p0.1 + t * d.1);
+        // walk the parts of the edge between consecutive crossings, each part
+        // is either inside or outside of the other polygon
+        while splits.next_if(|s| s.0 < i).is_some() {}
+        let mut start = 0.0;
+        loop {
+            let end = match splits.next_if(|s| s.0 == i) {
+                Some(&(_, t)) => t,
+                None => 1.0,
+            };
+            let (s, e) = (at(start), at(end));
+            let mid = ((s.0 + e.0) / 2.0, (s.1 + e.1) / 2.0);
+            let keep = match locate(mid, other) {
+                Location::Inside => true,
+                Location::Boundary(dir) => shared && d.0 * dir.0 + d.1 * dir.1 > 0.0,
+                Location::Outside => false,
+            };
+            if keep {
+                acc += cross(s, e);
+            }
+            if end >= 1.0 {
+                break acc;
+            }
+            start = end;
+        }
+    })
+}
+
+/// Get the sorted indices of the polygon edges touching a bounding box.
 #[inline]
-pub fn area_intersection(vertices_a: &[(f32, f32)], vertices_b: &[(f32, f32)]) -> f32 {
-    // convert vertices to geo types
-    let line_a: LineString<f32> = vertices_a.to_vec().into();
-    let line_b: LineString<f32> = vertices_b.to_vec().into();
-    let poly_a = Polygon::new(line_a, vec![]);
-    let poly_b = Polygon::new(line_b, vec![]);
-    let inter = poly_a.intersection(&poly_b);
-    inter.unsigned_area()
+fn bbox_edges(vertices: &[(f32, f32)], bbox: &(f32, f32, f32, f32)) -> Vec<usize> {
+    (0..vertices.len())
+        .filter(|&i| {
+            let (p0, p1) = edge(vertices, i);
+            segment_bbox_intersect(p0, p1, bbox)
+        })
+        .collect()
 }
 
 /// Create a vector of 2-dimensional polygons.
@@ -69,7 +200,7 @@ pub fn area_intersection(vertices_a: &[(f32, f32)], vertices_b: &[(f32, f32)]) -
 ///   compute.
 pub fn build_polygons(
     dist: ArrayView2<f32>,
-    pos: ArrayView2<usize>,
+    pos: ArrayView2<f32>,
     n_polys: usize,
     n_rays: usize,
 ) -> Vec<Polygon2D> {
@@ -80,9 +211,8 @@ pub fn build_polygons(
         .into_par_iter()
         .map(|p| {
             // get the current polygon center, set up the vars and bounding box
-            let py = pos[[p, 0]] as f32;
-            let px = pos[[p, 1]] as f32;
-            let mut max_radius: f32 = 0.0;
+            let py = pos[[p, 0]];
+            let px = pos[[p, 1]];
             let mut vertices: Vec<(f32, f32)> = Vec::with_capacity(n_rays);
             let mut y_min = f32::MAX;
             let mut y_max = f32::MIN;
@@ -100,17 +230,15 @@ pub fn build_polygons(
                 y_max = y_max.max(y);
                 x_min = x_min.min(x);
                 x_max = x_max.max(x);
-                // add the vertex to vertices and update the max radius with the
-                // current ray's distances
+                // add the vertex to vertices
                 vertices.push((y, x));
-                max_radius = max_radius.max(d);
             });
             // compute the polygon area and create a new 2D NMS polygon
             let area = polygon_area(&vertices, n_rays);
             Polygon2D {
                 bbox: (y_min, y_max, x_min, x_max),
                 area,
-                dist: max_radius,
+                center: (py, px),
                 vertices,
             }
         })
@@ -149,3 +277,200 @@ fn polygon_area(vertices: &[(f32, f32)], n_rays: usize) -> f32 {
 
     area.abs() / 2.0
 }
+
+/// A uniform grid of polygon bounding boxes for finding overlapping polygons.
+///
+/// Each polygon is registered in the grid cells covered by its bounding box.
+/// The cell size is the mean polygon bounding box size, so a polygon only
+/// covers a few cells. The cell size is grown for sparse polygons, keeping the
+/// number of grid cells at most `4 * n_polys`.
+#[derive(Debug, Clone)]
+pub struct PolygonGrid {
+    /// The polygon bounding boxes `(y1, y2, x1, x2)`.
+    bboxes: Vec<(f32, f32, f32, f32)>,
+    /// The `(y, x)` position of the first grid cell.
+    origin: (f32, f32),
+    /// The grid cell size.
+    cell: f32,
+    /// The number of grid `(rows, cols)`.
+    shape: (usize, usize),
+    /// The polygon indices registered in each grid cell, in row-major order.
+    cells: Vec<Vec<usize>>,
+}
+
+impl PolygonGrid {
+    /// Create a new grid of polygon bounding boxes.
+    ///
+    /// # Arguments
+    ///
+    /// * `polygons`: The polygons to register in the grid.
+    ///
+    /// # Returns
+    ///
+    /// * `PolygonGrid`: The grid of polygon bounding boxes.
+    pub fn build(polygons: &[Polygon2D]) -> Self {
+        let bboxes: Vec<(f32, f32, f32, f32)> = polygons.iter().map(|p| p.bbox).collect();
+        if bboxes.is_empty() {
+            return Self {
+                bboxes,
+                origin: (0.0, 0.0),
+                cell: 1.0,
+                shape: (0, 0),
+                cells: Vec::new(),
+            };
+        }
+        let (y_min, y_max, x_min, x_max) =
+            bboxes
+                .iter()
+                .fold((f32::MAX, f32::MIN, f32::MAX, f32::MIN), |acc, b| {
+                    (
+                        acc.0.min(b.0),
+                        acc.1.max(b.1),
+                        acc.2.min(b.2),
+                        acc.3.max(b.3),
+                    )
+                });
+        let size_sum: f32 = bboxes.iter().map(|b| (b.1 - b.0).max(b.3 - b.2)).sum();
+        let grid_shape = |cell: f32| {
+            (
+                ((y_max - y_min) / cell) as usize + 1,
+                ((x_max - x_min) / cell) as usize + 1,
+            )
+        };
+        // grow the cell size until the number of cells is linear in the number
+        // of polygons, e.g. for a few small polygons far apart
+        let max_cells = 4 * bboxes.len();
+        let mut cell = (size_sum / bboxes.len() as f32).max(1.0);
+        let mut shape = grid_shape(cell);
+        while shape.0.saturating_mul(shape.1) > max_cells && cell.is_finite() {
+            cell *= 2.0;
+            shape = grid_shape(cell);
+        }
+        let mut grid = Self {
+            bboxes,
+            origin: (y_min, x_min),
+            cell,
+            shape,
+            cells: vec![Vec::new(); shape.0 * shape.1],
+        };
+        (0..grid.bboxes.len()).for_each(|p| {
+            let b = grid.bboxes[p];
+            let (r1, c1) = grid.cell_index(b.0, b.2);
+            let (r2, c2) = grid.cell_index(b.1, b.3);
+            (r1..=r2).for_each(|r| {
+                (c1..=c2).for_each(|c| grid.cells[r * shape.1 + c].push(p));
+            });
+        });
+
+        grid
+    }
+
+    /// Find the polygons with a bounding box intersecting the bounding box of
+    /// a polygon.
+    ///
+    /// # Arguments
+    ///
+    /// * `i`: The index of the polygon.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<usize>`: The sorted indices of the intersecting polygons,
+    ///   without `i`.
+    pub fn neighbors(&self, i: usize) -> Vec<usize> {
+        let b = &self.bboxes[i];
+        let (r1, c1) = self.cell_index(b.0, b.2);
+        let (r2, c2) = self.cell_index(b.1, b.3);
+        let mut neighbors = Vec::new();
+        (r1..=r2).for_each(|r| {
+            (c1..=c2).for_each(|c| {
+                self.cells[r * self.shape.1 + c].iter().for_each(|&j| {
+                    let o = &self.bboxes[j];
+                    if j == i || !check_bbox_intersect(b, o) {
+                        return;
+                    }
+                    // only report a pair of polygons once, in the cell of the
+                    // bounding box intersection corner
+                    if self.cell_index(b.0.max(o.0), b.2.max(o.2)) == (r, c) {
+                        neighbors.push(j);
+                    }
+                });
+            });
+        });
+        neighbors.sort_unstable();
+
+        neighbors
+    }
+
+    /// Get the `(row, col)` grid cell of a `(y, x)` position.
+    #[inline]
+    fn cell_index(&self, y: f32, x: f32) -> (usize, usize) {
+        let r = ((y - self.origin.0) / self.cell).max(0.0) as usize;
+        let c = ((x - self.origin.1) / self.cell).max(0.0) as usize;
+        (r.min(self.shape.0 - 1), c.min(self.shape.1 - 1))
+    }
+}
+
+/// The location of a point relative to a polygon.
+enum Location {
+    Inside,
+    /// On the polygon boundary, with the direction of the boundary edge.
+    Boundary((f64, f64)),
+    Outside,
+}
+
+/// Locate a `(y, x)` point relative to a star-convex polygon.
+///
+/// The point is compared to the polygon edge of the ray sector containing it.
+/// The polygon vertices are counter-clockwise in `(x, y)`, so the polygon
+/// interior is on the left side of each edge.
+#[inline]
+fn locate(pnt: (f64, f64), poly: &Polygon2D) -> Location {
+    let (v0, v1) = edge(&poly.vertices, sector(pnt, poly));
+    let e = (v1.0 - v0.0, v1.1 - v0.1);
+    // the side is the edge length times the distance to the edge line, a
+    // point within 1e-6 of the edge line is on the boundary
+    let side = e.1 * (pnt.0 - v0.0) - e.0 * (pnt.1 - v0.1);
+    if side * side <= 1e-12 * (e.0 * e.0 + e.1 * e.1) {
+        Location::Boundary(e)
+    } else if side > 0.0 {
+        Location::Inside
+    } else {
+        Location::Outside
+    }
+}
+
+/// Get the ray sector of a star-convex polygon containing a `(y, x)` point,
+/// sector `k` lies between the rays `k` and `k + 1`.
+#[inline]
+fn sector(pnt: (f64, f64), poly: &Polygon2D) -> usize {
+    let n_rays = poly.vertices.len();
+    let dy = pnt.0 - poly.center.0 as f64;
+    let dx = pnt.1 - poly.center.1 as f64;
+    let angle = dy.atan2(dx).rem_euclid(TAU);
+    (angle / TAU * n_rays as f64) as usize % n_rays
+}
+
+/// Get the vertices of polygon edge `i` as `(y, x)` points.
+#[inline]
+fn edge(vertices: &[(f32, f32)], i: usize) -> ((f64, f64), (f64, f64)) {
+    let j = (i + 1) % vertices.len();
+    (
+        (vertices[i].0 as f64, vertices[i].1 as f64),
+        (vertices[j].0 as f64, vertices[j].1 as f64),
+    )
+}
+
+/// Compute the 2-dimensional cross product of two `(y, x)` vectors.
+#[inline]
+fn cross(a: (f64, f64), b: (f64, f64)) -> f64 {
+    a.0 * b.1 - a.1 * b.0
+}
+
+/// Determine if a segment intersects a bounding box `(y1, y2, x1, x2)`.
+#[inline]
+fn segment_bbox_intersect(p0: (f64, f64), p1: (f64, f64), bbox: &(f32, f32, f32, f32)) -> bool {
+    p0.0.min(p1.0) <= bbox.1 as f64
+        && p0.0.max(p1.0) >= bbox.0 as f64
+        && p0.1.min(p1.1) <= bbox.3 as f64
+        && p0.1.max(p1.1) >= bbox.2 as f64
+}
@@ -2,7 +2,7 @@ use std::f32::consts::PI;
 
 use imgal::prelude::*;
 use imgal::spatial::convex_hull::quickhull_3d;
-use imgal::spatial::geometry::{inside_polyhedron, tetrahedron_volume};
+use imgal::spatial::geometry::tetrahedron_volume;
 use imgal::spatial::halfspace::{face_to_halfspace, halfspace_intersection, hull_to_halfspace};
 use ndarray::{Array1, Array2, ArrayView1, ArrayView2, Axis, concatenate, stack};
 
@@ -94,11 +94,11 @@ pub fn bounding_inner_radius_iso(
             ]
         };
         // compute the edge vectors and cross product
-        let (baz, bay, bax) = (b[0] - a[0], b[1] - a[1], b[2] - a[2]);
-        let (caz, cay, cax) = (c[0] - a[0], c[1] - a[1], c[2] - a[2]);
-        let nz = bax * cay - bay * cax;
-        let ny = baz * cax - bax * caz;
-        let nx = bay * caz - baz * cay;
+        let (ab_z, ab_y, ab_x) = (b[0] - a[0], b[1] - a[1], b[2] - a[2]);
+        let (ac_z, ac_y, ac_x) = (c[0] - a[0], c[1] - a[1], c[2] - a[2]);
+        let nz = ab_x * ac_y - ab_y * ac_x;
+        let ny = ab_z * ac_x - ab_x * ac_z;
+        let nx = ab_y * ac_z - ab_z * ac_y;
         let norm = 1.0 / (nz * nz + ny * ny + nx * nx).sqrt().max(eps);
         let (nz, ny, nx) = (nz * norm, ny * norm, nx * norm);
         let dist = a[0] * nz + a[1] * ny + a[2] * nx;
@@ -369,7 +369,8 @@ pub fn golden_spiral_intersection_vol(
 /// # Description
 ///
 /// Counts the number of voxels within a mask (marked `true`) that fall inside
-/// the given polyhedron by testing if a voxel with `inside_polyhedron`.
+/// the given polyhedron, rendering the polyhedron voxel spans inside of `bbox`
+/// with [`polyhedron_spans`].
 ///
 /// # Arguments
 ///
@@ -402,34 +403,16 @@ pub fn overlap_polyhedron_mask(
     nx: usize,
     overlap_threshold: f32,
 ) -> f32 {
+    debug_assert_eq!(mask.len(), nz * ny * nx);
     let mut count = 0.0;
-    let mut query = [0.0; 3];
-    let nx_ny = nx * ny;
-    let bz = bbox[0] as f32;
-    let by = bbox[2] as f32;
-    let bx = bbox[4] as f32;
-    for z in 0..nz {
-        let z_nx_ny = z * nx_ny;
-        for y in 0..ny {
-            let y_nx = y * nx;
-            for x in 0..nx {
-                let idx = x + y_nx + z_nx_ny;
-                if !mask[idx] {
-                    continue;
-                }
-                query[0] = bz + z as f32;
-                query[1] = by + y as f32;
-                query[2] = bx + x as f32;
-                let query_view = ArrayView1::from(&query);
-                if inside_polyhedron(vertices, faces, center, query_view, None).unwrap_or(false) {
-                    count += 1.0;
-                    if count > overlap_threshold {
-                        return count;
-                    }
-                }
-            }
+    polyhedron_spans(vertices, faces, center, bbox, |z, y, x0, x1| {
+        if count > overlap_threshold {
+            return;
         }
-    }
+        let row = ((z - bbox[0]) as usize * ny + (y - bbox[2]) as usize) * nx;
+        let (x0, x1) = ((x0 - bbox[4]) as usize, (x1 - bbox[4]) as usize);
+        count += mask[row + x0..=row + x1].iter().filter(|&&v| v).count() as f32;
+    });
     count
 }
 
@@ -585,13 +568,179 @@ pub fn polyhedron_vol(
         .abs())
 }
 
+/// Render the voxel spans of a star-convex polyhedron.
+///
+/// # Description
+///
+/// Renders the given star-convex polyhedron row by row inside of `bbox`. The
+/// polyhedron is the union of the tetrahedra formed by its `center` and each
+/// face, and the voxels of a row inside of a (convex) tetrahedron form a single
+/// interval. The voxels inside of the polyhedron are the union of these
+/// intervals, found by clipping each row against the tetrahedra face planes
+/// instead of testing each voxel with `inside_polyhedron`.
+///
+/// # Arguments
+///
+/// * `vertices`: The polyhedron vertices with shape `(n_vertices, 3)`.
+/// * `gs_faces`: The triangular face indices with shape `(n_triangles, 3)`.
+/// * `center`: The center point of the polyhedron.
+/// * `bbox`: The bounding box coordinates in
+///   `[z_min, z_max, y_min, y_max, x_min, x_max]` order.
+/// * `span`: A function called with the `(z, y, x_start, x_end)` coordinates
+///   of each span of voxels inside of the polyhedron, in row order. The
+///   `x_end` coordinate is inclusive.
+pub fn polyhedron_spans<F>(
+    vertices: ArrayView2<f32>,
+    gs_faces: ArrayView2<usize>,
+    center: ArrayView1<f32>,
+    bbox: [i32; 6],
+    mut span: F,
+) where
+    F: FnMut(i32, i32, i32, i32),
+{
+    // the face plane inequalities "n . (q - center) <= w" of each tetrahedron,
+    // with its z and y extents relative to the center
+    let c = [center[0] as f64, center[1] as f64, center[2] as f64];
+    let rel = |i: usize| {
+        let v = vertices.row(i);
+        [v[0] as f64 - c[0], v[1] as f64 - c[1], v[2] as f64 - c[2]]
+    };
+    let tetras: Vec<TetraPlanes> = gs_faces
+        .rows()
+        .into_iter()
+        .filter_map(|f| TetraPlanes::new(rel(f[0]), rel(f[1]), rel(f[2])))
+        .collect();
+    let mut intervals: Vec<(i32, i32)> = Vec::with_capacity(tetras.len());
+    (bbox[0]..=bbox[1]).for_each(|z| {
+        let uz = z as f64 - c[0];
+        (bbox[2]..=bbox[3]).for_each(|y| {
+            let uy = y as f64 - c[1];
+            intervals.clear();
+            tetras.iter().for_each(|t| {
+                if let Some((lo, hi)) = t.clip_row(uz, uy) {
+                    let x0 = ((lo + c[2] - SPAN_EPS).ceil() as i32).max(bbox[4]);
+                    let x1 = ((hi + c[2] + SPAN_EPS).floor() as i32).min(bbox[5]);
+                    if x0 <= x1 {
+                        intervals.push((x0, x1));
+                    }
+                }
+            });
+            // merge the overlapping and adjacent tetrahedra intervals
+            intervals.sort_unstable();
+            let mut cur: Option<(i32, i32)> = None;
+            intervals.iter().for_each(|&(x0, x1)| match cur {
+                Some((s, e)) if x0 <= e + 1 => cur = Some((s, e.max(x1))),
+                _ => {
+                    if let Some((s, e)) = cur {
+                        span(z, y, s, e);
+                    }
+                    cur = Some((x0, x1));
+                }
+            });
+            if let Some((s, e)) = cur {
+                span(z, y, s, e);
+            }
+        });
+    });
+}
+
+/// The tolerance of the polyhedron span boundaries, keeping voxels on the
+/// polyhedron surface inside of the polyhedron.
+const SPAN_EPS: f64 = 1e-6;
+
+/// The face planes of a tetrahedron formed by a polyhedron face and the
+/// polyhedron center (at the origin).
+struct TetraPlanes {
+    planes: [([f64; 3], f64); 4],
+    z: (f64, f64),
+    y: (f64, f64),
+}
+
+impl TetraPlanes {
+    /// Create the tetrahedron planes of face `(a, b, c)`, with the center at
+    /// the origin. Returns `None` for flat tetrahedra.
+    fn new(a: [f64; 3], b: [f64; 3], c: [f64; 3]) -> Option<Self> {
+        let o = [0.0; 3];
+        let plane = |p0: [f64; 3], p1: [f64; 3], p2: [f64; 3], opp: [f64; 3]| {
+            let u = sub(p1, p0);
+            let v = sub(p2, p0);
+            let n = [
+                u[1] * v[2] - u[2] * v[1],
+                u[2] * v[0] - u[0] * v[2],
+                u[0] * v[1] - u[1] * v[0],
+            ];
+            // orient the normal away from the opposite vertex
+            let side = dot(n, sub(opp, p0));
+            if side == 0.0 {
+                return None;
+            }
+            let n = if side > 0.0 { n.map(|v| -v) } else { n };
+            Some((n, dot(n, p0)))
+        };
+        let planes = [
+            plane(a, b, c, o)?,
+            plane(o, a, b, c)?,
+            plane(o, b, c, a)?,
+            plane(o, c, a, b)?,
+        ];
+        let extent = |d: usize| {
+            let vals = [0.0, a[d], b[d], c[d]];
+            vals.iter()
+                .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &v| {
+                    (lo.min(v), hi.max(v))
+                })
+        };
+        Some(Self {
+            planes,
+            z: extent(0),
+            y: extent(1),
+        })
+    }
+
+    /// Clip the row `(uz, uy)` relative to the center against the tetrahedron.
+    /// Returns the (relative) x interval of the row inside of the tetrahedron.
+    #[inline]
+    fn clip_row(&self, uz: f64, uy: f64) -> Option<(f64, f64)> {
+        if uz < self.z.0 - SPAN_EPS
+            || uz > self.z.1 + SPAN_EPS
+            || uy < self.y.0 - SPAN_EPS
+            || uy > self.y.1 + SPAN_EPS
+        {
+            return None;
+        }
+        let mut lo = f64::NEG_INFINITY;
+        let mut hi = f64::INFINITY;
+        for (n, w) in self.planes.iter() {
+            let r = w - n[0] * uz - n[1] * uy;
+            if n[2] > 0.0 {
+                hi = hi.min(r / n[2]);
+            } else if n[2] < 0.0 {
+                lo = lo.max(r / n[2]);
+            } else if r < -SPAN_EPS {
+                return None;
+            }
+        }
+        (lo <= hi).then_some((lo, hi))
+    }
+}
+
+#[inline(always)]
+fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+#[inline(always)]
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
 /// Render a polyhedron into a boolean voxel mask.
 ///
 /// # Description
 ///
 /// Renders the given polyhedron into a 1D boolean mask of lengh `nz * ny * nx`,
-/// associated with the `bbox`. Each voxel in `bbox` is tested to determine if
-/// it lies inside the polyhedron.
+/// associated with the `bbox`, filling the voxel spans of the polyhedron
+/// rendered with [`polyhedron_spans`].
 ///
 /// # Arguments
 ///
@@ -619,25 +768,14 @@ pub fn polyhedron_to_mask(
     nx: usize,
 ) -> Vec<bool> {
     let mut render = vec![false; nz * ny * nx];
-    let center: [f32; 3] = [center[0], center[1], center[2]];
-    let mut query = [0.0; 3];
-    let bz = bbox[0] as f32;
-    let by = bbox[2] as f32;
-    let bx = bbox[4] as f32;
-    (0..nz).for_each(|z| {
-        query[0] = bz + z as f32;
-        (0..ny).for_each(|y| {
-            query[1] = by + y as f32;
-            (0..nx).for_each(|x| {
-                query[2] = bx + x as f32;
-                let idx = x + y * nx + z * nx * ny;
-                render[idx] = inside_polyhedron(vertices, gs_faces, &center, &query, Some(1))
-                    .unwrap_or(false);
-            });
-        });
+    polyhedron_spans(vertices, gs_faces, center, bbox, |z, y, x0, x1| {
+        let row = ((z - bbox[0]) as usize * ny + (y - bbox[2]) as usize) * nx;
+        let (x0, x1) = ((x0 - bbox[4]) as usize, (x1 - bbox[4]) as usize);
+        render[row + x0..=row + x1].fill(true);
     });
     render
 }
+
 /// Compute the intersection volume of two spheres.
 ///
 /// # Description
@@ -670,7 +808,7 @@ pub fn sphere_intersect_volume_iso(
     let dist_iso = (dz * dz + dy * dy + dx * dx).sqrt();
     let rad_min = radius_a.min(radius_b);
     let rad_max = radius_a.max(radius_b);
-    let pi = PI as f32;
+    let pi = PI;
     if dist_iso > radius_a + radius_b {
         return 0.0;
     }
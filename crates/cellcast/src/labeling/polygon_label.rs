@@ -6,6 +6,9 @@
 use std::f32::consts::TAU;
 
 use ndarray::{Array2, Array3, ArrayView1, ArrayView2, Axis};
+use rayon::prelude::*;
+
+use crate::utils::axes::scale_coord;
 
 /// Convert distance polygon representation into a 2D label image.
 ///
@@ -17,6 +20,10 @@ use ndarray::{Array2, Array3, ArrayView1, ArrayView2, Axis};
 /// polygons are rendered first with higher probability polygons overwritting
 /// lower ones.
 ///
+/// The polygons are filled with scanlines, computing the polygon edge
+/// crossings of each image row. The image rows are rendered in parallel, each
+/// row drawing the polygons covering it in probability order.
+///
 /// # Arguments
 ///
 /// * `polygon_dist`: A 2D array of radial polygon distances with shape
@@ -26,8 +33,10 @@ use ndarray::{Array2, Array3, ArrayView1, ArrayView2, Axis};
 /// * `polygon_pos`: A 2D array of polygon center positions with shape
 ///   `(n_polys, 2)`. The dimension order expected is (row, col).
 /// * `shape`: The shape of the output label image.
-/// * `scale`: Optional scaling factor for each axis. If `None` then no scaling
-///   is applied.
+/// * `scale`: Optional (row, col) scaling factor of the polygon centers and
+///   ray distances into the output label image, *e.g.* `(2.0, 2.0)` renders
+///   polygons predicted on a downsampled image at its full resolution. If
+///   `None` then no scaling is applied.
 ///
 /// # Returns
 ///
@@ -36,7 +45,7 @@ use ndarray::{Array2, Array3, ArrayView1, ArrayView2, Axis};
 pub fn distance_polygon_to_label(
     polygon_dist: ArrayView2<f32>,
     polygon_prob: ArrayView1<f32>,
-    polygon_pos: ArrayView2<usize>,
+    polygon_pos: ArrayView2<f32>,
     shape: (usize, usize),
     scale: Option<(f32, f32)>,
 ) -> Array2<u64> {
@@ -57,55 +66,89 @@ pub fn distance_polygon_to_label(
         n_rays,
         scale,
     );
-    // create output label image and render the polygons
-    let label_ids: Vec<u64> = (0..n_polys).map(|p| (p + 1) as u64).collect();
-    let mut labels = Array2::<u64>::zeros(shape);
-    (0..n_polys).zip(label_ids.iter()).for_each(|(p, &l)| {
-        let poly_rows: Vec<f32> = (0..n_rays).map(|r| poly_coords[[p, r, 0]]).collect();
-        let poly_cols: Vec<f32> = (0..n_rays).map(|r| poly_coords[[p, r, 1]]).collect();
-        let (raster_row, raster_col) = render_polygon_2d(&poly_rows, &poly_cols, n_rays, shape);
-        (0..raster_row.len()).for_each(|j| {
-            labels[[raster_row[j], raster_col[j]]] = l;
-        });
+    // bucket the polygons by the image rows they cover, in drawing order
+    let (n_row, n_col) = shape;
+    if n_row == 0 || n_col == 0 {
+        return Array2::<u64>::zeros(shape);
+    }
+    let mut row_polys: Vec<Vec<usize>> = vec![Vec::new(); n_row];
+    (0..n_polys).for_each(|p| {
+        let (min_row, max_row) = poly_coords
+            .index_axis(poly_ax, p)
+            .column(0)
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), &y| {
+                (lo.min(y), hi.max(y))
+            });
+        // only rows in "[min_row, max_row)" can cross the polygon edges
+        let start = min_row.ceil().max(0.0) as usize;
+        let end = (max_row.ceil().max(0.0) as usize).min(n_row);
+        (start..end).for_each(|y| row_polys[y].push(p));
     });
+    // render the image rows in parallel, each row is written by one thread
+    let mut labels = vec![0u64; n_row * n_col];
     labels
+        .par_chunks_mut(n_col)
+        .zip(row_polys.par_iter())
+        .enumerate()
+        .for_each(|(y, (row, polys))| {
+            let mut crossings: Vec<f32> = Vec::with_capacity(n_rays);
+            polys.iter().for_each(|&p| {
+                fill_polygon_row(
+                    poly_coords.index_axis(poly_ax, p),
+                    y as f32,
+                    &mut crossings,
+                    row,
+                    (p + 1) as u64,
+                );
+            });
+        });
+    Array2::from_shape_vec(shape, labels).unwrap()
 }
 
-/// Check if the query point is inside the given polygon using ray casting.
+/// Fill the pixels of an image row inside of a polygon.
 ///
-/// # Arguments
+/// # Description
 ///
-/// * `row`: The "y" coordinate of a polygon.
-/// * `col`: The "x" coordinate of a polygon.
-/// * `size`: The size of the polygon.
-/// * `row_coords`: A slice of row coordinates for a polygon.
-/// * `col_coords`: A slice of col coordinates for a polygon.
+/// Computes the crossings of the polygon edges with the image row and fills
+/// the pixels between each pair of sorted crossings. A pixel is inside of the
+/// polygon if an odd number of crossings lie after it (*i.e.* ray casting).
 ///
-/// # Returns
+/// # Arguments
 ///
-/// * `bool`: Returns `true`, if `row` and `col` are inside the polygon. Returns
-///   `false` if they are not.
+/// * `coords`: The polygon (row, col) coordinates with shape `(n_rays, 2)`.
+/// * `row`: The image row coordinate.
+/// * `crossings`: A buffer for the edge crossings.
+/// * `pixels`: The label image row.
+/// * `label`: The polygon label.
 #[inline]
-fn inside_polygon(
-    row: usize,
-    col: usize,
-    size: usize,
-    row_coords: &[f32],
-    col_coords: &[f32],
-) -> bool {
-    let mut inside = false;
-    let row = row as f32;
-    let col = col as f32;
+fn fill_polygon_row(
+    coords: ArrayView2<f32>,
+    row: f32,
+    crossings: &mut Vec<f32>,
+    pixels: &mut [u64],
+    label: u64,
+) {
+    crossings.clear();
+    let size = coords.dim().0;
     let mut j = size - 1;
     (0..size).for_each(|i| {
-        let (xi, yi) = (col_coords[i], row_coords[i]);
-        let (xj, yj) = (col_coords[j], row_coords[j]);
-        if ((yi > row) != (yj > row)) && (col < (xj - xi) * (row - yi) / (yj - yi) + xi) {
-            inside = !inside;
+        let (xi, yi) = (coords[[i, 1]], coords[[i, 0]]);
+        let (xj, yj) = (coords[[j, 1]], coords[[j, 0]]);
+        if (yi > row) != (yj > row) {
+            crossings.push((xj - xi) * (row - yi) / (yj - yi) + xi);
         }
         j = i;
     });
-    inside
+    crossings.sort_unstable_by(f32::total_cmp);
+    // the pixels "col" with "start <= col < end" are inside of the polygon
+    let n_col = pixels.len();
+    crossings.chunks_exact(2).for_each(|span| {
+        let start = span[0].ceil().max(0.0) as usize;
+        let end = (span[1].ceil().max(0.0) as usize).min(n_col);
+        if start < end {
+            pixels[start..end].fill(label);
+        }
+    });
 }
 
 /// Convert polar distance representation to Cartesian coordinates.
@@ -118,8 +161,8 @@ fn inside_polygon(
 ///   `(n_polys,)`.
 /// * `n_polys`: The number of polygons.
 /// * `n_rays`: The number of ray angles.
-/// * `scale`: The scaling factor per axis. If `None` then no scaling is
-///   applied.
+/// * `scale`: The scaling factor of the centers and ray distances per axis.
+///   If `None` then no scaling is applied.
 ///
 /// # Returns
 ///
@@ -129,12 +172,11 @@ fn inside_polygon(
 #[inline]
 fn radial_dist_to_coords_2d(
     polygon_dist: ArrayView2<f32>,
-    polygon_pos: ArrayView2<usize>,
+    polygon_pos: ArrayView2<f32>,
     n_polys: usize,
     n_rays: usize,
     scale: Option<(f32, f32)>,
 ) -> Array3<f32> {
-    let scale = scale.unwrap_or((1.0, 1.0));
     // get evenly spaced angles for 0 to 2*pi (TAU) and compute row (Y), col (X)
     // coordinates
     let angles: Vec<f32> = (0..n_rays)
@@ -142,8 +184,14 @@ fn radial_dist_to_coords_2d(
         .collect();
     let mut coords = Array3::<f32>::zeros((n_polys, n_rays, 2));
     (0..n_polys).for_each(|p| {
-        let poly_y = polygon_pos[[p, 0]] as f32;
-        let poly_x = polygon_pos[[p, 1]] as f32;
+        let (poly_y, poly_x, scale) = match scale {
+            Some(s) => (
+                scale_coord(polygon_pos[[p, 0]], s.0),
+                scale_coord(polygon_pos[[p, 1]], s.1),
+                s,
+            ),
+            None => (polygon_pos[[p, 0]], polygon_pos[[p, 1]], (1.0, 1.0)),
+        };
         (0..n_rays).for_each(|r| {
             let d = polygon_dist[[p, r]];
             let a = angles[r];
@@ -153,58 +201,3 @@ fn radial_dist_to_coords_2d(
     });
     coords
 }
-
-/// Render/draw a polygon from a set of polygon cartesian coordinates.
-///
-/// # Arguments
-///
-/// * `row_coords`: A slice of row coordinates for the polygon.
-/// * `col_coords`: A slice of col coordinates for the polygon.
-/// * `size`: The size of the polygon (*i.e.* the number of points).
-/// * `shape`: The shape of the output image where the polygon is drawn.
-///
-/// # Returns
-///
-/// * `(Vec<usize>, Vec<usize>)`: A tuple of row and col vectors indicating
-///   which pixels are valid for polygon drawing.
-#[inline]
-fn render_polygon_2d(
-    row_coords: &[f32],
-    col_coords: &[f32],
-    size: usize,
-    shape: (usize, usize),
-) -> (Vec<usize>, Vec<usize>) {
-    // find the bounding box clipped to the image bounds
-    let min_row = row_coords
-        .iter()
-        .copied()
-        .fold(f32::INFINITY, f32::min)
-        .max(0.0) as usize;
-    let max_row = row_coords
-        .iter()
-        .copied()
-        .fold(f32::NEG_INFINITY, f32::max)
-        .min((shape.0 - 1) as f32) as usize;
-    let min_col = col_coords
-        .iter()
-        .copied()
-        .fold(f32::INFINITY, f32::min)
-        .max(0.0) as usize;
-    let max_col = col_coords
-        .iter()
-        .copied()
-        .fold(f32::NEG_INFINITY, f32::max)
-        .min((shape.1 - 1) as f32) as usize;
-    // determine if pixels within the bounding box are within the polygon
-    // if pixel in question is within the polygon, save the coordinates
-    let mut raster_row: Vec<usize> = Vec::new();
-    let mut raster_col: Vec<usize> = Vec::new();
-    (min_row..=max_row)
-        .flat_map(|y| (min_col..=max_col).map(move |x| (y, x)))
-        .filter(|&(y, x)| inside_polygon(y, x, size, row_coords, col_coords))
-        .for_each(|(y, x)| {
-            raster_row.push(y);
-            raster_col.push(x)
-        });
-    (raster_row, raster_col)
-}
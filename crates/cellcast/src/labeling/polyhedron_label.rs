@@ -1,10 +1,11 @@
 use imgal::prelude::*;
-use imgal::spatial::convex_hull::quickhull_3d;
-use imgal::spatial::geometry::inside_polyhedron;
-use imgal::spatial::halfspace::{hull_to_halfspace, inside_halfspace_interior};
-use ndarray::{Array1, Array3, ArrayView1, ArrayView2, ArrayViewMut3, Axis, s};
+use ndarray::{Array1, Array3, ArrayView1, ArrayView2, Axis, s};
+use rayon::prelude::*;
 
-use crate::geometry::polyhedron::{golden_spiral, polyhedron_bbox, polyhedron_verts};
+use crate::geometry::polyhedron::{
+    golden_spiral, polyhedron_bbox, polyhedron_spans, polyhedron_verts,
+};
+use crate::utils::axes::scale_coord;
 
 /// Convert distance representation polyhedra into a labelled 3D volume.
 ///
@@ -12,7 +13,9 @@ use crate::geometry::polyhedron::{golden_spiral, polyhedron_bbox, polyhedron_ver
 ///
 /// Converts distance representation polyhedra into a labelled 3D volume of the
 /// given shape. Each polyhedron is rendered inside an axis-alixed voxel
-/// bounding box.
+/// bounding box as spans of voxel rows, in parallel across polyhedra. The
+/// spans are then drawn in descending probability order, lower probability
+/// polyhedra overwriting higher probability ones where they overlap.
 ///
 /// # Arguments
 ///
@@ -27,6 +30,11 @@ use crate::geometry::polyhedron::{golden_spiral, polyhedron_bbox, polyhedron_ver
 /// * `prob_threshold`: Minimum probability to include a polyhedron.
 /// * `anisotropy`: The 1D anisotropy array.
 /// * `shape`: Output label volume shape `[nz, ny, nx]`.
+/// * `scale`: Optional (pln, row, col) scaling factor of the polyhedron
+///   centers and ray distances into the output label volume, *e.g.*
+///   `[2.0, 1.0, 1.0]` renders polyhedra of a volume with a plane spacing of
+///   twice the pixel size on an isotropic grid. If `None` then no scaling is
+///   applied.
 ///
 /// # Returns
 ///
@@ -40,6 +48,7 @@ pub fn distance_polyhedron_to_label(
     prob_threshold: f32,
     anisotropy: [f32; 3],
     shape: [usize; 3],
+    scale: Option<[f32; 3]>,
 ) -> Result<Array3<u64>, ImgalError> {
     let n_polys = polyhedron_dist.dim().0;
     let n_rays = polyhedron_dist.dim().1;
@@ -64,51 +73,54 @@ pub fn distance_polyhedron_to_label(
     let dist = dist.select(poly_ax, &sorted_inds);
     let pnts = pnts.select(poly_ax, &sorted_inds);
     let ids = ids.select(poly_ax, &sorted_inds);
-    // next we render each label inside its bounding box
-    let (gs_verts, gs_faces) = golden_spiral(n_rays, Some(anisotropy))?;
+    // render the voxel spans of each label inside its bounding box in
+    // parallel, then draw the spans in order so later polyhedra overwrite
+    // earlier ones
+    let (mut gs_verts, gs_faces) = golden_spiral(n_rays, Some(anisotropy))?;
+    let mut pnts = pnts;
+    if let Some(scale) = scale {
+        // scaling the ray directions scales the ray distances per axis
+        (0..3).for_each(|d| {
+            gs_verts.column_mut(d).mapv_inplace(|v| v * scale[d]);
+            pnts.column_mut(d)
+                .mapv_inplace(|v| scale_coord(v, scale[d]));
+        });
+    }
     let n_polys = dist.dim().0;
     let [nz, ny, nx] = shape;
-    (0..n_polys).try_for_each(|i| -> Result<(), ImgalError> {
-        let cur_dist = dist.row(i);
-        let cur_pnt = pnts.row(i);
-        let bbox = polyhedron_bbox(cur_dist, cur_pnt, gs_verts.view());
-        let z_min = bbox[0].max(0) as usize;
-        let z_max = bbox[1].min(nz as i32 - 1) as usize;
-        let y_min = bbox[2].max(0) as usize;
-        let y_max = bbox[3].min(ny as i32 - 1) as usize;
-        let x_min = bbox[4].max(0) as usize;
-        let x_max = bbox[5].min(nx as i32 - 1) as usize;
-        if z_min > z_max || y_min > y_max || x_min > x_max {
-            return Ok(());
-        }
-        let cur_poly_verts = polyhedron_verts(cur_dist, cur_pnt, gs_verts.view());
-        let cur_pnt = cur_pnt.mapv(|v| v as f32);
-        let hull = quickhull_3d(&cur_poly_verts, None)?;
-        let convex_hs = hull_to_halfspace(&hull.0, &hull.1, None)?;
-        let kernel_hs = hull_to_halfspace(&cur_poly_verts, &gs_faces, None)?;
-        let mut bbox_slice: ArrayViewMut3<u64> =
-            labels.slice_mut(s![z_min..=z_max, y_min..=y_max, x_min..=x_max]);
-        bbox_slice.indexed_iter_mut().for_each(|(p, v)| {
-            let pnt = [z_min + p.0, y_min + p.1, x_min + p.2];
-            if inside_halfspace_interior(&kernel_hs, &pnt, true, Some(1)).unwrap() {
-                *v = ids[i];
-                return;
-            } else if inside_halfspace_interior(&convex_hs, &pnt, true, Some(1)).unwrap()
-                && inside_polyhedron(
-                    &cur_poly_verts,
-                    &gs_faces,
-                    cur_pnt.view(),
-                    ArrayView1::from(&[pnt[0] as f32, pnt[1] as f32, pnt[2] as f32]),
-                    Some(1),
-                )
-                .unwrap()
-            {
-                *v = ids[i];
-            } else {
-                return;
+    let spans: Vec<Vec<[usize; 4]>> = (0..n_polys)
+        .into_par_iter()
+        .map(|i| {
+            let cur_dist = dist.row(i);
+            let cur_pnt = pnts.row(i);
+            let bbox = polyhedron_bbox(cur_dist, cur_pnt, gs_verts.view());
+            let bbox = [
+                bbox[0].max(0),
+                bbox[1].min(nz as i32 - 1),
+                bbox[2].max(0),
+                bbox[3].min(ny as i32 - 1),
+                bbox[4].max(0),
+                bbox[5].min(nx as i32 - 1),
+            ];
+            let mut spans = Vec::new();
+            if bbox[0] > bbox[1] || bbox[2] > bbox[3] || bbox[4] > bbox[5] {
+                return spans;
             }
+            let cur_poly_verts = polyhedron_verts(cur_dist, cur_pnt, gs_verts.view());
+            polyhedron_spans(
+                cur_poly_verts.view(),
+                gs_faces.view(),
+                cur_pnt,
+                bbox,
+                |z, y, x0, x1| spans.push([z as usize, y as usize, x0 as usize, x1 as usize]),
+            );
+            spans
+        })
+        .collect();
+    spans.iter().zip(ids.iter()).for_each(|(spans, &id)| {
+        spans.iter().for_each(|&[z, y, x0, x1]| {
+            labels.slice_mut(s![z, y, x0..=x1]).fill(id);
         });
-        Ok(())
-    })?;
+    });
     Ok(labels)
 }
@@ -6,12 +6,14 @@
 //!
 //! This crate is still under active development and it's API is not stable.
 
+pub mod augment;
 mod config;
+pub mod convert;
 mod error;
-mod geometry;
-mod labeling;
+pub mod geometry;
+pub mod labeling;
 pub mod models;
 mod networks;
-mod process;
+pub mod process;
 mod utils;
 pub use error::CellcastError;
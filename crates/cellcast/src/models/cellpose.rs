@@ -0,0 +1,396 @@
+use std::path::Path;
+
+use burn::prelude::*;
+use burn::tensor::module::interpolate;
+use burn::tensor::ops::{InterpolateMode, InterpolateOptions, PadMode};
+use imgal::image::percentile_normalize;
+use imgal::prelude::*;
+use ndarray::{
+    Array2, Array3, ArrayBase, ArrayView2, ArrayView3, AsArray, Axis, Ix2, Ix3, ViewRepr, s,
+};
+
+use crate::CellcastError;
+use crate::config::backend::{CpuBackend, GpuBackend};
+use crate::networks::cellpose::config::CellposeNetworkConfig;
+use crate::networks::cellpose::unet;
+use crate::process::flows::{compute_masks, masks_to_flows};
+use crate::utils::axes;
+
+const PMIN: f64 = 1.0;
+const PMAX: f64 = 99.0;
+const FLOW_THRESHOLD: f64 = 0.4;
+const CELLPROB_THRESHOLD: f64 = 0.0;
+const NITER: f64 = 200.0;
+const MIN_SIZE: usize = 15;
+
+type CpuConfigBackend = CpuBackend<f32, i32>;
+type GpuConfigBackend = GpuBackend<f32, i32>;
+
+/// Backend variants for a `Cellpose` model.
+#[derive(Debug)]
+enum CellposeModels {
+    Cpu(Box<unet::Model<CpuConfigBackend>>),
+    Gpu(Box<unet::Model<GpuConfigBackend>>),
+}
+
+/// A Cellpose instance segmentation model.
+///
+/// Initializes a Cellpose instance segmentation model with custom weights
+/// converted from the reference PyTorch implementation. Cellpose predicts
+/// flows towards each cell's center and a cell probability for every pixel,
+/// which are followed to group pixels into cells of any shape. The model runs
+/// on either a CPU or GPU backend as determined at initialization time.
+#[derive(Debug)]
+pub struct Cellpose {
+    model: CellposeModels,
+    config: CellposeNetworkConfig,
+    flow_threshold: f64,
+    cellprob_threshold: f64,
+}
+
+impl Cellpose {
+    /// Initialize a Cellpose model.
+    ///
+    /// # Description
+    ///
+    /// Initializes a Cellpose model with weights in burnpack format, converted
+    /// from the reference PyTorch weights (*e.g.* with the `cellpose_to_bpk`
+    /// binary). A Cellpose model can be initialized on either the GPU or CPU,
+    /// but not both concurrently. The model is pre-warmed as part of the
+    /// initialization process.
+    ///
+    /// # Arguments
+    ///
+    /// * `weights_path`: The path to the Cellpose weights in burnpack (`.bpk`)
+    ///   format.
+    /// * `config`: The network configuration of the weights. If `None` then the
+    ///   `cyto` network configuration is used.
+    /// * `gpu`: If `true`, the configured GPU backend is used. If `false` then the
+    ///   configured CPU backend is used.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Cellpose)`: An initialized Cellpose model.
+    /// * `Err(CellcastError)`: If `config` is invalid. If the weights can not
+    ///   be loaded or do not match the configuration.
+    pub fn init(
+        weights_path: &str,
+        config: Option<CellposeNetworkConfig>,
+        gpu: bool,
+    ) -> Result<Self, CellcastError> {
+        let config = config.unwrap_or_else(CellposeNetworkConfig::cyto);
+        let file = Path::new(weights_path);
+        let model = if gpu {
+            CellposeModels::Gpu(Box::new(unet::Model::from_file(
+                file,
+                &Default::default(),
+                &config,
+            )?))
+        } else {
+            CellposeModels::Cpu(Box::new(unet::Model::from_file(
+                file,
+                &Default::default(),
+                &config,
+            )?))
+        };
+        let cp = Self {
+            model,
+            config,
+            flow_threshold: FLOW_THRESHOLD,
+            cellprob_threshold: CELLPROB_THRESHOLD,
+        };
+        cp.warm_up()?;
+        Ok(cp)
+    }
+
+    /// Get the network configuration.
+    pub fn config(&self) -> &CellposeNetworkConfig {
+        &self.config
+    }
+
+    /// Get the default `(flow, cellprob)` thresholds of the model.
+    pub fn thresholds(&self) -> (f64, f64) {
+        (self.flow_threshold, self.cellprob_threshold)
+    }
+
+    /// Predict instance segmentation labels of a single channel image with the
+    /// Cellpose model.
+    ///
+    /// # Description
+    ///
+    /// Performs model inference with the Cellpose model on a grayscale image,
+    /// used as the first (*i.e.* cytoplasm) input channel with any other input
+    /// channels left empty. See `predict_channels` for details.
+    ///
+    /// # Arguments
+    ///
+    /// * `data`: The input 2D image.
+    /// * `diameter`: The expected cell diameter in pixels. The image is
+    ///   rescaled so that cells of this diameter match the mean diameter of
+    ///   the training data. If `None`, then the image is not rescaled.
+    /// * `flow_threshold`: The maximum flow error of a cell. If `None`, then
+    ///   `flow_threshold = 0.4`. If `0.0`, then cells are not checked.
+    /// * `cellprob_threshold`: The cell probability logit threshold. If `None`,
+    ///   then `cellprob_threshold = 0.0`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Array2<u64>)`: The instance segmentation labels.
+    /// * `Err(CellcastError)`: If `diameter` is not positive.
+    pub fn predict<'a, T, A>(
+        &self,
+        data: A,
+        diameter: Option<f64>,
+        flow_threshold: Option<f64>,
+        cellprob_threshold: Option<f64>,
+    ) -> Result<Array2<u64>, CellcastError>
+    where
+        A: AsArray<'a, T, Ix2>,
+        T: 'a + AsNumeric,
+    {
+        let data: ArrayBase<ViewRepr<&'a T>, Ix2> = data.into();
+        let norm = percentile_normalize(&data, PMIN, PMAX, false, None, None, None)?;
+        let (row, col) = norm.dim();
+        let mut input = Array3::<f32>::zeros((self.config.n_channel_in, row, col));
+        input
+            .index_axis_mut(Axis(0), 0)
+            .assign(&norm.mapv(|v| v as f32));
+        self.run(input, diameter, flow_threshold, cellprob_threshold)
+    }
+
+    /// Predict instance segmentation labels of a multichannel image with the
+    /// Cellpose model.
+    ///
+    /// # Description
+    ///
+    /// Performs model inference with the Cellpose model, returning instance
+    /// segmentations of cells of any shape. Each channel is percentile
+    /// normalized (1% to 99%) and the image is rescaled by the ratio of the
+    /// network's mean diameter to `diameter`. The network flows are resized
+    /// back to the input shape and followed for `200 / rescale` iterations to
+    /// group pixels into cells.
+    ///
+    /// # Arguments
+    ///
+    /// * `data`: The input 3D image with up to `n_channel_in` channels, *e.g.*
+    ///   the cytoplasm and nucleus channels of the `cyto` models. Missing
+    ///   channels are left empty.
+    /// * `axis`: The channel axis. If `None`, then `axis = 2`.
+    /// * `diameter`: The expected cell diameter in pixels. The image is
+    ///   rescaled so that cells of this diameter match the mean diameter of
+    ///   the training data. If `None`, then the image is not rescaled.
+    /// * `flow_threshold`: The maximum flow error of a cell. If `None`, then
+    ///   `flow_threshold = 0.4`. If `0.0`, then cells are not checked.
+    /// * `cellprob_threshold`: The cell probability logit threshold. If `None`,
+    ///   then `cellprob_threshold = 0.0`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Array2<u64>)`: The instance segmentation labels.
+    /// * `Err(CellcastError)`: If `axis` is out of bounds. If the image has
+    ///   more channels than the network. If `diameter` is not positive.
+    pub fn predict_channels<'a, T, A>(
+        &self,
+        data: A,
+        axis: Option<usize>,
+        diameter: Option<f64>,
+        flow_threshold: Option<f64>,
+        cellprob_threshold: Option<f64>,
+    ) -> Result<Array2<u64>, CellcastError>
+    where
+        A: AsArray<'a, T, Ix3>,
+        T: 'a + AsNumeric,
+    {
+        let data: ArrayBase<ViewRepr<&'a T>, Ix3> = data.into();
+        let axis = axis.unwrap_or(2);
+        if axis >= 3 {
+            return Err(CellcastError::Imgal(ImgalError::InvalidAxis {
+                axis_idx: axis,
+                dim_len: 3,
+            }));
+        }
+        let n_channels = data.len_of(Axis(axis));
+        if n_channels > self.config.n_channel_in {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "The input image has more channels than the Cellpose network.",
+            }));
+        }
+        let norm = percentile_normalize(&data, PMIN, PMAX, false, Some(axis), None, None)?;
+        // move the channel axis first, the spatial axes keep their order
+        let spatial: Vec<usize> = (0..3).filter(|&a| a != axis).collect();
+        let norm = norm
+            .mapv(|v| v as f32)
+            .permuted_axes([axis, spatial[0], spatial[1]]);
+        let (_, row, col) = norm.dim();
+        let mut input = Array3::<f32>::zeros((self.config.n_channel_in, row, col));
+        input.slice_mut(s![..n_channels, .., ..]).assign(&norm);
+        self.run(input, diameter, flow_threshold, cellprob_threshold)
+    }
+
+    /// Compute instance segmentation labels from Cellpose flows.
+    ///
+    /// # Description
+    ///
+    /// Follows the `(dy, dx)` flows of the pixels above the cell probability
+    /// threshold and groups the pixels converging to the same point into
+    /// cells, following the reference Cellpose dynamics. Cells whose flows do
+    /// not match the flows recomputed from the cell are removed, as are cells
+    /// smaller than 15 pixels.
+    ///
+    /// # Arguments
+    ///
+    /// * `flows`: The `(dy, dx)` flows with shape `(2, row, col)`, scaled as
+    ///   the network output (*i.e.* 5 times the unit flows).
+    /// * `cellprob`: The cell probability logits with shape `(row, col)`.
+    /// * `flow_threshold`: The maximum flow error of a cell. If `0.0`, then
+    ///   cells are not checked.
+    /// * `cellprob_threshold`: The cell probability logit threshold.
+    /// * `niter`: The number of flow following iterations.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Array2<u64>)`: The instance segmentation labels.
+    /// * `Err(CellcastError)`: If the `flows` and `cellprob` shapes do not
+    ///   match.
+    pub fn masks_from_flows(
+        flows: ArrayView3<f32>,
+        cellprob: ArrayView2<f32>,
+        flow_threshold: f64,
+        cellprob_threshold: f64,
+        niter: usize,
+    ) -> Result<Array2<u64>, CellcastError> {
+        compute_masks(
+            flows,
+            cellprob,
+            flow_threshold as f32,
+            cellprob_threshold as f32,
+            niter,
+            MIN_SIZE,
+        )
+    }
+
+    /// Compute the Cellpose flows of instance segmentation labels.
+    ///
+    /// # Description
+    ///
+    /// Computes the unit `(dy, dx)` flows of each label towards its center
+    /// by simulated heat diffusion, as the flows used to train Cellpose
+    /// networks.
+    ///
+    /// # Arguments
+    ///
+    /// * `labels`: The instance segmentation labels, with `0` as background.
+    ///
+    /// # Returns
+    ///
+    /// * `Array3<f32>`: The unit flows with shape `(2, row, col)`.
+    pub fn flows_from_masks(labels: ArrayView2<u64>) -> Array3<f32> {
+        masks_to_flows(labels)
+    }
+
+    /// Run the Cellpose network on a normalized `(C, H, W)` image and compute
+    /// the instance segmentation labels.
+    fn run(
+        &self,
+        input: Array3<f32>,
+        diameter: Option<f64>,
+        flow_threshold: Option<f64>,
+        cellprob_threshold: Option<f64>,
+    ) -> Result<Array2<u64>, CellcastError> {
+        let rescale = match diameter {
+            Some(d) if d.is_nan() || d <= 0.0 => {
+                return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                    msg: "The Cellpose cell diameter must be positive.",
+                }));
+            }
+            Some(d) => self.config.diam_mean / d,
+            None => 1.0,
+        };
+        let flow_threshold = flow_threshold.unwrap_or(self.flow_threshold);
+        let cellprob_threshold = cellprob_threshold.unwrap_or(self.cellprob_threshold);
+        let (channels, row, col) = input.dim();
+        let (raw_data, _) = input.into_raw_vec_and_offset();
+        let td = TensorData::new(raw_data, [1, channels, row, col]);
+        let div = self.config.div();
+        // GPU and CPU computes must be in their own scope, the "device",
+        // "cellpose_net" and "tensor" types are all connected
+        let output = match &self.model {
+            CellposeModels::Gpu(m) => run_network(m, td, rescale, div),
+            CellposeModels::Cpu(m) => run_network(m, td, rescale, div),
+        };
+        let output = Array3::from_shape_vec((3, row, col), output).unwrap();
+        let flows = output.slice(s![..2, .., ..]);
+        let cellprob = output.index_axis(Axis(0), 2);
+        let niter = (NITER / rescale).round() as usize;
+        Self::masks_from_flows(flows, cellprob, flow_threshold, cellprob_threshold, niter)
+    }
+
+    /// Warm up the Cellpose model by running the network on an empty image.
+    fn warm_up(&self) -> Result<(), CellcastError> {
+        let size = 128.max(self.config.div());
+        let channels = self.config.n_channel_in;
+        let td = TensorData::new(
+            vec![0.0_f32; channels * size * size],
+            [1, channels, size, size],
+        );
+        let div = self.config.div();
+        let output = match &self.model {
+            CellposeModels::Gpu(m) => run_network(m, td, 1.0, div),
+            CellposeModels::Cpu(m) => run_network(m, td, 1.0, div),
+        };
+        if output.is_empty() {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "Failed to warm up the Cellpose model.",
+            }));
+        }
+        Ok(())
+    }
+}
+
+/// Run the Cellpose network on an input tensor.
+///
+/// # Description
+///
+/// Bilinearly resizes the `(1, C, H, W)` input by `rescale`, zero pads the
+/// resized image to be divisible by `div`, runs the network, crops the
+/// padding and resizes the output back to the input shape.
+///
+/// # Returns
+///
+/// * `Vec<f32>`: The flat `(3, H, W)` network output, *i.e.* the `(dy, dx)`
+///   flows and cell probability logits.
+fn run_network<B: Backend>(
+    model: &unet::Model<B>,
+    td: TensorData,
+    rescale: f64,
+    div: usize,
+) -> Vec<f32> {
+    let device = Default::default();
+    let [_, _, row, col] = td.shape[..] else {
+        unreachable!()
+    };
+    let options = InterpolateOptions::new(InterpolateMode::Bilinear).with_align_corners(false);
+    let tensor = Tensor::<B, 4>::from_data(td, &device);
+    let (res_row, res_col) = (
+        ((row as f64 * rescale).round() as usize).max(1),
+        ((col as f64 * rescale).round() as usize).max(1),
+    );
+    let tensor = if (res_row, res_col) != (row, col) {
+        interpolate(tensor, [res_row, res_col], options.clone())
+    } else {
+        tensor
+    };
+    let pad = (
+        axes::divisible_pad(res_row, div),
+        axes::divisible_pad(res_col, div),
+    );
+    let tensor = tensor.pad((0, pad.1, 0, pad.0), PadMode::Constant(0.0));
+    let (output, _) = model.forward(tensor);
+    let output = output.slice([0..1, 0..3, 0..res_row, 0..res_col]);
+    let output = if (res_row, res_col) != (row, col) {
+        interpolate(output, [row, col], options)
+    } else {
+        output
+    };
+    output.into_data().into_vec().unwrap()
+}
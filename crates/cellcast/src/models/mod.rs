@@ -2,10 +2,26 @@
 //!
 //! This module contains the supported cellcast cell segmentation models. Each
 //! model is first initialized on the GPU or CPU with either fetched pre-trained
-//! weights or custom weights.
+//! weights or custom weights. User-defined StarDist networks run through the
+//! StarDist postprocessing with `StarDist2DCustom` and `StarDist3DCustom`.
 
+mod cellpose;
 mod stardist_2d;
 mod stardist_3d;
+mod stardist_custom;
+mod stardist_ensemble;
+mod unet;
 
-pub use stardist_2d::StarDist2D;
-pub use stardist_3d::StarDist3D;
+pub use crate::networks::cellpose::config::CellposeNetworkConfig;
+pub use crate::networks::stardist::config::{StarDistBackbone, StarDistNetworkConfig};
+pub use crate::networks::stardist::metadata::StarDistMetadata;
+pub use crate::networks::stardist::network::{StarDistNetwork2D, StarDistNetwork3D};
+pub use crate::networks::unet::config::UNetConfig;
+pub use crate::process::filter::ObjectFilter;
+pub use crate::process::nms::{NmsAccuracy, NmsMode, NmsOptions, OverlapCriterion};
+pub use cellpose::Cellpose;
+pub use stardist_2d::{StarDist2D, StarDist2DClasses, StarDist2DConfig};
+pub use stardist_3d::{StarDist3D, StarDist3DConfig};
+pub use stardist_custom::{StarDist2DCustom, StarDist3DCustom};
+pub use stardist_ensemble::{EnsembleMode, StarDist2DConsensus, StarDist2DEnsemble};
+pub use unet::UNet;
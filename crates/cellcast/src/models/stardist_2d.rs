@@ -1,26 +1,39 @@
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 
 use burn::prelude::*;
 use imgal::image::percentile_normalize;
 use imgal::prelude::*;
-use imgal::threshold::manual::manual_mask;
 use imgal::transform::pad::reflect_pad;
-use ndarray::{Array1, Array2, Array3, ArrayBase, AsArray, Axis, Ix2, Ix3, ViewRepr};
+use ndarray::{
+    Array, Array1, Array2, Array3, ArrayBase, ArrayView, AsArray, Axis, Dimension, Ix2, Ix3,
+    RemoveAxis, ViewRepr, s,
+};
 
 use crate::CellcastError;
 use crate::config::backend::{CpuBackend, GpuBackend};
+use crate::config::bioimageio::{self, PackageTest};
+use crate::config::stardist;
+use crate::config::weights::{VERSATILE_FLUO_2D_URL, VERSATILE_HE_2D_URL};
 use crate::labeling;
-use crate::networks::stardist::{fluo_2d, he_2d};
-use crate::process::nms::polygon_nms;
-use crate::utils::{axes, border};
+use crate::networks::stardist::config::{StarDistBackbone, StarDistNetworkConfig};
+use crate::networks::stardist::metadata::StarDistMetadata;
+use crate::networks::stardist::{StarDistWeights, onnx, unet_2d};
+use crate::process::candidates::{self, GridCandidates, gather_candidates};
+use crate::process::filter::{ObjectFilter, polygon_filter};
+use crate::process::nms::{NmsMode, NmsOptions, polygon_nms, polygon_soft_nms};
+use crate::utils::resample::resize_linear;
+use crate::utils::{axes, fetch};
 
-const DIV: usize = 16;
 const N_RAYS: usize = 32;
+const GRID: (usize, usize) = (2, 2);
 const PMIN: f64 = 1.0;
 const PMAX: f64 = 99.8;
 const FLUO_PROB_THRESHOLD: f64 = 0.479071463157368;
 const HE_PROB_THRESHOLD: f64 = 0.6924782541382084;
 const NMS_THRESHOLD: f64 = 0.3;
+pub(super) const INPUT_SCALE_ERR: &str = "The input scale must be positive and finite.";
+pub(super) const OUTPUT_SCALE_ERR: &str = "The output scale must be positive and finite.";
 
 type CpuConfigBackend = CpuBackend<f32, i32>;
 type GpuConfigBackend = GpuBackend<f32, i32>;
@@ -31,10 +44,152 @@ type GpuConfigBackend = GpuBackend<f32, i32>;
 /// and `he` models initialized on the CPU or GPU.
 #[derive(Debug)]
 enum StarDist2DModels {
-    FluoCpu(fluo_2d::Model<CpuConfigBackend>),
-    FluoGpu(fluo_2d::Model<GpuConfigBackend>),
-    HeCpu(he_2d::Model<CpuConfigBackend>),
-    HeGpu(he_2d::Model<GpuConfigBackend>),
+    FluoCpu(unet_2d::Model<CpuConfigBackend>),
+    FluoGpu(unet_2d::Model<GpuConfigBackend>),
+    HeCpu(unet_2d::Model<CpuConfigBackend>),
+    HeGpu(unet_2d::Model<GpuConfigBackend>),
+}
+
+/// The raw StarDist2D network output of an image.
+pub(super) struct NetworkOutput {
+    pub(super) maps: OutputMaps,
+    pub(super) class_prob: Option<Vec<f32>>,
+    pub(super) pad_shape: Vec<usize>,
+    pub(super) src_shape: (usize, usize),
+    pub(super) image_shape: (usize, usize),
+}
+
+/// The object probabilities and ray distances of a StarDist2D network
+/// output.
+#[derive(Clone)]
+pub(super) enum OutputMaps {
+    /// The flat object probabilities and ray distances of every output grid
+    /// cell.
+    Full { prob: Vec<f32>, dist: Vec<f32> },
+    /// The object candidates gathered on the device.
+    Candidates(GridCandidates),
+}
+
+/// Multi-class StarDist2D instance segmentation.
+///
+/// The instance segmentation labels of a multi-class StarDist2D model with
+/// the class of each object. Class `0` is the background class of the
+/// network, object classes are numbered from `1` to `n_classes`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StarDist2DClasses {
+    /// The instance segmentation label image.
+    pub labels: Array2<u64>,
+    /// The semantic class image, *i.e.* the class of each pixel's object and
+    /// `0` for background pixels.
+    pub class_image: Array2<usize>,
+    /// The class of each object label.
+    pub classes: BTreeMap<u64, usize>,
+    /// The mean class probabilities (background first) of each object label.
+    pub class_probs: BTreeMap<u64, Vec<f32>>,
+}
+
+/// Configuration of a StarDist2D model.
+///
+/// Describes the StarDist2D network variant that a set of weights was trained
+/// with. The ray count sets the number of radial distances predicted for each
+/// pixel and the grid sets the subsampling factor of the network output
+/// relative to the input image. Other architectural variations (*e.g.* U-Net
+/// depth and filter counts) are set with a full network configuration. The
+/// default configuration matches the published versatile models (*i.e.* 32
+/// rays and a grid of `(2, 2)`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StarDist2DConfig {
+    network: StarDistNetworkConfig,
+}
+
+impl StarDist2DConfig {
+    /// Create a new StarDist2D model configuration.
+    ///
+    /// # Arguments
+    ///
+    /// * `n_rays`: The number of radial distances (rays) predicted per pixel.
+    ///   If `None` then `n_rays = 32`.
+    /// * `grid`: The `(row, col)` subsampling factors of the network output,
+    ///   each a power of 2. If `None` then `grid = (2, 2)`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(StarDist2DConfig)`: The model configuration.
+    /// * `Err(CellcastError)`: If `n_rays < 3`. If a `grid` factor is not a
+    ///   power of 2.
+    pub fn new(n_rays: Option<usize>, grid: Option<(usize, usize)>) -> Result<Self, CellcastError> {
+        let grid = grid.unwrap_or(GRID);
+        Self::from_network(StarDistNetworkConfig {
+            n_rays: n_rays.unwrap_or(N_RAYS),
+            grid: vec![grid.0, grid.1],
+            ..StarDistNetworkConfig::fluo_2d()
+        })
+    }
+
+    /// Create a new StarDist2D model configuration from a network
+    /// configuration.
+    ///
+    /// # Description
+    ///
+    /// Creates a StarDist2D model configuration for any StarDist2D network
+    /// architecture. The number of input channels is set by the model type
+    /// (*i.e.* 1 for `fluo` and 3 for `he` models) at initialization.
+    ///
+    /// # Arguments
+    ///
+    /// * `network`: The StarDist2D network configuration.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(StarDist2DConfig)`: The model configuration.
+    /// * `Err(CellcastError)`: If `network` is invalid or does not describe a
+    ///   2D U-Net.
+    pub fn from_network(network: StarDistNetworkConfig) -> Result<Self, CellcastError> {
+        network.validate()?;
+        if network.n_dim != 2 || network.backbone != StarDistBackbone::Unet {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "StarDist2D models require a 2D U-Net network configuration.",
+            }));
+        }
+        Ok(Self { network })
+    }
+
+    /// Get the number of rays.
+    pub fn n_rays(&self) -> usize {
+        self.network.n_rays
+    }
+
+    /// Get the `(row, col)` grid subsampling factors.
+    pub fn grid(&self) -> (usize, usize) {
+        (self.network.grid[0], self.network.grid[1])
+    }
+
+    /// Get the number of object classes (if the network has a class head).
+    pub fn n_classes(&self) -> Option<usize> {
+        self.network.n_classes
+    }
+
+    /// Get the network configuration.
+    pub fn network(&self) -> &StarDistNetworkConfig {
+        &self.network
+    }
+
+    /// Get the `(row, col)` values the input image shape must be divisible by.
+    ///
+    /// The input is subsampled by the grid and then by the pooling factor at
+    /// each U-Net level.
+    pub fn div(&self) -> (usize, usize) {
+        let div = self.network.div();
+        (div[0], div[1])
+    }
+}
+
+impl Default for StarDist2DConfig {
+    fn default() -> Self {
+        Self {
+            network: StarDistNetworkConfig::fluo_2d(),
+        }
+    }
 }
 
 /// A StarDist2D instance segmentation model.
@@ -46,6 +201,16 @@ enum StarDist2DModels {
 #[derive(Debug)]
 pub struct StarDist2D {
     model: StarDist2DModels,
+    config: StarDist2DConfig,
+    prob_threshold: f64,
+    nms_threshold: f64,
+    pmin: f64,
+    pmax: f64,
+    filter: ObjectFilter,
+    subpixel: bool,
+    nms: NmsOptions,
+    input_scale: Option<(f32, f32)>,
+    output_scale: Option<(f32, f32)>,
     gpu: bool,
 }
 
@@ -63,38 +228,40 @@ impl StarDist2D {
     ///
     /// * `weights_path`: The path to custom StarDist2D weights in burnpack (`.bpk`)
     ///   format. If `None` then the versatile fluo pretrained weights are used.
+    ///   The StarDist metadata stored with custom weights (if any) sets the
+    ///   default configuration, thresholds and normalization percentiles.
+    /// * `config`: The model configuration (*e.g.* ray count and grid) of the
+    ///   custom weights. If `None` then the configuration of the weights
+    ///   metadata or the default configuration is used.
     /// * `gpu`: If `true`, the configured GPU backend is used. If `false` then the
     ///   configured CPU backend is used.
     ///
     /// # Returns
     ///
     /// * `Ok(StarDist2D)`: An initialized StarDist2D fluo model.
-    /// * `Err(CellcastError)`: If the requested model can not be initialized.
-    pub fn init_fluo(weights_path: Option<&str>, gpu: bool) -> Result<Self, CellcastError> {
-        let weights_path = weights_path.map(PathBuf::from);
-        if gpu {
-            let device = Default::default();
-            let sd = Self {
-                model: StarDist2DModels::FluoGpu(fluo_2d::Model::<GpuConfigBackend>::init(
-                    &device,
-                    weights_path.clone(),
-                )),
-                gpu,
-            };
-            sd.warm_up_fluo()?;
-            Ok(sd)
-        } else {
-            let device = Default::default();
-            let sd = Self {
-                model: StarDist2DModels::FluoCpu(fluo_2d::Model::<CpuConfigBackend>::init(
-                    &device,
-                    weights_path.clone(),
-                )),
-                gpu,
-            };
-            sd.warm_up_fluo()?;
-            Ok(sd)
+    /// * `Err(CellcastError)`: If the requested model can not be initialized. If
+    ///   a non-default `config` is given without custom weights. If the weights
+    ///   metadata is invalid or describes a different model type. If `config`
+    ///   does not match the network of the weights metadata.
+    pub fn init_fluo(
+        weights_path: Option<&str>,
+        config: Option<StarDist2DConfig>,
+        gpu: bool,
+    ) -> Result<Self, CellcastError> {
+        let (weights_path, config, metadata) =
+            resolve_weights(weights_path, config, 1, VERSATILE_FLUO_2D_URL)?;
+        let mut sd = Self::init(
+            StarDistWeights::Burnpack(&weights_path),
+            config,
+            false,
+            FLUO_PROB_THRESHOLD,
+            NMS_THRESHOLD,
+            gpu,
+        )?;
+        if let Some(metadata) = metadata {
+            sd.apply_metadata(&metadata);
         }
+        Ok(sd)
     }
 
     /// Initialize a StarDist2D HE model.
@@ -110,38 +277,375 @@ impl StarDist2D {
     ///
     /// * `weights_path`: The path to custom StarDist2D weights in burnpack (`.bpk`)
     ///   format. If `None` then the versatile HE pretrained weights are used.
+    ///   The StarDist metadata stored with custom weights (if any) sets the
+    ///   default configuration, thresholds and normalization percentiles.
+    /// * `config`: The model configuration (*e.g.* ray count and grid) of the
+    ///   custom weights. If `None` then the configuration of the weights
+    ///   metadata or the default configuration is used.
     /// * `gpu`: If `true`, the configured GPU backend is used. If `false` then the
     ///   configured CPU backend is used.
     ///
     /// # Returns
     ///
     /// * `Ok(StarDist2D)`: An initialized StarDist2D HE model.
-    /// * `Err(CellcastError)`: If the requested model can not be initialized.
-    pub fn init_he(weights_path: Option<&str>, gpu: bool) -> Result<Self, CellcastError> {
-        let weights_path = weights_path.map(PathBuf::from);
-        if gpu {
-            let device = Default::default();
-            let sd = Self {
-                model: StarDist2DModels::HeGpu(he_2d::Model::<GpuConfigBackend>::init(
-                    &device,
-                    weights_path.clone(),
-                )),
-                gpu,
-            };
-            sd.warm_up_he()?;
-            Ok(sd)
-        } else {
-            let device = Default::default();
-            let sd = Self {
-                model: StarDist2DModels::HeCpu(he_2d::Model::<CpuConfigBackend>::init(
-                    &device,
-                    weights_path.clone(),
-                )),
-                gpu,
-            };
-            sd.warm_up_he()?;
-            Ok(sd)
+    /// * `Err(CellcastError)`: If the requested model can not be initialized. If
+    ///   a non-default `config` is given without custom weights. If the weights
+    ///   metadata is invalid or describes a different model type. If `config`
+    ///   does not match the network of the weights metadata.
+    pub fn init_he(
+        weights_path: Option<&str>,
+        config: Option<StarDist2DConfig>,
+        gpu: bool,
+    ) -> Result<Self, CellcastError> {
+        let (weights_path, config, metadata) =
+            resolve_weights(weights_path, config, 3, VERSATILE_HE_2D_URL)?;
+        let mut sd = Self::init(
+            StarDistWeights::Burnpack(&weights_path),
+            config,
+            true,
+            HE_PROB_THRESHOLD,
+            NMS_THRESHOLD,
+            gpu,
+        )?;
+        if let Some(metadata) = metadata {
+            sd.apply_metadata(&metadata);
         }
+        Ok(sd)
+    }
+
+    /// Initialize a StarDist2D model from a StarDist model folder.
+    ///
+    /// # Description
+    ///
+    /// Initializes a StarDist2D model from a model folder exported by the
+    /// reference StarDist, with the weights converted to burnpack (`.bpk`)
+    /// format. The ray count, grid, network architecture and number of input
+    /// channels are read from `config.json` and the default probability and
+    /// NMS thresholds from `thresholds.json` (if present). Single channel
+    /// models are used with `predict_fluo` and three channel (RGB) models with
+    /// `predict_he`.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: The path to the model folder containing `config.json`,
+    ///   `thresholds.json` and the weights as `weights_best.bpk`,
+    ///   `weights_last.bpk` or a single `.bpk` file.
+    /// * `gpu`: If `true`, the configured GPU backend is used. If `false` then the
+    ///   configured CPU backend is used.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(StarDist2D)`: An initialized StarDist2D model.
+    /// * `Err(CellcastError)`: If the model folder can not be read. If the model
+    ///   is not a 2D model with 1 or 3 input channels. If the requested model
+    ///   can not be initialized.
+    pub fn init_from_folder(path: &str, gpu: bool) -> Result<Self, CellcastError> {
+        let folder = stardist::read_model_folder(Path::new(path), 2)?;
+        let config = StarDist2DConfig::from_network(folder.network)?;
+        let (he, prob_threshold) = model_type(&config)?;
+        Self::init(
+            StarDistWeights::Burnpack(&folder.weights_path),
+            config,
+            he,
+            folder.prob_threshold.unwrap_or(prob_threshold),
+            folder.nms_threshold.unwrap_or(NMS_THRESHOLD),
+            gpu,
+        )
+    }
+
+    /// Initialize a StarDist2D model from an ONNX file.
+    ///
+    /// # Description
+    ///
+    /// Initializes a StarDist2D model from a StarDist2D network exported to
+    /// ONNX, loaded at runtime. The ONNX model must have a single image input
+    /// and the object probability and ray distance outputs of a StarDist
+    /// network. The number of input channels and rays are read from the graph
+    /// and, unless `config` is given, the grid and network architecture are
+    /// inferred from the graph. Single channel models are used with
+    /// `predict_fluo` and three channel (RGB) models with `predict_he`.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: The path to the ONNX (`.onnx`) file.
+    /// * `config`: The model configuration (*e.g.* grid and network
+    ///   architecture) of the ONNX model. If `None` then the configuration is
+    ///   inferred from the ONNX graph.
+    /// * `gpu`: If `true`, the configured GPU backend is used. If `false` then the
+    ///   configured CPU backend is used.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(StarDist2D)`: An initialized StarDist2D model.
+    /// * `Err(CellcastError)`: If the ONNX file can not be read or is not a
+    ///   StarDist2D model with 1 or 3 input channels. If the weights do not
+    ///   match the configuration.
+    pub fn init_from_onnx(
+        path: &str,
+        config: Option<StarDist2DConfig>,
+        gpu: bool,
+    ) -> Result<Self, CellcastError> {
+        let onnx = onnx::read_onnx(Path::new(path), 2, config.map(|c| c.network))?;
+        let config = StarDist2DConfig::from_network(onnx.network)?;
+        let (he, prob_threshold) = model_type(&config)?;
+        Self::init(
+            StarDistWeights::Convs(&onnx.convs),
+            config,
+            he,
+            prob_threshold,
+            NMS_THRESHOLD,
+            gpu,
+        )
+    }
+
+    /// Initialize a StarDist2D model from a bioimage.io model package.
+    ///
+    /// # Description
+    ///
+    /// Initializes a StarDist2D model from a bioimage.io model zoo package
+    /// exported by the reference StarDist, *i.e.* a zip file with an
+    /// `rdf.yaml` resource description. The ray count, grid, network
+    /// architecture and default thresholds are read from the StarDist
+    /// configuration of the package, the default normalization percentiles
+    /// from the `scale_range` preprocessing, and the weights from the
+    /// `keras_hdf5` or `onnx` weights of the package. Single channel models
+    /// are used with `predict_fluo` and three channel (RGB) models with
+    /// `predict_he`.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: The path to the model package zip file or unpacked folder.
+    /// * `validate`: If `true`, the import is validated by running the model
+    ///   on the package test input and comparing the object probabilities and
+    ///   ray distances with the package test output.
+    /// * `gpu`: If `true`, the configured GPU backend is used. If `false` then the
+    ///   configured CPU backend is used.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(StarDist2D)`: An initialized StarDist2D model.
+    /// * `Err(CellcastError)`: If the package can not be read or does not
+    ///   describe a StarDist2D model with 1 or 3 input channels. If the
+    ///   weights do not match the configuration. If `validate` is `true` and
+    ///   the package has no test tensors or the model output does not match
+    ///   the test output.
+    pub fn init_from_bioimageio(
+        path: &str,
+        validate: bool,
+        gpu: bool,
+    ) -> Result<Self, CellcastError> {
+        let package = bioimageio::read_package(Path::new(path), 2)?;
+        let config = StarDist2DConfig::from_network(package.network)?;
+        let (he, prob_threshold) = model_type(&config)?;
+        let mut sd = Self::init(
+            StarDistWeights::Convs(&package.convs),
+            config,
+            he,
+            package.prob_threshold.unwrap_or(prob_threshold),
+            package.nms_threshold.unwrap_or(NMS_THRESHOLD),
+            gpu,
+        )?;
+        (sd.pmin, sd.pmax) = package.percentiles.unwrap_or((PMIN, PMAX));
+        if validate {
+            let test = package.test.ok_or(ImgalError::InvalidGeneric {
+                msg: "The bioimage.io model package has no test input and output.",
+            })?;
+            sd.validate(&test)?;
+        }
+        Ok(sd)
+    }
+
+    /// Initialize a StarDist2D model from a source of weights.
+    ///
+    /// # Arguments
+    ///
+    /// * `weights`: The source of the StarDist2D weights.
+    /// * `config`: The model configuration of the weights.
+    /// * `he`: If `true` an HE model with channels last RGB input is created,
+    ///   otherwise a fluo model.
+    /// * `prob_threshold`: The default object probability threshold.
+    /// * `nms_threshold`: The default non-maximum suppression threshold.
+    /// * `gpu`: If `true`, the configured GPU backend is used.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(StarDist2D)`: An initialized and pre-warmed StarDist2D model.
+    /// * `Err(CellcastError)`: If the requested model can not be initialized.
+    fn init(
+        weights: StarDistWeights,
+        config: StarDist2DConfig,
+        he: bool,
+        prob_threshold: f64,
+        nms_threshold: f64,
+        gpu: bool,
+    ) -> Result<Self, CellcastError> {
+        let network = config.network();
+        let model = match (he, gpu) {
+            (false, true) => StarDist2DModels::FluoGpu(load_network(weights, network, false)?),
+            (false, false) => StarDist2DModels::FluoCpu(load_network(weights, network, false)?),
+            (true, true) => StarDist2DModels::HeGpu(load_network(weights, network, true)?),
+            (true, false) => StarDist2DModels::HeCpu(load_network(weights, network, true)?),
+        };
+        let sd = Self {
+            model,
+            config,
+            prob_threshold,
+            nms_threshold,
+            pmin: PMIN,
+            pmax: PMAX,
+            filter: ObjectFilter::default(),
+            subpixel: false,
+            nms: NmsOptions::default(),
+            input_scale: None,
+            output_scale: None,
+            gpu,
+        };
+        sd.warm_up()?;
+        Ok(sd)
+    }
+
+    /// Apply the default thresholds and normalization percentiles of the
+    /// weights metadata.
+    fn apply_metadata(&mut self, metadata: &StarDistMetadata) {
+        self.prob_threshold = metadata.prob_threshold.unwrap_or(self.prob_threshold);
+        self.nms_threshold = metadata.nms_threshold.unwrap_or(self.nms_threshold);
+        (self.pmin, self.pmax) = metadata.percentiles.unwrap_or((self.pmin, self.pmax));
+    }
+
+    /// Get the model configuration.
+    pub fn config(&self) -> &StarDist2DConfig {
+        &self.config
+    }
+
+    /// Get the default `(prob, nms)` thresholds of the model.
+    pub fn thresholds(&self) -> (f64, f64) {
+        (self.prob_threshold, self.nms_threshold)
+    }
+
+    /// Get the default `(pmin, pmax)` normalization percentiles of the model.
+    pub fn percentiles(&self) -> (f64, f64) {
+        (self.pmin, self.pmax)
+    }
+
+    /// Set the filter applied to the predicted objects.
+    ///
+    /// # Arguments
+    ///
+    /// * `filter`: The object filter, removing objects crossing the image
+    ///   border and/or outside of an area range before the objects are
+    ///   labeled. By default no objects are removed.
+    pub fn with_object_filter(mut self, filter: ObjectFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Get the filter applied to the predicted objects.
+    pub fn object_filter(&self) -> &ObjectFilter {
+        &self.filter
+    }
+
+    /// Set the placement of the object centers within their grid cell.
+    ///
+    /// # Arguments
+    ///
+    /// * `subpixel`: If `true`, the object centers are placed at the center of
+    ///   their grid cell (*e.g.* a half pixel offset with a grid of `2`),
+    ///   giving more accurate shapes and centroids for small objects. If
+    ///   `false` (the default), the centers are placed on the first pixel of
+    ///   their grid cell, matching the reference StarDist implementation.
+    pub fn with_subpixel_centers(mut self, subpixel: bool) -> Self {
+        self.subpixel = subpixel;
+        self
+    }
+
+    /// Get if the object centers are placed at the center of their grid cell.
+    pub fn subpixel_centers(&self) -> bool {
+        self.subpixel
+    }
+
+    /// Set the non-maximum suppression options.
+    ///
+    /// # Arguments
+    ///
+    /// * `nms`: The overlap criterion and suppression mode (*i.e.* hard or
+    ///   soft-NMS) of the non-maximum suppression. By default overlapping
+    ///   polygons are removed based on the intersection over the smaller
+    ///   polygon.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(StarDist2D)`: The model with the non-maximum suppression options.
+    /// * `Err(CellcastError)`: If the Gaussian soft-NMS `sigma` is not
+    ///   positive and finite.
+    pub fn with_nms_options(mut self, nms: NmsOptions) -> Result<Self, CellcastError> {
+        nms.validate()?;
+        self.nms = nms;
+        Ok(self)
+    }
+
+    /// Get the non-maximum suppression options.
+    pub fn nms_options(&self) -> &NmsOptions {
+        &self.nms
+    }
+
+    /// Set the scale of the input images.
+    ///
+    /// # Description
+    ///
+    /// The input images are resized with linear interpolation before
+    /// normalization and padding, matching the object size of the images to
+    /// the object size of the training data. The object centers and rays are
+    /// mapped back to the input image, the labels match the input image grid
+    /// (see `with_output_scale`).
+    ///
+    /// # Arguments
+    ///
+    /// * `scale`: The (row, col) scale of the network input relative to the
+    ///   input image, *e.g.* `(1.0 / 3.0, 1.0 / 3.0)` for nuclei three times
+    ///   larger than the nuclei of the training data. The input images are
+    ///   resized to shape `round(scale * input shape)`. By default the input
+    ///   images are not resized.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(StarDist2D)`: The model with the input scale.
+    /// * `Err(CellcastError)`: If a scale is not positive and finite.
+    pub fn with_input_scale(mut self, scale: (f32, f32)) -> Result<Self, CellcastError> {
+        self.input_scale =
+            resolve_scale([scale.0, scale.1], INPUT_SCALE_ERR)?.map(|s| (s[0], s[1]));
+        Ok(self)
+    }
+
+    /// Get the (row, col) scale of the network input relative to the input
+    /// image.
+    pub fn input_scale(&self) -> (f32, f32) {
+        self.input_scale.unwrap_or((1.0, 1.0))
+    }
+
+    /// Set the resolution of the output label images.
+    ///
+    /// # Arguments
+    ///
+    /// * `scale`: The (row, col) scale of the label images relative to the
+    ///   input image. The object centers and rays are scaled and the labels are
+    ///   rendered into an image of shape `round(scale * input shape)`, *e.g.*
+    ///   `(2.0, 2.0)` renders the objects of a 2x downsampled input image at
+    ///   its full resolution. By default the labels have the input resolution.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(StarDist2D)`: The model with the output label resolution.
+    /// * `Err(CellcastError)`: If a scale is not positive and finite.
+    pub fn with_output_scale(mut self, scale: (f32, f32)) -> Result<Self, CellcastError> {
+        self.output_scale =
+            resolve_scale([scale.0, scale.1], OUTPUT_SCALE_ERR)?.map(|s| (s[0], s[1]));
+        Ok(self)
+    }
+
+    /// Get the (row, col) scale of the output label images relative to the
+    /// input image.
+    pub fn output_scale(&self) -> (f32, f32) {
+        self.output_scale.unwrap_or((1.0, 1.0))
     }
 
     /// Predict instance segmentation labels with the StarDist2D fluo model.
@@ -155,13 +659,17 @@ impl StarDist2D {
     ///
     /// * `data`: The input 2D image.
     /// * `pmin`: The minimum percentage to linear percentile normalize the input
-    ///   image. If `None`, then `pmin = 1.0`.
+    ///   image. If `None`, then the model's default percentile is used (*i.e.*
+    ///   `pmin = 1.0` for the pretrained weights).
     /// * `pmax`: The maximum percentage to linear percentile normalize the input
-    ///   image. If `None`, then `pmax = 99.8`.
+    ///   image. If `None`, then the model's default percentile is used (*i.e.*
+    ///   `pmax = 99.8` for the pretrained weights).
     /// * `prob_threshold`: The object/polygon probability threshold. If `None`,
-    ///   then `prob_threshold == 0.479071463157368`.
+    ///   then the model's default threshold is used (*i.e.*
+    ///   `prob_threshold == 0.479071463157368` for the pretrained weights).
     /// * `nms_threshold`: The non-maximum suppression (NMS) threshold. If `None`,
-    ///   then `nms_threshold == 0.3`.
+    ///   then the model's default threshold is used (*i.e.*
+    ///   `nms_threshold == 0.3` for the pretrained weights).
     ///
     /// # Returns
     ///
@@ -186,71 +694,71 @@ impl StarDist2D {
         T: 'a + AsNumeric,
     {
         let data: ArrayBase<ViewRepr<&'a T>, Ix2> = data.into();
-        let pmin = pmin.unwrap_or(PMIN);
-        let pmax = pmax.unwrap_or(PMAX);
-        let prob_threshold = prob_threshold.unwrap_or(FLUO_PROB_THRESHOLD) as f32;
-        let nms_threshold = nms_threshold.unwrap_or(NMS_THRESHOLD) as f32;
-        let (src_row, src_col) = data.dim();
-        let norm = percentile_normalize(&data, pmin, pmax, false, None, None, None)?;
-        let norm = norm.mapv(|v| v as f32);
-        // this pattern determines how many pixels to pad in each axis to be
-        // divisible by 16 as expected by the network
-        let pad_config: Vec<usize> = data
-            .shape()
-            .iter()
-            .map(|&v| axes::divisible_pad(v, DIV))
-            .collect();
-        let norm_pad = reflect_pad(&norm, &pad_config, Some(0), None)?;
-        let pad_shape = norm_pad.shape().to_vec();
-        // GPU and CPU computes must be in their own scope, the "device",
-        // "stardist_net" and "tensor" types are all connected
-        let (raw_data, _) = norm_pad.into_raw_vec_and_offset();
-        let td = TensorData::new(raw_data, [1, 1, pad_shape[0], pad_shape[1]]);
-        let prob: Vec<f32>;
-        let dist: Vec<f32>;
-        if self.gpu {
-            match &self.model {
-                StarDist2DModels::FluoGpu(m) => {
-                    let device = Default::default();
-                    let tensor = Tensor::<GpuConfigBackend, 4>::from_data(td, &device);
-                    let (p, d) = m.forward(tensor, (pad_shape[0] as i32, pad_shape[1] as i32));
-                    prob = p.into_data().into_vec().unwrap();
-                    dist = d.into_data().into_vec().unwrap();
-                }
-                _ => {
-                    return Err(ImgalError::InvalidGeneric {
-                        msg: "No initialized StarDist2D Fluo GPU model found.",
-                    })
-                    .map_err(CellcastError::Imgal);
-                }
-            }
-        } else {
-            match &self.model {
-                StarDist2DModels::FluoCpu(m) => {
-                    let device = Default::default();
-                    let tensor = Tensor::<CpuConfigBackend, 4>::from_data(td, &device);
-                    let (p, d) = m.forward(tensor, (pad_shape[0] as i32, pad_shape[1] as i32));
-                    prob = p.into_data().into_vec().unwrap();
-                    dist = d.into_data().into_vec().unwrap();
-                }
-                _ => {
-                    return Err(ImgalError::InvalidGeneric {
-                        msg: "No initialized StarDist2D Fluo CPU model found.",
-                    })
-                    .map_err(CellcastError::Imgal);
-                }
-            }
-        }
+        let prob_threshold = prob_threshold.unwrap_or(self.prob_threshold) as f32;
+        let nms_threshold = nms_threshold.unwrap_or(self.nms_threshold) as f32;
+        let output = self.run_fluo(data, pmin, pmax, false, Some(prob_threshold))?;
         Ok(prob_dist_to_labels_2d(
-            prob,
-            dist,
+            output.maps,
             prob_threshold,
             nms_threshold,
-            pad_shape,
-            (src_row, src_col),
+            output.pad_shape,
+            output.src_shape,
+            output.image_shape,
+            &self.config,
+            self.subpixel,
+            &self.nms,
+            &self.filter,
+            self.output_scale,
         ))
     }
 
+    /// Predict multi-class instance segmentation labels with the StarDist2D
+    /// fluo model.
+    ///
+    /// # Description
+    ///
+    /// Performs model inference with a multi-class StarDist2D fluo model,
+    /// returning instance segmentations of star-convex shapes and the class
+    /// of each object. The class of an object is the class with the highest
+    /// mean probability over the object's pixels.
+    ///
+    /// # Arguments
+    ///
+    /// * `data`: The input 2D image.
+    /// * `pmin`: The minimum percentage to linear percentile normalize the input
+    ///   image. If `None`, then the model's default percentile is used.
+    /// * `pmax`: The maximum percentage to linear percentile normalize the input
+    ///   image. If `None`, then the model's default percentile is used.
+    /// * `prob_threshold`: The object/polygon probability threshold. If `None`,
+    ///   then the model's default threshold is used.
+    /// * `nms_threshold`: The non-maximum suppression (NMS) threshold. If `None`,
+    ///   then the model's default threshold is used.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(StarDist2DClasses)`: The instance segmentation label image and
+    ///   the object classes.
+    /// * `Err(CellcastError)`: If the model has no object class head. If `pmin`
+    ///   and/or `pmax` are outside of range `0.0` to `1.0.`
+    pub fn predict_fluo_classes<'a, T, A>(
+        &self,
+        data: A,
+        pmin: Option<f64>,
+        pmax: Option<f64>,
+        prob_threshold: Option<f64>,
+        nms_threshold: Option<f64>,
+    ) -> Result<StarDist2DClasses, CellcastError>
+    where
+        A: AsArray<'a, T, Ix2>,
+        T: 'a + AsNumeric,
+    {
+        let data: ArrayBase<ViewRepr<&'a T>, Ix2> = data.into();
+        let prob_threshold = prob_threshold.unwrap_or(self.prob_threshold) as f32;
+        let nms_threshold = nms_threshold.unwrap_or(self.nms_threshold) as f32;
+        let output = self.run_fluo(data, pmin, pmax, true, Some(prob_threshold))?;
+        self.classify(output, prob_threshold, nms_threshold)
+    }
+
     /// Predict instance segmentation labels with the StarDist2D HE model.
     ///
     /// # Description
@@ -262,13 +770,17 @@ impl StarDist2D {
     ///
     /// * `data`: The input 3D image, where the third dimension is the channel axis.
     /// * `pmin`: The minimum percentage to linear percentile normalize the input
-    ///   image. If `None`, then `pmin = 1.0`.
+    ///   image. If `None`, then the model's default percentile is used (*i.e.*
+    ///   `pmin = 1.0` for the pretrained weights).
     /// * `pmax`: The maximum percentage to linear percentile normalize the input
-    ///   image. If `None`, then `pmax = 99.8`.
+    ///   image. If `None`, then the model's default percentile is used (*i.e.*
+    ///   `pmax = 99.8` for the pretrained weights).
     /// * `prob_threshold`: The object/polygon probability threshold. If `None`,
-    ///   then `prob_threshold == 0.6924782541382084`.
+    ///   then the model's default threshold is used (*i.e.*
+    ///   `prob_threshold == 0.6924782541382084` for the pretrained weights).
     /// * `nms_threshold`: The non-maximum suppression (NMS) threshold. If `None`,
-    ///   then `nms_threshold == 0.3`.
+    ///   then the model's default threshold is used (*i.e.*
+    ///   `nms_threshold == 0.3` for the pretrained weights).
     /// * `axis`: The channel axis. If `None` then `axis == 2`.
     ///
     /// # Returns
@@ -295,188 +807,672 @@ impl StarDist2D {
         T: 'a + AsNumeric,
     {
         let data: ArrayBase<ViewRepr<&'a T>, Ix3> = data.into();
-        let pmin = pmin.unwrap_or(PMIN);
-        let pmax = pmax.unwrap_or(PMAX);
-        let prob_threshold = prob_threshold.unwrap_or(HE_PROB_THRESHOLD) as f32;
-        let nms_threshold = nms_threshold.unwrap_or(NMS_THRESHOLD) as f32;
-        let norm = percentile_normalize(&data, pmin, pmax, false, axis, None, None)?;
-        let axis = axis.unwrap_or(2);
-        if axis >= 3 {
-            return Err(ImgalError::InvalidAxis {
-                axis_idx: axis,
+        let prob_threshold = prob_threshold.unwrap_or(self.prob_threshold) as f32;
+        let nms_threshold = nms_threshold.unwrap_or(self.nms_threshold) as f32;
+        let output = self.run_he(data, pmin, pmax, axis, false, Some(prob_threshold))?;
+        Ok(prob_dist_to_labels_2d(
+            output.maps,
+            prob_threshold,
+            nms_threshold,
+            output.pad_shape,
+            output.src_shape,
+            output.image_shape,
+            &self.config,
+            self.subpixel,
+            &self.nms,
+            &self.filter,
+            self.output_scale,
+        ))
+    }
+
+    /// Predict multi-class instance segmentation labels with the StarDist2D HE
+    /// model.
+    ///
+    /// # Description
+    ///
+    /// Performs model inference with a multi-class StarDist2D HE model,
+    /// returning instance segmentations of star-convex shapes and the class
+    /// of each object (*e.g.* tumor, lymphocyte or stroma nuclei). The class
+    /// of an object is the class with the highest mean probability over the
+    /// object's pixels.
+    ///
+    /// # Arguments
+    ///
+    /// * `data`: The input 3D image, where the third dimension is the channel axis.
+    /// * `pmin`: The minimum percentage to linear percentile normalize the input
+    ///   image. If `None`, then the model's default percentile is used.
+    /// * `pmax`: The maximum percentage to linear percentile normalize the input
+    ///   image. If `None`, then the model's default percentile is used.
+    /// * `prob_threshold`: The object/polygon probability threshold. If `None`,
+    ///   then the model's default threshold is used.
+    /// * `nms_threshold`: The non-maximum suppression (NMS) threshold. If `None`,
+    ///   then the model's default threshold is used.
+    /// * `axis`: The channel axis. If `None` then `axis == 2`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(StarDist2DClasses)`: The instance segmentation label image and
+    ///   the object classes.
+    /// * `Err(CellcastError)`: If the model has no object class head. If `pmin`
+    ///   and/or `pmax` are outside of range `0.0` to `1.0.`
+    pub fn predict_he_classes<'a, T, A>(
+        &self,
+        data: A,
+        pmin: Option<f64>,
+        pmax: Option<f64>,
+        prob_threshold: Option<f64>,
+        nms_threshold: Option<f64>,
+        axis: Option<usize>,
+    ) -> Result<StarDist2DClasses, CellcastError>
+    where
+        A: AsArray<'a, T, Ix3>,
+        T: 'a + AsNumeric,
+    {
+        let data: ArrayBase<ViewRepr<&'a T>, Ix3> = data.into();
+        let prob_threshold = prob_threshold.unwrap_or(self.prob_threshold) as f32;
+        let nms_threshold = nms_threshold.unwrap_or(self.nms_threshold) as f32;
+        let output = self.run_he(data, pmin, pmax, axis, true, Some(prob_threshold))?;
+        self.classify(output, prob_threshold, nms_threshold)
+    }
+
+    /// Normalize, pad and run the StarDist2D fluo network on a 2D image.
+    ///
+    /// # Description
+    ///
+    /// If `gather` is a probability threshold, the object candidates are
+    /// gathered on the device instead of copying the full object
+    /// probabilities and ray distances to the host.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(NetworkOutput)`: The network output, with the object class
+    ///   probabilities if `classes` is `true`.
+    /// * `Err(CellcastError)`: If the model is not a fluo model. If `classes`
+    ///   is `true` and the model has no object class head. If the percentiles
+    ///   are out of range.
+    pub(super) fn run_fluo<T: AsNumeric>(
+        &self,
+        data: ArrayBase<ViewRepr<&T>, Ix2>,
+        pmin: Option<f64>,
+        pmax: Option<f64>,
+        classes: bool,
+        gather: Option<f32>,
+    ) -> Result<NetworkOutput, CellcastError> {
+        self.check_classes(classes)?;
+        let pmin = pmin.unwrap_or(self.pmin);
+        let pmax = pmax.unwrap_or(self.pmax);
+        let image_shape = data.dim();
+        let resized = self
+            .input_scale
+            .map(|(s_row, s_col)| resize_input(data.view(), &[s_row, s_col], None));
+        let norm = match &resized {
+            Some(resized) => percentile_normalize(resized, pmin, pmax, false, None, None, None)?,
+            None => percentile_normalize(&data, pmin, pmax, false, None, None, None)?,
+        };
+        let (src_row, src_col) = norm.dim();
+        let norm = norm.mapv(|v| v as f32);
+        // this pattern determines how many pixels to pad in each axis to be
+        // divisible by the grid and U-Net depth as expected by the network
+        let div = self.config.div();
+        let pad_config: Vec<usize> = vec![
+            axes::divisible_pad(src_row, div.0),
+            axes::divisible_pad(src_col, div.1),
+        ];
+        let norm_pad = reflect_pad(&norm, &pad_config, Some(0), None)?;
+        let pad_shape = norm_pad.shape().to_vec();
+        // GPU and CPU computes must be in their own scope, the "device",
+        // "stardist_net" and "tensor" types are all connected
+        let (raw_data, _) = norm_pad.into_raw_vec_and_offset();
+        let td = TensorData::new(raw_data, [1, 1, pad_shape[0], pad_shape[1]]);
+        let (maps, class_prob) = match &self.model {
+            StarDist2DModels::FluoGpu(m) if self.gpu => run_network_classes(m, td, classes, gather),
+            StarDist2DModels::FluoCpu(m) if !self.gpu => {
+                run_network_classes(m, td, classes, gather)
+            }
+            _ => {
+                return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                    msg: "No initialized StarDist2D Fluo model found.",
+                }));
+            }
+        };
+        Ok(NetworkOutput {
+            maps,
+            class_prob,
+            pad_shape,
+            src_shape: (src_row, src_col),
+            image_shape,
+        })
+    }
+
+    /// Normalize, pad and run the StarDist2D HE network on a 3D image.
+    ///
+    /// # Description
+    ///
+    /// If `gather` is a probability threshold, the object candidates are
+    /// gathered on the device instead of copying the full object
+    /// probabilities and ray distances to the host.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(NetworkOutput)`: The network output, with the object class
+    ///   probabilities if `classes` is `true`.
+    /// * `Err(CellcastError)`: If the model is not an HE model. If `classes`
+    ///   is `true` and the model has no object class head. If the percentiles
+    ///   or `axis` are out of range.
+    pub(super) fn run_he<T: AsNumeric>(
+        &self,
+        data: ArrayBase<ViewRepr<&T>, Ix3>,
+        pmin: Option<f64>,
+        pmax: Option<f64>,
+        axis: Option<usize>,
+        classes: bool,
+        gather: Option<f32>,
+    ) -> Result<NetworkOutput, CellcastError> {
+        self.check_classes(classes)?;
+        let pmin = pmin.unwrap_or(self.pmin);
+        let pmax = pmax.unwrap_or(self.pmax);
+        let channel_axis = axis.unwrap_or(2);
+        if channel_axis >= 3 {
+            return Err(CellcastError::Imgal(ImgalError::InvalidAxis {
+                axis_idx: channel_axis,
                 dim_len: 3,
-            })
-            .map_err(CellcastError::Imgal);
+            }));
         }
-        let (src_row, src_col, _) = data.dim();
+        let spatial_shape = |shape: &[usize]| {
+            let mut spatial = shape.iter().enumerate().filter(|&(i, _)| i != channel_axis);
+            (*spatial.next().unwrap().1, *spatial.next().unwrap().1)
+        };
+        let image_shape = spatial_shape(data.shape());
+        let resized = self
+            .input_scale
+            .map(|(s_row, s_col)| resize_input(data.view(), &[s_row, s_col], Some(channel_axis)));
+        let norm = match &resized {
+            Some(resized) => percentile_normalize(resized, pmin, pmax, false, axis, None, None)?,
+            None => percentile_normalize(&data, pmin, pmax, false, axis, None, None)?,
+        };
+        let (src_row, src_col) = spatial_shape(norm.shape());
         let norm = norm.mapv(|v| v as f32);
         // this iterator determines how many pixels to pad in each axis (except the
-        // channel axis) to be divisible by 16 as expected by the network
-        let pad_config: Vec<usize> = data
+        // channel axis) to be divisible by the grid and U-Net depth as expected by
+        // the network
+        let div = self.config.div();
+        let mut spatial_div = [div.0, div.1].into_iter();
+        let pad_config: Vec<usize> = norm
             .shape()
             .iter()
             .enumerate()
             .map(|(i, &v)| {
-                if i == axis {
+                if i == channel_axis {
                     0
                 } else {
-                    axes::divisible_pad(v, DIV)
+                    axes::divisible_pad(v, spatial_div.next().unwrap())
                 }
             })
             .collect();
         let norm_pad = reflect_pad(&norm, &pad_config, Some(0), None)?;
         let mut pad_shape = norm_pad.shape().to_vec();
-        pad_shape.remove(axis);
+        pad_shape.remove(channel_axis);
         // GPU and CPU computes must be in their own scope, the "device",
         // "stardist_net" and "tensor" types are all connected
         let (raw_data, _) = norm_pad.into_raw_vec_and_offset();
         let td = TensorData::new(raw_data, [1, pad_shape[0], pad_shape[1], 3]);
-        let prob: Vec<f32>;
-        let dist: Vec<f32>;
-        if self.gpu {
-            match &self.model {
-                StarDist2DModels::HeGpu(m) => {
-                    let device = Default::default();
-                    let tensor = Tensor::<GpuConfigBackend, 4>::from_data(td, &device);
-                    let (p, d) = m.forward(tensor, (pad_shape[0] as i32, pad_shape[1] as i32));
-                    prob = p.into_data().into_vec().unwrap();
-                    dist = d.into_data().into_vec().unwrap();
-                }
-                _ => {
-                    return Err(ImgalError::InvalidGeneric {
-                        msg: "No initialized StarDist2D HE GPU model found.",
-                    })
-                    .map_err(CellcastError::Imgal);
-                }
-            }
-        } else {
-            match &self.model {
-                StarDist2DModels::HeCpu(m) => {
-                    let device = Default::default();
-                    let tensor = Tensor::<CpuConfigBackend, 4>::from_data(td, &device);
-                    let (p, d) = m.forward(tensor, (pad_shape[0] as i32, pad_shape[1] as i32));
-                    prob = p.into_data().into_vec().unwrap();
-                    dist = d.into_data().into_vec().unwrap();
-                }
-                _ => {
-                    return Err(ImgalError::InvalidGeneric {
-                        msg: "No initialized StarDist2D HE CPU model found.",
-                    })
-                    .map_err(CellcastError::Imgal);
-                }
+        let (maps, class_prob) = match &self.model {
+            StarDist2DModels::HeGpu(m) if self.gpu => run_network_classes(m, td, classes, gather),
+            StarDist2DModels::HeCpu(m) if !self.gpu => run_network_classes(m, td, classes, gather),
+            _ => {
+                return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                    msg: "No initialized StarDist2D HE model found.",
+                }));
             }
+        };
+        Ok(NetworkOutput {
+            maps,
+            class_prob,
+            pad_shape,
+            src_shape: (src_row, src_col),
+            image_shape,
+        })
+    }
+
+    /// Check that the model has an object class head if `classes` is `true`.
+    fn check_classes(&self, classes: bool) -> Result<(), CellcastError> {
+        if classes && self.config.n_classes().is_none() {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "The StarDist2D model has no object class head.",
+            }));
         }
-        Ok(prob_dist_to_labels_2d(
-            prob,
-            dist,
+        Ok(())
+    }
+
+    /// Create the instance segmentation labels and object classes of a
+    /// multi-class network output.
+    fn classify(
+        &self,
+        output: NetworkOutput,
+        prob_threshold: f32,
+        nms_threshold: f32,
+    ) -> Result<StarDist2DClasses, CellcastError> {
+        let class_prob = output.class_prob.ok_or(ImgalError::InvalidGeneric {
+            msg: "The StarDist2D model has no object class head.",
+        })?;
+        let labels = prob_dist_to_labels_2d(
+            output.maps,
             prob_threshold,
             nms_threshold,
-            pad_shape,
-            (src_row, src_col),
+            output.pad_shape.clone(),
+            output.src_shape,
+            output.image_shape,
+            &self.config,
+            self.subpixel,
+            &self.nms,
+            &self.filter,
+            self.output_scale,
+        );
+        Ok(labels_to_classes(
+            labels,
+            class_prob,
+            &output.pad_shape,
+            &self.config,
+            label_scale_2d(output.src_shape, output.image_shape, self.output_scale),
         ))
     }
 
-    /// Warm up the StarDist2D fluo model.
+    /// Warm up the StarDist2D model.
     ///
     /// # Description
     ///
-    /// Warms up the StarDist2D fluo model by creating a small tensor of zeros
-    /// and passing it to the initialized model. During this time model
+    /// Warms up the StarDist2D model by creating a small tensor of zeros and
+    /// passing it to the initialized model. During this time model
     /// optimizations like autotuning are performed.
     ///
     /// # Returns
     ///
     /// * `Ok(())`: If successful.
     /// * `Err(CellcastError)`: If the requested model can not be initialized.
-    fn warm_up_fluo(&self) -> Result<(), CellcastError> {
-        let zeros = vec![0.0; 16384];
-        let td = TensorData::new(zeros, [1, 1, 128, 128]);
-        if self.gpu {
-            match &self.model {
-                StarDist2DModels::FluoGpu(m) => {
-                    let device = Default::default();
-                    let tensor = Tensor::<GpuConfigBackend, 4>::from_data(td, &device);
-                    let (p, d) = m.forward(tensor, (128, 128));
-                    let _ = p.into_data();
-                    let _ = d.into_data();
-                    Ok(())
-                }
-                _ => {
-                    return Err(ImgalError::InvalidGeneric {
-                        msg: "No initialized StarDist2D Fluo GPU model found.",
-                    })
-                    .map_err(CellcastError::Imgal);
-                }
-            }
-        } else {
-            match &self.model {
-                StarDist2DModels::FluoCpu(m) => {
-                    let device = Default::default();
-                    let tensor = Tensor::<CpuConfigBackend, 4>::from_data(td, &device);
-                    let (p, d) = m.forward(tensor, (128, 128));
-                    let _ = p.into_data();
-                    let _ = d.into_data();
-                    Ok(())
-                }
-                _ => {
-                    return Err(ImgalError::InvalidGeneric {
-                        msg: "No initialized StarDist2D Fluo CPU model found.",
-                    })
-                    .map_err(CellcastError::Imgal);
-                }
-            }
+    fn warm_up(&self) -> Result<(), CellcastError> {
+        let div = self.config.div();
+        let (row, col) = (128.max(div.0), 128.max(div.1));
+        let (prob, dist) = match &self.model {
+            StarDist2DModels::FluoGpu(m) => run_network(
+                m,
+                TensorData::new(vec![0.0_f32; row * col], [1, 1, row, col]),
+            ),
+            StarDist2DModels::FluoCpu(m) => run_network(
+                m,
+                TensorData::new(vec![0.0_f32; row * col], [1, 1, row, col]),
+            ),
+            StarDist2DModels::HeGpu(m) => run_network(
+                m,
+                TensorData::new(vec![0.0_f32; row * col * 3], [1, row, col, 3]),
+            ),
+            StarDist2DModels::HeCpu(m) => run_network(
+                m,
+                TensorData::new(vec![0.0_f32; row * col * 3], [1, row, col, 3]),
+            ),
+        };
+        if prob.is_empty() || dist.is_empty() {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "Failed to warm up the StarDist2D model.",
+            }));
         }
+        Ok(())
     }
 
-    /// Warm up the StarDist2D HE model.
+    /// Validate the StarDist2D model with the test tensors of a bioimage.io
+    /// model package.
     ///
     /// # Description
     ///
-    /// Warms up the StarDist2D HE model by creating a small tensor of zeros and
-    /// passing it to the initialized model. During this time model optimizations
-    /// like autotuning are performed.
+    /// Normalizes the `(row, col, ch)` test input with the default percentiles,
+    /// runs the network and compares the object probabilities and ray
+    /// distances with the test output.
     ///
     /// # Returns
     ///
-    /// * `Ok(())`: If successful.
-    /// * `Err(CellcastError)`: If the requested model can not be initialized.
-    fn warm_up_he(&self) -> Result<(), CellcastError> {
-        let zeros = vec![0.0; 49152];
-        let td = TensorData::new(zeros, [1, 128, 128, 3]);
-        if self.gpu {
-            match &self.model {
-                StarDist2DModels::HeGpu(m) => {
-                    let device = Default::default();
-                    let tensor = Tensor::<GpuConfigBackend, 4>::from_data(td, &device);
-                    let (p, d) = m.forward(tensor, (128, 128));
-                    let _ = p.into_data();
-                    let _ = d.into_data();
-                    Ok(())
-                }
-                _ => {
-                    return Err(ImgalError::InvalidGeneric {
-                        msg: "No initialized StarDist2D Fluo GPU model found.",
-                    })
-                    .map_err(CellcastError::Imgal);
-                }
+    /// * `Ok(())`: If the model output matches the test output.
+    /// * `Err(CellcastError)`: If the test input does not match the model
+    ///   input. If the model output does not match the test output.
+    fn validate(&self, test: &PackageTest) -> Result<(), CellcastError> {
+        let shape = test.input.shape().to_vec();
+        if shape.len() != 3 || shape[2] != self.config.network.n_channel_in {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "The bioimage.io model package test input does not match the model input.",
+            }));
+        }
+        let norm = percentile_normalize(
+            &test.input,
+            self.pmin,
+            self.pmax,
+            false,
+            Some(2),
+            None,
+            None,
+        )?;
+        let (raw_data, _) = norm.mapv(|v| v as f32).into_raw_vec_and_offset();
+        // the single channel of fluo inputs is moved to the front, which does
+        // not change the flat data order
+        let (prob, dist) = match &self.model {
+            StarDist2DModels::FluoGpu(m) => {
+                run_network(m, TensorData::new(raw_data, [1, 1, shape[0], shape[1]]))
             }
-        } else {
-            match &self.model {
-                StarDist2DModels::HeCpu(m) => {
-                    let device = Default::default();
-                    let tensor = Tensor::<CpuConfigBackend, 4>::from_data(td, &device);
-                    let (p, d) = m.forward(tensor, (128, 128));
-                    let _ = p.into_data();
-                    let _ = d.into_data();
-                    Ok(())
-                }
-                _ => {
-                    return Err(ImgalError::InvalidGeneric {
-                        msg: "No initialized StarDist2D Fluo CPU model found.",
-                    })
-                    .map_err(CellcastError::Imgal);
-                }
+            StarDist2DModels::FluoCpu(m) => {
+                run_network(m, TensorData::new(raw_data, [1, 1, shape[0], shape[1]]))
+            }
+            StarDist2DModels::HeGpu(m) => {
+                run_network(m, TensorData::new(raw_data, [1, shape[0], shape[1], 3]))
+            }
+            StarDist2DModels::HeCpu(m) => {
+                run_network(m, TensorData::new(raw_data, [1, shape[0], shape[1], 3]))
+            }
+        };
+        bioimageio::validate_outputs(&test.output, &prob, &dist, true)
+    }
+}
+
+/// Load a StarDist2D network from a source of weights.
+///
+/// # Arguments
+///
+/// * `weights`: The source of the StarDist2D weights.
+/// * `network`: The network configuration of the weights.
+/// * `channels_last`: If `true` the network input has shape `(1, H, W, C)`.
+///
+/// # Returns
+///
+/// * `Ok(unet_2d::Model<B>)`: The network with weights loaded.
+/// * `Err(CellcastError)`: If the weights can not be loaded.
+fn load_network<B: Backend>(
+    weights: StarDistWeights,
+    network: &StarDistNetworkConfig,
+    channels_last: bool,
+) -> Result<unet_2d::Model<B>, CellcastError> {
+    let device = Default::default();
+    match weights {
+        StarDistWeights::Burnpack(file) => {
+            unet_2d::Model::from_file(file, &device, network, channels_last)
+        }
+        StarDistWeights::Convs(convs) => {
+            unet_2d::Model::from_conv_weights(convs, &device, network, channels_last)
+        }
+    }
+}
+
+/// Get the model type and default probability threshold of a configuration.
+///
+/// # Returns
+///
+/// * `Ok((bool, f64))`: If the model is an HE model and its default object
+///   probability threshold.
+/// * `Err(CellcastError)`: If the model does not have 1 (fluo) or 3 (he) input
+///   channels.
+fn model_type(config: &StarDist2DConfig) -> Result<(bool, f64), CellcastError> {
+    match config.network().n_channel_in {
+        1 => Ok((false, FLUO_PROB_THRESHOLD)),
+        3 => Ok((true, HE_PROB_THRESHOLD)),
+        _ => Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+            msg: "StarDist2D models require 1 (fluo) or 3 (he) input channels.",
+        })),
+    }
+}
+
+/// Resolve the weights file, model configuration and weights metadata.
+///
+/// # Description
+///
+/// Returns the custom weights path, configuration and StarDist metadata (if
+/// stored with the weights), or fetches the given pretrained weights if no
+/// custom weights path is provided. If no `config` is given, the network of
+/// the weights metadata is used. The number of network input channels is set
+/// to `n_channel_in`. Pretrained weights only support the default
+/// configuration.
+///
+/// # Arguments
+///
+/// * `weights_path`: The path to custom weights.
+/// * `config`: The model configuration of the custom weights.
+/// * `n_channel_in`: The number of input channels of the model type.
+/// * `url`: The URL of the pretrained weights.
+///
+/// # Returns
+///
+/// * `Ok((PathBuf, StarDist2DConfig, Option<StarDistMetadata>))`: The weights
+///   path, configuration and metadata.
+/// * `Err(CellcastError)`: If a non-default `config` is given without custom
+///   weights. If the weights metadata is invalid or does not describe a
+///   StarDist2D model with `n_channel_in` input channels. If `config` does not
+///   match the network of the weights metadata.
+fn resolve_weights(
+    weights_path: Option<&str>,
+    config: Option<StarDist2DConfig>,
+    n_channel_in: usize,
+    url: &str,
+) -> Result<(PathBuf, StarDist2DConfig, Option<StarDistMetadata>), CellcastError> {
+    match weights_path {
+        Some(wp) => {
+            let metadata = StarDistMetadata::from_file(wp)?;
+            if metadata
+                .as_ref()
+                .is_some_and(|m| m.network.n_dim != 2 || m.network.n_channel_in != n_channel_in)
+            {
+                return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                    msg: "The weights metadata does not describe a StarDist2D model of this type.",
+                }));
+            }
+            let mut config = match (config, &metadata) {
+                (Some(config), _) => config,
+                (None, Some(m)) => StarDist2DConfig::from_network(m.network.clone())?,
+                (None, None) => StarDist2DConfig::default(),
+            };
+            config.network.n_channel_in = n_channel_in;
+            if metadata
+                .as_ref()
+                .is_some_and(|m| m.network != config.network)
+            {
+                return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                    msg: "The StarDist2D configuration does not match the network of the weights metadata.",
+                }));
+            }
+            Ok((PathBuf::from(wp), config, metadata))
+        }
+        None => {
+            let mut config = config.unwrap_or_default();
+            config.network.n_channel_in = n_channel_in;
+            let mut default = StarDist2DConfig::default();
+            default.network.n_channel_in = n_channel_in;
+            if config != default {
+                return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                    msg: "A custom StarDist2D configuration requires custom weights.",
+                }));
+            }
+            let wp = fetch::fetch_weights(url, false)
+                .expect("Failed to download the StarDist2D pretrained weights.");
+            Ok((wp, config, None))
+        }
+    }
+}
+
+/// Run the StarDist2D network on an input tensor.
+///
+/// # Returns
+///
+/// * `(Vec<f32>, Vec<f32>)`: The flat object probabilities and ray distances.
+fn run_network<B: Backend>(model: &unet_2d::Model<B>, td: TensorData) -> (Vec<f32>, Vec<f32>) {
+    let device = Default::default();
+    let tensor = Tensor::<B, 4>::from_data(td, &device);
+    let (p, d) = model.forward(tensor);
+    (
+        p.into_data().into_vec().unwrap(),
+        d.into_data().into_vec().unwrap(),
+    )
+}
+
+/// Resolve the input or output scale of a StarDist model.
+///
+/// # Arguments
+///
+/// * `scale`: The scale of each spatial axis.
+/// * `msg`: The error message of an invalid scale.
+///
+/// # Returns
+///
+/// * `Ok(Option<[f32; D]>)`: The scale, `None` if no axis is scaled.
+/// * `Err(CellcastError)`: If a scale is not positive and finite.
+pub(super) fn resolve_scale<const D: usize>(
+    scale: [f32; D],
+    msg: &'static str,
+) -> Result<Option<[f32; D]>, CellcastError> {
+    if scale.iter().any(|&s| !s.is_finite() || s <= 0.0) {
+        return Err(CellcastError::Imgal(ImgalError::InvalidGeneric { msg }));
+    }
+    Ok((scale != [1.0; D]).then_some(scale))
+}
+
+/// Resize an input image by the input scale of a StarDist model.
+///
+/// # Arguments
+///
+/// * `data`: The input image.
+/// * `scale`: The scale of each spatial axis, in axis order.
+/// * `channel_axis`: The channel axis, which is not resized. If `None`, then
+///   every axis is a spatial axis.
+///
+/// # Returns
+///
+/// * `Array<f64, D>`: The resized image.
+pub(super) fn resize_input<T: AsNumeric, D: Dimension + RemoveAxis>(
+    data: ArrayView<T, D>,
+    scale: &[f32],
+    channel_axis: Option<usize>,
+) -> Array<f64, D> {
+    let mut spatial_scale = scale.iter();
+    let shape: Vec<usize> = data
+        .shape()
+        .iter()
+        .enumerate()
+        .map(|(i, &v)| {
+            if Some(i) == channel_axis {
+                v
+            } else {
+                axes::scaled_len(v, *spatial_scale.next().unwrap())
             }
+        })
+        .collect();
+    resize_linear(data, &shape)
+}
+
+/// Get the scale of a StarDist2D label image relative to the network input
+/// image.
+///
+/// # Arguments
+///
+/// * `src_shape`: The network input image shape.
+/// * `image_shape`: The input image shape before rescaling.
+/// * `output_scale`: The (row, col) scale of the label image relative to the
+///   input image.
+///
+/// # Returns
+///
+/// * `Option<(f32, f32)>`: The (row, col) scale of the label image, `None` if
+///   the label image has the network input resolution.
+pub(super) fn label_scale_2d(
+    src_shape: (usize, usize),
+    image_shape: (usize, usize),
+    output_scale: Option<(f32, f32)>,
+) -> Option<(f32, f32)> {
+    let (s_row, s_col) = output_scale.unwrap_or((1.0, 1.0));
+    let scale = (
+        s_row * image_shape.0 as f32 / src_shape.0 as f32,
+        s_col * image_shape.1 as f32 / src_shape.1 as f32,
+    );
+    (scale != (1.0, 1.0)).then_some(scale)
+}
+
+/// Run the StarDist2D network on an input tensor, with the object class head
+/// if `classes` is `true`.
+///
+/// # Arguments
+///
+/// * `model`: The StarDist2D network.
+/// * `td`: The network input data.
+/// * `classes`: If `true`, the object class probabilities are returned.
+/// * `gather`: If a probability threshold is given, the object candidates are
+///   gathered on the device. If `None`, the full object probabilities and ray
+///   distances are copied to the host.
+///
+/// # Returns
+///
+/// * `(OutputMaps, Option<Vec<f32>>)`: The object probabilities and ray
+///   distances and the flat object class probabilities.
+fn run_network_classes<B: Backend>(
+    model: &unet_2d::Model<B>,
+    td: TensorData,
+    classes: bool,
+    gather: Option<f32>,
+) -> (OutputMaps, Option<Vec<f32>>) {
+    let device = Default::default();
+    let tensor = Tensor::<B, 4>::from_data(td, &device);
+    let (p, d, c) = if classes {
+        model.forward_classes(tensor)
+    } else {
+        let (p, d) = model.forward(tensor);
+        (p, d, None)
+    };
+    let maps = match gather {
+        // the network output is (1, row, col, ch) and the rays are last
+        Some(t) => {
+            let [_, res_row, res_col, n_rays] = d.dims();
+            OutputMaps::Candidates(gather_candidates(
+                p.reshape([res_row, res_col]),
+                d.reshape([res_row * res_col, n_rays]),
+                t,
+                2,
+            ))
+        }
+        None => OutputMaps::Full {
+            prob: p.into_data().into_vec().unwrap(),
+            dist: d.into_data().into_vec().unwrap(),
+        },
+    };
+    (maps, c.map(|c| c.into_data().into_vec().unwrap()))
+}
+
+/// StarDist2D object candidates, sorted by descending object probability.
+pub(super) struct PolygonCandidates {
+    /// The ray distances with shape `(n_polys, n_rays)`.
+    pub(super) dist: Array2<f32>,
+    /// The object probabilities with shape `(n_polys,)`.
+    pub(super) prob: Array1<f32>,
+    /// The `(row, col)` center positions with shape `(n_polys, 2)`.
+    pub(super) pos: Array2<f32>,
+}
+
+impl PolygonCandidates {
+    /// Concatenate the candidates of several networks and sort them by
+    /// descending object probability.
+    ///
+    /// # Arguments
+    ///
+    /// * `candidates`: The candidates of each network, with the same number of
+    ///   rays.
+    ///
+    /// # Returns
+    ///
+    /// * `PolygonCandidates`: The merged candidates.
+    pub(super) fn merge(candidates: &[PolygonCandidates]) -> Self {
+        let poly_ax = Axis(0);
+        let dist_views: Vec<_> = candidates.iter().map(|c| c.dist.view()).collect();
+        let prob_views: Vec<_> = candidates.iter().map(|c| c.prob.view()).collect();
+        let pos_views: Vec<_> = candidates.iter().map(|c| c.pos.view()).collect();
+        let dist = ndarray::concatenate(poly_ax, &dist_views).unwrap();
+        let prob = ndarray::concatenate(poly_ax, &prob_views).unwrap();
+        let pos = ndarray::concatenate(poly_ax, &pos_views).unwrap();
+        // a stable sort keeps the network order of equal probabilities
+        let mut sorted_inds: Vec<usize> = (0..prob.len()).collect();
+        sorted_inds.sort_by(|&a, &b| prob[b].partial_cmp(&prob[a]).unwrap());
+        Self {
+            dist: dist.select(poly_ax, &sorted_inds),
+            prob: prob.select(poly_ax, &sorted_inds),
+            pos: pos.select(poly_ax, &sorted_inds),
         }
     }
 }
@@ -486,64 +1482,103 @@ impl StarDist2D {
 ///
 /// # Arguments
 ///
-/// * `prob`: The object probabilities as a flat 1D array.
-/// * `dist`: The ray distances as a flat 1D array.
+/// * `maps`: The object probabilities and ray distances of the network output.
 /// * `prob_threshold`: The object probability threshold.
 /// * `nms_threshold`: The non-maximum suppression threshold.
 /// * `pad_shape`: The padded image shape.
 /// * `src_shape`: The original/source image shape.
+/// * `image_shape`: The input image shape before rescaling, the objects are
+///   mapped back to this shape.
+/// * `config`: The model configuration, providing the ray count and grid.
+/// * `subpixel`: If `true`, the object centers are placed at the center of
+///   their grid cell instead of its first pixel.
+/// * `nms`: The non-maximum suppression options.
+/// * `filter`: The filter applied to the objects before labeling.
+/// * `output_scale`: The (row, col) scale of the label image relative to the
+///   input image. If `None`, the labels have the input image shape.
 ///
 /// # Returns
 ///
 /// * `Array2<u64>`: The instance segmentation label image.
-fn prob_dist_to_labels_2d(
-    prob: Vec<f32>,
-    dist: Vec<f32>,
+pub(super) fn prob_dist_to_labels_2d(
+    maps: OutputMaps,
     prob_threshold: f32,
     nms_threshold: f32,
     pad_shape: Vec<usize>,
     src_shape: (usize, usize),
+    image_shape: (usize, usize),
+    config: &StarDist2DConfig,
+    subpixel: bool,
+    nms: &NmsOptions,
+    filter: &ObjectFilter,
+    output_scale: Option<(f32, f32)>,
 ) -> Array2<u64> {
-    // create arrays from the flat StarDist network output
-    let res_row: usize = pad_shape[0] / 2;
-    let res_col: usize = pad_shape[1] / 2;
-    let prob_arr = Array2::from_shape_vec((res_row, res_col), prob)
-        .expect("StarDist 2D object probabilites reshape failed.");
-    let dist_arr = Array3::from_shape_vec((res_row, res_col, N_RAYS), dist)
-        .expect("StarDist 2D radial distances reshape failed.");
-    // ensure all values in the dist array are at least 1e-3, prevents negative and/or zero
-    // distances
-    let dist_arr = dist_arr.mapv(|v| v.max(1e-3));
-    let mut valid_mask = manual_mask(&prob_arr, prob_threshold, None);
-    border::clip_mask_border(&mut valid_mask.view_mut().into_dyn(), 2);
-    let valid_mask = valid_mask.into_dimensionality::<Ix2>().unwrap();
-    // collect all valid (row, col) positions to avoid iterating the mask
-    // repeatedly
-    let valid_pos: Vec<(usize, usize)> = valid_mask
-        .indexed_iter()
-        .filter(|&((_, _), &v)| v)
-        .map(|((r, c), _)| (r, c))
-        .collect();
-    let flat_pos = valid_pos.iter().flat_map(|&(r, c)| [r, c]).collect();
-    let mut valid_pos = Array2::from_shape_vec((valid_pos.len(), 2), flat_pos).unwrap();
-    // filter probabilities and distances with valid indices, removing invalid
-    // positions
-    let mut valid_prob =
-        Array1::from_iter(valid_pos.axis_iter(Axis(0)).map(|v| prob_arr[[v[0], v[1]]]));
-    let mut valid_dist = Array2::<f32>::zeros((valid_pos.dim().0, N_RAYS));
-    (0..N_RAYS).for_each(|n| {
-        valid_pos.axis_iter(Axis(0)).enumerate().for_each(|(i, v)| {
-            valid_dist[[i, n]] = dist_arr[[v[0], v[1], n]];
-        });
-    });
-    // scale each valid position by 2 and collect the valid indices of positions
-    // inside of the source image dimensions (used for point filtering)
-    valid_pos.mapv_inplace(|v| v * 2);
+    let candidates = prob_dist_to_candidates_2d(
+        maps,
+        prob_threshold,
+        &pad_shape,
+        src_shape,
+        config,
+        subpixel,
+    );
+    candidates_to_labels_2d(
+        candidates,
+        prob_threshold,
+        nms_threshold,
+        src_shape,
+        image_shape,
+        nms,
+        filter,
+        output_scale,
+    )
+}
+
+/// Extract the StarDist2D object candidates of the object probabilities and
+/// ray distance arrays.
+///
+/// # Arguments
+///
+/// * `maps`: The object probabilities and ray distances of the network output.
+///   Candidates gathered on the device are used as is.
+/// * `prob_threshold`: The object probability threshold.
+/// * `pad_shape`: The padded image shape.
+/// * `src_shape`: The original/source image shape.
+/// * `config`: The model configuration, providing the ray count and grid.
+/// * `subpixel`: If `true`, the object centers are placed at the center of
+///   their grid cell instead of its first pixel.
+///
+/// # Returns
+///
+/// * `PolygonCandidates`: The object candidates above `prob_threshold`, with
+///   center positions in image coordinates.
+pub(super) fn prob_dist_to_candidates_2d(
+    maps: OutputMaps,
+    prob_threshold: f32,
+    pad_shape: &[usize],
+    src_shape: (usize, usize),
+    config: &StarDist2DConfig,
+    subpixel: bool,
+) -> PolygonCandidates {
+    let GridCandidates {
+        pos: mut valid_pos,
+        prob: mut valid_prob,
+        dist: mut valid_dist,
+    } = match maps {
+        OutputMaps::Full { prob, dist } => {
+            gather_candidates_host(prob, dist, prob_threshold, pad_shape, config)
+        }
+        OutputMaps::Candidates(candidates) => candidates,
+    };
+    let (grid_row, grid_col) = config.grid();
+    // scale each valid position by the grid and collect the valid indices of
+    // positions inside of the source image dimensions (used for point filtering)
+    valid_pos.column_mut(0).mapv_inplace(|v| v * grid_row);
+    valid_pos.column_mut(1).mapv_inplace(|v| v * grid_col);
     let valid_inds: Vec<usize> = valid_pos
         .axis_iter(Axis(0))
         .enumerate()
         .filter_map(|(i, v)| {
-            if v[0] < src_shape.0 || v[1] < src_shape.1 {
+            if v[0] < src_shape.0 && v[1] < src_shape.1 {
                 Some(i)
             } else {
                 None
@@ -557,44 +1592,262 @@ fn prob_dist_to_labels_2d(
         valid_prob = valid_prob.select(poly_ax, &valid_inds);
         valid_pos = valid_pos.select(poly_ax, &valid_inds);
     }
+    // optionally shift the centers from the grid cell corners to the grid cell
+    // centers (e.g. by half a pixel with a grid of 2)
+    let mut valid_pos = valid_pos.mapv(|v| v as f32);
+    if subpixel {
+        valid_pos
+            .column_mut(0)
+            .mapv_inplace(|v| v + (grid_row - 1) as f32 / 2.0);
+        valid_pos
+            .column_mut(1)
+            .mapv_inplace(|v| v + (grid_col - 1) as f32 / 2.0);
+    }
     // get the indices that would sort probs in descending order
     let n_polys = valid_prob.len();
     let mut sorted_poly_inds: Vec<usize> = (0..n_polys).collect();
     sorted_poly_inds.sort_by(|&a, &b| valid_prob[b].partial_cmp(&valid_prob[a]).unwrap());
     // sort dist, prob and pos arrays with prob descending order indices
-    let poly_dist = valid_dist.select(poly_ax, &sorted_poly_inds);
-    let poly_pos = valid_pos.select(poly_ax, &sorted_poly_inds);
-    // perform non-maximum supression (NMS) and obtain indices of valid polygons
-    let valid_poly_inds = polygon_nms(
-        poly_dist.view(),
-        poly_pos.view(),
-        n_polys,
-        N_RAYS,
-        nms_threshold,
-    );
-    let valid_poly_inds: Vec<usize> = valid_poly_inds
-        .iter()
-        .enumerate()
-        .filter(|&(_, &v)| v)
-        .map(|(i, _)| i)
-        .collect();
-    // filter dist, prob and pos arrays with for valid polygons after NMS
-    let poly_dist = poly_dist.select(poly_ax, &valid_poly_inds);
-    let poly_prob = valid_prob.select(poly_ax, &valid_poly_inds);
-    let poly_pos = poly_pos.select(poly_ax, &valid_poly_inds);
+    PolygonCandidates {
+        dist: valid_dist.select(poly_ax, &sorted_poly_inds),
+        prob: valid_prob.select(poly_ax, &sorted_poly_inds),
+        pos: valid_pos.select(poly_ax, &sorted_poly_inds),
+    }
+}
+
+/// Gather the StarDist2D object candidates of flat object probabilities and
+/// ray distances on the host.
+///
+/// # Arguments
+///
+/// * `prob`: The object probabilities as a flat 1D array.
+/// * `dist`: The ray distances as a flat 1D array.
+/// * `prob_threshold`: The object probability threshold.
+/// * `pad_shape`: The padded image shape.
+/// * `config`: The model configuration, providing the ray count and grid.
+///
+/// # Returns
+///
+/// * `GridCandidates`: The object candidates above `prob_threshold`, in
+///   row-major grid order.
+fn gather_candidates_host(
+    prob: Vec<f32>,
+    dist: Vec<f32>,
+    prob_threshold: f32,
+    pad_shape: &[usize],
+    config: &StarDist2DConfig,
+) -> GridCandidates {
+    let n_rays = config.n_rays();
+    let (grid_row, grid_col) = config.grid();
+    // create arrays from the flat StarDist network output
+    let res_row: usize = pad_shape[0] / grid_row;
+    let res_col: usize = pad_shape[1] / grid_col;
+    let prob_arr = Array2::from_shape_vec((res_row, res_col), prob)
+        .expect("StarDist 2D object probabilites reshape failed.");
+    let dist_arr = Array2::from_shape_vec((res_row * res_col, n_rays), dist)
+        .expect("StarDist 2D radial distances reshape failed.");
+    candidates::gather_candidates_host(
+        prob_arr.view().into_dyn(),
+        dist_arr.view(),
+        prob_threshold,
+        2,
+    )
+}
+
+/// Suppress overlapping StarDist2D object candidates and label the remaining
+/// objects.
+///
+/// # Arguments
+///
+/// * `candidates`: The object candidates, sorted by descending probability.
+/// * `prob_threshold`: The object probability threshold.
+/// * `nms_threshold`: The non-maximum suppression threshold.
+/// * `src_shape`: The original/source image shape.
+/// * `image_shape`: The input image shape before rescaling, the objects are
+///   mapped back to this shape.
+/// * `nms`: The non-maximum suppression options.
+/// * `filter`: The filter applied to the objects before labeling. The object
+///   sizes are in input image pixels.
+/// * `output_scale`: The (row, col) scale of the label image relative to the
+///   input image. If `None`, the labels have the input image shape.
+///
+/// # Returns
+///
+/// * `Array2<u64>`: The instance segmentation label image.
+pub(super) fn candidates_to_labels_2d(
+    candidates: PolygonCandidates,
+    prob_threshold: f32,
+    nms_threshold: f32,
+    src_shape: (usize, usize),
+    image_shape: (usize, usize),
+    nms: &NmsOptions,
+    filter: &ObjectFilter,
+    output_scale: Option<(f32, f32)>,
+) -> Array2<u64> {
+    let poly_ax = Axis(0);
+    let PolygonCandidates {
+        dist: poly_dist,
+        prob: poly_prob,
+        pos: poly_pos,
+    } = candidates;
+    let n_polys = poly_prob.len();
+    let n_rays = poly_dist.dim().1;
+    let (poly_dist, poly_prob, poly_pos) = if nms.mode == NmsMode::Hard {
+        // perform non-maximum supression (NMS) and obtain indices of valid
+        // polygons
+        let valid_poly_inds = polygon_nms(
+            poly_dist.view(),
+            poly_pos.view(),
+            n_polys,
+            n_rays,
+            nms_threshold,
+            nms.overlap,
+        );
+        let valid_poly_inds: Vec<usize> = valid_poly_inds
+            .iter()
+            .enumerate()
+            .filter(|&(_, &v)| v)
+            .map(|(i, _)| i)
+            .collect();
+        // filter dist, prob and pos arrays with for valid polygons after NMS
+        (
+            poly_dist.select(poly_ax, &valid_poly_inds),
+            poly_prob.select(poly_ax, &valid_poly_inds),
+            poly_pos.select(poly_ax, &valid_poly_inds),
+        )
+    } else {
+        // soft-NMS decays the probabilities of overlapping polygons, which are
+        // then removed by the probability threshold
+        let poly_prob = polygon_soft_nms(
+            poly_dist.view(),
+            poly_pos.view(),
+            poly_prob.view(),
+            prob_threshold,
+            nms_threshold,
+            nms.overlap,
+            nms.mode,
+        );
+        (poly_dist, Array1::from(poly_prob), poly_pos)
+    };
     // filter dist, prob and pos arrays by probability threshold
     let valid_prob_inds: Vec<usize> = (0..poly_prob.len())
-        .filter(|&i| poly_prob[i] > prob_threshold)
+        .filter(|&i| poly_prob[i] >= prob_threshold)
         .collect();
     let poly_dist = poly_dist.select(poly_ax, &valid_prob_inds);
     let poly_prob = poly_prob.select(poly_ax, &valid_prob_inds);
     let poly_pos = poly_pos.select(poly_ax, &valid_prob_inds);
+    // remove filtered objects before labeling to keep the label ids contiguous,
+    // the object sizes are converted to network input pixels
+    let area_scale = (src_shape.0 * src_shape.1) as f32 / (image_shape.0 * image_shape.1) as f32;
+    let valid_filter_inds: Vec<usize> = polygon_filter(
+        poly_dist.view(),
+        poly_pos.view(),
+        src_shape,
+        &filter.scale_size(area_scale),
+    )
+    .iter()
+    .enumerate()
+    .filter(|&(_, &v)| v)
+    .map(|(i, _)| i)
+    .collect();
+    let poly_dist = poly_dist.select(poly_ax, &valid_filter_inds);
+    let poly_prob = poly_prob.select(poly_ax, &valid_filter_inds);
+    let poly_pos = poly_pos.select(poly_ax, &valid_filter_inds);
     // convert radial distances and polygons to labels
+    let label_shape = match output_scale {
+        Some((s_row, s_col)) => (
+            axes::scaled_len(image_shape.0, s_row),
+            axes::scaled_len(image_shape.1, s_col),
+        ),
+        None => image_shape,
+    };
     labeling::distance_polygon_to_label(
         poly_dist.view(),
         poly_prob.view(),
         poly_pos.view(),
-        (src_shape.0, src_shape.1),
-        None,
+        label_shape,
+        label_scale_2d(src_shape, image_shape, output_scale),
     )
 }
+
+/// Aggregate StarDist2D object class probabilities over instance labels.
+///
+/// # Description
+///
+/// Upsamples the object class probabilities to the image with nearest
+/// neighbor interpolation and averages them over the pixels of each label,
+/// matching the reference StarDist implementation. The class of a label is
+/// the class with the highest mean probability.
+///
+/// # Arguments
+///
+/// * `labels`: The instance segmentation label image.
+/// * `class_prob`: The object class probabilities as a flat 1D array.
+/// * `pad_shape`: The padded image shape.
+/// * `config`: The model configuration, providing the class count and grid.
+/// * `label_scale`: The (row, col) scale of the label image relative to the
+///   network input image.
+///
+/// # Returns
+///
+/// * `StarDist2DClasses`: The labels, class image and object classes.
+fn labels_to_classes(
+    labels: Array2<u64>,
+    class_prob: Vec<f32>,
+    pad_shape: &[usize],
+    config: &StarDist2DConfig,
+    label_scale: Option<(f32, f32)>,
+) -> StarDist2DClasses {
+    let n_classes = config.n_classes().unwrap_or_default() + 1;
+    let (grid_row, grid_col) = config.grid();
+    let res_shape = (pad_shape[0] / grid_row, pad_shape[1] / grid_col, n_classes);
+    let class_arr = Array3::from_shape_vec(res_shape, class_prob)
+        .expect("StarDist 2D object class probabilities reshape failed.");
+    // the grid cell of a label pixel, through its network input image pixel
+    let (s_row, s_col) = label_scale.unwrap_or((1.0, 1.0));
+    let cell = |i: usize, scale: f32, grid: usize, res: usize| {
+        let src = ((i as f32 + 0.5) / scale).floor() as usize;
+        (src / grid).min(res - 1)
+    };
+    // sum the class probabilities and pixel count of each label
+    let mut sums: BTreeMap<u64, (Vec<f32>, usize)> = BTreeMap::new();
+    labels.indexed_iter().for_each(|((r, c), &l)| {
+        if l == 0 {
+            return;
+        }
+        let (sum, count) = sums.entry(l).or_insert((vec![0.0; n_classes], 0));
+        sum.iter_mut()
+            .zip(class_arr.slice(s![
+                cell(r, s_row, grid_row, res_shape.0),
+                cell(c, s_col, grid_col, res_shape.1),
+                ..
+            ]))
+            .for_each(|(s, &p)| *s += p);
+        *count += 1;
+    });
+    let class_probs: BTreeMap<u64, Vec<f32>> = sums
+        .into_iter()
+        .map(|(l, (sum, count))| (l, sum.into_iter().map(|s| s / count as f32).collect()))
+        .collect();
+    let classes: BTreeMap<u64, usize> = class_probs
+        .iter()
+        .map(|(&l, probs)| {
+            let class = probs
+                .iter()
+                .enumerate()
+                .fold(
+                    (0, f32::MIN),
+                    |max, (i, &p)| if p > max.1 { (i, p) } else { max },
+                )
+                .0;
+            (l, class)
+        })
+        .collect();
+    let class_image = labels.mapv(|l| classes.get(&l).copied().unwrap_or(0));
+    StarDist2DClasses {
+        labels,
+        class_image,
+        classes,
+        class_probs,
+    }
+}
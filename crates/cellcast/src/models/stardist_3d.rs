@@ -1,21 +1,29 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use burn::prelude::*;
 use imgal::image::percentile_normalize;
 use imgal::prelude::*;
-use imgal::threshold::manual::manual_mask;
 use imgal::transform::pad::reflect_pad;
-use ndarray::{Array1, Array2, Array3, Array4, ArrayBase, AsArray, Axis, Ix3, ViewRepr};
+use ndarray::{Array1, Array3, ArrayBase, AsArray, Axis, Ix3, ViewRepr};
 
 use crate::CellcastError;
 use crate::config::backend::{CpuBackend, GpuBackend};
+use crate::config::bioimageio::{self, PackageTest};
+use crate::config::stardist;
+use crate::config::weights::DEMO_3D_URL;
 use crate::labeling::distance_polyhedron_to_label;
-use crate::networks::stardist::fluo_3d;
-use crate::process::nms::polyhedron_nms;
-use crate::utils::{axes, border};
+use crate::models::stardist_2d::{INPUT_SCALE_ERR, OUTPUT_SCALE_ERR, resize_input, resolve_scale};
+use crate::networks::stardist::config::{StarDistBackbone, StarDistNetworkConfig};
+use crate::networks::stardist::metadata::StarDistMetadata;
+use crate::networks::stardist::{Network3d, StarDistWeights, onnx};
+use crate::process::candidates::{GridCandidates, gather_candidates};
+use crate::process::filter::{ObjectFilter, polyhedron_filter};
+use crate::process::nms::{NmsAccuracy, NmsMode, NmsOptions, polyhedron_nms, polyhedron_soft_nms};
+use crate::utils::{axes, fetch};
 
 const DIV: usize = 16;
 const N_RAYS: usize = 96;
+const GRID: (usize, usize, usize) = (1, 2, 2);
 const PMIN: f64 = 1.0;
 const PMAX: f64 = 99.8;
 const PROB_THRESHOLD: f64 = 0.7079326182611463;
@@ -30,8 +38,112 @@ type GpuConfigBackend = GpuBackend<f32, i32>;
 /// initialized on the CPU or GPU.
 #[derive(Debug)]
 enum StarDist3DModels {
-    FluoCpu(fluo_3d::Model<CpuConfigBackend>),
-    FluoGpu(fluo_3d::Model<GpuConfigBackend>),
+    FluoCpu(Network3d<CpuConfigBackend>),
+    FluoGpu(Network3d<GpuConfigBackend>),
+}
+
+/// Configuration of a StarDist3D model.
+///
+/// Describes the StarDist3D network variant that a set of weights was trained
+/// with. The ray count sets the number of radial distances predicted for each
+/// voxel (distributed on a golden spiral) and the grid sets the subsampling
+/// factor of the network output relative to the input image. Other
+/// architectural variations (*e.g.* U-Net or ResNet backbones) are set with a
+/// full network configuration. The default configuration matches the published
+/// 3D demo model (*i.e.* 96 rays and a grid of `(1, 2, 2)`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StarDist3DConfig {
+    network: StarDistNetworkConfig,
+}
+
+impl StarDist3DConfig {
+    /// Create a new StarDist3D model configuration.
+    ///
+    /// # Arguments
+    ///
+    /// * `n_rays`: The number of radial distances (rays) predicted per voxel.
+    ///   If `None` then `n_rays = 96`.
+    /// * `grid`: The `(pln, row, col)` subsampling factors of the network
+    ///   output, each a power of 2. If `None` then `grid = (1, 2, 2)`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(StarDist3DConfig)`: The model configuration.
+    /// * `Err(CellcastError)`: If `n_rays < 4`. If a `grid` factor is not a
+    ///   power of 2.
+    pub fn new(
+        n_rays: Option<usize>,
+        grid: Option<(usize, usize, usize)>,
+    ) -> Result<Self, CellcastError> {
+        let grid = grid.unwrap_or(GRID);
+        Self::from_network(StarDistNetworkConfig {
+            n_rays: n_rays.unwrap_or(N_RAYS),
+            grid: vec![grid.0, grid.1, grid.2],
+            ..StarDistNetworkConfig::fluo_3d()
+        })
+    }
+
+    /// Create a new StarDist3D model configuration from a network
+    /// configuration.
+    ///
+    /// # Arguments
+    ///
+    /// * `network`: The StarDist3D network configuration, with a single input
+    ///   channel.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(StarDist3DConfig)`: The model configuration.
+    /// * `Err(CellcastError)`: If `network` is invalid, is not 3D or does not
+    ///   have a single input channel.
+    pub fn from_network(network: StarDistNetworkConfig) -> Result<Self, CellcastError> {
+        network.validate()?;
+        if network.n_dim != 3 || network.n_channel_in != 1 {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "StarDist3D models require a single channel 3D network configuration.",
+            }));
+        }
+        Ok(Self { network })
+    }
+
+    /// Get the number of rays.
+    pub fn n_rays(&self) -> usize {
+        self.network.n_rays
+    }
+
+    /// Get the `(pln, row, col)` grid subsampling factors.
+    pub fn grid(&self) -> (usize, usize, usize) {
+        let grid = &self.network.grid;
+        (grid[0], grid[1], grid[2])
+    }
+
+    /// Get the network configuration.
+    pub fn network(&self) -> &StarDistNetworkConfig {
+        &self.network
+    }
+
+    /// Get the `(pln, row, col)` values the input image shape is padded to be
+    /// divisible by.
+    ///
+    /// For a ResNet backbone the planes axis is padded to a multiple of its
+    /// grid factor, the row and col axes to a multiple of 16 (or their grid
+    /// factor if larger). For a U-Net backbone each axis is padded as required
+    /// by the U-Net pooling.
+    pub fn div(&self) -> (usize, usize, usize) {
+        let div = self.network.div();
+        match self.network.backbone {
+            StarDistBackbone::Resnet => (div[0], DIV.max(div[1]), DIV.max(div[2])),
+            StarDistBackbone::Unet => (div[0], div[1], div[2]),
+        }
+    }
+}
+
+impl Default for StarDist3DConfig {
+    fn default() -> Self {
+        Self {
+            network: StarDistNetworkConfig::fluo_3d(),
+        }
+    }
 }
 
 /// A StarDist3D instance segmentation model.
@@ -43,6 +155,16 @@ enum StarDist3DModels {
 pub struct StarDist3D {
     model: StarDist3DModels,
     anisotropy: [f32; 3],
+    config: StarDist3DConfig,
+    prob_threshold: f64,
+    nms_threshold: f64,
+    pmin: f64,
+    pmax: f64,
+    filter: ObjectFilter,
+    nms: NmsOptions,
+    nms_accuracy: NmsAccuracy,
+    input_scale: Option<[f32; 3]>,
+    output_scale: Option<[f32; 3]>,
     gpu: bool,
 }
 
@@ -60,8 +182,15 @@ impl StarDist3D {
     ///
     /// * `weights_path`: The path to custom StarDist3D weights in burnpack (`.bpk`)
     ///   format. If `None` then the versatile fluo pretrained weights are used.
+    ///   The StarDist metadata stored with custom weights (if any) sets the
+    ///   default configuration, anisotropy, thresholds and normalization
+    ///   percentiles.
     /// * `anisotropy`: The anisotropy the model was trained with for all three
-    ///   axes. If `None` then anisotropy of `[2.0, 1.0, 1.0]` is used.
+    ///   axes. If `None` then the anisotropy of the weights metadata or
+    ///   `[2.0, 1.0, 1.0]` is used.
+    /// * `config`: The model configuration (*e.g.* ray count and grid) of the
+    ///   custom weights. If `None` then the configuration of the weights
+    ///   metadata or the default configuration is used.
     /// * `gpu`: If `true`, the configured GPU backend is used. If `false` then the
     ///   configured CPU backend is used.
     ///
@@ -69,48 +198,418 @@ impl StarDist3D {
     ///
     /// * `Ok(StarDist2D)`: An initialized StarDist3D fluo model.
     /// * `Err(CellcastError)`: If the requested model can not be initialized. If
-    ///   `anisotropy.len() != 3`.
+    ///   `anisotropy.len() != 3`. If a non-default `config` is given without
+    ///   custom weights. If the weights metadata is invalid or describes a
+    ///   StarDist2D model. If `config` does not match the network of the
+    ///   weights metadata.
     pub fn init_fluo(
         weights_path: Option<&str>,
         anisotropy: Option<&[f32]>,
+        config: Option<StarDist3DConfig>,
         gpu: bool,
     ) -> Result<Self, CellcastError> {
-        let weights_path = weights_path.map(PathBuf::from);
-        let anisotropy = anisotropy.unwrap_or(&[2.0, 1.0, 1.0]);
-        if anisotropy.len() != 3 {
-            return Err(ImgalError::InvalidArrayLengthExpected {
-                arr_name: "anisotropy",
-                expected: 3,
-                got: anisotropy.len(),
-            })
-            .map_err(CellcastError::Imgal);
+        let metadata = match weights_path {
+            Some(wp) => StarDistMetadata::from_file(wp)?,
+            None => None,
+        };
+        if metadata.as_ref().is_some_and(|m| m.network.n_dim != 3) {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "The weights metadata does not describe a StarDist3D model.",
+            }));
         }
-        let anisotropy = [anisotropy[0], anisotropy[1], anisotropy[2]];
-        if gpu {
-            let device = Default::default();
-            let sd = Self {
-                model: StarDist3DModels::FluoGpu(fluo_3d::Model::<GpuConfigBackend>::init(
-                    &device,
-                    weights_path.clone(),
-                )),
-                anisotropy,
-                gpu,
-            };
-            sd.warm_up_fluo()?;
-            Ok(sd)
-        } else {
-            let device = Default::default();
-            let sd = Self {
-                model: StarDist3DModels::FluoCpu(fluo_3d::Model::<CpuConfigBackend>::init(
-                    &device,
-                    weights_path.clone(),
-                )),
-                anisotropy,
-                gpu,
-            };
-            sd.warm_up_fluo()?;
-            Ok(sd)
+        let anisotropy = match (anisotropy, metadata.as_ref().and_then(|m| m.anisotropy)) {
+            (None, Some(anisotropy)) => anisotropy,
+            (anisotropy, _) => resolve_anisotropy(anisotropy)?,
+        };
+        let config = match (config, &metadata) {
+            (Some(config), _) => config,
+            (None, Some(m)) => StarDist3DConfig::from_network(m.network.clone())?,
+            (None, None) => StarDist3DConfig::default(),
+        };
+        if metadata
+            .as_ref()
+            .is_some_and(|m| &m.network != config.network())
+        {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "The StarDist3D configuration does not match the network of the weights metadata.",
+            }));
+        }
+        let weights_path = match weights_path {
+            Some(wp) => PathBuf::from(wp),
+            None => {
+                if config != StarDist3DConfig::default() {
+                    return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                        msg: "A custom StarDist3D configuration requires custom weights.",
+                    }));
+                }
+                fetch::fetch_weights(DEMO_3D_URL, false)
+                    .expect("Failed to download the stardist_3d_demo weights.")
+            }
+        };
+        let mut sd = Self::init(
+            StarDistWeights::Burnpack(&weights_path),
+            config,
+            anisotropy,
+            PROB_THRESHOLD,
+            NMS_THRESHOLD,
+            gpu,
+        )?;
+        if let Some(metadata) = metadata {
+            sd.apply_metadata(&metadata);
+        }
+        Ok(sd)
+    }
+
+    /// Initialize a StarDist3D model from a StarDist model folder.
+    ///
+    /// # Description
+    ///
+    /// Initializes a StarDist3D model from a model folder exported by the
+    /// reference StarDist, with the weights converted to burnpack (`.bpk`)
+    /// format. The ray count, grid, network architecture and anisotropy are
+    /// read from `config.json` and the default probability and NMS thresholds
+    /// from `thresholds.json` (if present). The model is used with
+    /// `predict_fluo`.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: The path to the model folder containing `config.json`,
+    ///   `thresholds.json` and the weights as `weights_best.bpk`,
+    ///   `weights_last.bpk` or a single `.bpk` file.
+    /// * `gpu`: If `true`, the configured GPU backend is used. If `false` then the
+    ///   configured CPU backend is used.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(StarDist3D)`: An initialized StarDist3D model.
+    /// * `Err(CellcastError)`: If the model folder can not be read. If the model
+    ///   is not a single channel 3D model. If the requested model can not be
+    ///   initialized.
+    pub fn init_from_folder(path: &str, gpu: bool) -> Result<Self, CellcastError> {
+        let folder = stardist::read_model_folder(Path::new(path), 3)?;
+        let config = StarDist3DConfig::from_network(folder.network)?;
+        Self::init(
+            StarDistWeights::Burnpack(&folder.weights_path),
+            config,
+            folder.anisotropy.unwrap_or([1.0; 3]),
+            folder.prob_threshold.unwrap_or(PROB_THRESHOLD),
+            folder.nms_threshold.unwrap_or(NMS_THRESHOLD),
+            gpu,
+        )
+    }
+
+    /// Initialize a StarDist3D model from an ONNX file.
+    ///
+    /// # Description
+    ///
+    /// Initializes a StarDist3D model from a StarDist3D network exported to
+    /// ONNX, loaded at runtime. The ONNX model must have a single image input
+    /// and the object probability and ray distance outputs of a StarDist
+    /// network. The ray count is read from the distance output head and,
+    /// unless `config` is given, the grid and network architecture are
+    /// inferred from the graph. The model is used with `predict_fluo`.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: The path to the ONNX (`.onnx`) file.
+    /// * `anisotropy`: The anisotropy the model was trained with for all three
+    ///   axes. If `None` then anisotropy of `[2.0, 1.0, 1.0]` is used.
+    /// * `config`: The model configuration (*e.g.* grid and network
+    ///   architecture) of the ONNX model. If `None` then the configuration is
+    ///   inferred from the ONNX graph.
+    /// * `gpu`: If `true`, the configured GPU backend is used. If `false` then the
+    ///   configured CPU backend is used.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(StarDist3D)`: An initialized StarDist3D model.
+    /// * `Err(CellcastError)`: If the ONNX file can not be read or is not a
+    ///   StarDist3D model. If the weights do not match the configuration. If
+    ///   `anisotropy.len() != 3`.
+    pub fn init_from_onnx(
+        path: &str,
+        anisotropy: Option<&[f32]>,
+        config: Option<StarDist3DConfig>,
+        gpu: bool,
+    ) -> Result<Self, CellcastError> {
+        let anisotropy = resolve_anisotropy(anisotropy)?;
+        let onnx = onnx::read_onnx(Path::new(path), 3, config.map(|c| c.network))?;
+        let config = StarDist3DConfig::from_network(onnx.network)?;
+        if config.network().n_channel_in != 1 {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "StarDist3D models require a single input channel.",
+            }));
         }
+        Self::init(
+            StarDistWeights::Convs(&onnx.convs),
+            config,
+            anisotropy,
+            PROB_THRESHOLD,
+            NMS_THRESHOLD,
+            gpu,
+        )
+    }
+
+    /// Initialize a StarDist3D model from a bioimage.io model package.
+    ///
+    /// # Description
+    ///
+    /// Initializes a StarDist3D model from a bioimage.io model zoo package
+    /// exported by the reference StarDist, *i.e.* a zip file with an
+    /// `rdf.yaml` resource description. The ray count, grid, network
+    /// architecture, anisotropy and default thresholds are read from the
+    /// StarDist configuration of the package, the default normalization
+    /// percentiles from the `scale_range` preprocessing, and the weights from
+    /// the `keras_hdf5` or `onnx` weights of the package. The model is used
+    /// with `predict_fluo`.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: The path to the model package zip file or unpacked folder.
+    /// * `validate`: If `true`, the import is validated by running the model
+    ///   on the package test input and comparing the object probabilities and
+    ///   ray distances with the package test output.
+    /// * `gpu`: If `true`, the configured GPU backend is used. If `false` then the
+    ///   configured CPU backend is used.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(StarDist3D)`: An initialized StarDist3D model.
+    /// * `Err(CellcastError)`: If the package can not be read or does not
+    ///   describe a single channel StarDist3D model. If the weights do not
+    ///   match the configuration. If `validate` is `true` and the package has
+    ///   no test tensors or the model output does not match the test output.
+    pub fn init_from_bioimageio(
+        path: &str,
+        validate: bool,
+        gpu: bool,
+    ) -> Result<Self, CellcastError> {
+        let package = bioimageio::read_package(Path::new(path), 3)?;
+        let config = StarDist3DConfig::from_network(package.network)?;
+        if config.network().n_channel_in != 1 {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "StarDist3D models require a single input channel.",
+            }));
+        }
+        let mut sd = Self::init(
+            StarDistWeights::Convs(&package.convs),
+            config,
+            package.anisotropy.unwrap_or([1.0; 3]),
+            package.prob_threshold.unwrap_or(PROB_THRESHOLD),
+            package.nms_threshold.unwrap_or(NMS_THRESHOLD),
+            gpu,
+        )?;
+        (sd.pmin, sd.pmax) = package.percentiles.unwrap_or((PMIN, PMAX));
+        if validate {
+            let test = package.test.ok_or(ImgalError::InvalidGeneric {
+                msg: "The bioimage.io model package has no test input and output.",
+            })?;
+            sd.validate(&test)?;
+        }
+        Ok(sd)
+    }
+
+    /// Initialize a StarDist3D model from a source of weights.
+    ///
+    /// # Arguments
+    ///
+    /// * `weights`: The source of the StarDist3D weights.
+    /// * `config`: The model configuration of the weights.
+    /// * `anisotropy`: The anisotropy the model was trained with.
+    /// * `prob_threshold`: The default object probability threshold.
+    /// * `nms_threshold`: The default non-maximum suppression threshold.
+    /// * `gpu`: If `true`, the configured GPU backend is used.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(StarDist3D)`: An initialized and pre-warmed StarDist3D model.
+    /// * `Err(CellcastError)`: If the requested model can not be initialized.
+    fn init(
+        weights: StarDistWeights,
+        config: StarDist3DConfig,
+        anisotropy: [f32; 3],
+        prob_threshold: f64,
+        nms_threshold: f64,
+        gpu: bool,
+    ) -> Result<Self, CellcastError> {
+        let model = if gpu {
+            StarDist3DModels::FluoGpu(Network3d::from_weights(
+                weights,
+                &Default::default(),
+                config.network(),
+            )?)
+        } else {
+            StarDist3DModels::FluoCpu(Network3d::from_weights(
+                weights,
+                &Default::default(),
+                config.network(),
+            )?)
+        };
+        let sd = Self {
+            model,
+            anisotropy,
+            config,
+            prob_threshold,
+            nms_threshold,
+            pmin: PMIN,
+            pmax: PMAX,
+            filter: ObjectFilter::default(),
+            nms: NmsOptions::default(),
+            nms_accuracy: NmsAccuracy::default(),
+            input_scale: None,
+            output_scale: None,
+            gpu,
+        };
+        sd.warm_up_fluo()?;
+        Ok(sd)
+    }
+
+    /// Apply the default thresholds and normalization percentiles of the
+    /// weights metadata.
+    fn apply_metadata(&mut self, metadata: &StarDistMetadata) {
+        self.prob_threshold = metadata.prob_threshold.unwrap_or(self.prob_threshold);
+        self.nms_threshold = metadata.nms_threshold.unwrap_or(self.nms_threshold);
+        (self.pmin, self.pmax) = metadata.percentiles.unwrap_or((self.pmin, self.pmax));
+    }
+
+    /// Get the model configuration.
+    pub fn config(&self) -> &StarDist3DConfig {
+        &self.config
+    }
+
+    /// Get the anisotropy the model was trained with.
+    pub fn anisotropy(&self) -> [f32; 3] {
+        self.anisotropy
+    }
+
+    /// Get the default `(prob, nms)` thresholds of the model.
+    pub fn thresholds(&self) -> (f64, f64) {
+        (self.prob_threshold, self.nms_threshold)
+    }
+
+    /// Get the default `(pmin, pmax)` normalization percentiles of the model.
+    pub fn percentiles(&self) -> (f64, f64) {
+        (self.pmin, self.pmax)
+    }
+
+    /// Set the filter applied to the predicted objects.
+    ///
+    /// # Arguments
+    ///
+    /// * `filter`: The object filter, removing objects crossing the image
+    ///   border and/or outside of a volume range before the objects are
+    ///   labeled. By default no objects are removed.
+    pub fn with_object_filter(mut self, filter: ObjectFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Get the filter applied to the predicted objects.
+    pub fn object_filter(&self) -> &ObjectFilter {
+        &self.filter
+    }
+
+    /// Set the non-maximum suppression options.
+    ///
+    /// # Arguments
+    ///
+    /// * `nms`: The overlap criterion and suppression mode (*i.e.* hard or
+    ///   soft-NMS) of the non-maximum suppression. By default overlapping
+    ///   polyhedra are removed based on the intersection over the smaller
+    ///   polyhedron.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(StarDist3D)`: The model with the non-maximum suppression options.
+    /// * `Err(CellcastError)`: If the Gaussian soft-NMS `sigma` is not
+    ///   positive and finite.
+    pub fn with_nms_options(mut self, nms: NmsOptions) -> Result<Self, CellcastError> {
+        nms.validate()?;
+        self.nms = nms;
+        Ok(self)
+    }
+
+    /// Get the non-maximum suppression options.
+    pub fn nms_options(&self) -> &NmsOptions {
+        &self.nms
+    }
+
+    /// Set the accuracy of the polyhedron intersection volumes of the
+    /// non-maximum suppression.
+    ///
+    /// # Arguments
+    ///
+    /// * `accuracy`: The accuracy of the polyhedron intersection volumes. The
+    ///   cheaper accuracies trade the NMS accuracy for speed, *e.g.* for
+    ///   screening. By default the exact intersection volumes are used.
+    pub fn with_nms_accuracy(mut self, accuracy: NmsAccuracy) -> Self {
+        self.nms_accuracy = accuracy;
+        self
+    }
+
+    /// Get the accuracy of the polyhedron intersection volumes of the
+    /// non-maximum suppression.
+    pub fn nms_accuracy(&self) -> NmsAccuracy {
+        self.nms_accuracy
+    }
+
+    /// Set the scale of the input volumes.
+    ///
+    /// # Description
+    ///
+    /// The input volumes are resized with linear interpolation before
+    /// normalization and padding, matching the object size of the volumes to
+    /// the object size of the training data. The object centers and rays are
+    /// mapped back to the input volume, the labels match the input volume grid
+    /// (see `with_output_scale`).
+    ///
+    /// # Arguments
+    ///
+    /// * `scale`: The (pln, row, col) scale of the network input relative to
+    ///   the input volume, *e.g.* `[1.0, 0.5, 0.5]` for nuclei twice as wide
+    ///   as the nuclei of the training data. The input volumes are resized to
+    ///   shape `round(scale * input shape)`. By default the input volumes are
+    ///   not resized.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(StarDist3D)`: The model with the input scale.
+    /// * `Err(CellcastError)`: If a scale is not positive and finite.
+    pub fn with_input_scale(mut self, scale: [f32; 3]) -> Result<Self, CellcastError> {
+        self.input_scale = resolve_scale(scale, INPUT_SCALE_ERR)?;
+        Ok(self)
+    }
+
+    /// Get the (pln, row, col) scale of the network input relative to the
+    /// input volume.
+    pub fn input_scale(&self) -> [f32; 3] {
+        self.input_scale.unwrap_or([1.0; 3])
+    }
+
+    /// Set the resolution of the output label volumes.
+    ///
+    /// # Arguments
+    ///
+    /// * `scale`: The (pln, row, col) scale of the label volumes relative to
+    ///   the input volume. The object centers and rays are scaled and the
+    ///   labels are rendered into a volume of shape `round(scale * input
+    ///   shape)`, *e.g.* `[2.0, 1.0, 1.0]` renders the objects of a volume with
+    ///   a plane spacing of twice the pixel size on an isotropic grid. By
+    ///   default the labels have the input resolution.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(StarDist3D)`: The model with the output label resolution.
+    /// * `Err(CellcastError)`: If a scale is not positive and finite.
+    pub fn with_output_scale(mut self, scale: [f32; 3]) -> Result<Self, CellcastError> {
+        self.output_scale = resolve_scale(scale, OUTPUT_SCALE_ERR)?;
+        Ok(self)
+    }
+
+    /// Get the (pln, row, col) scale of the output label volumes relative to
+    /// the input volume.
+    pub fn output_scale(&self) -> [f32; 3] {
+        self.output_scale.unwrap_or([1.0; 3])
     }
 
     /// Predict instance segmentation labels with the StarDist3D fluo model.
@@ -124,13 +623,17 @@ impl StarDist3D {
     ///
     /// * `data`: The input 3D image.
     /// * `pmin`: The minimum percentage to linear percentile normalize the input
-    ///   image. If `None`, then `pmin = 1.0`.
+    ///   image. If `None`, then the model's default percentile is used (*i.e.*
+    ///   `pmin = 1.0` for the pretrained weights).
     /// * `pmax`: The maximum percentage to linear percentile normalize the input
-    ///   image. If `None`, then `pmax = 99.8`.
+    ///   image. If `None`, then the model's default percentile is used (*i.e.*
+    ///   `pmax = 99.8` for the pretrained weights).
     /// * `prob_threshold`: The object/polyhedron probability threshold. If `None`,
-    ///   then `prob_threshold == 0.7079326182611463`.
+    ///   then the model's default threshold is used (*i.e.*
+    ///   `prob_threshold == 0.7079326182611463` for the pretrained weights).
     /// * `nms_threshold`: The non-maximum suppression (NMS) threshold. If `None`,
-    ///   then `nms_threshold == 0.3`.
+    ///   then the model's default threshold is used (*i.e.*
+    ///   `nms_threshold == 0.3` for the pretrained weights).
     /// * `axis`: The `pln` or `z` axis. If `None` then `axis == 0`.
     ///
     /// # Returns
@@ -158,36 +661,48 @@ impl StarDist3D {
     {
         let axis = axis.unwrap_or(0);
         if axis >= 3 {
-            return Err(ImgalError::InvalidAxis {
+            return Err(CellcastError::Imgal(ImgalError::InvalidAxis {
                 axis_idx: axis,
                 dim_len: 3,
-            })
-            .map_err(CellcastError::Imgal);
+            }));
         }
         let data: ArrayBase<ViewRepr<&'a T>, Ix3> = data.into();
-        let pmin = pmin.unwrap_or(PMIN);
-        let pmax = pmax.unwrap_or(PMAX);
-        let prob_threshold = prob_threshold.unwrap_or(PROB_THRESHOLD) as f32;
-        let nms_threshold = nms_threshold.unwrap_or(NMS_THRESHOLD) as f32;
-        let norm = percentile_normalize(&data, pmin, pmax, false, None, None, None)?;
-        let norm = norm.mapv(|v| v as f32);
-        let [src_row, src_col] = {
-            let mut shape = data.shape().to_vec();
-            shape.remove(axis);
-            [shape[0], shape[1]]
+        let pmin = pmin.unwrap_or(self.pmin);
+        let pmax = pmax.unwrap_or(self.pmax);
+        let prob_threshold = prob_threshold.unwrap_or(self.prob_threshold) as f32;
+        let nms_threshold = nms_threshold.unwrap_or(self.nms_threshold) as f32;
+        // the (pln, row, col) shape of a volume with the planes axis at "axis"
+        let spatial_shape = |shape: &[usize]| {
+            let mut shape = shape.to_vec();
+            let pln = shape.remove(axis);
+            [pln, shape[0], shape[1]]
         };
+        let image_shape = spatial_shape(data.shape());
+        let resized = self.input_scale.map(|scale| {
+            let mut axis_scale = vec![scale[1], scale[2]];
+            axis_scale.insert(axis, scale[0]);
+            resize_input(data.view(), &axis_scale, None)
+        });
+        let norm = match &resized {
+            Some(resized) => percentile_normalize(resized, pmin, pmax, false, None, None, None)?,
+            None => percentile_normalize(&data, pmin, pmax, false, None, None, None)?,
+        };
+        let norm = norm.mapv(|v| v as f32);
+        let src_shape = spatial_shape(norm.shape());
         // this pattern determines how many pixels to pad in each axis to be
-        // divisible by 16 as expected by the network, except for the planes (z)
-        // axis which remains fixed (i.e. an asymmetrical pad)
-        let pad_config: Vec<usize> = data
+        // divisible as expected by the network, the planes (z) axis is only
+        // padded to its grid factor (i.e. an asymmetrical pad)
+        let (div_pln, div_row, div_col) = self.config.div();
+        let mut spatial_div = [div_row, div_col].into_iter();
+        let pad_config: Vec<usize> = norm
             .shape()
             .iter()
             .enumerate()
             .map(|(i, &v)| {
                 if i == axis {
-                    0
+                    axes::divisible_pad(v, div_pln)
                 } else {
-                    axes::divisible_pad(v, DIV)
+                    axes::divisible_pad(v, spatial_div.next().unwrap())
                 }
             })
             .collect();
@@ -196,67 +711,31 @@ impl StarDist3D {
         let plns = pad_shape.remove(axis);
         let (raw_data, _) = norm_pad.into_raw_vec_and_offset();
         let td = TensorData::new(raw_data, [1, 1, plns, pad_shape[0], pad_shape[1]]);
-        let prob: Vec<f32>;
-        let dist: Vec<f32>;
-        if self.gpu {
-            match &self.model {
-                StarDist3DModels::FluoGpu(m) => {
-                    let device = Default::default();
-                    let tensor = Tensor::<GpuConfigBackend, 5>::from_data(td, &device);
-                    let (p, d) = m.forward(
-                        tensor,
-                        (plns as i32, pad_shape[0] as i32, pad_shape[1] as i32),
-                    );
-                    prob = p
-                        .into_data()
-                        .into_vec()
-                        .expect("Failed to copy StarDist3D probabiliates from the output tensor.");
-                    dist = d
-                        .into_data()
-                        .into_vec()
-                        .expect("Failed to copy StarDist3D distances from the output tensor.");
-                }
-                _ => {
-                    return Err(ImgalError::InvalidGeneric {
-                        msg: "No initialized StarDist3D Fluo GPU model found.",
-                    })
-                    .map_err(CellcastError::Imgal);
-                }
+        let candidates = match &self.model {
+            StarDist3DModels::FluoGpu(m) if self.gpu => {
+                run_network_candidates(m, td, prob_threshold)
             }
-        } else {
-            match &self.model {
-                StarDist3DModels::FluoCpu(m) => {
-                    let device = Default::default();
-                    let tensor = Tensor::<CpuConfigBackend, 5>::from_data(td, &device);
-                    let (p, d) = m.forward(
-                        tensor,
-                        (plns as i32, pad_shape[0] as i32, pad_shape[1] as i32),
-                    );
-                    prob = p
-                        .into_data()
-                        .into_vec()
-                        .expect("Failed to copy StarDist3D probabiliates from the output tensor.");
-                    dist = d
-                        .into_data()
-                        .into_vec()
-                        .expect("Failed to copy StarDist3D distances from the output tensor.");
-                }
-                _ => {
-                    return Err(ImgalError::InvalidGeneric {
-                        msg: "No initialized StarDist3D Fluo CPU model found.",
-                    })
-                    .map_err(CellcastError::Imgal);
-                }
+            StarDist3DModels::FluoCpu(m) if !self.gpu => {
+                run_network_candidates(m, td, prob_threshold)
             }
-        }
+            _ => {
+                return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                    msg: "No initialized StarDist3D Fluo model found.",
+                }));
+            }
+        };
         prob_dist_to_labels_3d(
-            prob,
-            dist,
+            candidates,
             prob_threshold,
             nms_threshold,
             self.anisotropy,
-            pad_shape,
-            [plns, src_row, src_col],
+            src_shape,
+            image_shape,
+            &self.config,
+            &self.nms,
+            self.nms_accuracy,
+            &self.filter,
+            self.output_scale,
         )
         .map_err(CellcastError::Imgal)
     }
@@ -274,122 +753,178 @@ impl StarDist3D {
     /// * `Ok(())`: If successful.
     /// * `Err(CellcastError)`: If the requested model can not be initialized.
     fn warm_up_fluo(&self) -> Result<(), CellcastError> {
-        let zeros = vec![0.0; 131072];
-        let td = TensorData::new(zeros, [1, 1, 32, 64, 64]);
-        if self.gpu {
-            match &self.model {
-                StarDist3DModels::FluoGpu(m) => {
-                    let device = Default::default();
-                    let tensor = Tensor::<GpuConfigBackend, 5>::from_data(td, &device);
-                    let (p, d) = m.forward(tensor, (32, 64, 64));
-                    let _ = p.into_data();
-                    let _ = d.into_data();
-                    Ok(())
-                }
-                _ => {
-                    return Err(ImgalError::InvalidGeneric {
-                        msg: "No initialized StarDist3D Fluo GPU model found.",
-                    })
-                    .map_err(CellcastError::Imgal);
-                }
-            }
-        } else {
-            match &self.model {
-                StarDist3DModels::FluoCpu(m) => {
-                    let device = Default::default();
-                    let tensor = Tensor::<CpuConfigBackend, 5>::from_data(td, &device);
-                    let (p, d) = m.forward(tensor, (32, 64, 64));
-                    let _ = p.into_data();
-                    let _ = d.into_data();
-                    Ok(())
-                }
-                _ => {
-                    return Err(ImgalError::InvalidGeneric {
-                        msg: "No initialized StarDist2D Fluo CPU model found.",
-                    })
-                    .map_err(CellcastError::Imgal);
-                }
-            }
+        let (div_pln, div_row, div_col) = self.config.div();
+        let shape = [1, 1, 32.max(div_pln), 64.max(div_row), 64.max(div_col)];
+        let zeros = vec![0.0_f32; shape.iter().product()];
+        let td = TensorData::new(zeros, shape);
+        let (prob, dist) = match &self.model {
+            StarDist3DModels::FluoGpu(m) => run_network(m, td),
+            StarDist3DModels::FluoCpu(m) => run_network(m, td),
+        };
+        if prob.is_empty() || dist.is_empty() {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "Failed to warm up the StarDist3D model.",
+            }));
         }
+        Ok(())
+    }
+
+    /// Validate the StarDist3D model with the test tensors of a bioimage.io
+    /// model package.
+    ///
+    /// # Description
+    ///
+    /// Normalizes the `(pln, row, col, ch)` test input with the default
+    /// percentiles, runs the network and compares the object probabilities
+    /// and ray distances with the test output.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())`: If the model output matches the test output.
+    /// * `Err(CellcastError)`: If the test input does not match the model
+    ///   input. If the model output does not match the test output.
+    fn validate(&self, test: &PackageTest) -> Result<(), CellcastError> {
+        let shape = test.input.shape().to_vec();
+        if shape.len() != 4 || shape[3] != 1 {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "The bioimage.io model package test input does not match the model input.",
+            }));
+        }
+        let norm =
+            percentile_normalize(&test.input, self.pmin, self.pmax, false, None, None, None)?;
+        let (raw_data, _) = norm.mapv(|v| v as f32).into_raw_vec_and_offset();
+        let td = TensorData::new(raw_data, [1, 1, shape[0], shape[1], shape[2]]);
+        let (prob, dist) = match &self.model {
+            StarDist3DModels::FluoGpu(m) => run_network(m, td),
+            StarDist3DModels::FluoCpu(m) => run_network(m, td),
+        };
+        bioimageio::validate_outputs(&test.output, &prob, &dist, false)
     }
 }
 
+/// Resolve the anisotropy of a StarDist3D model.
+///
+/// # Returns
+///
+/// * `Ok([f32; 3])`: The anisotropy, `[2.0, 1.0, 1.0]` if `anisotropy` is
+///   `None`.
+/// * `Err(CellcastError)`: If `anisotropy.len() != 3`.
+pub(super) fn resolve_anisotropy(anisotropy: Option<&[f32]>) -> Result<[f32; 3], CellcastError> {
+    let anisotropy = anisotropy.unwrap_or(&[2.0, 1.0, 1.0]);
+    if anisotropy.len() != 3 {
+        return Err(CellcastError::Imgal(
+            ImgalError::InvalidArrayLengthExpected {
+                arr_name: "anisotropy",
+                expected: 3,
+                got: anisotropy.len(),
+            },
+        ));
+    }
+    Ok([anisotropy[0], anisotropy[1], anisotropy[2]])
+}
+
+/// Run the StarDist3D network on an input tensor.
+///
+/// # Returns
+///
+/// * `(Vec<f32>, Vec<f32>)`: The flat object probabilities and ray distances.
+fn run_network<B: Backend>(model: &Network3d<B>, td: TensorData) -> (Vec<f32>, Vec<f32>) {
+    let device = Default::default();
+    let tensor = Tensor::<B, 5>::from_data(td, &device);
+    let (p, d) = model.forward(tensor);
+    let prob = p
+        .into_data()
+        .into_vec()
+        .expect("Failed to copy StarDist3D probabiliates from the output tensor.");
+    let dist = d
+        .into_data()
+        .into_vec()
+        .expect("Failed to copy StarDist3D distances from the output tensor.");
+    (prob, dist)
+}
+
+/// Run the StarDist3D network on an input tensor and gather the object
+/// candidates above `prob_threshold` on the device.
+///
+/// # Returns
+///
+/// * `GridCandidates`: The object candidates at their output grid positions.
+fn run_network_candidates<B: Backend>(
+    model: &Network3d<B>,
+    td: TensorData,
+    prob_threshold: f32,
+) -> GridCandidates {
+    let device = Default::default();
+    let tensor = Tensor::<B, 5>::from_data(td, &device);
+    let (p, d) = model.forward(tensor);
+    // the probabilities are channels last and the distances channels first
+    let [_, res_pln, res_row, res_col, _] = p.dims();
+    let [_, n_rays, ..] = d.dims();
+    let n_out = res_pln * res_row * res_col;
+    gather_candidates(
+        p.reshape([res_pln, res_row, res_col]),
+        d.reshape([n_rays, n_out]).swap_dims(0, 1),
+        prob_threshold,
+        2,
+    )
+}
+
 /// Process StarDist3D object probabilities and ray distance arrays into
 /// instance segmentations.
 ///
 /// # Arguments
 ///
-/// * `prob`: The object probabilities as a flat 1D array.
-/// * `dist`: The ray distances as a flat 1D array.
+/// * `candidates`: The object candidates gathered from the network output.
 /// * `prob_threshold`: The object probability threshold.
 /// * `nms_threshold`: The non-maximum suppression threshold.
 /// * `anisotropy`: The anisotropy the model was trained with for all three
 ///   axes.
-/// * `pad_shape`: The padded image shape.
 /// * `src_shape`: The original/source image shape.
+/// * `image_shape`: The input volume shape before rescaling, the objects are
+///   mapped back to this shape.
+/// * `config`: The model configuration, providing the ray count and grid.
+/// * `nms`: The non-maximum suppression options.
+/// * `nms_accuracy`: The accuracy of the NMS polyhedron intersection volumes.
+/// * `filter`: The filter applied to the objects before labeling. The object
+///   volumes are in input volume voxels.
+/// * `output_scale`: The (pln, row, col) scale of the label volume relative to
+///   the input volume. If `None`, the labels have the input volume shape.
 ///
 /// # Returns
 ///
 /// * `Array2<u64>`: The instance segmentation label image.
-fn prob_dist_to_labels_3d(
-    prob: Vec<f32>,
-    dist: Vec<f32>,
+pub(super) fn prob_dist_to_labels_3d(
+    candidates: GridCandidates,
     prob_threshold: f32,
     nms_threshold: f32,
     anisotropy: [f32; 3],
-    pad_shape: Vec<usize>,
     src_shape: [usize; 3],
+    image_shape: [usize; 3],
+    config: &StarDist3DConfig,
+    nms: &NmsOptions,
+    nms_accuracy: NmsAccuracy,
+    filter: &ObjectFilter,
+    output_scale: Option<[f32; 3]>,
 ) -> Result<Array3<u64>, ImgalError> {
-    // create arrays from the flat StarDist network output
-    let res_row: usize = pad_shape[0] / 2;
-    let res_col: usize = pad_shape[1] / 2;
-    let prob_arr = Array3::from_shape_vec((src_shape[0], res_row, res_col), prob)
-        .expect("StarDist 3D object probabilites reshape failed.");
-    let dist_arr = Array4::from_shape_vec((N_RAYS, src_shape[0], res_row, res_col), dist)
-        .expect("StarDist 3D radial distances reshape failed.");
-    // ensure all values in the dist array are at least 1e-3, prevents negative and/or zero
-    // distances
-    let dist_arr = dist_arr.mapv(|v| v.max(1e-3));
-    let mut valid_mask = manual_mask(&prob_arr, prob_threshold, None);
-    border::clip_mask_border(&mut valid_mask.view_mut().into_dyn(), 2);
-    let valid_mask = valid_mask.into_dimensionality::<Ix3>().unwrap();
-    // collect all valid (pln, row, col) positions to avoid iterating the mask
-    // repeatedly
-    let valid_pnts: Vec<(usize, usize, usize)> = valid_mask
-        .indexed_iter()
-        .filter(|&((_, _, _), &v)| v)
-        .map(|((p, r, c), _)| (p, r, c))
-        .collect();
-    let flat_pnts = valid_pnts.iter().flat_map(|&(p, r, c)| [p, r, c]).collect();
-    let mut valid_pnts = Array2::from_shape_vec((valid_pnts.len(), 3), flat_pnts).unwrap();
-    // filter probabilities and distances with valid indices, removing invalid
-    // positions
-    let mut valid_prob = Array1::from_iter(
-        valid_pnts
-            .axis_iter(Axis(0))
-            .map(|v| prob_arr[[v[0], v[1], v[2]]]),
-    );
-    let mut valid_dist = Array2::<f32>::zeros((valid_pnts.dim().0, N_RAYS));
-    (0..N_RAYS).for_each(|n| {
-        valid_pnts
-            .axis_iter(Axis(0))
-            .enumerate()
-            .for_each(|(i, v)| {
-                valid_dist[[i, n]] = dist_arr[[n, v[0], v[1], v[2]]];
-            });
-    });
-    // scale each valid position by 2 and collect the valid indices of positions
-    // inside of the source image dimensions (used for point filtering)
+    let n_rays = config.n_rays();
+    let (grid_pln, grid_row, grid_col) = config.grid();
+    let grid = [grid_pln, grid_row, grid_col];
+    let GridCandidates {
+        pos: mut valid_pnts,
+        prob: mut valid_prob,
+        dist: mut valid_dist,
+    } = candidates;
+    // scale each valid position by the grid and collect the valid indices of
+    // positions inside of the source image dimensions (used for point filtering)
     let poly_ax = Axis(0);
     valid_pnts.axis_iter_mut(poly_ax).for_each(|mut v| {
-        v[1] = v[1] * 2;
-        v[2] = v[2] * 2;
+        (0..3).for_each(|d| v[d] *= grid[d]);
     });
     let valid_inds: Vec<usize> = valid_pnts
         .axis_iter(poly_ax)
         .enumerate()
         .filter_map(|(i, v)| {
-            if v[0] < src_shape[0] || v[1] < src_shape[1] || v[2] < src_shape[2] {
+            if v[0] < src_shape[0] && v[1] < src_shape[1] && v[2] < src_shape[2] {
                 Some(i)
             } else {
                 None
@@ -411,15 +946,36 @@ fn prob_dist_to_labels_3d(
     let poly_pnts = valid_pnts.select(poly_ax, &sorted_poly_inds);
     let poly_prob = valid_prob.select(poly_ax, &sorted_poly_inds);
     let poly_pnts = poly_pnts.mapv(|v| v as f32);
-    let valid_poly_inds = polyhedron_nms(
-        poly_dist.view(),
-        poly_pnts.view(),
-        anisotropy,
-        n_polys,
-        N_RAYS,
-        nms_threshold,
-    )
-    .unwrap();
+    let (poly_prob, valid_poly_inds) = if nms.mode == NmsMode::Hard {
+        let valid_poly_inds = polyhedron_nms(
+            poly_dist.view(),
+            poly_pnts.view(),
+            anisotropy,
+            n_polys,
+            n_rays,
+            nms_threshold,
+            nms.overlap,
+            nms_accuracy,
+        )
+        .unwrap();
+        (poly_prob, valid_poly_inds)
+    } else {
+        // soft-NMS decays the probabilities of overlapping polyhedra, which are
+        // then removed by the probability threshold
+        let poly_prob = polyhedron_soft_nms(
+            poly_dist.view(),
+            poly_pnts.view(),
+            poly_prob.view(),
+            anisotropy,
+            prob_threshold,
+            nms_threshold,
+            nms.overlap,
+            nms.mode,
+            nms_accuracy,
+        )?;
+        let valid_poly_inds = poly_prob.iter().map(|&p| p >= prob_threshold).collect();
+        (Array1::from(poly_prob), valid_poly_inds)
+    };
     // here we select the valid polyhedrons and construct the 3D labels
     let valid_poly_inds: Vec<usize> = valid_poly_inds
         .iter()
@@ -430,12 +986,38 @@ fn prob_dist_to_labels_3d(
     let poly_dist = poly_dist.select(poly_ax, &valid_poly_inds);
     let poly_pnts = poly_pnts.select(poly_ax, &valid_poly_inds);
     let poly_prob = poly_prob.select(poly_ax, &valid_poly_inds);
+    // remove filtered objects before labeling to keep the label ids contiguous,
+    // the object volumes are converted to network input voxels
+    let vol_scale =
+        src_shape.iter().product::<usize>() as f32 / image_shape.iter().product::<usize>() as f32;
+    let valid_filter_inds: Vec<usize> = polyhedron_filter(
+        poly_dist.view(),
+        poly_pnts.view(),
+        anisotropy,
+        src_shape,
+        &filter.scale_size(vol_scale),
+    )?
+    .iter()
+    .enumerate()
+    .filter(|&(_, &v)| v)
+    .map(|(i, _)| i)
+    .collect();
+    let poly_dist = poly_dist.select(poly_ax, &valid_filter_inds);
+    let poly_pnts = poly_pnts.select(poly_ax, &valid_filter_inds);
+    let poly_prob = poly_prob.select(poly_ax, &valid_filter_inds);
+    // the labels are rendered on the input volume grid, scaled by the output
+    // scale
+    let output_scale = output_scale.unwrap_or([1.0; 3]);
+    let label_shape = std::array::from_fn(|d| axes::scaled_len(image_shape[d], output_scale[d]));
+    let label_scale: [f32; 3] =
+        std::array::from_fn(|d| output_scale[d] * image_shape[d] as f32 / src_shape[d] as f32);
     distance_polyhedron_to_label(
         poly_dist.view(),
         poly_pnts.view(),
         poly_prob.view(),
         prob_threshold,
         anisotropy,
-        src_shape,
+        label_shape,
+        (label_scale != [1.0; 3]).then_some(label_scale),
     )
 }
@@ -0,0 +1,655 @@
+use std::marker::PhantomData;
+
+use burn::prelude::*;
+use imgal::image::percentile_normalize;
+use imgal::prelude::*;
+use imgal::transform::pad::reflect_pad;
+use ndarray::{Array2, Array3, ArrayBase, ArrayD, AsArray, Axis, Ix2, Ix3, ViewRepr};
+
+use crate::CellcastError;
+use crate::models::stardist_2d::{
+    INPUT_SCALE_ERR, OUTPUT_SCALE_ERR, OutputMaps, StarDist2DConfig, prob_dist_to_labels_2d,
+    resize_input, resolve_scale,
+};
+use crate::models::stardist_3d::{StarDist3DConfig, prob_dist_to_labels_3d, resolve_anisotropy};
+use crate::networks::stardist::network::{StarDistNetwork2D, StarDistNetwork3D};
+use crate::process::candidates::gather_candidates;
+use crate::process::filter::ObjectFilter;
+use crate::process::nms::{NmsAccuracy, NmsOptions};
+use crate::utils::axes;
+
+const PMIN: f64 = 1.0;
+const PMAX: f64 = 99.8;
+const PROB_THRESHOLD: f64 = 0.5;
+const NMS_THRESHOLD: f64 = 0.3;
+
+/// A StarDist2D model with a user-defined network.
+///
+/// Runs any network implementing `StarDistNetwork2D` (*e.g.* a custom Burn
+/// `Module` with StarDist-style object probability and ray distance heads)
+/// with the StarDist2D normalization, padding and postprocessing. The model
+/// configuration provides the ray count, the grid and the padding of the
+/// network input.
+#[derive(Debug)]
+pub struct StarDist2DCustom<B: Backend, N: StarDistNetwork2D<B>> {
+    network: N,
+    config: StarDist2DConfig,
+    device: B::Device,
+    prob_threshold: f64,
+    nms_threshold: f64,
+    pmin: f64,
+    pmax: f64,
+    filter: ObjectFilter,
+    subpixel: bool,
+    nms: NmsOptions,
+    input_scale: Option<(f32, f32)>,
+    output_scale: Option<(f32, f32)>,
+    backend: PhantomData<B>,
+}
+
+impl<B: Backend, N: StarDistNetwork2D<B>> StarDist2DCustom<B, N> {
+    /// Create a StarDist2D model with a user-defined network.
+    ///
+    /// # Arguments
+    ///
+    /// * `network`: The network, initialized on `device`.
+    /// * `config`: The model configuration of the network, the number of input
+    ///   channels is set by the network configuration `n_channel_in`.
+    /// * `device`: The backend device of the network.
+    ///
+    /// # Returns
+    ///
+    /// * `StarDist2DCustom`: The model with a probability threshold of `0.5`,
+    ///   an NMS threshold of `0.3` and normalization percentiles of `1.0` and
+    ///   `99.8`.
+    pub fn new(network: N, config: StarDist2DConfig, device: B::Device) -> Self {
+        Self {
+            network,
+            config,
+            device,
+            prob_threshold: PROB_THRESHOLD,
+            nms_threshold: NMS_THRESHOLD,
+            pmin: PMIN,
+            pmax: PMAX,
+            filter: ObjectFilter::default(),
+            subpixel: false,
+            nms: NmsOptions::default(),
+            input_scale: None,
+            output_scale: None,
+            backend: PhantomData,
+        }
+    }
+
+    /// Set the default object probability and NMS thresholds.
+    pub fn with_thresholds(mut self, prob_threshold: f64, nms_threshold: f64) -> Self {
+        self.prob_threshold = prob_threshold;
+        self.nms_threshold = nms_threshold;
+        self
+    }
+
+    /// Set the default normalization percentiles.
+    pub fn with_percentiles(mut self, pmin: f64, pmax: f64) -> Self {
+        self.pmin = pmin;
+        self.pmax = pmax;
+        self
+    }
+
+    /// Set the filter applied to the predicted objects.
+    pub fn with_object_filter(mut self, filter: ObjectFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Set the non-maximum suppression options, the Gaussian soft-NMS
+    /// `sigma` must be positive and finite.
+    pub fn with_nms_options(mut self, nms: NmsOptions) -> Result<Self, CellcastError> {
+        nms.validate()?;
+        self.nms = nms;
+        Ok(self)
+    }
+
+    /// Set if the object centers are placed at the center of their grid cell.
+    pub fn with_subpixel_centers(mut self, subpixel: bool) -> Self {
+        self.subpixel = subpixel;
+        self
+    }
+
+    /// Set the (row, col) scale of the network input relative to the input
+    /// image, the input images are resized before normalization.
+    pub fn with_input_scale(mut self, scale: (f32, f32)) -> Result<Self, CellcastError> {
+        self.input_scale =
+            resolve_scale([scale.0, scale.1], INPUT_SCALE_ERR)?.map(|s| (s[0], s[1]));
+        Ok(self)
+    }
+
+    /// Set the (row, col) scale of the output label images relative to the
+    /// input image.
+    pub fn with_output_scale(mut self, scale: (f32, f32)) -> Result<Self, CellcastError> {
+        self.output_scale =
+            resolve_scale([scale.0, scale.1], OUTPUT_SCALE_ERR)?.map(|s| (s[0], s[1]));
+        Ok(self)
+    }
+
+    /// Get the network.
+    pub fn network(&self) -> &N {
+        &self.network
+    }
+
+    /// Get the model configuration.
+    pub fn config(&self) -> &StarDist2DConfig {
+        &self.config
+    }
+
+    /// Get the default `(prob_threshold, nms_threshold)` thresholds.
+    pub fn thresholds(&self) -> (f64, f64) {
+        (self.prob_threshold, self.nms_threshold)
+    }
+
+    /// Get the default `(pmin, pmax)` normalization percentiles.
+    pub fn percentiles(&self) -> (f64, f64) {
+        (self.pmin, self.pmax)
+    }
+
+    /// Get the filter applied to the predicted objects.
+    pub fn object_filter(&self) -> &ObjectFilter {
+        &self.filter
+    }
+
+    /// Get the non-maximum suppression options.
+    pub fn nms_options(&self) -> &NmsOptions {
+        &self.nms
+    }
+
+    /// Get if the object centers are placed at the center of their grid cell.
+    pub fn subpixel_centers(&self) -> bool {
+        self.subpixel
+    }
+
+    /// Get the (row, col) scale of the network input relative to the input
+    /// image.
+    pub fn input_scale(&self) -> (f32, f32) {
+        self.input_scale.unwrap_or((1.0, 1.0))
+    }
+
+    /// Get the (row, col) scale of the output label images relative to the
+    /// input image.
+    pub fn output_scale(&self) -> (f32, f32) {
+        self.output_scale.unwrap_or((1.0, 1.0))
+    }
+
+    /// Predict instance segmentation labels of a single channel image.
+    ///
+    /// # Arguments
+    ///
+    /// * `data`: The input 2D image.
+    /// * `pmin`: The minimum percentage to linear percentile normalize the input
+    ///   image. If `None`, then the model's default percentile is used.
+    /// * `pmax`: The maximum percentage to linear percentile normalize the input
+    ///   image. If `None`, then the model's default percentile is used.
+    /// * `prob_threshold`: The object/polygon probability threshold. If `None`,
+    ///   then the model's default threshold is used.
+    /// * `nms_threshold`: The non-maximum suppression (NMS) threshold. If `None`,
+    ///   then the model's default threshold is used.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Array2<u64>)`: The instance segmentation label image.
+    /// * `Err(CellcastError)`: If the network does not have a single input
+    ///   channel. If the percentiles are out of range.
+    pub fn predict<'a, T, A>(
+        &self,
+        data: A,
+        pmin: Option<f64>,
+        pmax: Option<f64>,
+        prob_threshold: Option<f64>,
+        nms_threshold: Option<f64>,
+    ) -> Result<Array2<u64>, CellcastError>
+    where
+        A: AsArray<'a, T, Ix2>,
+        T: 'a + AsNumeric,
+    {
+        let data: ArrayBase<ViewRepr<&'a T>, Ix2> = data.into();
+        self.check_channels(1)?;
+        let pmin = pmin.unwrap_or(self.pmin);
+        let pmax = pmax.unwrap_or(self.pmax);
+        let resized = self
+            .input_scale
+            .map(|(s_row, s_col)| resize_input(data.view(), &[s_row, s_col], None));
+        let norm = match &resized {
+            Some(resized) => percentile_normalize(resized, pmin, pmax, false, None, None, None)?,
+            None => percentile_normalize(&data, pmin, pmax, false, None, None, None)?,
+        };
+        let norm = norm
+            .mapv(|v| v as f32)
+            .insert_axis(Axis(0))
+            .into_dimensionality::<Ix3>()
+            .unwrap();
+        self.run(norm, data.dim(), prob_threshold, nms_threshold)
+    }
+
+    /// Predict instance segmentation labels of a multichannel image.
+    ///
+    /// # Arguments
+    ///
+    /// * `data`: The input 2D image with `n_channel_in` channels.
+    /// * `pmin`: The minimum percentage to linear percentile normalize each
+    ///   channel. If `None`, then the model's default percentile is used.
+    /// * `pmax`: The maximum percentage to linear percentile normalize each
+    ///   channel. If `None`, then the model's default percentile is used.
+    /// * `prob_threshold`: The object/polygon probability threshold. If `None`,
+    ///   then the model's default threshold is used.
+    /// * `nms_threshold`: The non-maximum suppression (NMS) threshold. If `None`,
+    ///   then the model's default threshold is used.
+    /// * `axis`: The channel axis. If `None` then `axis == 2`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Array2<u64>)`: The instance segmentation label image.
+    /// * `Err(CellcastError)`: If the number of channels does not match the
+    ///   network. If `axis >= 3`. If the percentiles are out of range.
+    pub fn predict_channels<'a, T, A>(
+        &self,
+        data: A,
+        pmin: Option<f64>,
+        pmax: Option<f64>,
+        prob_threshold: Option<f64>,
+        nms_threshold: Option<f64>,
+        axis: Option<usize>,
+    ) -> Result<Array2<u64>, CellcastError>
+    where
+        A: AsArray<'a, T, Ix3>,
+        T: 'a + AsNumeric,
+    {
+        let data: ArrayBase<ViewRepr<&'a T>, Ix3> = data.into();
+        let axis = axis.unwrap_or(2);
+        if axis >= 3 {
+            return Err(CellcastError::Imgal(ImgalError::InvalidAxis {
+                axis_idx: axis,
+                dim_len: 3,
+            }));
+        }
+        self.check_channels(data.len_of(Axis(axis)))?;
+        let pmin = pmin.unwrap_or(self.pmin);
+        let pmax = pmax.unwrap_or(self.pmax);
+        let resized = self
+            .input_scale
+            .map(|(s_row, s_col)| resize_input(data.view(), &[s_row, s_col], Some(axis)));
+        let norm = match &resized {
+            Some(resized) => {
+                percentile_normalize(resized, pmin, pmax, false, Some(axis), None, None)?
+            }
+            None => percentile_normalize(&data, pmin, pmax, false, Some(axis), None, None)?,
+        };
+        let norm = norm
+            .into_dimensionality::<Ix3>()
+            .unwrap()
+            .mapv(|v| v as f32);
+        // move the channel axis first, the spatial axes keep their order
+        let spatial: Vec<usize> = (0..3).filter(|&a| a != axis).collect();
+        let norm = norm.permuted_axes([axis, spatial[0], spatial[1]]);
+        let image_shape = (data.len_of(Axis(spatial[0])), data.len_of(Axis(spatial[1])));
+        self.run(norm, image_shape, prob_threshold, nms_threshold)
+    }
+
+    /// Check that the network has `n_channels` input channels.
+    fn check_channels(&self, n_channels: usize) -> Result<(), CellcastError> {
+        if self.config.network().n_channel_in != n_channels {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "The input image channels do not match the StarDist2D network.",
+            }));
+        }
+        Ok(())
+    }
+
+    /// Pad and run the network on a normalized `(ch, row, col)` image and
+    /// process its output into instance segmentation labels of the
+    /// `image_shape` input image.
+    fn run(
+        &self,
+        norm: Array3<f32>,
+        image_shape: (usize, usize),
+        prob_threshold: Option<f64>,
+        nms_threshold: Option<f64>,
+    ) -> Result<Array2<u64>, CellcastError> {
+        let prob_threshold = prob_threshold.unwrap_or(self.prob_threshold) as f32;
+        let nms_threshold = nms_threshold.unwrap_or(self.nms_threshold) as f32;
+        let (n_ch, src_row, src_col) = norm.dim();
+        // this pattern determines how many pixels to pad in each axis to be
+        // divisible by the grid and network depth as expected by the network
+        let div = self.config.div();
+        let pad_config = vec![
+            0,
+            axes::divisible_pad(src_row, div.0),
+            axes::divisible_pad(src_col, div.1),
+        ];
+        let norm_pad = reflect_pad(&norm.as_standard_layout(), &pad_config, Some(0), None)?;
+        let pad_shape = norm_pad.shape()[1..].to_vec();
+        let (raw_data, _) = norm_pad.into_raw_vec_and_offset();
+        let td = TensorData::new(raw_data, [1, n_ch, pad_shape[0], pad_shape[1]]);
+        let tensor = Tensor::<B, 4>::from_data(td, &self.device);
+        let (prob, dist) = self.network.forward_prob_dist(tensor);
+        let (grid_row, grid_col) = self.config.grid();
+        let (res_row, res_col) = (pad_shape[0] / grid_row, pad_shape[1] / grid_col);
+        let n_out = res_row * res_col;
+        let n_rays = self.config.n_rays();
+        if prob.shape().num_elements() != n_out || dist.shape().num_elements() != n_out * n_rays {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "The StarDist2D network output does not match the model configuration.",
+            }));
+        }
+        // the candidates are gathered on the device with the rays last
+        let candidates = gather_candidates(
+            prob.reshape([res_row, res_col]),
+            dist.reshape([n_rays, n_out]).swap_dims(0, 1),
+            prob_threshold,
+            2,
+        );
+        Ok(prob_dist_to_labels_2d(
+            OutputMaps::Candidates(candidates),
+            prob_threshold,
+            nms_threshold,
+            pad_shape,
+            (src_row, src_col),
+            image_shape,
+            &self.config,
+            self.subpixel,
+            &self.nms,
+            &self.filter,
+            self.output_scale,
+        ))
+    }
+}
+
+/// A StarDist3D model with a user-defined network.
+///
+/// Runs any network implementing `StarDistNetwork3D` (*e.g.* a custom Burn
+/// `Module` with StarDist-style object probability and ray distance heads)
+/// with the StarDist3D normalization, padding and postprocessing. The model
+/// configuration provides the ray count, the grid and the padding of the
+/// network input.
+#[derive(Debug)]
+pub struct StarDist3DCustom<B: Backend, N: StarDistNetwork3D<B>> {
+    network: N,
+    config: StarDist3DConfig,
+    anisotropy: [f32; 3],
+    device: B::Device,
+    prob_threshold: f64,
+    nms_threshold: f64,
+    pmin: f64,
+    pmax: f64,
+    filter: ObjectFilter,
+    nms: NmsOptions,
+    nms_accuracy: NmsAccuracy,
+    input_scale: Option<[f32; 3]>,
+    output_scale: Option<[f32; 3]>,
+    backend: PhantomData<B>,
+}
+
+impl<B: Backend, N: StarDistNetwork3D<B>> StarDist3DCustom<B, N> {
+    /// Create a StarDist3D model with a user-defined network.
+    ///
+    /// # Arguments
+    ///
+    /// * `network`: The network, initialized on `device`.
+    /// * `config`: The model configuration of the network.
+    /// * `anisotropy`: The anisotropy the network was trained with for all
+    ///   three axes. If `None` then `[2.0, 1.0, 1.0]` is used.
+    /// * `device`: The backend device of the network.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(StarDist3DCustom)`: The model with a probability threshold of
+    ///   `0.5`, an NMS threshold of `0.3` and normalization percentiles of
+    ///   `1.0` and `99.8`.
+    /// * `Err(CellcastError)`: If `anisotropy.len() != 3`.
+    pub fn new(
+        network: N,
+        config: StarDist3DConfig,
+        anisotropy: Option<&[f32]>,
+        device: B::Device,
+    ) -> Result<Self, CellcastError> {
+        Ok(Self {
+            network,
+            config,
+            anisotropy: resolve_anisotropy(anisotropy)?,
+            device,
+            prob_threshold: PROB_THRESHOLD,
+            nms_threshold: NMS_THRESHOLD,
+            pmin: PMIN,
+            pmax: PMAX,
+            filter: ObjectFilter::default(),
+            nms: NmsOptions::default(),
+            nms_accuracy: NmsAccuracy::default(),
+            input_scale: None,
+            output_scale: None,
+            backend: PhantomData,
+        })
+    }
+
+    /// Set the default object probability and NMS thresholds.
+    pub fn with_thresholds(mut self, prob_threshold: f64, nms_threshold: f64) -> Self {
+        self.prob_threshold = prob_threshold;
+        self.nms_threshold = nms_threshold;
+        self
+    }
+
+    /// Set the default normalization percentiles.
+    pub fn with_percentiles(mut self, pmin: f64, pmax: f64) -> Self {
+        self.pmin = pmin;
+        self.pmax = pmax;
+        self
+    }
+
+    /// Set the filter applied to the predicted objects.
+    pub fn with_object_filter(mut self, filter: ObjectFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Set the non-maximum suppression options, the Gaussian soft-NMS
+    /// `sigma` must be positive and finite.
+    pub fn with_nms_options(mut self, nms: NmsOptions) -> Result<Self, CellcastError> {
+        nms.validate()?;
+        self.nms = nms;
+        Ok(self)
+    }
+
+    /// Set the accuracy of the NMS polyhedron intersection volumes.
+    pub fn with_nms_accuracy(mut self, accuracy: NmsAccuracy) -> Self {
+        self.nms_accuracy = accuracy;
+        self
+    }
+
+    /// Set the (pln, row, col) scale of the network input relative to the
+    /// input volume, the input volumes are resized before normalization.
+    pub fn with_input_scale(mut self, scale: [f32; 3]) -> Result<Self, CellcastError> {
+        self.input_scale = resolve_scale(scale, INPUT_SCALE_ERR)?;
+        Ok(self)
+    }
+
+    /// Set the (pln, row, col) scale of the output label volumes relative to
+    /// the input volume.
+    pub fn with_output_scale(mut self, scale: [f32; 3]) -> Result<Self, CellcastError> {
+        self.output_scale = resolve_scale(scale, OUTPUT_SCALE_ERR)?;
+        Ok(self)
+    }
+
+    /// Get the network.
+    pub fn network(&self) -> &N {
+        &self.network
+    }
+
+    /// Get the model configuration.
+    pub fn config(&self) -> &StarDist3DConfig {
+        &self.config
+    }
+
+    /// Get the model anisotropy.
+    pub fn anisotropy(&self) -> [f32; 3] {
+        self.anisotropy
+    }
+
+    /// Get the default `(prob_threshold, nms_threshold)` thresholds.
+    pub fn thresholds(&self) -> (f64, f64) {
+        (self.prob_threshold, self.nms_threshold)
+    }
+
+    /// Get the default `(pmin, pmax)` normalization percentiles.
+    pub fn percentiles(&self) -> (f64, f64) {
+        (self.pmin, self.pmax)
+    }
+
+    /// Get the filter applied to the predicted objects.
+    pub fn object_filter(&self) -> &ObjectFilter {
+        &self.filter
+    }
+
+    /// Get the non-maximum suppression options.
+    pub fn nms_options(&self) -> &NmsOptions {
+        &self.nms
+    }
+
+    /// Get the accuracy of the NMS polyhedron intersection volumes.
+    pub fn nms_accuracy(&self) -> NmsAccuracy {
+        self.nms_accuracy
+    }
+
+    /// Get the (pln, row, col) scale of the network input relative to the
+    /// input volume.
+    pub fn input_scale(&self) -> [f32; 3] {
+        self.input_scale.unwrap_or([1.0; 3])
+    }
+
+    /// Get the (pln, row, col) scale of the output label volumes relative to
+    /// the input volume.
+    pub fn output_scale(&self) -> [f32; 3] {
+        self.output_scale.unwrap_or([1.0; 3])
+    }
+
+    /// Predict instance segmentation labels of a 3D image.
+    ///
+    /// # Arguments
+    ///
+    /// * `data`: The input 3D image.
+    /// * `pmin`: The minimum percentage to linear percentile normalize the input
+    ///   image. If `None`, then the model's default percentile is used.
+    /// * `pmax`: The maximum percentage to linear percentile normalize the input
+    ///   image. If `None`, then the model's default percentile is used.
+    /// * `prob_threshold`: The object/polyhedron probability threshold. If
+    ///   `None`, then the model's default threshold is used.
+    /// * `nms_threshold`: The non-maximum suppression (NMS) threshold. If `None`,
+    ///   then the model's default threshold is used.
+    /// * `axis`: The `pln` or `z` axis. If `None` then `axis == 0`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Array3<u64>)`: The instance segmentation label image, with the
+    ///   axis order of `data`.
+    /// * `Err(CellcastError)`: If the percentiles are out of range. If
+    ///   `axis >= 3`.
+    pub fn predict<'a, T, A>(
+        &self,
+        data: A,
+        pmin: Option<f64>,
+        pmax: Option<f64>,
+        prob_threshold: Option<f64>,
+        nms_threshold: Option<f64>,
+        axis: Option<usize>,
+    ) -> Result<Array3<u64>, CellcastError>
+    where
+        A: AsArray<'a, T, Ix3>,
+        T: 'a + AsNumeric,
+    {
+        let axis = axis.unwrap_or(0);
+        if axis >= 3 {
+            return Err(CellcastError::Imgal(ImgalError::InvalidAxis {
+                axis_idx: axis,
+                dim_len: 3,
+            }));
+        }
+        let data: ArrayBase<ViewRepr<&'a T>, Ix3> = data.into();
+        let prob_threshold = prob_threshold.unwrap_or(self.prob_threshold) as f32;
+        let nms_threshold = nms_threshold.unwrap_or(self.nms_threshold) as f32;
+        // move the planes axis first, the other axes keep their order
+        let spatial: Vec<usize> = (0..3).filter(|&a| a != axis).collect();
+        let pmin = pmin.unwrap_or(self.pmin);
+        let pmax = pmax.unwrap_or(self.pmax);
+        let resized = self.input_scale.map(|scale| {
+            let mut axis_scale = vec![scale[1], scale[2]];
+            axis_scale.insert(axis, scale[0]);
+            resize_input(data.view(), &axis_scale, None)
+        });
+        let norm = match &resized {
+            Some(resized) => percentile_normalize(resized, pmin, pmax, false, None, None, None)?,
+            None => percentile_normalize(&data, pmin, pmax, false, None, None, None)?,
+        };
+        let image_shape = [axis, spatial[0], spatial[1]].map(|a| data.len_of(Axis(a)));
+        let norm: ArrayD<f32> = norm
+            .mapv(|v| v as f32)
+            .permuted_axes([axis, spatial[0], spatial[1]])
+            .as_standard_layout()
+            .into_owned()
+            .into_dyn();
+        let src_shape = [norm.shape()[0], norm.shape()[1], norm.shape()[2]];
+        let (div_pln, div_row, div_col) = self.config.div();
+        let pad_config = vec![
+            axes::divisible_pad(src_shape[0], div_pln),
+            axes::divisible_pad(src_shape[1], div_row),
+            axes::divisible_pad(src_shape[2], div_col),
+        ];
+        let norm_pad = reflect_pad(&norm, &pad_config, Some(0), None)?;
+        let pad_shape = [
+            norm_pad.shape()[0],
+            norm_pad.shape()[1],
+            norm_pad.shape()[2],
+        ];
+        let (raw_data, _) = norm_pad.into_raw_vec_and_offset();
+        let td = TensorData::new(raw_data, [1, 1, pad_shape[0], pad_shape[1], pad_shape[2]]);
+        let tensor = Tensor::<B, 5>::from_data(td, &self.device);
+        let (prob, dist) = self.network.forward_prob_dist(tensor);
+        let (grid_pln, grid_row, grid_col) = self.config.grid();
+        let res_shape = [
+            pad_shape[0] / grid_pln,
+            pad_shape[1] / grid_row,
+            pad_shape[2] / grid_col,
+        ];
+        let n_out: usize = res_shape.iter().product();
+        let n_rays = self.config.n_rays();
+        if prob.shape().num_elements() != n_out || dist.shape().num_elements() != n_out * n_rays {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "The StarDist3D network output does not match the model configuration.",
+            }));
+        }
+        // the candidates are gathered on the device with the rays last
+        let candidates = gather_candidates(
+            prob.reshape(res_shape),
+            dist.reshape([n_rays, n_out]).swap_dims(0, 1),
+            prob_threshold,
+            2,
+        );
+        let labels = prob_dist_to_labels_3d(
+            candidates,
+            prob_threshold,
+            nms_threshold,
+            self.anisotropy,
+            src_shape,
+            image_shape,
+            &self.config,
+            &self.nms,
+            self.nms_accuracy,
+            &self.filter,
+            self.output_scale,
+        )?;
+        // restore the planes axis position of the input
+        let mut order = [0; 3];
+        order[axis] = 0;
+        spatial
+            .iter()
+            .enumerate()
+            .for_each(|(i, &a)| order[a] = i + 1);
+        Ok(labels
+            .permuted_axes(order)
+            .as_standard_layout()
+            .into_owned())
+    }
+}
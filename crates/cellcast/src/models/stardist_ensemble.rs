@@ -0,0 +1,387 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use imgal::prelude::*;
+use ndarray::{Array2, ArrayBase, AsArray, Ix2, Ix3, ViewRepr};
+
+use crate::CellcastError;
+use crate::models::stardist_2d::{
+    NetworkOutput, OutputMaps, PolygonCandidates, StarDist2D, candidates_to_labels_2d,
+    label_scale_2d, prob_dist_to_candidates_2d, prob_dist_to_labels_2d,
+};
+use crate::process::filter::ObjectFilter;
+use crate::process::nms::NmsOptions;
+use crate::utils::axes::scale_coord;
+
+/// The strategy combining the predictions of a StarDist2D ensemble.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnsembleMode {
+    /// Average the object probabilities and ray distances of all models
+    /// before non-maximum suppression. Requires models with the same ray
+    /// count, grid and input padding.
+    Average,
+    /// Merge the object candidates of all models into a single non-maximum
+    /// suppression pass. Requires models with the same ray count.
+    Merge,
+}
+
+/// Consensus StarDist2D instance segmentation of an ensemble.
+///
+/// The instance segmentation labels of a StarDist2D ensemble with the number
+/// of models agreeing on each object, *i.e.* the number of models with an
+/// object candidate (a pixel above the model's probability threshold)
+/// centered inside the object.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StarDist2DConsensus {
+    /// The instance segmentation label image.
+    pub labels: Array2<u64>,
+    /// The number of agreeing models of each label.
+    pub agreement: BTreeMap<u64, usize>,
+}
+
+/// An ensemble of StarDist2D models.
+///
+/// Combines the predictions of several StarDist2D models of the same type
+/// (*e.g.* `fluo` weights trained on different datasets) into a consensus
+/// segmentation, either by averaging the network outputs or by merging the
+/// object candidates of all models (see `EnsembleMode`).
+#[derive(Debug)]
+pub struct StarDist2DEnsemble {
+    models: Vec<StarDist2D>,
+    filter: ObjectFilter,
+    subpixel: bool,
+    nms: NmsOptions,
+}
+
+impl StarDist2DEnsemble {
+    /// Create an ensemble of StarDist2D models.
+    ///
+    /// # Arguments
+    ///
+    /// * `models`: The initialized StarDist2D models.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(StarDist2DEnsemble)`: The ensemble.
+    /// * `Err(CellcastError)`: If `models` is empty. If the models do not have
+    ///   the same ray count, number of input channels and input scale.
+    pub fn new(models: Vec<StarDist2D>) -> Result<Self, CellcastError> {
+        let Some(first) = models.first() else {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "A StarDist2D ensemble requires at least one model.",
+            }));
+        };
+        let n_rays = first.config().n_rays();
+        let n_channel_in = first.config().network().n_channel_in;
+        let input_scale = first.input_scale();
+        if models.iter().any(|m| {
+            m.config().n_rays() != n_rays
+                || m.config().network().n_channel_in != n_channel_in
+                || m.input_scale() != input_scale
+        }) {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "The StarDist2D ensemble models must have the same ray count, input channels and input scale.",
+            }));
+        }
+        Ok(Self {
+            models,
+            filter: ObjectFilter::default(),
+            subpixel: false,
+            nms: NmsOptions::default(),
+        })
+    }
+
+    /// Set the filter applied to the consensus objects.
+    ///
+    /// # Arguments
+    ///
+    /// * `filter`: The object filter, removing objects crossing the image
+    ///   border and/or outside of an area range before the objects are
+    ///   labeled. By default no objects are removed.
+    pub fn with_object_filter(mut self, filter: ObjectFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Set if the consensus object centers are placed at the center of their
+    /// grid cell (see `StarDist2D::with_subpixel_centers`).
+    pub fn with_subpixel_centers(mut self, subpixel: bool) -> Self {
+        self.subpixel = subpixel;
+        self
+    }
+
+    /// Set the non-maximum suppression options of the consensus objects (see
+    /// `StarDist2D::with_nms_options`).
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(StarDist2DEnsemble)`: The ensemble with the non-maximum
+    ///   suppression options.
+    /// * `Err(CellcastError)`: If the Gaussian soft-NMS `sigma` is not
+    ///   positive and finite.
+    pub fn with_nms_options(mut self, nms: NmsOptions) -> Result<Self, CellcastError> {
+        nms.validate()?;
+        self.nms = nms;
+        Ok(self)
+    }
+
+    /// Get the ensemble models.
+    pub fn models(&self) -> &[StarDist2D] {
+        &self.models
+    }
+
+    /// Get the filter applied to the consensus objects.
+    pub fn object_filter(&self) -> &ObjectFilter {
+        &self.filter
+    }
+
+    /// Predict consensus instance segmentation labels with an ensemble of
+    /// StarDist2D fluo models.
+    ///
+    /// # Arguments
+    ///
+    /// * `data`: The input 2D image.
+    /// * `pmin`: The minimum percentage to linear percentile normalize the input
+    ///   image. If `None`, then each model's default percentile is used.
+    /// * `pmax`: The maximum percentage to linear percentile normalize the input
+    ///   image. If `None`, then each model's default percentile is used.
+    /// * `prob_threshold`: The object/polygon probability threshold. If `None`,
+    ///   then the mean of the models' default thresholds is used to average
+    ///   and each model's default threshold is used to merge.
+    /// * `nms_threshold`: The non-maximum suppression (NMS) threshold. If `None`,
+    ///   then the mean of the models' default thresholds is used.
+    /// * `mode`: How the model predictions are combined.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(StarDist2DConsensus)`: The consensus labels and the number of
+    ///   agreeing models of each object.
+    /// * `Err(CellcastError)`: If the models are not fluo models. If the models
+    ///   can not be averaged. If `pmin` and/or `pmax` are outside of range
+    ///   `0.0` to `1.0.`
+    pub fn predict_fluo<'a, T, A>(
+        &self,
+        data: A,
+        pmin: Option<f64>,
+        pmax: Option<f64>,
+        prob_threshold: Option<f64>,
+        nms_threshold: Option<f64>,
+        mode: EnsembleMode,
+    ) -> Result<StarDist2DConsensus, CellcastError>
+    where
+        A: AsArray<'a, T, Ix2>,
+        T: 'a + AsNumeric,
+    {
+        let data: ArrayBase<ViewRepr<&'a T>, Ix2> = data.into();
+        let prob_thresholds = self.prob_thresholds(prob_threshold, mode);
+        let outputs = self
+            .models
+            .iter()
+            .zip(prob_thresholds.iter())
+            .map(|(m, &t)| {
+                // averaging needs the full network outputs, merging only the
+                // object candidates
+                let gather = (mode == EnsembleMode::Merge).then_some(t);
+                m.run_fluo(data.view(), pmin, pmax, false, gather)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        self.combine(outputs, &prob_thresholds, nms_threshold, mode)
+    }
+
+    /// Predict consensus instance segmentation labels with an ensemble of
+    /// StarDist2D HE models.
+    ///
+    /// # Arguments
+    ///
+    /// * `data`: The input 3D image, where the third dimension is the channel axis.
+    /// * `pmin`: The minimum percentage to linear percentile normalize the input
+    ///   image. If `None`, then each model's default percentile is used.
+    /// * `pmax`: The maximum percentage to linear percentile normalize the input
+    ///   image. If `None`, then each model's default percentile is used.
+    /// * `prob_threshold`: The object/polygon probability threshold. If `None`,
+    ///   then the mean of the models' default thresholds is used to average
+    ///   and each model's default threshold is used to merge.
+    /// * `nms_threshold`: The non-maximum suppression (NMS) threshold. If `None`,
+    ///   then the mean of the models' default thresholds is used.
+    /// * `axis`: The channel axis. If `None` then `axis == 2`.
+    /// * `mode`: How the model predictions are combined.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(StarDist2DConsensus)`: The consensus labels and the number of
+    ///   agreeing models of each object.
+    /// * `Err(CellcastError)`: If the models are not HE models. If the models
+    ///   can not be averaged. If `pmin` and/or `pmax` are outside of range
+    ///   `0.0` to `1.0.`
+    pub fn predict_he<'a, T, A>(
+        &self,
+        data: A,
+        pmin: Option<f64>,
+        pmax: Option<f64>,
+        prob_threshold: Option<f64>,
+        nms_threshold: Option<f64>,
+        axis: Option<usize>,
+        mode: EnsembleMode,
+    ) -> Result<StarDist2DConsensus, CellcastError>
+    where
+        A: AsArray<'a, T, Ix3>,
+        T: 'a + AsNumeric,
+    {
+        let data: ArrayBase<ViewRepr<&'a T>, Ix3> = data.into();
+        let prob_thresholds = self.prob_thresholds(prob_threshold, mode);
+        let outputs = self
+            .models
+            .iter()
+            .zip(prob_thresholds.iter())
+            .map(|(m, &t)| {
+                // averaging needs the full network outputs, merging only the
+                // object candidates
+                let gather = (mode == EnsembleMode::Merge).then_some(t);
+                m.run_he(data.view(), pmin, pmax, axis, false, gather)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        self.combine(outputs, &prob_thresholds, nms_threshold, mode)
+    }
+
+    /// Get the object probability threshold of each ensemble model.
+    fn prob_thresholds(&self, prob_threshold: Option<f64>, mode: EnsembleMode) -> Vec<f32> {
+        let n_models = self.models.len() as f64;
+        let mean_prob = self.models.iter().map(|m| m.thresholds().0).sum::<f64>() / n_models;
+        self.models
+            .iter()
+            .map(|m| match (prob_threshold, mode) {
+                (Some(t), _) => t as f32,
+                (None, EnsembleMode::Average) => mean_prob as f32,
+                (None, EnsembleMode::Merge) => m.thresholds().0 as f32,
+            })
+            .collect()
+    }
+
+    /// Combine the network outputs of the ensemble models into a consensus
+    /// segmentation.
+    fn combine(
+        &self,
+        outputs: Vec<NetworkOutput>,
+        prob_thresholds: &[f32],
+        nms_threshold: Option<f64>,
+        mode: EnsembleMode,
+    ) -> Result<StarDist2DConsensus, CellcastError> {
+        let n_models = self.models.len() as f64;
+        let mean_nms = self.models.iter().map(|m| m.thresholds().1).sum::<f64>() / n_models;
+        let nms_threshold = nms_threshold.unwrap_or(mean_nms) as f32;
+        let src_shape = outputs[0].src_shape;
+        let image_shape = outputs[0].image_shape;
+        // the candidates of each model, used to count the agreeing models
+        let candidates: Vec<PolygonCandidates> = outputs
+            .iter()
+            .zip(self.models.iter().zip(prob_thresholds.iter()))
+            .map(|(out, (m, &t))| {
+                prob_dist_to_candidates_2d(
+                    out.maps.clone(),
+                    t,
+                    &out.pad_shape,
+                    src_shape,
+                    m.config(),
+                    self.subpixel,
+                )
+            })
+            .collect();
+        let labels = match mode {
+            EnsembleMode::Average => {
+                let config = self.models[0].config();
+                if self.models.iter().zip(outputs.iter()).any(|(m, out)| {
+                    m.config().grid() != config.grid() || out.pad_shape != outputs[0].pad_shape
+                }) {
+                    return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                        msg: "The StarDist2D ensemble models can not be averaged, merge the model candidates instead.",
+                    }));
+                }
+                let (probs, dists): (Vec<&[f32]>, Vec<&[f32]>) = outputs
+                    .iter()
+                    .filter_map(|o| match &o.maps {
+                        OutputMaps::Full { prob, dist } => Some((prob.as_slice(), dist.as_slice())),
+                        OutputMaps::Candidates(_) => None,
+                    })
+                    .unzip();
+                let mean = |maps: Vec<&[f32]>| -> Vec<f32> {
+                    (0..maps[0].len())
+                        .map(|i| maps.iter().map(|m| m[i]).sum::<f32>() / maps.len() as f32)
+                        .collect()
+                };
+                prob_dist_to_labels_2d(
+                    OutputMaps::Full {
+                        prob: mean(probs),
+                        dist: mean(dists),
+                    },
+                    prob_thresholds[0],
+                    nms_threshold,
+                    outputs[0].pad_shape.clone(),
+                    src_shape,
+                    image_shape,
+                    config,
+                    self.subpixel,
+                    &self.nms,
+                    &self.filter,
+                    None,
+                )
+            }
+            EnsembleMode::Merge => {
+                let merged = PolygonCandidates::merge(&candidates);
+                let min_prob = prob_thresholds.iter().copied().fold(f32::MAX, f32::min);
+                candidates_to_labels_2d(
+                    merged,
+                    min_prob,
+                    nms_threshold,
+                    src_shape,
+                    image_shape,
+                    &self.nms,
+                    &self.filter,
+                    None,
+                )
+            }
+        };
+        let agreement = count_agreement(
+            &labels,
+            &candidates,
+            label_scale_2d(src_shape, image_shape, None),
+        );
+        Ok(StarDist2DConsensus { labels, agreement })
+    }
+}
+
+/// Count the number of models with an object candidate centered inside each
+/// label.
+///
+/// # Arguments
+///
+/// * `labels`: The instance segmentation label image.
+/// * `candidates`: The object candidates of each model.
+/// * `label_scale`: The (row, col) scale of the label image relative to the
+///   network input image.
+///
+/// # Returns
+///
+/// * `BTreeMap<u64, usize>`: The number of agreeing models of each label.
+fn count_agreement(
+    labels: &Array2<u64>,
+    candidates: &[PolygonCandidates],
+    label_scale: Option<(f32, f32)>,
+) -> BTreeMap<u64, usize> {
+    let (s_row, s_col) = label_scale.unwrap_or((1.0, 1.0));
+    let mut models: BTreeMap<u64, BTreeSet<usize>> = labels
+        .iter()
+        .filter(|&&l| l > 0)
+        .map(|&l| (l, BTreeSet::new()))
+        .collect();
+    candidates.iter().enumerate().for_each(|(i, c)| {
+        c.pos.rows().into_iter().for_each(|p| {
+            let (r, c) = (
+                scale_coord(p[0], s_row).round() as usize,
+                scale_coord(p[1], s_col).round() as usize,
+            );
+            if let Some(set) = labels.get((r, c)).and_then(|l| models.get_mut(l)) {
+                set.insert(i);
+            }
+        });
+    });
+    models.into_iter().map(|(l, set)| (l, set.len())).collect()
+}
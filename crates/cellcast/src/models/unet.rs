@@ -0,0 +1,485 @@
+use std::path::Path;
+
+use burn::prelude::*;
+use imgal::image::percentile_normalize;
+use imgal::prelude::*;
+use imgal::transform::pad::reflect_pad;
+use ndarray::{
+    Array2, Array3, Array4, ArrayBase, ArrayD, ArrayViewD, AsArray, Axis, Ix2, Ix3, IxDyn, ViewRepr,
+};
+
+use crate::CellcastError;
+use crate::config::backend::{CpuBackend, GpuBackend};
+use crate::networks::unet::config::UNetConfig;
+use crate::networks::unet::{unet_2d, unet_3d};
+use crate::process::watershed::boundary_watershed;
+use crate::utils::axes;
+
+const PMIN: f64 = 1.0;
+const PMAX: f64 = 99.8;
+const FG_THRESHOLD: f64 = 0.5;
+const SEED_THRESHOLD: f64 = 0.5;
+
+type CpuConfigBackend = CpuBackend<f32, i32>;
+type GpuConfigBackend = GpuBackend<f32, i32>;
+
+/// Backend variants for a `UNet` model.
+///
+/// This enum tracks the possible U-Net model variants between the 2D and 3D
+/// networks initialized on the CPU or GPU.
+#[derive(Debug)]
+enum UNetModels {
+    Cpu2d(Box<unet_2d::Model<CpuConfigBackend>>),
+    Gpu2d(Box<unet_2d::Model<GpuConfigBackend>>),
+    Cpu3d(Box<unet_3d::Model<CpuConfigBackend>>),
+    Gpu3d(Box<unet_3d::Model<GpuConfigBackend>>),
+}
+
+/// A generic U-Net semantic segmentation model.
+///
+/// Initializes a 2D or 3D U-Net semantic segmentation model with custom
+/// weights, predicting per-pixel class probabilities (*e.g.* background,
+/// foreground and boundary). The class probabilities can be converted into
+/// instance segmentations with a seeded watershed. The model runs on either a
+/// CPU or GPU backend as determined at initialization time.
+#[derive(Debug)]
+pub struct UNet {
+    model: UNetModels,
+    config: UNetConfig,
+}
+
+impl UNet {
+    /// Initialize a U-Net model.
+    ///
+    /// # Description
+    ///
+    /// Initializes a U-Net model with custom weights in burnpack format,
+    /// stored with the model layer names or ONNX layer names. A U-Net model
+    /// can be initialized on either the GPU or CPU, but not both concurrently.
+    /// The model is pre-warmed as part of the initialization process.
+    ///
+    /// # Arguments
+    ///
+    /// * `weights_path`: The path to the U-Net weights in burnpack (`.bpk`)
+    ///   format.
+    /// * `config`: The network configuration of the weights.
+    /// * `gpu`: If `true`, the configured GPU backend is used. If `false` then the
+    ///   configured CPU backend is used.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(UNet)`: An initialized U-Net model.
+    /// * `Err(CellcastError)`: If `config` is invalid. If the weights can not
+    ///   be loaded or do not match the configuration.
+    pub fn init(weights_path: &str, config: UNetConfig, gpu: bool) -> Result<Self, CellcastError> {
+        let file = Path::new(weights_path);
+        let model = match (config.n_dim, gpu) {
+            (2, false) => UNetModels::Cpu2d(Box::new(unet_2d::Model::from_file(
+                file,
+                &Default::default(),
+                &config,
+            )?)),
+            (2, true) => UNetModels::Gpu2d(Box::new(unet_2d::Model::from_file(
+                file,
+                &Default::default(),
+                &config,
+            )?)),
+            (_, false) => UNetModels::Cpu3d(Box::new(unet_3d::Model::from_file(
+                file,
+                &Default::default(),
+                &config,
+            )?)),
+            (_, true) => UNetModels::Gpu3d(Box::new(unet_3d::Model::from_file(
+                file,
+                &Default::default(),
+                &config,
+            )?)),
+        };
+        let unet = Self { model, config };
+        unet.warm_up()?;
+        Ok(unet)
+    }
+
+    /// Get the network configuration.
+    pub fn config(&self) -> &UNetConfig {
+        &self.config
+    }
+
+    /// Predict class probabilities of a 2D image with the U-Net model.
+    ///
+    /// # Arguments
+    ///
+    /// * `data`: The input 2D image.
+    /// * `pmin`: The minimum percentage to linear percentile normalize the input
+    ///   image. If `None`, then `pmin = 1.0`.
+    /// * `pmax`: The maximum percentage to linear percentile normalize the input
+    ///   image. If `None`, then `pmax = 99.8`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Array3<f32>)`: The class probabilities with shape
+    ///   `(n_classes, row, col)`.
+    /// * `Err(CellcastError)`: If the model is not a single channel 2D U-Net.
+    ///   If the percentiles are out of range.
+    pub fn predict_2d<'a, T, A>(
+        &self,
+        data: A,
+        pmin: Option<f64>,
+        pmax: Option<f64>,
+    ) -> Result<Array3<f32>, CellcastError>
+    where
+        A: AsArray<'a, T, Ix2>,
+        T: 'a + AsNumeric,
+    {
+        let data: ArrayBase<ViewRepr<&'a T>, Ix2> = data.into();
+        self.check_input(2, 1)?;
+        let norm = percentile_normalize(
+            &data,
+            pmin.unwrap_or(PMIN),
+            pmax.unwrap_or(PMAX),
+            false,
+            None,
+            None,
+            None,
+        )?;
+        let norm = norm.mapv(|v| v as f32).insert_axis(Axis(0)).into_dyn();
+        let probs = self.run(norm)?;
+        Ok(probs.into_dimensionality::<Ix3>().unwrap())
+    }
+
+    /// Predict class probabilities of a multichannel 2D image with the U-Net
+    /// model.
+    ///
+    /// # Arguments
+    ///
+    /// * `data`: The input 2D image with `n_channel_in` channels.
+    /// * `pmin`: The minimum percentage to linear percentile normalize each
+    ///   channel. If `None`, then `pmin = 1.0`.
+    /// * `pmax`: The maximum percentage to linear percentile normalize each
+    ///   channel. If `None`, then `pmax = 99.8`.
+    /// * `axis`: The channel axis. If `None`, then `axis = 2`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Array3<f32>)`: The class probabilities with shape
+    ///   `(n_classes, row, col)`.
+    /// * `Err(CellcastError)`: If the model is not a 2D U-Net with the number
+    ///   of channels of `data`. If `axis` is out of bounds. If the percentiles
+    ///   are out of range.
+    pub fn predict_2d_channels<'a, T, A>(
+        &self,
+        data: A,
+        pmin: Option<f64>,
+        pmax: Option<f64>,
+        axis: Option<usize>,
+    ) -> Result<Array3<f32>, CellcastError>
+    where
+        A: AsArray<'a, T, Ix3>,
+        T: 'a + AsNumeric,
+    {
+        let data: ArrayBase<ViewRepr<&'a T>, Ix3> = data.into();
+        let axis = axis.unwrap_or(2);
+        if axis >= 3 {
+            return Err(CellcastError::Imgal(ImgalError::InvalidAxis {
+                axis_idx: axis,
+                dim_len: 3,
+            }));
+        }
+        self.check_input(2, data.len_of(Axis(axis)))?;
+        let norm = percentile_normalize(
+            &data,
+            pmin.unwrap_or(PMIN),
+            pmax.unwrap_or(PMAX),
+            false,
+            Some(axis),
+            None,
+            None,
+        )?;
+        // move the channel axis first, the spatial axes keep their order
+        let spatial: Vec<usize> = (0..3).filter(|&a| a != axis).collect();
+        let norm = norm
+            .mapv(|v| v as f32)
+            .permuted_axes([axis, spatial[0], spatial[1]])
+            .as_standard_layout()
+            .into_owned()
+            .into_dyn();
+        let probs = self.run(norm)?;
+        Ok(probs.into_dimensionality::<Ix3>().unwrap())
+    }
+
+    /// Predict class probabilities of a 3D image with the U-Net model.
+    ///
+    /// # Arguments
+    ///
+    /// * `data`: The input 3D image with shape `(pln, row, col)`.
+    /// * `pmin`: The minimum percentage to linear percentile normalize the input
+    ///   image. If `None`, then `pmin = 1.0`.
+    /// * `pmax`: The maximum percentage to linear percentile normalize the input
+    ///   image. If `None`, then `pmax = 99.8`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Array4<f32>)`: The class probabilities with shape
+    ///   `(n_classes, pln, row, col)`.
+    /// * `Err(CellcastError)`: If the model is not a single channel 3D U-Net.
+    ///   If the percentiles are out of range.
+    pub fn predict_3d<'a, T, A>(
+        &self,
+        data: A,
+        pmin: Option<f64>,
+        pmax: Option<f64>,
+    ) -> Result<Array4<f32>, CellcastError>
+    where
+        A: AsArray<'a, T, Ix3>,
+        T: 'a + AsNumeric,
+    {
+        let data: ArrayBase<ViewRepr<&'a T>, Ix3> = data.into();
+        self.check_input(3, 1)?;
+        let norm = percentile_normalize(
+            &data,
+            pmin.unwrap_or(PMIN),
+            pmax.unwrap_or(PMAX),
+            false,
+            None,
+            None,
+            None,
+        )?;
+        let norm = norm.mapv(|v| v as f32).insert_axis(Axis(0)).into_dyn();
+        let probs = self.run(norm)?;
+        Ok(probs.into_dimensionality().unwrap())
+    }
+
+    /// Predict instance segmentation labels of a 2D image with the U-Net
+    /// model.
+    ///
+    /// # Description
+    ///
+    /// Predicts the class probabilities of a 2D image (see `predict_2d`) and
+    /// computes instance labels with a seeded watershed (see
+    /// `labels_from_probs`).
+    ///
+    /// # Arguments
+    ///
+    /// * `data`: The input 2D image.
+    /// * `pmin`: The minimum percentage to linear percentile normalize the input
+    ///   image. If `None`, then `pmin = 1.0`.
+    /// * `pmax`: The maximum percentage to linear percentile normalize the input
+    ///   image. If `None`, then `pmax = 99.8`.
+    /// * `fg_threshold`: The foreground probability threshold. If `None`, then
+    ///   `fg_threshold = 0.5`.
+    /// * `seed_threshold`: The interior probability threshold of the watershed
+    ///   seeds. If `None`, then `seed_threshold = 0.5`.
+    /// * `min_size`: The minimum number of pixels of an object. If `None`, then
+    ///   `min_size = 0`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Array2<u64>)`: The instance segmentation labels.
+    /// * `Err(CellcastError)`: If the model is not a single channel 2D U-Net.
+    ///   If the percentiles are out of range.
+    pub fn segment_2d<'a, T, A>(
+        &self,
+        data: A,
+        pmin: Option<f64>,
+        pmax: Option<f64>,
+        fg_threshold: Option<f64>,
+        seed_threshold: Option<f64>,
+        min_size: Option<usize>,
+    ) -> Result<Array2<u64>, CellcastError>
+    where
+        A: AsArray<'a, T, Ix2>,
+        T: 'a + AsNumeric,
+    {
+        let probs = self.predict_2d(data, pmin, pmax)?;
+        let labels = Self::labels_from_probs(
+            probs.view().into_dyn(),
+            fg_threshold.unwrap_or(FG_THRESHOLD),
+            seed_threshold.unwrap_or(SEED_THRESHOLD),
+            min_size.unwrap_or(0),
+        )?;
+        Ok(labels.into_dimensionality().unwrap())
+    }
+
+    /// Predict instance segmentation labels of a 3D image with the U-Net
+    /// model.
+    ///
+    /// # Description
+    ///
+    /// Predicts the class probabilities of a 3D image (see `predict_3d`) and
+    /// computes instance labels with a seeded watershed (see
+    /// `labels_from_probs`).
+    ///
+    /// # Arguments
+    ///
+    /// * `data`: The input 3D image with shape `(pln, row, col)`.
+    /// * `pmin`: The minimum percentage to linear percentile normalize the input
+    ///   image. If `None`, then `pmin = 1.0`.
+    /// * `pmax`: The maximum percentage to linear percentile normalize the input
+    ///   image. If `None`, then `pmax = 99.8`.
+    /// * `fg_threshold`: The foreground probability threshold. If `None`, then
+    ///   `fg_threshold = 0.5`.
+    /// * `seed_threshold`: The interior probability threshold of the watershed
+    ///   seeds. If `None`, then `seed_threshold = 0.5`.
+    /// * `min_size`: The minimum number of voxels of an object. If `None`, then
+    ///   `min_size = 0`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Array3<u64>)`: The instance segmentation labels.
+    /// * `Err(CellcastError)`: If the model is not a single channel 3D U-Net.
+    ///   If the percentiles are out of range.
+    pub fn segment_3d<'a, T, A>(
+        &self,
+        data: A,
+        pmin: Option<f64>,
+        pmax: Option<f64>,
+        fg_threshold: Option<f64>,
+        seed_threshold: Option<f64>,
+        min_size: Option<usize>,
+    ) -> Result<Array3<u64>, CellcastError>
+    where
+        A: AsArray<'a, T, Ix3>,
+        T: 'a + AsNumeric,
+    {
+        let probs = self.predict_3d(data, pmin, pmax)?;
+        let labels = Self::labels_from_probs(
+            probs.view().into_dyn(),
+            fg_threshold.unwrap_or(FG_THRESHOLD),
+            seed_threshold.unwrap_or(SEED_THRESHOLD),
+            min_size.unwrap_or(0),
+        )?;
+        Ok(labels.into_dimensionality().unwrap())
+    }
+
+    /// Compute instance segmentation labels from class probabilities.
+    ///
+    /// # Description
+    ///
+    /// Computes instance labels from the class probabilities of a semantic
+    /// segmentation network with a seeded watershed. Pixels whose foreground
+    /// probability (*i.e.* one minus the background probability of class `0`)
+    /// exceeds `fg_threshold` are segmented, seeded by the connected regions
+    /// whose interior probability (class `1`) exceeds `seed_threshold`. The
+    /// seeds are flooded in order of decreasing interior probability, so that
+    /// touching objects split along their boundary (*e.g.* class `2`). A
+    /// single class is used as both the foreground and interior probability.
+    ///
+    /// # Arguments
+    ///
+    /// * `probs`: The class probabilities with the class axis first, *i.e.*
+    ///   the output of `predict_2d` or `predict_3d`.
+    /// * `fg_threshold`: The foreground probability threshold.
+    /// * `seed_threshold`: The interior probability threshold of the seeds.
+    /// * `min_size`: The minimum number of pixels of an object.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(ArrayD<u64>)`: The instance segmentation labels.
+    /// * `Err(CellcastError)`: If `probs` has no spatial axes or no classes.
+    pub fn labels_from_probs(
+        probs: ArrayViewD<f32>,
+        fg_threshold: f64,
+        seed_threshold: f64,
+        min_size: usize,
+    ) -> Result<ArrayD<u64>, CellcastError> {
+        boundary_watershed(probs, fg_threshold as f32, seed_threshold as f32, min_size)
+    }
+
+    /// Check that the model accepts an input with `n_dim` spatial axes and
+    /// `n_channels` channels.
+    fn check_input(&self, n_dim: usize, n_channels: usize) -> Result<(), CellcastError> {
+        if self.config.n_dim != n_dim {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "The input image dimensions do not match the U-Net model.",
+            }));
+        }
+        if self.config.n_channel_in != n_channels {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "The input image channels do not match the U-Net model.",
+            }));
+        }
+        Ok(())
+    }
+
+    /// Pad and run the U-Net on a normalized `(C, ...spatial)` image.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(ArrayD<f32>)`: The class probabilities with shape
+    ///   `(n_classes, ...spatial)`.
+    /// * `Err(CellcastError)`: If the image can not be padded.
+    fn run(&self, norm: ArrayD<f32>) -> Result<ArrayD<f32>, CellcastError> {
+        let src_shape = norm.shape()[1..].to_vec();
+        // this pattern determines how many pixels to pad in each spatial axis
+        // to be divisible by the pooling factors as expected by the network
+        let div = self.config.div();
+        let pad_config: Vec<usize> = std::iter::once(0)
+            .chain(src_shape.iter().map(|&v| axes::divisible_pad(v, div)))
+            .collect();
+        let norm_pad = reflect_pad(&norm, &pad_config, Some(0), None)?;
+        let pad_shape = norm_pad.shape().to_vec();
+        let (raw_data, _) = norm_pad.into_raw_vec_and_offset();
+        let td = TensorData::new(raw_data, [&[1], &pad_shape[..]].concat());
+        let probs = match &self.model {
+            UNetModels::Cpu2d(m) => run_network_2d(m, td, &src_shape),
+            UNetModels::Gpu2d(m) => run_network_2d(m, td, &src_shape),
+            UNetModels::Cpu3d(m) => run_network_3d(m, td, &src_shape),
+            UNetModels::Gpu3d(m) => run_network_3d(m, td, &src_shape),
+        };
+        let shape: Vec<usize> = [&[self.config.n_classes], &src_shape[..]].concat();
+        Ok(ArrayD::from_shape_vec(IxDyn(&shape), probs).unwrap())
+    }
+
+    /// Warm up the U-Net model by running the network on an empty image.
+    fn warm_up(&self) -> Result<(), CellcastError> {
+        let size = 32.max(self.config.div());
+        let shape: Vec<usize> = std::iter::once(self.config.n_channel_in)
+            .chain(std::iter::repeat_n(size, self.config.n_dim))
+            .collect();
+        let probs = self.run(ArrayD::zeros(IxDyn(&shape)))?;
+        if probs.is_empty() {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "Failed to warm up the U-Net model.",
+            }));
+        }
+        Ok(())
+    }
+}
+
+/// Run the 2D U-Net on an input tensor and crop the output to `src_shape`.
+fn run_network_2d<B: Backend>(
+    model: &unet_2d::Model<B>,
+    td: TensorData,
+    src_shape: &[usize],
+) -> Vec<f32> {
+    let device = Default::default();
+    let probs = model.forward(Tensor::<B, 4>::from_data(td, &device));
+    let [_, n_classes, _, _] = probs.dims();
+    probs
+        .slice([0..1, 0..n_classes, 0..src_shape[0], 0..src_shape[1]])
+        .into_data()
+        .into_vec()
+        .unwrap()
+}
+
+/// Run the 3D U-Net on an input tensor and crop the output to `src_shape`.
+fn run_network_3d<B: Backend>(
+    model: &unet_3d::Model<B>,
+    td: TensorData,
+    src_shape: &[usize],
+) -> Vec<f32> {
+    let device = Default::default();
+    let probs = model.forward(Tensor::<B, 5>::from_data(td, &device));
+    let [_, n_classes, _, _, _] = probs.dims();
+    probs
+        .slice([
+            0..1,
+            0..n_classes,
+            0..src_shape[0],
+            0..src_shape[1],
+            0..src_shape[2],
+        ])
+        .into_data()
+        .into_vec()
+        .unwrap()
+}
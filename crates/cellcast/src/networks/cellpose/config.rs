@@ -0,0 +1,86 @@
+use imgal::prelude::*;
+
+use crate::CellcastError;
+
+/// Configuration of a Cellpose network.
+///
+/// Describes the architecture of a Cellpose U-Net (CPnet), following the
+/// reference Cellpose implementation. Each U-Net level is a residual block of
+/// `n_filters[n]` filters, with a style vector computed from the deepest level
+/// added to every upsampling convolution. The `cyto` and `nuclei` presets
+/// describe the networks of the published Cellpose models.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CellposeNetworkConfig {
+    /// The number of input channels.
+    pub n_channel_in: usize,
+    /// The number of filters of each U-Net level, *i.e.* the reference Cellpose
+    /// `nbase` without the input channels.
+    pub n_filters: Vec<usize>,
+    /// The convolution kernel size.
+    pub kernel_size: usize,
+    /// The mean object diameter (in pixels) of the training data. Images are
+    /// rescaled so that objects of the given `diameter` match this size.
+    pub diam_mean: f64,
+}
+
+impl CellposeNetworkConfig {
+    /// The network of the Cellpose `cyto` models (*i.e.* `cyto`, `cyto2` and
+    /// `cyto3`), with a cytoplasm and a nucleus input channel.
+    pub fn cyto() -> Self {
+        Self {
+            n_channel_in: 2,
+            n_filters: vec![32, 64, 128, 256],
+            kernel_size: 3,
+            diam_mean: 30.0,
+        }
+    }
+
+    /// The network of the Cellpose `nuclei` model.
+    pub fn nuclei() -> Self {
+        Self {
+            diam_mean: 17.0,
+            ..Self::cyto()
+        }
+    }
+
+    /// Validate the network configuration.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())`: If the network can be constructed from the configuration.
+    /// * `Err(CellcastError)`: If `n_channel_in` is `0`. If `n_filters` is
+    ///   empty or has a level without filters. If `kernel_size` is even. If
+    ///   `diam_mean` is not positive.
+    pub fn validate(&self) -> Result<(), CellcastError> {
+        if self.n_channel_in == 0 {
+            return Err(CellcastError::Imgal(
+                ImgalError::InvalidParameterValueLess {
+                    param_name: "n_channel_in",
+                    value: 1,
+                },
+            ));
+        }
+        if self.n_filters.is_empty() || self.n_filters.contains(&0) {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "The Cellpose network requires at least one level with filters.",
+            }));
+        }
+        if self.kernel_size.is_multiple_of(2) {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "The Cellpose convolution kernel size must be odd.",
+            }));
+        }
+        if self.diam_mean.is_nan() || self.diam_mean <= 0.0 {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "The Cellpose mean object diameter must be positive.",
+            }));
+        }
+        Ok(())
+    }
+
+    /// Get the value the network input height and width must be divisible
+    /// by, *i.e.* 2 to the power of the number of pooling layers.
+    pub fn div(&self) -> usize {
+        1 << self.n_filters.len().saturating_sub(1)
+    }
+}
@@ -0,0 +1,9 @@
+//! Cellpose network backbones.
+//!
+//! The Cellpose U-Net (CPnet) is constructed from a `CellposeNetworkConfig`,
+//! with presets for the published `cyto` and `nuclei` model families. Weights
+//! are loaded from burnpack files with the parameter names of the reference
+//! PyTorch implementation.
+
+pub mod config;
+pub mod unet;
@@ -0,0 +1,296 @@
+// Adapted from the CPnet network of the reference Cellpose implementation.
+// Layers are stored as Burn modules and the weights are loaded from the
+// reference PyTorch parameter names (i.e. "downsample.down.res_down_0...").
+use std::path::Path;
+
+use burn::nn::conv::{Conv2d, Conv2dConfig};
+use burn::nn::pool::{MaxPool2d, MaxPool2dConfig};
+use burn::nn::{BatchNorm, BatchNormConfig, Linear, LinearConfig, PaddingConfig2d};
+use burn::prelude::*;
+use burn_store::{BurnpackStore, KeyRemapper, ModuleSnapshot, PyTorchToBurnAdapter};
+use imgal::prelude::*;
+
+use crate::CellcastError;
+use crate::networks::cellpose::config::CellposeNetworkConfig;
+use crate::networks::stardist::unet_2d::upsample_2d;
+
+/// The number of output channels, the `(dy, dx)` flows and the cell
+/// probability logits.
+const N_OUT: usize = 3;
+
+/// A batch normalization followed by an optional ReLU and a convolution.
+#[derive(Module, Debug)]
+struct BatchConv<B: Backend> {
+    bn: BatchNorm<B>,
+    conv: Conv2d<B>,
+    #[module(skip)]
+    relu: bool,
+}
+
+impl<B: Backend> BatchConv<B> {
+    fn new(device: &B::Device, c_in: usize, c_out: usize, kernel: usize, relu: bool) -> Self {
+        let pad = kernel / 2;
+        Self {
+            bn: BatchNormConfig::new(c_in)
+                .with_epsilon(1e-5)
+                .with_momentum(0.05)
+                .init(device),
+            conv: Conv2dConfig::new([c_in, c_out], [kernel, kernel])
+                .with_padding(PaddingConfig2d::Explicit(pad, pad, pad, pad))
+                .init(device),
+            relu,
+        }
+    }
+
+    fn forward(&self, x: Tensor<B, 4>) -> Tensor<B, 4> {
+        let x = self.bn.forward(x);
+        let x = if self.relu {
+            burn::tensor::activation::relu(x)
+        } else {
+            x
+        };
+        self.conv.forward(x)
+    }
+}
+
+/// A batch convolution with the style vector added to its input.
+#[derive(Module, Debug)]
+struct StyleConv<B: Backend> {
+    full: Linear<B>,
+    conv: BatchConv<B>,
+}
+
+impl<B: Backend> StyleConv<B> {
+    fn new(device: &B::Device, c: usize, style: usize, kernel: usize) -> Self {
+        Self {
+            full: LinearConfig::new(style, c).init(device),
+            conv: BatchConv::new(device, c, c, kernel, true),
+        }
+    }
+
+    fn forward(&self, style: &Tensor<B, 2>, x: Tensor<B, 4>) -> Tensor<B, 4> {
+        let [n, c, h, w] = x.dims();
+        let feat: Tensor<B, 4> = self.full.forward(style.clone()).reshape([n, c, 1, 1]);
+        self.conv.forward(x + feat.expand([n, c, h, w]))
+    }
+}
+
+/// A residual downsampling level.
+#[derive(Module, Debug)]
+struct ResDown<B: Backend> {
+    proj: BatchConv<B>,
+    convs: Vec<BatchConv<B>>,
+}
+
+impl<B: Backend> ResDown<B> {
+    fn new(device: &B::Device, c_in: usize, c_out: usize, kernel: usize) -> Self {
+        Self {
+            proj: BatchConv::new(device, c_in, c_out, 1, false),
+            convs: (0..4)
+                .map(|t| {
+                    BatchConv::new(
+                        device,
+                        if t == 0 { c_in } else { c_out },
+                        c_out,
+                        kernel,
+                        true,
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    fn forward(&self, x: Tensor<B, 4>) -> Tensor<B, 4> {
+        let x = self.proj.forward(x.clone()) + self.convs[1].forward(self.convs[0].forward(x));
+        x.clone() + self.convs[3].forward(self.convs[2].forward(x))
+    }
+}
+
+/// A residual upsampling level with style convolutions.
+#[derive(Module, Debug)]
+struct ResUp<B: Backend> {
+    proj: BatchConv<B>,
+    conv: BatchConv<B>,
+    style_convs: Vec<StyleConv<B>>,
+}
+
+impl<B: Backend> ResUp<B> {
+    fn new(device: &B::Device, c_in: usize, c_out: usize, style: usize, kernel: usize) -> Self {
+        Self {
+            proj: BatchConv::new(device, c_in, c_out, 1, false),
+            conv: BatchConv::new(device, c_in, c_out, kernel, true),
+            style_convs: (0..3)
+                .map(|_| StyleConv::new(device, c_out, style, kernel))
+                .collect(),
+        }
+    }
+
+    fn forward(&self, x: Tensor<B, 4>, skip: Tensor<B, 4>, style: &Tensor<B, 2>) -> Tensor<B, 4> {
+        let x = self.proj.forward(x.clone())
+            + self.style_convs[0].forward(style, self.conv.forward(x) + skip);
+        x.clone() + self.style_convs[2].forward(style, self.style_convs[1].forward(style, x))
+    }
+}
+
+#[derive(Module, Debug)]
+pub struct Model<B: Backend> {
+    down: Vec<ResDown<B>>,
+    up: Vec<ResUp<B>>,
+    output: BatchConv<B>,
+    pool: MaxPool2d,
+}
+
+impl<B: Backend> Model<B> {
+    /// Create a new Cellpose U-Net with uninitialized weights.
+    ///
+    /// # Description
+    ///
+    /// Creates a Cellpose U-Net as described by `config`. The input is
+    /// downsampled by a residual block per level, the style vector is the
+    /// normalized global average of the deepest level, and the upsampling
+    /// levels add the style vector to their convolutions, matching the
+    /// reference Cellpose implementation.
+    ///
+    /// # Arguments
+    ///
+    /// * `device`: The backend device to initialize the model on.
+    /// * `config`: The network configuration.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Model)`: A new U-Net.
+    /// * `Err(CellcastError)`: If `config` is invalid.
+    pub fn new(device: &B::Device, config: &CellposeNetworkConfig) -> Result<Self, CellcastError> {
+        config.validate()?;
+        let kernel = config.kernel_size;
+        let mut n_base = vec![config.n_channel_in];
+        n_base.extend(config.n_filters.iter());
+        let down = n_base
+            .windows(2)
+            .map(|c| ResDown::new(device, c[0], c[1], kernel))
+            .collect();
+        // the deepest level is upsampled into itself, with the style vector
+        // of the deepest level filters
+        let mut n_base_up = config.n_filters.clone();
+        n_base_up.push(*n_base_up.last().unwrap());
+        let style = *n_base_up.last().unwrap();
+        let up = n_base_up
+            .windows(2)
+            .map(|c| ResUp::new(device, c[1], c[0], style, kernel))
+            .collect();
+        Ok(Self {
+            down,
+            up,
+            output: BatchConv::new(device, n_base_up[0], N_OUT, 1, true),
+            pool: MaxPool2dConfig::new([2, 2]).with_strides([2, 2]).init(),
+        })
+    }
+
+    /// Load model weights from a burnpack file into a newly constructed model.
+    ///
+    /// # Description
+    ///
+    /// Constructs a new `Model` on `device` and loads parameters from the
+    /// given burnpack file. The weights are stored with the parameter names
+    /// and layouts of the reference Cellpose PyTorch model (*e.g.*
+    /// `downsample.down.res_down_0.proj.0.weight`), which are mapped onto the
+    /// model's layers.
+    ///
+    /// # Arguments
+    ///
+    /// * `file`: Path to a burnpack file containing model weights.
+    /// * `device`: The backend device to initialize the model on.
+    /// * `config`: The network configuration.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Model)`: A `Model` with weights loaded from `file`.
+    /// * `Err(CellcastError)`: If `config` is invalid. If the weights can not
+    ///   be loaded (*e.g.* the weights do not match the configuration).
+    pub fn from_file(
+        file: &Path,
+        device: &B::Device,
+        config: &CellposeNetworkConfig,
+    ) -> Result<Self, CellcastError> {
+        let mut model = Self::new(device, config)?;
+        let mut store = BurnpackStore::from_file(file)
+            .remap(pytorch_key_remapper())
+            .with_from_adapter(PyTorchToBurnAdapter);
+        model
+            .load_from(&mut store)
+            .map_err(|_| ImgalError::InvalidGeneric {
+                msg: "Failed to load the Cellpose model weights burnpack file.",
+            })?;
+        Ok(model)
+    }
+
+    /// Run the network.
+    ///
+    /// # Arguments
+    ///
+    /// * `input`: The `(N, C, H, W)` input tensor, with height and width
+    ///   divisible by the configuration `div`.
+    ///
+    /// # Returns
+    ///
+    /// * `(Tensor<B, 4>, Tensor<B, 2>)`: The `(N, 3, H, W)` output with the
+    ///   `(dy, dx)` flows and cell probability logits, and the `(N, S)` style
+    ///   vectors.
+    pub fn forward(&self, input: Tensor<B, 4>) -> (Tensor<B, 4>, Tensor<B, 2>) {
+        let mut x = input;
+        let mut skips = Vec::with_capacity(self.down.len());
+        self.down.iter().enumerate().for_each(|(n, down)| {
+            if n > 0 {
+                x = self.pool.forward(x.clone());
+            }
+            x = down.forward(x.clone());
+            skips.push(x.clone());
+        });
+        let [n, c, _, _] = x.dims();
+        let style: Tensor<B, 2> = x.clone().mean_dim(3).mean_dim(2).reshape([n, c]);
+        let norm = style.clone().powf_scalar(2.0).sum_dim(1).sqrt();
+        let style = style / norm.expand([n, c]);
+        let n_up = self.up.len();
+        let mut x = self.up[n_up - 1].forward(x.clone(), x, &style);
+        (0..n_up - 1).rev().for_each(|n| {
+            x = upsample_2d(x.clone(), [2, 2]);
+            x = self.up[n].forward(x.clone(), skips[n].clone(), &style);
+        });
+        (self.output.forward(x), style)
+    }
+}
+
+/// Create a key remapper from the reference Cellpose PyTorch parameter names
+/// to model layers.
+fn pytorch_key_remapper() -> KeyRemapper {
+    // SAFE: the patterns are valid regular expressions
+    KeyRemapper::from_pattern_iter([
+        (
+            r"^downsample\.down\.res_down_(\d+)\.conv\.conv_(\d+)\.",
+            "down.$1.convs.$2.",
+        ),
+        (r"^downsample\.down\.res_down_(\d+)\.", "down.$1."),
+        (
+            r"^upsample\.up\.res_up_(\d+)\.conv\.conv_0\.",
+            "up.$1.conv.",
+        ),
+        (
+            r"^upsample\.up\.res_up_(\d+)\.conv\.conv_1\.",
+            "up.$1.style_convs.0.",
+        ),
+        (
+            r"^upsample\.up\.res_up_(\d+)\.conv\.conv_2\.",
+            "up.$1.style_convs.1.",
+        ),
+        (
+            r"^upsample\.up\.res_up_(\d+)\.conv\.conv_3\.",
+            "up.$1.style_convs.2.",
+        ),
+        (r"^upsample\.up\.res_up_(\d+)\.", "up.$1."),
+        // sequential batch convolutions: 0 is the batch norm, 1 (without a
+        // ReLU) or 2 (with a ReLU) the convolution
+        (r"\.0\.(weight|bias|running_mean|running_var)$", ".bn.$1"),
+        (r"\.[12]\.(weight|bias)$", ".conv.$1"),
+    ])
+    .unwrap()
+}
@@ -1,7 +1,13 @@
 //! Network backbones.
 //!
 //! This module contains network backbones for each supported model. These
-//! networks have been converted into Rust from ONNX using the `burn-onnx`
-//! crate, followed by manual modification to fit cellcast's needs.
+//! networks were originally converted into Rust from ONNX using the `burn-onnx`
+//! crate and are now constructed from a network configuration, loading
+//! weights exported from ONNX by layer name. The Cellpose network is
+//! constructed from a network configuration and loads weights by the layer
+//! names of the reference PyTorch implementation. The generic U-Nets for
+//! semantic segmentation are constructed from a U-Net configuration.
 
+pub mod cellpose;
 pub mod stardist;
+pub mod unet;
@@ -0,0 +1,296 @@
+use std::path::Path;
+
+use imgal::prelude::*;
+
+use crate::CellcastError;
+use crate::config::stardist;
+
+/// StarDist network backbones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StarDistBackbone {
+    Unet,
+    Resnet,
+}
+
+/// Configuration of a StarDist network backbone.
+///
+/// Describes the architecture of a StarDist network, following the parameter
+/// names of the reference StarDist implementation. Per-axis parameters (*e.g.*
+/// `grid` and kernel sizes) have one value for each spatial axis, in
+/// `(row, col)` order for 2D networks and `(pln, row, col)` order for 3D
+/// networks. The `fluo_2d`, `he_2d` and `fluo_3d` presets describe the networks
+/// of the published pretrained weights.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StarDistNetworkConfig {
+    /// The number of spatial dimensions, 2 or 3.
+    pub n_dim: usize,
+    /// The network backbone.
+    pub backbone: StarDistBackbone,
+    /// The number of input channels.
+    pub n_channel_in: usize,
+    /// The number of radial distances (rays) predicted per pixel/voxel.
+    pub n_rays: usize,
+    /// The subsampling factors of the network output, each a power of 2.
+    pub grid: Vec<usize>,
+    /// The number of U-Net down and up sampling levels.
+    pub unet_n_depth: usize,
+    /// The U-Net convolution kernel size.
+    pub unet_kernel_size: Vec<usize>,
+    /// The number of filters of the first U-Net level.
+    pub unet_n_filter_base: usize,
+    /// The number of convolutions per U-Net level.
+    pub unet_n_conv_per_depth: usize,
+    /// The U-Net pooling (and upsampling) factors.
+    pub unet_pool: Vec<usize>,
+    /// The number of residual blocks.
+    pub resnet_n_blocks: usize,
+    /// The residual block convolution kernel size.
+    pub resnet_kernel_size: Vec<usize>,
+    /// The number of filters of the first residual block.
+    pub resnet_n_filter_base: usize,
+    /// The number of convolutions per residual block.
+    pub resnet_n_conv_per_block: usize,
+    /// The number of filters of the feature layer after the backbone.
+    pub net_conv_after_unet: usize,
+    /// The number of object classes of the classification head (2D networks
+    /// only). If `None` then the network has no classification head.
+    pub n_classes: Option<usize>,
+}
+
+impl StarDistNetworkConfig {
+    /// The network of the versatile fluo StarDist2D pretrained weights.
+    pub fn fluo_2d() -> Self {
+        Self {
+            n_dim: 2,
+            backbone: StarDistBackbone::Unet,
+            n_channel_in: 1,
+            n_rays: 32,
+            grid: vec![2, 2],
+            unet_n_depth: 3,
+            unet_kernel_size: vec![3, 3],
+            unet_n_filter_base: 32,
+            unet_n_conv_per_depth: 2,
+            unet_pool: vec![2, 2],
+            resnet_n_blocks: 4,
+            resnet_kernel_size: vec![3, 3],
+            resnet_n_filter_base: 32,
+            resnet_n_conv_per_block: 3,
+            net_conv_after_unet: 128,
+            n_classes: None,
+        }
+    }
+
+    /// The network of the versatile HE StarDist2D pretrained weights.
+    pub fn he_2d() -> Self {
+        Self {
+            n_channel_in: 3,
+            ..Self::fluo_2d()
+        }
+    }
+
+    /// The network of the 3D demo StarDist3D pretrained weights.
+    pub fn fluo_3d() -> Self {
+        Self {
+            n_dim: 3,
+            backbone: StarDistBackbone::Resnet,
+            n_channel_in: 1,
+            n_rays: 96,
+            grid: vec![1, 2, 2],
+            unet_n_depth: 2,
+            unet_kernel_size: vec![3, 3, 3],
+            unet_n_filter_base: 32,
+            unet_n_conv_per_depth: 2,
+            unet_pool: vec![2, 2, 2],
+            resnet_n_blocks: 4,
+            resnet_kernel_size: vec![3, 3, 3],
+            resnet_n_filter_base: 32,
+            resnet_n_conv_per_block: 3,
+            net_conv_after_unet: 128,
+            n_classes: None,
+        }
+    }
+
+    /// Read the network configuration from a StarDist `config.json` file.
+    ///
+    /// # Description
+    ///
+    /// Reads the network configuration of a model trained with the reference
+    /// StarDist. Parameters missing from the file fall back to the `fluo_2d`
+    /// or `fluo_3d` preset with the same number of dimensions.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: The path to the `config.json` file.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(StarDistNetworkConfig)`: The network configuration.
+    /// * `Err(CellcastError)`: If the file can not be read or parsed. If the
+    ///   model is not 2D or 3D. If the axes or backbone are not supported. If
+    ///   the configuration is invalid.
+    pub fn from_config_file(path: &str) -> Result<Self, CellcastError> {
+        stardist::read_config(Path::new(path), None).map(|(network, _)| network)
+    }
+
+    /// Validate the network configuration.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())`: If the network can be constructed from the configuration.
+    /// * `Err(CellcastError)`: If `n_dim` is not 2 or 3. If a per-axis
+    ///   parameter does not have `n_dim` values. If a `grid` factor is not a
+    ///   power of 2. If a kernel size is even. If `n_rays` is too small for
+    ///   `n_dim`. If a level or block has too few convolutions. If a 2D ResNet
+    ///   is requested. If the ResNet has too few blocks to subsample by `grid`.
+    ///   If `n_classes` is `0` or given for a 3D network.
+    pub fn validate(&self) -> Result<(), CellcastError> {
+        if !(2..=3).contains(&self.n_dim) {
+            return Err(CellcastError::Imgal(
+                ImgalError::InvalidParameterValueOutsideRange {
+                    param_name: "n_dim",
+                    value: self.n_dim as f64,
+                    min: 2.0,
+                    max: 3.0,
+                },
+            ));
+        }
+        for (name, v) in [
+            ("grid", &self.grid),
+            ("unet_kernel_size", &self.unet_kernel_size),
+            ("unet_pool", &self.unet_pool),
+            ("resnet_kernel_size", &self.resnet_kernel_size),
+        ] {
+            if v.len() != self.n_dim {
+                return Err(CellcastError::Imgal(
+                    ImgalError::InvalidArrayLengthExpected {
+                        arr_name: name,
+                        expected: self.n_dim,
+                        got: v.len(),
+                    },
+                ));
+            }
+        }
+        if !self.grid.iter().all(|g| g.is_power_of_two()) {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "The StarDist grid factors must be powers of 2.",
+            }));
+        }
+        if self.unet_pool.contains(&0) {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "The StarDist U-Net pooling factors must be greater than 0.",
+            }));
+        }
+        if self
+            .unet_kernel_size
+            .iter()
+            .chain(self.resnet_kernel_size.iter())
+            .any(|k| k % 2 == 0)
+        {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "The StarDist convolution kernel sizes must be odd.",
+            }));
+        }
+        if self.unet_n_conv_per_depth == 0 {
+            return Err(CellcastError::Imgal(
+                ImgalError::InvalidParameterValueLess {
+                    param_name: "unet_n_conv_per_depth",
+                    value: 1,
+                },
+            ));
+        }
+        let min_rays = self.n_dim + 1;
+        if self.n_rays < min_rays {
+            return Err(CellcastError::Imgal(
+                ImgalError::InvalidParameterValueLess {
+                    param_name: "n_rays",
+                    value: min_rays,
+                },
+            ));
+        }
+        if self.n_channel_in == 0 {
+            return Err(CellcastError::Imgal(
+                ImgalError::InvalidParameterValueLess {
+                    param_name: "n_channel_in",
+                    value: 1,
+                },
+            ));
+        }
+        if let Some(n_classes) = self.n_classes {
+            if n_classes == 0 {
+                return Err(CellcastError::Imgal(
+                    ImgalError::InvalidParameterValueLess {
+                        param_name: "n_classes",
+                        value: 1,
+                    },
+                ));
+            }
+            if self.n_dim != 2 {
+                return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                    msg: "Multi-class StarDist networks are only supported in 2D.",
+                }));
+            }
+        }
+        if self.backbone == StarDistBackbone::Resnet {
+            if self.n_dim == 2 {
+                return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                    msg: "The ResNet backbone is only supported for 3D StarDist networks.",
+                }));
+            }
+            if self.resnet_n_conv_per_block < 2 {
+                return Err(CellcastError::Imgal(
+                    ImgalError::InvalidParameterValueLess {
+                        param_name: "resnet_n_conv_per_block",
+                        value: 2,
+                    },
+                ));
+            }
+            let max_grid = self.grid.iter().max().copied().unwrap_or(1);
+            if max_grid.trailing_zeros() as usize > self.resnet_n_blocks {
+                return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                    msg: "The StarDist ResNet has too few blocks to subsample by the grid.",
+                }));
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the per-axis values the network input shape must be divisible by.
+    ///
+    /// A U-Net subsamples its input by the grid and then by the pooling factor
+    /// at each level, a ResNet only by the grid.
+    pub fn div(&self) -> Vec<usize> {
+        match self.backbone {
+            StarDistBackbone::Unet => self
+                .grid
+                .iter()
+                .zip(self.unet_pool.iter())
+                .map(|(g, p)| g * p.pow(self.unet_n_depth as u32))
+                .collect(),
+            StarDistBackbone::Resnet => self.grid.clone(),
+        }
+    }
+
+    /// Get the pooling factors of the stages that subsample the input by the
+    /// grid.
+    ///
+    /// Mirrors the reference StarDist implementation, where each stage
+    /// subsamples the axes that have not yet reached their grid factor by 2.
+    pub fn grid_pool_sizes(&self) -> Vec<Vec<usize>> {
+        let mut pooled = vec![1; self.grid.len()];
+        let mut sizes = Vec::new();
+        while pooled != self.grid {
+            let pool: Vec<usize> = self
+                .grid
+                .iter()
+                .zip(pooled.iter())
+                .map(|(g, p)| if g > p { 2 } else { 1 })
+                .collect();
+            pooled
+                .iter_mut()
+                .zip(pool.iter())
+                .for_each(|(p, f)| *p *= f);
+            sizes.push(pool);
+        }
+        sizes
+    }
+}
@@ -0,0 +1,172 @@
+use std::path::Path;
+
+use imgal::prelude::*;
+use serde_json::{Value, json};
+
+use crate::CellcastError;
+use crate::config::stardist;
+use crate::networks::stardist::config::StarDistNetworkConfig;
+use crate::utils::burnpack;
+
+/// The burnpack metadata key of the StarDist metadata block.
+pub(crate) const METADATA_KEY: &str = "cellcast.stardist";
+
+/// Metadata of StarDist weights, stored with a burnpack (`.bpk`) file.
+///
+/// Describes the model that a set of weights was trained as, so that the
+/// weights can be loaded without passing the network configuration,
+/// anisotropy, thresholds or normalization separately. The metadata is stored
+/// as a JSON object in the burnpack file metadata, with the network
+/// configuration and anisotropy in the layout of a StarDist `config.json`
+/// file. Values that are `None` fall back to the model defaults when the
+/// weights are loaded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StarDistMetadata {
+    /// The network configuration, *i.e.* the model type (2D or 3D), ray count,
+    /// grid and number of input channels.
+    pub network: StarDistNetworkConfig,
+    /// The version of the trained model.
+    pub version: Option<String>,
+    /// The anisotropy the model was trained with (3D models only).
+    pub anisotropy: Option<[f32; 3]>,
+    /// The default object probability threshold.
+    pub prob_threshold: Option<f64>,
+    /// The default non-maximum suppression threshold.
+    pub nms_threshold: Option<f64>,
+    /// The default `(pmin, pmax)` normalization percentiles.
+    pub percentiles: Option<(f64, f64)>,
+    /// A description of the model and its training provenance.
+    pub description: Option<String>,
+    /// The license of the weights.
+    pub license: Option<String>,
+}
+
+impl StarDistMetadata {
+    /// Create new StarDist metadata for a network configuration.
+    ///
+    /// # Arguments
+    ///
+    /// * `network`: The network configuration of the weights.
+    ///
+    /// # Returns
+    ///
+    /// * `StarDistMetadata`: The metadata, with all other values `None`.
+    pub fn new(network: StarDistNetworkConfig) -> Self {
+        Self {
+            network,
+            version: None,
+            anisotropy: None,
+            prob_threshold: None,
+            nms_threshold: None,
+            percentiles: None,
+            description: None,
+            license: None,
+        }
+    }
+
+    /// Get the model type, `"StarDist2D"` or `"StarDist3D"`.
+    pub fn model_type(&self) -> &'static str {
+        if self.network.n_dim == 2 {
+            "StarDist2D"
+        } else {
+            "StarDist3D"
+        }
+    }
+
+    /// Read the StarDist metadata of a burnpack file.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: The path to the burnpack (`.bpk`) weights file.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Some(StarDistMetadata))`: The metadata of the weights.
+    /// * `Ok(None)`: If the weights have no StarDist metadata.
+    /// * `Err(CellcastError)`: If the file can not be read or is not a
+    ///   burnpack file. If the metadata is invalid.
+    pub fn from_file(path: &str) -> Result<Option<Self>, CellcastError> {
+        let metadata =
+            burnpack::read_metadata(Path::new(path)).ok_or(ImgalError::InvalidGeneric {
+                msg: "Failed to read the burnpack weights file.",
+            })?;
+        let Some(text) = metadata.get(METADATA_KEY) else {
+            return Ok(None);
+        };
+        let invalid = || ImgalError::InvalidGeneric {
+            msg: "The burnpack weights file has invalid StarDist metadata.",
+        };
+        let json: Value = serde_json::from_str(text).map_err(|_| invalid())?;
+        let (network, anisotropy) =
+            stardist::parse_config(json.get("config").ok_or_else(invalid)?, None)?;
+        let mut metadata = Self::new(network);
+        if json.get("model_type").and_then(Value::as_str) != Some(metadata.model_type()) {
+            return Err(CellcastError::Imgal(invalid()));
+        }
+        let string = |key: &str| json.get(key).and_then(Value::as_str).map(String::from);
+        let number = |obj: &str, key: &str| json.get(obj)?.get(key)?.as_f64();
+        metadata.version = string("version");
+        metadata.anisotropy = anisotropy;
+        metadata.prob_threshold = number("thresholds", "prob");
+        metadata.nms_threshold = number("thresholds", "nms");
+        metadata.percentiles = number("normalization", "pmin").zip(number("normalization", "pmax"));
+        metadata.description = string("description");
+        metadata.license = string("license");
+        Ok(Some(metadata))
+    }
+
+    /// Write the StarDist metadata to a burnpack file.
+    ///
+    /// # Description
+    ///
+    /// Stores the metadata in an exported burnpack weights file, replacing any
+    /// existing StarDist metadata. The weights are not changed.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: The path to the burnpack (`.bpk`) weights file.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())`: If the metadata was written.
+    /// * `Err(CellcastError)`: If the network configuration is invalid. If the
+    ///   file can not be read or written or is not a burnpack file.
+    pub fn write_to_file(&self, path: &str) -> Result<(), CellcastError> {
+        self.network.validate()?;
+        burnpack::write_metadata(Path::new(path), METADATA_KEY, &self.to_json().to_string()).ok_or(
+            CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "Failed to write the burnpack weights file metadata.",
+            }),
+        )
+    }
+
+    /// Create the JSON object of the metadata.
+    pub(crate) fn to_json(&self) -> Value {
+        let mut json = json!({
+            "model_type": self.model_type(),
+            "cellcast_version": env!("CARGO_PKG_VERSION"),
+            "config": stardist::config_json(&self.network, self.anisotropy),
+        });
+        if let Some(version) = &self.version {
+            json["version"] = json!(version);
+        }
+        let mut thresholds = json!({});
+        if let Some(prob) = self.prob_threshold {
+            thresholds["prob"] = json!(prob);
+        }
+        if let Some(nms) = self.nms_threshold {
+            thresholds["nms"] = json!(nms);
+        }
+        json["thresholds"] = thresholds;
+        if let Some((pmin, pmax)) = self.percentiles {
+            json["normalization"] = json!({"pmin": pmin, "pmax": pmax});
+        }
+        if let Some(description) = &self.description {
+            json["description"] = json!(description);
+        }
+        if let Some(license) = &self.license {
+            json["license"] = json!(license);
+        }
+        json
+    }
+}
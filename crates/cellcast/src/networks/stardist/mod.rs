@@ -1,3 +1,152 @@
-pub mod fluo_2d;
-pub mod fluo_3d;
-pub mod he_2d;
+//! StarDist network backbones.
+//!
+//! The StarDist networks are constructed from a `StarDistNetworkConfig`, with
+//! presets for each of the published pretrained weights. Weights are loaded
+//! from burnpack files, with optional embedded model metadata, or read from
+//! ONNX files at runtime. User-defined networks plug into the StarDist
+//! postprocessing by implementing `StarDistNetwork2D` or `StarDistNetwork3D`.
+
+pub mod config;
+pub mod metadata;
+pub mod network;
+pub mod onnx;
+pub mod resnet_3d;
+pub mod unet_2d;
+pub mod unet_3d;
+
+use std::path::Path;
+
+use burn::module::Param;
+use burn::prelude::*;
+use imgal::prelude::*;
+
+use crate::CellcastError;
+use config::{StarDistBackbone, StarDistNetworkConfig};
+use network::StarDistNetwork3D;
+
+/// The weights and bias of a convolution layer, with the weight in
+/// `(c_out, c_in, ...kernel)` layout.
+#[derive(Debug, Clone)]
+pub struct ConvWeights {
+    pub weight: TensorData,
+    pub bias: Option<TensorData>,
+}
+
+/// The source of StarDist network weights.
+#[derive(Debug, Clone, Copy)]
+pub enum StarDistWeights<'a> {
+    /// A burnpack file with ONNX layer names.
+    Burnpack(&'a Path),
+    /// Convolution weights in ONNX graph order.
+    Convs(&'a [ConvWeights]),
+}
+
+/// A StarDist3D network with either a U-Net or ResNet backbone.
+#[derive(Debug)]
+pub enum Network3d<B: Backend> {
+    Unet(unet_3d::Model<B>),
+    Resnet(resnet_3d::Model<B>),
+}
+
+impl<B: Backend> Network3d<B> {
+    /// Load a StarDist3D network with the backbone of `config`.
+    ///
+    /// # Arguments
+    ///
+    /// * `weights`: The source of the model weights.
+    /// * `device`: The backend device to initialize the model on.
+    /// * `config`: The network configuration.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Network3d)`: The network with weights loaded from `weights`.
+    /// * `Err(CellcastError)`: If `config` is invalid. If the weights can not
+    ///   be loaded.
+    pub fn from_weights(
+        weights: StarDistWeights,
+        device: &B::Device,
+        config: &StarDistNetworkConfig,
+    ) -> Result<Self, CellcastError> {
+        Ok(match (config.backbone, weights) {
+            (StarDistBackbone::Unet, StarDistWeights::Burnpack(file)) => {
+                Self::Unet(unet_3d::Model::from_file(file, device, config)?)
+            }
+            (StarDistBackbone::Unet, StarDistWeights::Convs(convs)) => {
+                Self::Unet(unet_3d::Model::from_conv_weights(convs, device, config)?)
+            }
+            (StarDistBackbone::Resnet, StarDistWeights::Burnpack(file)) => {
+                Self::Resnet(resnet_3d::Model::from_file(file, device, config)?)
+            }
+            (StarDistBackbone::Resnet, StarDistWeights::Convs(convs)) => {
+                Self::Resnet(resnet_3d::Model::from_conv_weights(convs, device, config)?)
+            }
+        })
+    }
+
+    /// Run the network, see the backbone `forward` methods.
+    pub fn forward(&self, input: Tensor<B, 5>) -> (Tensor<B, 5>, Tensor<B, 5>) {
+        match self {
+            Self::Unet(m) => m.forward(input),
+            Self::Resnet(m) => m.forward(input),
+        }
+    }
+}
+
+impl<B: Backend> StarDistNetwork3D<B> for Network3d<B> {
+    fn forward_prob_dist(&self, input: Tensor<B, 5>) -> (Tensor<B, 5>, Tensor<B, 5>) {
+        let (prob, dist) = self.forward(input);
+        (prob.permute([0, 4, 1, 2, 3]), dist)
+    }
+}
+
+/// Load convolution weights into convolution layers.
+///
+/// # Arguments
+///
+/// * `convs`: The `(weight, bias)` parameters of the convolution layers, in
+///   ONNX graph order.
+/// * `weights`: The convolution weights, in ONNX graph order.
+///
+/// # Returns
+///
+/// * `Ok(())`: If the weights were loaded.
+/// * `Err(CellcastError)`: If the number of layers or a weight shape does not
+///   match.
+pub(crate) fn load_convs<'a, B, const D: usize, I>(
+    convs: I,
+    weights: &[ConvWeights],
+) -> Result<(), CellcastError>
+where
+    B: Backend,
+    I: ExactSizeIterator<
+        Item = (
+            &'a mut Param<Tensor<B, D>>,
+            &'a mut Option<Param<Tensor<B, 1>>>,
+        ),
+    >,
+{
+    let mismatch = ImgalError::InvalidGeneric {
+        msg: "The convolution weights do not match the StarDist network configuration.",
+    };
+    if convs.len() != weights.len() {
+        return Err(CellcastError::Imgal(mismatch));
+    }
+    for ((weight, bias), w) in convs.zip(weights.iter()) {
+        let device = weight.device();
+        if w.weight.shape.to_vec() != weight.dims().to_vec() {
+            return Err(CellcastError::Imgal(mismatch));
+        }
+        *weight = Param::from_tensor(Tensor::from_data(w.weight.clone(), &device));
+        if let Some(bias) = bias {
+            let dims = bias.dims();
+            *bias = match &w.bias {
+                Some(b) if b.shape.to_vec() == dims.to_vec() => {
+                    Param::from_tensor(Tensor::from_data(b.clone(), &device))
+                }
+                Some(_) => return Err(CellcastError::Imgal(mismatch)),
+                None => Param::from_tensor(Tensor::zeros(dims, &device)),
+            };
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,49 @@
+use burn::prelude::*;
+
+/// A network predicting StarDist2D object probabilities and ray distances.
+///
+/// Implement this trait to run any Burn network with StarDist-style heads
+/// through the StarDist2D normalization, padding and postprocessing (*i.e.*
+/// candidate extraction, polygon non-maximum suppression and labeling). The
+/// output is subsampled by the grid of the model configuration.
+pub trait StarDistNetwork2D<B: Backend> {
+    /// Run the network.
+    ///
+    /// # Arguments
+    ///
+    /// * `input`: The percentile normalized input tensor with shape
+    ///   `(1, C, H, W)`, with height and width divisible by the model
+    ///   configuration `div`.
+    ///
+    /// # Returns
+    ///
+    /// * `(Tensor<B, 4>, Tensor<B, 4>)`: The object probabilities with shape
+    ///   `(1, 1, H / grid.0, W / grid.1)` and ray distances with shape
+    ///   `(1, n_rays, H / grid.0, W / grid.1)`.
+    fn forward_prob_dist(&self, input: Tensor<B, 4>) -> (Tensor<B, 4>, Tensor<B, 4>);
+}
+
+/// A network predicting StarDist3D object probabilities and ray distances.
+///
+/// Implement this trait to run any Burn network with StarDist-style heads
+/// through the StarDist3D normalization, padding and postprocessing (*i.e.*
+/// candidate extraction, polyhedron non-maximum suppression and labeling).
+/// The output is subsampled by the grid of the model configuration and the
+/// rays follow the golden spiral of the reference StarDist implementation.
+pub trait StarDistNetwork3D<B: Backend> {
+    /// Run the network.
+    ///
+    /// # Arguments
+    ///
+    /// * `input`: The percentile normalized input tensor with shape
+    ///   `(1, C, D, H, W)`, each spatial axis divisible by the model
+    ///   configuration `div`.
+    ///
+    /// # Returns
+    ///
+    /// * `(Tensor<B, 5>, Tensor<B, 5>)`: The object probabilities with shape
+    ///   `(1, 1, D', H', W')` and ray distances with shape
+    ///   `(1, n_rays, D', H', W')`, where `D'`, `H'` and `W'` are the input
+    ///   shape divided by the grid.
+    fn forward_prob_dist(&self, input: Tensor<B, 5>) -> (Tensor<B, 5>, Tensor<B, 5>);
+}
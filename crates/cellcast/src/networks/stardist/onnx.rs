@@ -0,0 +1,279 @@
+use std::path::Path;
+
+use imgal::prelude::*;
+use onnx_ir::{Node, OnnxGraph, OnnxGraphBuilder};
+
+use crate::CellcastError;
+use crate::networks::stardist::ConvWeights;
+use crate::networks::stardist::config::{StarDistBackbone, StarDistNetworkConfig};
+
+/// A StarDist network read from an ONNX file.
+#[derive(Debug)]
+pub(crate) struct OnnxStarDist {
+    pub network: StarDistNetworkConfig,
+    pub convs: Vec<ConvWeights>,
+}
+
+/// A convolution layer of an ONNX graph.
+struct OnnxConv {
+    kernel: Vec<usize>,
+    stride: Vec<usize>,
+    c_in: usize,
+    c_out: usize,
+    weights: ConvWeights,
+}
+
+/// Read a StarDist network from an ONNX file.
+///
+/// # Description
+///
+/// Parses an ONNX file and validates that it is a StarDist network, *i.e.*
+/// a single `n_dim` dimensional image input, object probability and ray
+/// distance outputs (or a single output concatenating both), and a 1x1
+/// convolution head for each output. The
+/// convolution weights are collected in graph order, with the ray distance
+/// head before the object probability head. If `network` is `None`, the
+/// network configuration is inferred from the graph: the number of pooling
+/// and upsampling layers of a U-Net or the strided convolutions and residual
+/// additions of a ResNet.
+///
+/// # Arguments
+///
+/// * `path`: The path to the ONNX file.
+/// * `n_dim`: The expected number of spatial dimensions, 2 or 3.
+/// * `network`: The network configuration of the ONNX file. If `None` then
+///   the configuration is inferred from the graph. The number of input
+///   channels and rays are always read from the graph.
+///
+/// # Returns
+///
+/// * `Ok(OnnxStarDist)`: The network configuration and convolution weights.
+/// * `Err(CellcastError)`: If the ONNX file can not be parsed. If the input or
+///   outputs do not match a StarDist network. If the network configuration
+///   can not be inferred.
+pub(crate) fn read_onnx(
+    path: &Path,
+    n_dim: usize,
+    network: Option<StarDistNetworkConfig>,
+) -> Result<OnnxStarDist, CellcastError> {
+    let graph =
+        OnnxGraphBuilder::new()
+            .parse_file(path)
+            .map_err(|_| ImgalError::InvalidGeneric {
+                msg: "Failed to read the StarDist ONNX file.",
+            })?;
+    read_graph(graph, n_dim, network)
+}
+
+/// Read a StarDist network from the contents of an ONNX file.
+///
+/// # Description
+///
+/// Reads a StarDist network from an ONNX file loaded in memory (*e.g.* read
+/// from a model package), see `read_onnx`.
+///
+/// # Arguments
+///
+/// * `data`: The contents of the ONNX file.
+/// * `n_dim`: The expected number of spatial dimensions, 2 or 3.
+/// * `network`: The network configuration of the ONNX file. If `None` then
+///   the configuration is inferred from the graph.
+///
+/// # Returns
+///
+/// * `Ok(OnnxStarDist)`: The network configuration and convolution weights.
+/// * `Err(CellcastError)`: If the ONNX file can not be parsed or is not a
+///   StarDist network.
+pub(crate) fn read_onnx_bytes(
+    data: &[u8],
+    n_dim: usize,
+    network: Option<StarDistNetworkConfig>,
+) -> Result<OnnxStarDist, CellcastError> {
+    let graph =
+        OnnxGraphBuilder::new()
+            .parse_bytes(data)
+            .map_err(|_| ImgalError::InvalidGeneric {
+                msg: "Failed to read the StarDist ONNX file.",
+            })?;
+    read_graph(graph, n_dim, network)
+}
+
+/// Read a StarDist network from a parsed ONNX graph.
+fn read_graph(
+    graph: OnnxGraph,
+    n_dim: usize,
+    network: Option<StarDistNetworkConfig>,
+) -> Result<OnnxStarDist, CellcastError> {
+    let rank = n_dim + 2;
+    if graph.inputs.len() != 1 || graph.inputs[0].ty.rank() != rank {
+        return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+            msg: "The ONNX model input does not match a StarDist model.",
+        }));
+    }
+    // the outputs are either separate or concatenated along the channel axis
+    let n_outputs = graph.outputs.len();
+    if !(1..=2).contains(&n_outputs) || graph.outputs.iter().any(|o| o.ty.rank() != rank) {
+        return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+            msg: "The ONNX model outputs do not match the StarDist probability and distance outputs.",
+        }));
+    }
+    let mut convs = Vec::new();
+    let mut pools: Vec<Vec<usize>> = Vec::new();
+    let mut n_upsample = 0;
+    let mut n_add = 0;
+    let mut has_sigmoid = false;
+    for node in graph.nodes.iter() {
+        match node {
+            Node::Conv2d(n) if n_dim == 2 => convs.push(read_conv(
+                node,
+                &n.config.kernel_size,
+                &n.config.stride,
+                &n.config.dilation,
+                n.config.groups,
+            )?),
+            Node::Conv3d(n) if n_dim == 3 => convs.push(read_conv(
+                node,
+                &n.config.kernel_size,
+                &n.config.stride,
+                &n.config.dilation,
+                n.config.groups,
+            )?),
+            Node::MaxPool2d(n) if n_dim == 2 => pools.push(n.config.kernel_size.to_vec()),
+            Node::MaxPool3d(n) if n_dim == 3 => pools.push(n.config.kernel_size.to_vec()),
+            Node::Resize(_) | Node::Upsample(_) => n_upsample += 1,
+            Node::Add(_) => n_add += 1,
+            Node::Sigmoid(_) => has_sigmoid = true,
+            _ => (),
+        }
+    }
+    let n = convs.len();
+    if n < 4 || !has_sigmoid {
+        return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+            msg: "The ONNX model does not have StarDist probability and distance heads.",
+        }));
+    }
+    // the object probability head has a single output channel, the model
+    // convolutions are ordered with the ray distance head first
+    if convs[n - 2].c_out == 1 {
+        convs.swap(n - 2, n - 1);
+    }
+    let (feature, dist, prob) = (&convs[n - 3], &convs[n - 2], &convs[n - 1]);
+    let is_head = |c: &OnnxConv| c.kernel.iter().all(|&k| k == 1) && c.c_in == feature.c_out;
+    if prob.c_out != 1 || !is_head(dist) || !is_head(prob) {
+        return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+            msg: "The ONNX model does not have StarDist probability and distance heads.",
+        }));
+    }
+    let mut network = match network {
+        Some(network) => network,
+        None => infer_network(&convs, &pools, n_upsample, n_add, n_dim).ok_or(
+            ImgalError::InvalidGeneric {
+                msg: "Failed to infer the StarDist network configuration of the ONNX model.",
+            },
+        )?,
+    };
+    network.n_channel_in = convs[0].c_in;
+    network.n_rays = dist.c_out;
+    network.validate()?;
+    Ok(OnnxStarDist {
+        network,
+        convs: convs.into_iter().map(|c| c.weights).collect(),
+    })
+}
+
+/// Read a convolution node of an ONNX graph.
+fn read_conv(
+    node: &Node,
+    kernel: &[usize],
+    stride: &[usize],
+    dilation: &[usize],
+    groups: usize,
+) -> Result<OnnxConv, CellcastError> {
+    let inputs = node.inputs();
+    let weight = inputs.get(1).and_then(|a| a.value());
+    let weight = match weight {
+        Some(w) if groups == 1 && dilation.iter().all(|&d| d == 1) && w.shape.len() >= 2 => w,
+        _ => {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "The ONNX model has a convolution not supported by StarDist networks.",
+            }));
+        }
+    };
+    Ok(OnnxConv {
+        kernel: kernel.to_vec(),
+        stride: stride.to_vec(),
+        c_in: weight.shape[1],
+        c_out: weight.shape[0],
+        weights: ConvWeights {
+            weight,
+            bias: inputs.get(2).and_then(|a| a.value()),
+        },
+    })
+}
+
+/// Infer the StarDist network configuration of an ONNX graph.
+///
+/// A U-Net has a max pooling layer for each grid stage and level, and an
+/// upsampling layer for each level. A ResNet has strided convolutions to
+/// subsample by the grid and a residual addition for each block.
+fn infer_network(
+    convs: &[OnnxConv],
+    pools: &[Vec<usize>],
+    n_upsample: usize,
+    n_add: usize,
+    n_dim: usize,
+) -> Option<StarDistNetworkConfig> {
+    let mut network = if n_dim == 2 {
+        StarDistNetworkConfig::fluo_2d()
+    } else {
+        StarDistNetworkConfig::fluo_3d()
+    };
+    let n = convs.len();
+    let feature = &convs[n - 3];
+    network.net_conv_after_unet = feature.c_out;
+    let body = &convs[..n - 3];
+    let strided = body.iter().any(|c| c.stride.iter().any(|&s| s > 1));
+    if n_add > 0 || strided {
+        network.backbone = StarDistBackbone::Resnet;
+        network.resnet_n_filter_base = convs[0].c_out;
+        network.unet_kernel_size = feature.kernel.clone();
+        // the two input convolutions are followed by the residual blocks
+        let blocks = body.get(2..)?;
+        let is_shortcut = |c: &OnnxConv| c.kernel.iter().all(|&k| k == 1);
+        let n_block_convs = blocks.iter().filter(|c| !is_shortcut(c)).count();
+        if n_add == 0 || !n_block_convs.is_multiple_of(n_add) {
+            return None;
+        }
+        network.resnet_n_blocks = n_add;
+        network.resnet_n_conv_per_block = n_block_convs / n_add;
+        network.resnet_kernel_size = blocks.first()?.kernel.clone();
+        network.grid = blocks
+            .iter()
+            .filter(|c| !is_shortcut(c))
+            .fold(vec![1; n_dim], |grid, c| {
+                grid.iter()
+                    .zip(c.stride.iter())
+                    .map(|(g, s)| g * s)
+                    .collect()
+            });
+    } else {
+        network.backbone = StarDistBackbone::Unet;
+        let n_depth = n_upsample;
+        let n_grid = pools.len().checked_sub(n_depth)?;
+        let n_stages = n_grid + 2 * n_depth + 1;
+        if body.is_empty() || !body.len().is_multiple_of(n_stages) {
+            return None;
+        }
+        network.unet_n_depth = n_depth;
+        network.unet_n_conv_per_depth = body.len() / n_stages;
+        network.unet_n_filter_base = convs[0].c_out;
+        network.unet_kernel_size = convs[0].kernel.clone();
+        network.grid = pools[..n_grid].iter().fold(vec![1; n_dim], |grid, p| {
+            grid.iter().zip(p.iter()).map(|(g, p)| g * p).collect()
+        });
+        if let Some(pool) = pools.last().filter(|_| n_depth > 0) {
+            network.unet_pool = pool.clone();
+        }
+    }
+    Some(network)
+}
@@ -0,0 +1,264 @@
+// Adapted from the StarDist3D network generated from ONNX using burn-import.
+// Convolution layers are stored in ONNX graph order, allowing weights exported
+// from ONNX (i.e. "conv3d1" ... "conv3dN") to be loaded for any ray count and
+// grid configuration.
+use std::path::Path;
+
+use burn::nn::PaddingConfig3d;
+use burn::nn::conv::{Conv3d, Conv3dConfig};
+use burn::prelude::*;
+use burn::tensor::ops::PadMode;
+use burn_store::{BurnpackStore, KeyRemapper, ModuleSnapshot};
+use imgal::prelude::*;
+
+use crate::CellcastError;
+use crate::networks::stardist::config::{StarDistBackbone, StarDistNetworkConfig};
+use crate::networks::stardist::{ConvWeights, load_convs};
+
+#[derive(Module, Debug)]
+pub struct Model<B: Backend> {
+    convs: Vec<Conv3d<B>>,
+    #[module(skip)]
+    block_pools: Vec<[usize; 3]>,
+    #[module(skip)]
+    block_shortcuts: Vec<bool>,
+    #[module(skip)]
+    kernel_size: [usize; 3],
+    #[module(skip)]
+    n_conv_per_block: usize,
+}
+
+impl<B: Backend> Model<B> {
+    /// Create a new StarDist3D ResNet with uninitialized weights.
+    ///
+    /// # Description
+    ///
+    /// Creates a StarDist3D ResNet as described by `config`, whose output is
+    /// subsampled by the grid. The subsampling is performed by strided
+    /// convolutions in the first residual blocks, where each block subsamples
+    /// the axes that have not yet reached their grid factor by 2, matching the
+    /// reference StarDist implementation.
+    ///
+    /// # Arguments
+    ///
+    /// * `device`: The backend device to initialize the model on.
+    /// * `config`: The network configuration.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Model)`: A new ResNet.
+    /// * `Err(CellcastError)`: If `config` is invalid or does not describe a 3D
+    ///   ResNet.
+    pub fn new(device: &B::Device, config: &StarDistNetworkConfig) -> Result<Self, CellcastError> {
+        config.validate()?;
+        if config.n_dim != 3 || config.backbone != StarDistBackbone::Resnet {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "The StarDist network configuration does not describe a 3D ResNet.",
+            }));
+        }
+        let conv = |c_in: usize, c_out: usize, k: [usize; 3], stride: [usize; 3]| {
+            // strided convolutions are padded in the forward pass
+            let padding = if stride.iter().all(|&s| s == 1) {
+                PaddingConfig3d::Explicit(k[0] / 2, k[1] / 2, k[2] / 2)
+            } else {
+                PaddingConfig3d::Valid
+            };
+            Conv3dConfig::new([c_in, c_out], k)
+                .with_stride(stride)
+                .with_padding(padding)
+                .with_bias(true)
+                .init(device)
+        };
+        let to_array = |v: &[usize]| [v[0], v[1], v[2]];
+        let kernel_size = to_array(&config.resnet_kernel_size);
+        let n_conv_per_block = config.resnet_n_conv_per_block;
+        let mut n_filter = config.resnet_n_filter_base;
+        let mut convs = vec![
+            conv(config.n_channel_in, n_filter, [7; 3], [1; 3]),
+            conv(n_filter, n_filter, [3; 3], [1; 3]),
+        ];
+        let mut c_in = n_filter;
+        let mut block_pools: Vec<[usize; 3]> = config
+            .grid_pool_sizes()
+            .iter()
+            .map(|p| to_array(p))
+            .collect();
+        block_pools.resize(config.resnet_n_blocks, [1; 3]);
+        let block_shortcuts: Vec<bool> = block_pools
+            .iter()
+            .map(|&pool| {
+                if pool.iter().any(|&p| p > 1) {
+                    n_filter *= 2;
+                }
+                convs.push(conv(c_in, n_filter, kernel_size, pool));
+                (0..n_conv_per_block - 1)
+                    .for_each(|_| convs.push(conv(n_filter, n_filter, kernel_size, [1; 3])));
+                let shortcut = pool.iter().any(|&p| p > 1) || c_in != n_filter;
+                if shortcut {
+                    convs.push(conv(c_in, n_filter, [1; 3], pool));
+                }
+                c_in = n_filter;
+                shortcut
+            })
+            .collect();
+        // feature layer, ray distance and object probability heads
+        let feature_kernel = to_array(&config.unet_kernel_size);
+        convs.push(conv(
+            c_in,
+            config.net_conv_after_unet,
+            feature_kernel,
+            [1; 3],
+        ));
+        convs.push(conv(
+            config.net_conv_after_unet,
+            config.n_rays,
+            [1; 3],
+            [1; 3],
+        ));
+        convs.push(conv(config.net_conv_after_unet, 1, [1; 3], [1; 3]));
+        Ok(Self {
+            convs,
+            block_pools,
+            block_shortcuts,
+            kernel_size,
+            n_conv_per_block,
+        })
+    }
+
+    /// Load model weights from a burnpack file into a newly constructed model.
+    ///
+    /// # Description
+    ///
+    /// Constructs a new `Model` on `device` and loads parameters from the
+    /// given burnpack file. Weights stored with ONNX layer names (*e.g.*
+    /// `conv3d1`) are mapped onto the model's convolution layers in order.
+    ///
+    /// # Arguments
+    ///
+    /// * `file`: Path to a burnpack file containing model weights.
+    /// * `device`: The backend device to initialize the model on.
+    /// * `config`: The network configuration.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Model)`: A `Model` with weights loaded from `file`.
+    /// * `Err(CellcastError)`: If `config` is invalid. If the weights can not
+    ///   be loaded (*e.g.* the weights do not match the configuration).
+    pub fn from_file(
+        file: &Path,
+        device: &B::Device,
+        config: &StarDistNetworkConfig,
+    ) -> Result<Self, CellcastError> {
+        let mut model = Self::new(device, config)?;
+        let mut store = BurnpackStore::from_file(file).remap(onnx_key_remapper(model.convs.len()));
+        model
+            .load_from(&mut store)
+            .map_err(|_| ImgalError::InvalidGeneric {
+                msg: "Failed to load the StarDist3D model weights burnpack file.",
+            })?;
+        Ok(model)
+    }
+
+    /// Load convolution weights into a newly constructed model.
+    ///
+    /// # Description
+    ///
+    /// Constructs a new `Model` on `device` and loads the convolution weights
+    /// in ONNX graph order, *e.g.* read from an ONNX file or converted from
+    /// Keras weights.
+    ///
+    /// # Arguments
+    ///
+    /// * `weights`: The convolution weights in ONNX graph order.
+    /// * `device`: The backend device to initialize the model on.
+    /// * `config`: The network configuration.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Model)`: A `Model` with weights loaded from `weights`.
+    /// * `Err(CellcastError)`: If `config` is invalid. If the weights do not
+    ///   match the configuration.
+    pub fn from_conv_weights(
+        weights: &[ConvWeights],
+        device: &B::Device,
+        config: &StarDistNetworkConfig,
+    ) -> Result<Self, CellcastError> {
+        let mut model = Self::new(device, config)?;
+        load_convs(
+            model.convs.iter_mut().map(|c| (&mut c.weight, &mut c.bias)),
+            weights,
+        )?;
+        Ok(model)
+    }
+
+    /// Run the network.
+    ///
+    /// # Arguments
+    ///
+    /// * `input`: The input tensor with shape `(1, 1, D, H, W)`, each spatial
+    ///   axis divisible by its grid factor.
+    ///
+    /// # Returns
+    ///
+    /// * `(Tensor<B, 5>, Tensor<B, 5>)`: The object probabilities with shape
+    ///   `(1, D', H', W', 1)` and ray distances with shape
+    ///   `(1, n_rays, D', H', W')`, where `D'`, `H'` and `W'` are the input
+    ///   shape divided by the grid.
+    pub fn forward(&self, input: Tensor<B, 5>) -> (Tensor<B, 5>, Tensor<B, 5>) {
+        let relu = burn::tensor::activation::relu;
+        let x = self.convs[0].forward(input);
+        let mut x = self.convs[1].forward(x);
+        let mut idx = 2;
+        self.block_pools
+            .iter()
+            .zip(self.block_shortcuts.iter())
+            .for_each(|(&pool, &shortcut)| {
+                let inp = x.clone();
+                let mut y =
+                    relu(self.convs[idx].forward(same_pad(x.clone(), self.kernel_size, pool)));
+                idx += 1;
+                (0..self.n_conv_per_block - 2).for_each(|_| {
+                    y = relu(self.convs[idx].forward(y.clone()));
+                    idx += 1;
+                });
+                let y = self.convs[idx].forward(y);
+                idx += 1;
+                let inp = if shortcut {
+                    idx += 1;
+                    self.convs[idx - 1].forward(inp)
+                } else {
+                    inp
+                };
+                x = relu(inp.add(y));
+            });
+        let features = relu(self.convs[idx].forward(x));
+        let dist = self.convs[idx + 1].forward(features.clone());
+        let prob = burn::tensor::activation::sigmoid(self.convs[idx + 2].forward(features));
+        let [_, _, d, h, w] = prob.dims();
+        (prob.reshape([1, d, h, w, 1]), dist)
+    }
+}
+
+/// Pad the input of a strided convolution.
+///
+/// Reproduces the asymmetric "same" padding of Keras for the given kernel size
+/// and strides.
+fn same_pad<B: Backend>(x: Tensor<B, 5>, kernel: [usize; 3], stride: [usize; 3]) -> Tensor<B, 5> {
+    if stride.iter().all(|&s| s == 1) {
+        return x;
+    }
+    let padding: [(usize, usize); 3] = std::array::from_fn(|i| {
+        let total = kernel[i].saturating_sub(stride[i]);
+        (total / 2, total - total / 2)
+    });
+    x.pad(padding, PadMode::Constant(0.0))
+}
+
+/// Create a key remapper from ONNX convolution names to model layers.
+fn onnx_key_remapper(n_convs: usize) -> KeyRemapper {
+    // SAFE: the patterns are valid regular expressions
+    KeyRemapper::from_pattern_iter(
+        (1..=n_convs).map(|k| (format!(r"^conv3d{}\.", k), format!("convs.{}.", k - 1))),
+    )
+    .unwrap()
+}
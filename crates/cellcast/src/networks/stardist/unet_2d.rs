@@ -0,0 +1,345 @@
+// Adapted from the StarDist2D networks generated from ONNX using burn-import.
+// Convolution layers are stored in ONNX graph order, allowing weights exported
+// from ONNX (i.e. "conv2d1" ... "conv2dN") to be loaded for any ray count and
+// grid configuration.
+use std::path::Path;
+
+use burn::nn::PaddingConfig2d;
+use burn::nn::conv::{Conv2d, Conv2dConfig};
+use burn::nn::pool::{MaxPool2d, MaxPool2dConfig};
+use burn::prelude::*;
+use burn_store::{BurnpackStore, KeyRemapper, ModuleSnapshot};
+use imgal::prelude::*;
+
+use crate::CellcastError;
+use crate::networks::stardist::config::{StarDistBackbone, StarDistNetworkConfig};
+use crate::networks::stardist::network::StarDistNetwork2D;
+use crate::networks::stardist::{ConvWeights, load_convs};
+
+#[derive(Module, Debug)]
+pub struct Model<B: Backend> {
+    convs: Vec<Conv2d<B>>,
+    grid_pools: Vec<MaxPool2d>,
+    unet_pool: MaxPool2d,
+    #[module(skip)]
+    unet_pool_size: [usize; 2],
+    #[module(skip)]
+    unet_n_depth: usize,
+    #[module(skip)]
+    unet_n_conv_per_depth: usize,
+    #[module(skip)]
+    n_classes: Option<usize>,
+    #[module(skip)]
+    channels_last: bool,
+}
+
+impl<B: Backend> Model<B> {
+    /// Create a new StarDist2D U-Net with uninitialized weights.
+    ///
+    /// # Description
+    ///
+    /// Creates a StarDist2D U-Net as described by `config`, whose output is
+    /// subsampled by the grid. For each factor of 2 in the grid a stage of
+    /// convolutions followed by max pooling is placed before the U-Net,
+    /// matching the reference StarDist implementation.
+    /// Networks with `n_classes` have a classification head with its own
+    /// feature layer, after the object probability head.
+    ///
+    /// # Arguments
+    ///
+    /// * `device`: The backend device to initialize the model on.
+    /// * `config`: The network configuration.
+    /// * `channels_last`: If `true` the input tensor has shape `(1, H, W, C)`,
+    ///   otherwise `(1, C, H, W)`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Model)`: A new U-Net.
+    /// * `Err(CellcastError)`: If `config` is invalid or does not describe a 2D
+    ///   U-Net.
+    pub fn new(
+        device: &B::Device,
+        config: &StarDistNetworkConfig,
+        channels_last: bool,
+    ) -> Result<Self, CellcastError> {
+        config.validate()?;
+        if config.n_dim != 2 || config.backbone != StarDistBackbone::Unet {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "The StarDist network configuration does not describe a 2D U-Net.",
+            }));
+        }
+        let kernel = [config.unet_kernel_size[0], config.unet_kernel_size[1]];
+        let conv = |c_in: usize, c_out: usize, k: [usize; 2]| {
+            Conv2dConfig::new([c_in, c_out], k)
+                .with_padding(PaddingConfig2d::Explicit(
+                    k[0] / 2,
+                    k[1] / 2,
+                    k[0] / 2,
+                    k[1] / 2,
+                ))
+                .with_bias(true)
+                .init(device)
+        };
+        let n_depth = config.unet_n_depth;
+        let n_conv = config.unet_n_conv_per_depth;
+        let n_filter = |n: usize| config.unet_n_filter_base * 2_usize.pow(n as u32);
+        let mut convs = Vec::new();
+        let mut c_in = config.n_channel_in;
+        // grid stages, one for each factor of 2 in the grid
+        let grid_pools: Vec<MaxPool2d> = config
+            .grid_pool_sizes()
+            .into_iter()
+            .map(|pool| {
+                (0..n_conv).for_each(|_| {
+                    convs.push(conv(c_in, config.unet_n_filter_base, kernel));
+                    c_in = config.unet_n_filter_base;
+                });
+                let pool = [pool[0], pool[1]];
+                MaxPool2dConfig::new(pool).with_strides(pool).init()
+            })
+            .collect();
+        // down sampling levels
+        (0..n_depth).for_each(|n| {
+            (0..n_conv).for_each(|_| {
+                convs.push(conv(c_in, n_filter(n), kernel));
+                c_in = n_filter(n);
+            });
+        });
+        let mut skips: Vec<usize> = (0..n_depth).map(n_filter).collect();
+        // middle level
+        (0..n_conv - 1).for_each(|_| {
+            convs.push(conv(c_in, n_filter(n_depth), kernel));
+            c_in = n_filter(n_depth);
+        });
+        convs.push(conv(c_in, n_filter(n_depth.saturating_sub(1)), kernel));
+        c_in = n_filter(n_depth.saturating_sub(1));
+        // up sampling levels
+        (0..n_depth).rev().for_each(|n| {
+            c_in += skips.pop().unwrap();
+            (0..n_conv - 1).for_each(|_| {
+                convs.push(conv(c_in, n_filter(n), kernel));
+                c_in = n_filter(n);
+            });
+            convs.push(conv(c_in, n_filter(n.saturating_sub(1)), kernel));
+            c_in = n_filter(n.saturating_sub(1));
+        });
+        // feature layer, ray distance and object probability heads
+        convs.push(conv(c_in, config.net_conv_after_unet, kernel));
+        convs.push(conv(config.net_conv_after_unet, config.n_rays, [1, 1]));
+        convs.push(conv(config.net_conv_after_unet, 1, [1, 1]));
+        // feature layer and object class head
+        if let Some(n_classes) = config.n_classes {
+            convs.push(conv(c_in, config.net_conv_after_unet, kernel));
+            convs.push(conv(config.net_conv_after_unet, n_classes + 1, [1, 1]));
+        }
+        let unet_pool_size = [config.unet_pool[0], config.unet_pool[1]];
+        Ok(Self {
+            convs,
+            grid_pools,
+            unet_pool: MaxPool2dConfig::new(unet_pool_size)
+                .with_strides(unet_pool_size)
+                .init(),
+            unet_pool_size,
+            unet_n_depth: n_depth,
+            unet_n_conv_per_depth: n_conv,
+            n_classes: config.n_classes,
+            channels_last,
+        })
+    }
+
+    /// Load model weights from a burnpack file into a newly constructed model.
+    ///
+    /// # Description
+    ///
+    /// Constructs a new `Model` on `device` and loads parameters from the
+    /// given burnpack file. Weights stored with ONNX layer names (*e.g.*
+    /// `conv2d1`) are mapped onto the model's convolution layers in order.
+    ///
+    /// # Arguments
+    ///
+    /// * `file`: Path to a burnpack file containing model weights.
+    /// * `device`: The backend device to initialize the model on.
+    /// * `config`: The network configuration.
+    /// * `channels_last`: If `true` the input tensor has shape `(1, H, W, C)`,
+    ///   otherwise `(1, C, H, W)`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Model)`: A `Model` with weights loaded from `file`.
+    /// * `Err(CellcastError)`: If `config` is invalid. If the weights can not
+    ///   be loaded (*e.g.* the weights do not match the configuration).
+    pub fn from_file(
+        file: &Path,
+        device: &B::Device,
+        config: &StarDistNetworkConfig,
+        channels_last: bool,
+    ) -> Result<Self, CellcastError> {
+        let mut model = Self::new(device, config, channels_last)?;
+        let mut store = BurnpackStore::from_file(file).remap(onnx_key_remapper(model.convs.len()));
+        model
+            .load_from(&mut store)
+            .map_err(|_| ImgalError::InvalidGeneric {
+                msg: "Failed to load the StarDist2D model weights burnpack file.",
+            })?;
+        Ok(model)
+    }
+
+    /// Load convolution weights into a newly constructed model.
+    ///
+    /// # Description
+    ///
+    /// Constructs a new `Model` on `device` and loads the convolution weights
+    /// in ONNX graph order, *e.g.* read from an ONNX file or converted from
+    /// Keras weights.
+    ///
+    /// # Arguments
+    ///
+    /// * `weights`: The convolution weights in ONNX graph order.
+    /// * `device`: The backend device to initialize the model on.
+    /// * `config`: The network configuration.
+    /// * `channels_last`: If `true` the input tensor has shape `(1, H, W, C)`,
+    ///   otherwise `(1, C, H, W)`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Model)`: A `Model` with weights loaded from `weights`.
+    /// * `Err(CellcastError)`: If `config` is invalid. If the weights do not
+    ///   match the configuration.
+    pub fn from_conv_weights(
+        weights: &[ConvWeights],
+        device: &B::Device,
+        config: &StarDistNetworkConfig,
+        channels_last: bool,
+    ) -> Result<Self, CellcastError> {
+        let mut model = Self::new(device, config, channels_last)?;
+        load_convs(
+            model.convs.iter_mut().map(|c| (&mut c.weight, &mut c.bias)),
+            weights,
+        )?;
+        Ok(model)
+    }
+
+    /// Run the network.
+    ///
+    /// # Arguments
+    ///
+    /// * `input`: The input tensor, with height and width divisible by the
+    ///   grid times the U-Net pooling factors to the power of the depth.
+    ///
+    /// # Returns
+    ///
+    /// * `(Tensor<B, 4>, Tensor<B, 4>)`: The object probabilities with shape
+    ///   `(1, H / grid.0, W / grid.1, 1)` and ray distances with shape
+    ///   `(1, H / grid.0, W / grid.1, n_rays)`.
+    pub fn forward(&self, input: Tensor<B, 4>) -> (Tensor<B, 4>, Tensor<B, 4>) {
+        let (prob, dist, _) = self.run(input, false);
+        (prob, dist)
+    }
+
+    /// Run the network, including the object class head.
+    ///
+    /// # Arguments
+    ///
+    /// * `input`: The input tensor, with height and width divisible by the
+    ///   grid times the U-Net pooling factors to the power of the depth.
+    ///
+    /// # Returns
+    ///
+    /// * `(Tensor<B, 4>, Tensor<B, 4>, Option<Tensor<B, 4>>)`: The object
+    ///   probabilities and ray distances (see `forward`) and, if the network
+    ///   has a class head, the class probabilities with shape
+    ///   `(1, H / grid.0, W / grid.1, n_classes + 1)`, where class `0` is the
+    ///   background.
+    pub fn forward_classes(
+        &self,
+        input: Tensor<B, 4>,
+    ) -> (Tensor<B, 4>, Tensor<B, 4>, Option<Tensor<B, 4>>) {
+        self.run(input, true)
+    }
+
+    /// Run the network, with the object class head if `classes` is `true`.
+    fn run(
+        &self,
+        input: Tensor<B, 4>,
+        classes: bool,
+    ) -> (Tensor<B, 4>, Tensor<B, 4>, Option<Tensor<B, 4>>) {
+        let mut x = if self.channels_last {
+            input.permute([0, 3, 1, 2])
+        } else {
+            input
+        };
+        let mut idx = 0;
+        let n_conv = self.unet_n_conv_per_depth;
+        self.grid_pools.iter().for_each(|pool| {
+            (0..n_conv).for_each(|_| x = self.conv_relu(&mut idx, x.clone()));
+            x = pool.forward(x.clone());
+        });
+        let mut skips = Vec::with_capacity(self.unet_n_depth);
+        (0..self.unet_n_depth).for_each(|_| {
+            (0..n_conv).for_each(|_| x = self.conv_relu(&mut idx, x.clone()));
+            skips.push(x.clone());
+            x = self.unet_pool.forward(x.clone());
+        });
+        (0..n_conv).for_each(|_| x = self.conv_relu(&mut idx, x.clone()));
+        skips.into_iter().rev().for_each(|skip| {
+            x = Tensor::cat(vec![upsample_2d(x.clone(), self.unet_pool_size), skip], 1);
+            (0..n_conv).for_each(|_| x = self.conv_relu(&mut idx, x.clone()));
+        });
+        let features = self.conv_relu(&mut idx, x.clone());
+        let dist = self.convs[idx].forward(features.clone());
+        let prob = burn::tensor::activation::sigmoid(self.convs[idx + 1].forward(features));
+        let class_prob = match self.n_classes {
+            Some(_) if classes => {
+                idx += 2;
+                let features = self.conv_relu(&mut idx, x);
+                let class_prob =
+                    burn::tensor::activation::softmax(self.convs[idx].forward(features), 1);
+                Some(class_prob.permute([0, 2, 3, 1]))
+            }
+            _ => None,
+        };
+        (
+            prob.permute([0, 2, 3, 1]),
+            dist.permute([0, 2, 3, 1]),
+            class_prob,
+        )
+    }
+
+    /// Apply the convolution at `idx` followed by a ReLU, advancing `idx`.
+    fn conv_relu(&self, idx: &mut usize, x: Tensor<B, 4>) -> Tensor<B, 4> {
+        let out = burn::tensor::activation::relu(self.convs[*idx].forward(x));
+        *idx += 1;
+        out
+    }
+}
+
+impl<B: Backend> StarDistNetwork2D<B> for Model<B> {
+    fn forward_prob_dist(&self, input: Tensor<B, 4>) -> (Tensor<B, 4>, Tensor<B, 4>) {
+        let input = if self.channels_last {
+            input.permute([0, 2, 3, 1])
+        } else {
+            input
+        };
+        let (prob, dist) = self.forward(input);
+        (prob.permute([0, 3, 1, 2]), dist.permute([0, 3, 1, 2]))
+    }
+}
+
+/// Create a key remapper from ONNX convolution names to model layers.
+pub(crate) fn onnx_key_remapper(n_convs: usize) -> KeyRemapper {
+    // SAFE: the patterns are valid regular expressions
+    KeyRemapper::from_pattern_iter(
+        (1..=n_convs).map(|k| (format!(r"^conv2d{}\.", k), format!("convs.{}.", k - 1))),
+    )
+    .unwrap()
+}
+
+/// Nearest neighbor upsampling of the spatial axes of a `(N, C, H, W)` tensor.
+pub(crate) fn upsample_2d<B: Backend>(x: Tensor<B, 4>, factor: [usize; 2]) -> Tensor<B, 4> {
+    let [n, c, h, w] = x.dims();
+    let x: Tensor<B, 5> = x.unsqueeze_dim(3);
+    let x = x.repeat_dim(3, factor[0]).reshape([n, c, h * factor[0], w]);
+    let x: Tensor<B, 5> = x.unsqueeze_dim(4);
+    x.repeat_dim(4, factor[1])
+        .reshape([n, c, h * factor[0], w * factor[1]])
+}
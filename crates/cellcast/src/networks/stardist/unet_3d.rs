@@ -0,0 +1,269 @@
+// A StarDist3D U-Net following the layout of the 2D U-Net. Convolution layers
+// are stored in ONNX graph order, allowing weights exported from ONNX (i.e.
+// "conv3d1" ... "conv3dN") to be loaded for any network configuration.
+use std::path::Path;
+
+use burn::nn::PaddingConfig3d;
+use burn::nn::conv::{Conv3d, Conv3dConfig};
+use burn::prelude::*;
+use burn_store::{BurnpackStore, KeyRemapper, ModuleSnapshot};
+use imgal::prelude::*;
+
+use crate::CellcastError;
+use crate::networks::stardist::config::{StarDistBackbone, StarDistNetworkConfig};
+use crate::networks::stardist::{ConvWeights, load_convs};
+
+#[derive(Module, Debug)]
+pub struct Model<B: Backend> {
+    convs: Vec<Conv3d<B>>,
+    #[module(skip)]
+    grid_pools: Vec<[usize; 3]>,
+    #[module(skip)]
+    unet_pool: [usize; 3],
+    #[module(skip)]
+    unet_n_depth: usize,
+    #[module(skip)]
+    unet_n_conv_per_depth: usize,
+}
+
+impl<B: Backend> Model<B> {
+    /// Create a new StarDist3D U-Net with uninitialized weights.
+    ///
+    /// # Description
+    ///
+    /// Creates a StarDist3D U-Net as described by `config`, whose output is
+    /// subsampled by the grid. For each factor of 2 in the grid a stage of
+    /// convolutions followed by max pooling is placed before the U-Net,
+    /// matching the reference StarDist implementation.
+    ///
+    /// # Arguments
+    ///
+    /// * `device`: The backend device to initialize the model on.
+    /// * `config`: The network configuration.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Model)`: A new U-Net.
+    /// * `Err(CellcastError)`: If `config` is invalid or does not describe a 3D
+    ///   U-Net.
+    pub fn new(device: &B::Device, config: &StarDistNetworkConfig) -> Result<Self, CellcastError> {
+        config.validate()?;
+        if config.n_dim != 3 || config.backbone != StarDistBackbone::Unet {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "The StarDist network configuration does not describe a 3D U-Net.",
+            }));
+        }
+        let to_array = |v: &[usize]| [v[0], v[1], v[2]];
+        let kernel = to_array(&config.unet_kernel_size);
+        let conv = |c_in: usize, c_out: usize, k: [usize; 3]| {
+            Conv3dConfig::new([c_in, c_out], k)
+                .with_padding(PaddingConfig3d::Explicit(k[0] / 2, k[1] / 2, k[2] / 2))
+                .with_bias(true)
+                .init(device)
+        };
+        let n_depth = config.unet_n_depth;
+        let n_conv = config.unet_n_conv_per_depth;
+        let n_filter = |n: usize| config.unet_n_filter_base * 2_usize.pow(n as u32);
+        let mut convs = Vec::new();
+        let mut c_in = config.n_channel_in;
+        // grid stages, one for each factor of 2 in the grid
+        let grid_pools: Vec<[usize; 3]> = config
+            .grid_pool_sizes()
+            .iter()
+            .map(|pool| {
+                (0..n_conv).for_each(|_| {
+                    convs.push(conv(c_in, config.unet_n_filter_base, kernel));
+                    c_in = config.unet_n_filter_base;
+                });
+                to_array(pool)
+            })
+            .collect();
+        // down sampling levels
+        (0..n_depth).for_each(|n| {
+            (0..n_conv).for_each(|_| {
+                convs.push(conv(c_in, n_filter(n), kernel));
+                c_in = n_filter(n);
+            });
+        });
+        let mut skips: Vec<usize> = (0..n_depth).map(n_filter).collect();
+        // middle level
+        (0..n_conv - 1).for_each(|_| {
+            convs.push(conv(c_in, n_filter(n_depth), kernel));
+            c_in = n_filter(n_depth);
+        });
+        convs.push(conv(c_in, n_filter(n_depth.saturating_sub(1)), kernel));
+        c_in = n_filter(n_depth.saturating_sub(1));
+        // up sampling levels
+        (0..n_depth).rev().for_each(|n| {
+            c_in += skips.pop().unwrap();
+            (0..n_conv - 1).for_each(|_| {
+                convs.push(conv(c_in, n_filter(n), kernel));
+                c_in = n_filter(n);
+            });
+            convs.push(conv(c_in, n_filter(n.saturating_sub(1)), kernel));
+            c_in = n_filter(n.saturating_sub(1));
+        });
+        // feature layer, ray distance and object probability heads
+        convs.push(conv(c_in, config.net_conv_after_unet, kernel));
+        convs.push(conv(config.net_conv_after_unet, config.n_rays, [1; 3]));
+        convs.push(conv(config.net_conv_after_unet, 1, [1; 3]));
+        Ok(Self {
+            convs,
+            grid_pools,
+            unet_pool: to_array(&config.unet_pool),
+            unet_n_depth: n_depth,
+            unet_n_conv_per_depth: n_conv,
+        })
+    }
+
+    /// Load model weights from a burnpack file into a newly constructed model.
+    ///
+    /// # Description
+    ///
+    /// Constructs a new `Model` on `device` and loads parameters from the
+    /// given burnpack file. Weights stored with ONNX layer names (*e.g.*
+    /// `conv3d1`) are mapped onto the model's convolution layers in order.
+    ///
+    /// # Arguments
+    ///
+    /// * `file`: Path to a burnpack file containing model weights.
+    /// * `device`: The backend device to initialize the model on.
+    /// * `config`: The network configuration.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Model)`: A `Model` with weights loaded from `file`.
+    /// * `Err(CellcastError)`: If `config` is invalid. If the weights can not
+    ///   be loaded (*e.g.* the weights do not match the configuration).
+    pub fn from_file(
+        file: &Path,
+        device: &B::Device,
+        config: &StarDistNetworkConfig,
+    ) -> Result<Self, CellcastError> {
+        let mut model = Self::new(device, config)?;
+        let mut store = BurnpackStore::from_file(file).remap(onnx_key_remapper(model.convs.len()));
+        model
+            .load_from(&mut store)
+            .map_err(|_| ImgalError::InvalidGeneric {
+                msg: "Failed to load the StarDist3D model weights burnpack file.",
+            })?;
+        Ok(model)
+    }
+
+    /// Load convolution weights into a newly constructed model.
+    ///
+    /// # Description
+    ///
+    /// Constructs a new `Model` on `device` and loads the convolution weights
+    /// in ONNX graph order, *e.g.* read from an ONNX file or converted from
+    /// Keras weights.
+    ///
+    /// # Arguments
+    ///
+    /// * `weights`: The convolution weights in ONNX graph order.
+    /// * `device`: The backend device to initialize the model on.
+    /// * `config`: The network configuration.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Model)`: A `Model` with weights loaded from `weights`.
+    /// * `Err(CellcastError)`: If `config` is invalid. If the weights do not
+    ///   match the configuration.
+    pub fn from_conv_weights(
+        weights: &[ConvWeights],
+        device: &B::Device,
+        config: &StarDistNetworkConfig,
+    ) -> Result<Self, CellcastError> {
+        let mut model = Self::new(device, config)?;
+        load_convs(
+            model.convs.iter_mut().map(|c| (&mut c.weight, &mut c.bias)),
+            weights,
+        )?;
+        Ok(model)
+    }
+
+    /// Run the network.
+    ///
+    /// # Arguments
+    ///
+    /// * `input`: The input tensor with shape `(1, C, D, H, W)`, each spatial
+    ///   axis divisible by its grid factor times the U-Net pooling factor to
+    ///   the power of the depth.
+    ///
+    /// # Returns
+    ///
+    /// * `(Tensor<B, 5>, Tensor<B, 5>)`: The object probabilities with shape
+    ///   `(1, D', H', W', 1)` and ray distances with shape
+    ///   `(1, n_rays, D', H', W')`, where `D'`, `H'` and `W'` are the input
+    ///   shape divided by the grid.
+    pub fn forward(&self, input: Tensor<B, 5>) -> (Tensor<B, 5>, Tensor<B, 5>) {
+        let mut x = input;
+        let mut idx = 0;
+        let n_conv = self.unet_n_conv_per_depth;
+        self.grid_pools.iter().for_each(|&pool| {
+            (0..n_conv).for_each(|_| x = self.conv_relu(&mut idx, x.clone()));
+            x = max_pool_3d(x.clone(), pool);
+        });
+        let mut skips = Vec::with_capacity(self.unet_n_depth);
+        (0..self.unet_n_depth).for_each(|_| {
+            (0..n_conv).for_each(|_| x = self.conv_relu(&mut idx, x.clone()));
+            skips.push(x.clone());
+            x = max_pool_3d(x.clone(), self.unet_pool);
+        });
+        (0..n_conv).for_each(|_| x = self.conv_relu(&mut idx, x.clone()));
+        skips.into_iter().rev().for_each(|skip| {
+            x = Tensor::cat(vec![upsample_3d(x.clone(), self.unet_pool), skip], 1);
+            (0..n_conv).for_each(|_| x = self.conv_relu(&mut idx, x.clone()));
+        });
+        let features = self.conv_relu(&mut idx, x);
+        let dist = self.convs[idx].forward(features.clone());
+        let prob = burn::tensor::activation::sigmoid(self.convs[idx + 1].forward(features));
+        let [_, _, d, h, w] = prob.dims();
+        (prob.reshape([1, d, h, w, 1]), dist)
+    }
+
+    /// Apply the convolution at `idx` followed by a ReLU, advancing `idx`.
+    fn conv_relu(&self, idx: &mut usize, x: Tensor<B, 5>) -> Tensor<B, 5> {
+        let out = burn::tensor::activation::relu(self.convs[*idx].forward(x));
+        *idx += 1;
+        out
+    }
+}
+
+/// Create a key remapper from ONNX convolution names to model layers.
+pub(crate) fn onnx_key_remapper(n_convs: usize) -> KeyRemapper {
+    // SAFE: the patterns are valid regular expressions
+    KeyRemapper::from_pattern_iter(
+        (1..=n_convs).map(|k| (format!(r"^conv3d{}\.", k), format!("convs.{}.", k - 1))),
+    )
+    .unwrap()
+}
+
+/// Max pooling of the spatial axes of a `(N, C, D, H, W)` tensor, with each
+/// spatial axis divisible by its pooling factor.
+pub(crate) fn max_pool_3d<B: Backend>(x: Tensor<B, 5>, pool: [usize; 3]) -> Tensor<B, 5> {
+    if pool == [1; 3] {
+        return x;
+    }
+    let [n, c, d, h, w] = x.dims();
+    let [d, h, w] = [d / pool[0], h / pool[1], w / pool[2]];
+    let x: Tensor<B, 8> = x.reshape([n, c, d, pool[0], h, pool[1], w, pool[2]]);
+    x.max_dim(7).max_dim(5).max_dim(3).reshape([n, c, d, h, w])
+}
+
+/// Nearest neighbor upsampling of the spatial axes of a `(N, C, D, H, W)`
+/// tensor.
+pub(crate) fn upsample_3d<B: Backend>(x: Tensor<B, 5>, factor: [usize; 3]) -> Tensor<B, 5> {
+    let [n, c, d, h, w] = x.dims();
+    let x: Tensor<B, 6> = x.unsqueeze_dim(3);
+    let x = x
+        .repeat_dim(3, factor[0])
+        .reshape([n, c, d * factor[0], h, w]);
+    let x: Tensor<B, 6> = x.unsqueeze_dim(4);
+    let x = x
+        .repeat_dim(4, factor[1])
+        .reshape([n, c, d * factor[0], h * factor[1], w]);
+    let x: Tensor<B, 6> = x.unsqueeze_dim(5);
+    x.repeat_dim(5, factor[2])
+        .reshape([n, c, d * factor[0], h * factor[1], w * factor[2]])
+}
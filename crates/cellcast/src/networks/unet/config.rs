@@ -0,0 +1,127 @@
+use imgal::prelude::*;
+
+use crate::CellcastError;
+
+/// Configuration of a generic U-Net.
+///
+/// Describes a 2D or 3D U-Net for semantic segmentation. Each U-Net level has
+/// `n_conv_per_depth` convolutions of `n_filter_base * 2^level` filters and is
+/// followed by max pooling, the upsampling levels concatenate the matching
+/// downsampling level, and a final `1x1` convolution predicts `n_classes`
+/// outputs. Networks with a single output predict a foreground probability
+/// (sigmoid), otherwise the class probabilities sum to one (softmax), with
+/// class `0` as the background.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UNetConfig {
+    /// The number of spatial dimensions (2 or 3).
+    pub n_dim: usize,
+    /// The number of input channels.
+    pub n_channel_in: usize,
+    /// The number of output classes.
+    pub n_classes: usize,
+    /// The number of pooling levels.
+    pub n_depth: usize,
+    /// The number of filters of the first level.
+    pub n_filter_base: usize,
+    /// The number of convolutions per level.
+    pub n_conv_per_depth: usize,
+    /// The convolution kernel size of each spatial axis.
+    pub kernel_size: usize,
+    /// The max pooling factor of each spatial axis.
+    pub pool: usize,
+}
+
+impl UNetConfig {
+    /// A 2D U-Net predicting background, foreground and boundary classes.
+    pub fn semantic_2d() -> Self {
+        Self {
+            n_dim: 2,
+            n_channel_in: 1,
+            n_classes: 3,
+            n_depth: 3,
+            n_filter_base: 32,
+            n_conv_per_depth: 2,
+            kernel_size: 3,
+            pool: 2,
+        }
+    }
+
+    /// A 3D U-Net predicting background, foreground and boundary classes.
+    pub fn semantic_3d() -> Self {
+        Self {
+            n_dim: 3,
+            n_depth: 2,
+            n_filter_base: 16,
+            ..Self::semantic_2d()
+        }
+    }
+
+    /// Validate the network configuration.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())`: If the network can be constructed from the configuration.
+    /// * `Err(CellcastError)`: If `n_dim` is not 2 or 3. If `n_channel_in`,
+    ///   `n_classes`, `n_filter_base`, `n_conv_per_depth` or `pool` is `0`. If
+    ///   `kernel_size` is even.
+    pub fn validate(&self) -> Result<(), CellcastError> {
+        if self.n_dim != 2 && self.n_dim != 3 {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "U-Net networks must have 2 or 3 spatial dimensions.",
+            }));
+        }
+        let counts = [
+            ("n_channel_in", self.n_channel_in),
+            ("n_classes", self.n_classes),
+            ("n_filter_base", self.n_filter_base),
+            ("n_conv_per_depth", self.n_conv_per_depth),
+            ("pool", self.pool),
+        ];
+        if let Some(&(param_name, _)) = counts.iter().find(|(_, v)| *v == 0) {
+            return Err(CellcastError::Imgal(
+                ImgalError::InvalidParameterValueLess {
+                    param_name,
+                    value: 1,
+                },
+            ));
+        }
+        if self.kernel_size.is_multiple_of(2) {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "The U-Net convolution kernel size must be odd.",
+            }));
+        }
+        Ok(())
+    }
+
+    /// Get the value each spatial axis of the network input must be divisible
+    /// by, *i.e.* the pooling factor to the power of the depth.
+    pub fn div(&self) -> usize {
+        self.pool.pow(self.n_depth as u32)
+    }
+
+    /// Get the number of filters of a U-Net level.
+    pub(crate) fn n_filter(&self, level: usize) -> usize {
+        self.n_filter_base * 2_usize.pow(level as u32)
+    }
+
+    /// Get the `(c_in, c_out)` channels of each convolution layer, in forward
+    /// order.
+    pub(crate) fn conv_channels(&self) -> Vec<(usize, usize)> {
+        let mut channels = Vec::new();
+        let mut c_in = self.n_channel_in;
+        let mut conv = |c_in: &mut usize, c_out: usize| {
+            channels.push((*c_in, c_out));
+            *c_in = c_out;
+        };
+        // down sampling levels and the middle level
+        (0..=self.n_depth).for_each(|n| {
+            (0..self.n_conv_per_depth).for_each(|_| conv(&mut c_in, self.n_filter(n)));
+        });
+        // up sampling levels, concatenated with the down sampling level
+        (0..self.n_depth).rev().for_each(|n| {
+            c_in += self.n_filter(n);
+            (0..self.n_conv_per_depth).for_each(|_| conv(&mut c_in, self.n_filter(n)));
+        });
+        channels
+    }
+}
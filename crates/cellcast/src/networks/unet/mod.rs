@@ -0,0 +1,11 @@
+//! Generic U-Net network backbones.
+//!
+//! The generic U-Nets are constructed from a `UNetConfig` and predict
+//! per-pixel class probabilities (*e.g.* background, foreground and boundary)
+//! for semantic segmentation. Weights are loaded from burnpack files with
+//! either the model layer names or ONNX layer names (*i.e.* `conv2d1` ...
+//! `conv2dN` and `conv3d1` ... `conv3dN`).
+
+pub mod config;
+pub mod unet_2d;
+pub mod unet_3d;
@@ -0,0 +1,147 @@
+// A generic 3D U-Net for semantic segmentation. Convolution layers are stored
+// in forward order, allowing weights exported from ONNX (i.e. "conv3d1" ...
+// "conv3dN") to be loaded.
+use std::path::Path;
+
+use burn::nn::PaddingConfig3d;
+use burn::nn::conv::{Conv3d, Conv3dConfig};
+use burn::prelude::*;
+use burn_store::{BurnpackStore, ModuleSnapshot};
+use imgal::prelude::*;
+
+use crate::CellcastError;
+use crate::networks::stardist::unet_3d::{max_pool_3d, onnx_key_remapper, upsample_3d};
+use crate::networks::unet::config::UNetConfig;
+
+#[derive(Module, Debug)]
+pub struct Model<B: Backend> {
+    convs: Vec<Conv3d<B>>,
+    output: Conv3d<B>,
+    #[module(skip)]
+    pool_size: usize,
+    #[module(skip)]
+    n_depth: usize,
+    #[module(skip)]
+    n_conv_per_depth: usize,
+}
+
+impl<B: Backend> Model<B> {
+    /// Create a new 3D U-Net with uninitialized weights.
+    ///
+    /// # Arguments
+    ///
+    /// * `device`: The backend device to initialize the model on.
+    /// * `config`: The network configuration.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Model)`: A new U-Net.
+    /// * `Err(CellcastError)`: If `config` is invalid or does not describe a 3D
+    ///   U-Net.
+    pub fn new(device: &B::Device, config: &UNetConfig) -> Result<Self, CellcastError> {
+        config.validate()?;
+        if config.n_dim != 3 {
+            return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+                msg: "The U-Net configuration does not describe a 3D U-Net.",
+            }));
+        }
+        let conv = |c_in: usize, c_out: usize, k: usize| {
+            Conv3dConfig::new([c_in, c_out], [k, k, k])
+                .with_padding(PaddingConfig3d::Explicit(k / 2, k / 2, k / 2))
+                .init(device)
+        };
+        let convs = config
+            .conv_channels()
+            .into_iter()
+            .map(|(c_in, c_out)| conv(c_in, c_out, config.kernel_size))
+            .collect();
+        Ok(Self {
+            convs,
+            output: conv(config.n_filter_base, config.n_classes, 1),
+            pool_size: config.pool,
+            n_depth: config.n_depth,
+            n_conv_per_depth: config.n_conv_per_depth,
+        })
+    }
+
+    /// Load model weights from a burnpack file into a newly constructed model.
+    ///
+    /// # Description
+    ///
+    /// Constructs a new `Model` on `device` and loads parameters from the
+    /// given burnpack file. Weights stored with ONNX layer names (*e.g.*
+    /// `conv3d1`) are mapped onto the model's convolution layers in order,
+    /// with the last layer as the output layer.
+    ///
+    /// # Arguments
+    ///
+    /// * `file`: Path to a burnpack file containing model weights.
+    /// * `device`: The backend device to initialize the model on.
+    /// * `config`: The network configuration.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Model)`: A `Model` with weights loaded from `file`.
+    /// * `Err(CellcastError)`: If `config` is invalid. If the weights can not
+    ///   be loaded (*e.g.* the weights do not match the configuration).
+    pub fn from_file(
+        file: &Path,
+        device: &B::Device,
+        config: &UNetConfig,
+    ) -> Result<Self, CellcastError> {
+        let mut model = Self::new(device, config)?;
+        let n_convs = model.convs.len();
+        // SAFE: the pattern is a valid regular expression
+        let remapper = onnx_key_remapper(n_convs)
+            .add_pattern(format!(r"^conv3d{}\.", n_convs + 1), "output.")
+            .unwrap();
+        let mut store = BurnpackStore::from_file(file).remap(remapper);
+        model
+            .load_from(&mut store)
+            .map_err(|_| ImgalError::InvalidGeneric {
+                msg: "Failed to load the U-Net model weights burnpack file.",
+            })?;
+        Ok(model)
+    }
+
+    /// Run the network.
+    ///
+    /// # Arguments
+    ///
+    /// * `input`: The `(1, C, D, H, W)` input tensor, with each spatial axis
+    ///   divisible by the configuration `div`.
+    ///
+    /// # Returns
+    ///
+    /// * `Tensor<B, 5>`: The `(1, n_classes, D, H, W)` class probabilities.
+    pub fn forward(&self, input: Tensor<B, 5>) -> Tensor<B, 5> {
+        let mut x = input;
+        let mut idx = 0;
+        let n_conv = self.n_conv_per_depth;
+        let factor = [self.pool_size; 3];
+        let mut skips = Vec::with_capacity(self.n_depth);
+        (0..self.n_depth).for_each(|_| {
+            (0..n_conv).for_each(|_| x = self.conv_relu(&mut idx, x.clone()));
+            skips.push(x.clone());
+            x = max_pool_3d(x.clone(), factor);
+        });
+        (0..n_conv).for_each(|_| x = self.conv_relu(&mut idx, x.clone()));
+        skips.into_iter().rev().for_each(|skip| {
+            x = Tensor::cat(vec![upsample_3d(x.clone(), factor), skip], 1);
+            (0..n_conv).for_each(|_| x = self.conv_relu(&mut idx, x.clone()));
+        });
+        let logits = self.output.forward(x);
+        if logits.dims()[1] == 1 {
+            burn::tensor::activation::sigmoid(logits)
+        } else {
+            burn::tensor::activation::softmax(logits, 1)
+        }
+    }
+
+    /// Apply the convolution at `idx` followed by a ReLU, advancing `idx`.
+    fn conv_relu(&self, idx: &mut usize, x: Tensor<B, 5>) -> Tensor<B, 5> {
+        let out = burn::tensor::activation::relu(self.convs[*idx].forward(x));
+        *idx += 1;
+        out
+    }
+}
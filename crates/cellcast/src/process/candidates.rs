@@ -0,0 +1,149 @@
+use std::ops::Range;
+
+use burn::prelude::*;
+use imgal::threshold::manual::manual_mask;
+use ndarray::{Array1, Array2, ArrayView2, ArrayViewD, Axis, IxDyn};
+
+use crate::utils::border;
+
+/// StarDist object candidates at their network output grid positions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GridCandidates {
+    /// The output grid positions with shape `(n_cands, n_dims)`.
+    pub pos: Array2<usize>,
+    /// The object probabilities with shape `(n_cands,)`.
+    pub prob: Array1<f32>,
+    /// The ray distances (at least `1e-3`) with shape `(n_cands, n_rays)`.
+    pub dist: Array2<f32>,
+}
+
+impl GridCandidates {
+    /// Create empty object candidates.
+    fn empty(n_dims: usize, n_rays: usize) -> Self {
+        Self {
+            pos: Array2::zeros((0, n_dims)),
+            prob: Array1::zeros(0),
+            dist: Array2::zeros((0, n_rays)),
+        }
+    }
+}
+
+/// Gather the object candidates of a StarDist network output on the device.
+///
+/// # Description
+///
+/// Thresholds the object probabilities, clips the candidates within `border`
+/// cells of the output grid border and gathers the probabilities and ray
+/// distances of the remaining candidates with tensor operations on the
+/// device. Only the candidate positions, probabilities and ray distances are
+/// copied to the host instead of the full network output.
+///
+/// # Arguments
+///
+/// * `prob`: The object probabilities with the output grid shape.
+/// * `dist`: The ray distances with shape `(n_cells, n_rays)`, where the
+///   output grid cells are in row-major order.
+/// * `prob_threshold`: The object probability threshold.
+/// * `border`: The number of output grid cells clipped at each border.
+///
+/// # Returns
+///
+/// * `GridCandidates`: The object candidates above `prob_threshold`, in
+///   row-major grid order.
+pub fn gather_candidates<B: Backend, const D: usize>(
+    prob: Tensor<B, D>,
+    dist: Tensor<B, 2>,
+    prob_threshold: f32,
+    border: usize,
+) -> GridCandidates {
+    let shape: [usize; D] = prob.dims();
+    let n_rays = dist.dims()[1];
+    if shape.iter().any(|&s| s <= 2 * border) {
+        return GridCandidates::empty(D, n_rays);
+    }
+    // threshold the inner grid cells, only the mask is read to count the
+    // candidates
+    let inner: [Range<usize>; D] = std::array::from_fn(|d| border..shape[d] - border);
+    let pos = prob
+        .clone()
+        .slice(inner)
+        .greater_equal_elem(prob_threshold)
+        .argwhere()
+        .add_scalar(border as i64);
+    let n_cands = pos.dims()[0];
+    if n_cands == 0 {
+        return GridCandidates::empty(D, n_rays);
+    }
+    // the flat (row-major) grid cell index of each candidate
+    let inds = (0..D)
+        .map(|d| {
+            let stride: usize = shape[d + 1..].iter().product();
+            pos.clone().narrow(1, d, 1).mul_scalar(stride as i64)
+        })
+        .reduce(|a, b| a + b)
+        .unwrap()
+        .reshape([n_cands]);
+    let n_cells: usize = shape.iter().product();
+    let prob = prob.reshape([n_cells]).select(0, inds.clone());
+    let dist = dist.select(0, inds).clamp_min(1e-3);
+    let pos: Vec<usize> = pos.into_data().iter::<i64>().map(|v| v as usize).collect();
+    GridCandidates {
+        pos: Array2::from_shape_vec((n_cands, D), pos).unwrap(),
+        prob: Array1::from_vec(prob.into_data().iter::<f32>().collect()),
+        dist: Array2::from_shape_vec((n_cands, n_rays), dist.into_data().iter::<f32>().collect())
+            .unwrap(),
+    }
+}
+
+/// Gather the object candidates of a StarDist network output on the host.
+///
+/// # Description
+///
+/// Thresholds the object probabilities, clips the candidates within `border`
+/// cells of the output grid border and gathers the probabilities and ray
+/// distances of the remaining candidates from host arrays. This is the host
+/// equivalent of `gather_candidates`.
+///
+/// # Arguments
+///
+/// * `prob`: The object probabilities with the output grid shape.
+/// * `dist`: The ray distances with shape `(n_cells, n_rays)`, where the
+///   output grid cells are in row-major order.
+/// * `prob_threshold`: The object probability threshold.
+/// * `border`: The number of output grid cells clipped at each border.
+///
+/// # Returns
+///
+/// * `GridCandidates`: The object candidates above `prob_threshold`, in
+///   row-major grid order.
+pub fn gather_candidates_host(
+    prob: ArrayViewD<f32>,
+    dist: ArrayView2<f32>,
+    prob_threshold: f32,
+    border: usize,
+) -> GridCandidates {
+    let n_dims = prob.ndim();
+    let n_rays = dist.dim().1;
+    if prob.shape().iter().any(|&s| s <= 2 * border) {
+        return GridCandidates::empty(n_dims, n_rays);
+    }
+    let mut valid_mask = manual_mask(&prob, prob_threshold, None);
+    border::clip_mask_border(&mut valid_mask.view_mut(), border);
+    // collect the valid grid positions, probabilities and flat (row-major)
+    // grid cell indices to avoid iterating the mask repeatedly
+    let valid: Vec<(usize, IxDyn, f32)> = prob
+        .indexed_iter()
+        .zip(valid_mask.iter())
+        .enumerate()
+        .filter(|&(_, (_, &v))| v)
+        .map(|(i, ((pos, &p), _))| (i, pos, p))
+        .collect();
+    let valid_inds: Vec<usize> = valid.iter().map(|v| v.0).collect();
+    // the distances are at least 1e-3 to prevent negative and/or zero
+    // distances
+    GridCandidates {
+        pos: Array2::from_shape_fn((valid.len(), n_dims), |(c, d)| valid[c].1[d]),
+        prob: valid.iter().map(|v| v.2).collect(),
+        dist: dist.select(Axis(0), &valid_inds).mapv(|v| v.max(1e-3)),
+    }
+}
@@ -0,0 +1,121 @@
+use imgal::prelude::*;
+use ndarray::ArrayView2;
+
+use crate::geometry::polygon::build_polygons;
+use crate::geometry::polyhedron::{golden_spiral, polyhedron_bbox, polyhedron_vol};
+
+/// Object filters applied to predicted objects before rendering.
+///
+/// Objects are filtered after non-maximum suppression, using the object
+/// centers and ray distances (*i.e.* without rendering the objects). Removed
+/// objects do not take a label id, keeping the label ids contiguous.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ObjectFilter {
+    /// If `true`, objects whose polygon/polyhedron crosses the image boundary
+    /// are removed.
+    pub exclude_border: bool,
+    /// The minimum object area (2D) or volume (3D) in pixels/voxels. If
+    /// `None` then no minimum size is applied.
+    pub min_size: Option<f32>,
+    /// The maximum object area (2D) or volume (3D) in pixels/voxels. If
+    /// `None` then no maximum size is applied.
+    pub max_size: Option<f32>,
+}
+
+impl ObjectFilter {
+    /// Check if the filter keeps every object.
+    pub fn is_noop(&self) -> bool {
+        !self.exclude_border && self.min_size.is_none() && self.max_size.is_none()
+    }
+
+    /// Scale the size range of the filter, *e.g.* into the pixels of a resized
+    /// image.
+    pub(crate) fn scale_size(&self, factor: f32) -> Self {
+        Self {
+            min_size: self.min_size.map(|s| s * factor),
+            max_size: self.max_size.map(|s| s * factor),
+            ..*self
+        }
+    }
+
+    /// Check if an object size is within the size range.
+    fn keep_size(&self, size: f32) -> bool {
+        self.min_size.is_none_or(|s| size >= s) && self.max_size.is_none_or(|s| size <= s)
+    }
+}
+
+/// Filter distance representation polygons by image border and area.
+///
+/// # Arguments
+///
+/// * `polygon_dist`: Input radial distance array with shape `(n_polys, n_rays)`.
+/// * `polygon_pnts`: Input polygon positions array with shape `(n_polys, 2)`
+///   containing the (row, col) coordinates of polygon centers.
+/// * `shape`: The `(row, col)` image shape.
+/// * `filter`: The object filter.
+///
+/// # Returns
+///
+/// * `Vec<bool>`: A boolean array of length `n_polys` where `true` indicates
+///   polygons that should be retained.
+pub fn polygon_filter(
+    polygon_dist: ArrayView2<f32>,
+    polygon_pnts: ArrayView2<f32>,
+    shape: (usize, usize),
+    filter: &ObjectFilter,
+) -> Vec<bool> {
+    let (n_polys, n_rays) = polygon_dist.dim();
+    if filter.is_noop() {
+        return vec![true; n_polys];
+    }
+    let max_row = shape.0 as f32 - 1.0;
+    let max_col = shape.1 as f32 - 1.0;
+    build_polygons(polygon_dist, polygon_pnts, n_polys, n_rays)
+        .iter()
+        .map(|p| {
+            let (y_min, y_max, x_min, x_max) = p.bbox;
+            let inside = y_min >= 0.0 && x_min >= 0.0 && y_max <= max_row && x_max <= max_col;
+            (inside || !filter.exclude_border) && filter.keep_size(p.area)
+        })
+        .collect()
+}
+
+/// Filter distance representation polyhedra by image border and volume.
+///
+/// # Arguments
+///
+/// * `polyhedron_dist`: Input radial distance array with shape
+///   `(n_polys, n_rays)`.
+/// * `polyhedron_pnts`: Input polyhedron points array with shape `(n_polys, 3)`
+///   containing the (pln, row, col) coordinates of polyhedron centers.
+/// * `anisotropy`: The anisotropy of the polyhedron rays.
+/// * `shape`: The `[pln, row, col]` image shape.
+/// * `filter`: The object filter.
+///
+/// # Returns
+///
+/// * `Ok(Vec<bool>)`: A boolean array of length `n_polys` where `true`
+///   indicates polyhedra that should be retained.
+/// * `Err(ImgalError)`: If the golden spiral can not be constructed.
+pub fn polyhedron_filter(
+    polyhedron_dist: ArrayView2<f32>,
+    polyhedron_pnts: ArrayView2<f32>,
+    anisotropy: [f32; 3],
+    shape: [usize; 3],
+    filter: &ObjectFilter,
+) -> Result<Vec<bool>, ImgalError> {
+    let (n_polys, n_rays) = polyhedron_dist.dim();
+    if filter.is_noop() || n_polys == 0 {
+        return Ok(vec![true; n_polys]);
+    }
+    let (verts, faces) = golden_spiral(n_rays, Some(anisotropy))?;
+    (0..n_polys)
+        .map(|i| {
+            let dist = polyhedron_dist.row(i);
+            let bbox = polyhedron_bbox(dist, polyhedron_pnts.row(i), verts.view());
+            let inside = (0..3).all(|d| bbox[2 * d] >= 0 && bbox[2 * d + 1] < shape[d] as i32);
+            let vol = polyhedron_vol(dist, verts.view(), faces.view())?;
+            Ok((inside || !filter.exclude_border) && filter.keep_size(vol))
+        })
+        .collect()
+}
@@ -0,0 +1,404 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use imgal::prelude::*;
+use ndarray::{Array2, Array3, ArrayView2, ArrayView3, Axis};
+use rayon::prelude::*;
+
+use crate::CellcastError;
+
+/// The histogram padding of the flow end positions.
+const RPAD: usize = 20;
+/// The minimum flow end point count of a mask seed.
+const SEED_MIN_COUNT: u32 = 10;
+/// The minimum flow end point count of a mask seed's neighborhood.
+const EXPAND_MIN_COUNT: u32 = 2;
+/// The number of mask seed expansion iterations.
+const EXPAND_ITERS: usize = 5;
+/// The maximum fraction of the image a single mask may cover.
+const MAX_MASK_FRACTION: f64 = 0.4;
+
+/// Compute instance segmentation masks from Cellpose flows.
+///
+/// # Description
+///
+/// Computes instance segmentation masks from the flows and cell probability
+/// logits of a Cellpose network, following the reference Cellpose dynamics.
+/// Each pixel above the cell probability threshold follows the flow field for
+/// `niter` steps, pixels converging to the same sink are grouped into a mask,
+/// masks whose flows do not match the flows recomputed from the mask are
+/// removed, and holes are filled after removing small masks.
+///
+/// # Arguments
+///
+/// * `dp`: The `(dy, dx)` flows with shape `(2, row, col)`.
+/// * `cellprob`: The cell probability logits with shape `(row, col)`.
+/// * `flow_threshold`: The maximum mean squared flow error of a mask. Masks
+///   are not checked if `flow_threshold <= 0.0`.
+/// * `cellprob_threshold`: The cell probability logit threshold of the pixels
+///   that follow the flows.
+/// * `niter`: The number of flow following iterations.
+/// * `min_size`: The minimum number of pixels of a mask.
+///
+/// # Returns
+///
+/// * `Ok(Array2<u64>)`: The instance segmentation labels, numbered from `1`.
+/// * `Err(CellcastError)`: If the `dp` and `cellprob` shapes do not match.
+pub fn compute_masks(
+    dp: ArrayView3<f32>,
+    cellprob: ArrayView2<f32>,
+    flow_threshold: f32,
+    cellprob_threshold: f32,
+    niter: usize,
+    min_size: usize,
+) -> Result<Array2<u64>, CellcastError> {
+    let (row, col) = cellprob.dim();
+    if dp.dim() != (2, row, col) {
+        return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+            msg: "The flows must have shape (2, row, col) matching the cell probabilities.",
+        }));
+    }
+    let cell_mask = cellprob.mapv(|v| v > cellprob_threshold);
+    if !cell_mask.iter().any(|&v| v) {
+        return Ok(Array2::zeros((row, col)));
+    }
+    let ends = follow_flows(dp, cell_mask.view(), niter);
+    let mut masks = get_masks(&ends, (row, col));
+    if flow_threshold > 0.0 {
+        remove_bad_flow_masks(&mut masks, dp, flow_threshold);
+    }
+    Ok(fill_holes_and_remove_small_masks(masks.view(), min_size))
+}
+
+/// Compute Cellpose flows from instance segmentation masks.
+///
+/// # Description
+///
+/// Computes the normalized `(dy, dx)` flows of each mask by simulated heat
+/// diffusion from the mask pixel nearest to the mask's median position, as
+/// the flows are computed to train Cellpose networks.
+///
+/// # Arguments
+///
+/// * `masks`: The instance segmentation labels, with `0` as background.
+///
+/// # Returns
+///
+/// * `Array3<f32>`: The unit `(dy, dx)` flows with shape `(2, row, col)`, zero
+///   for background pixels.
+pub fn masks_to_flows(masks: ArrayView2<u64>) -> Array3<f32> {
+    let (row, col) = masks.dim();
+    let mut flows = Array3::<f32>::zeros((2, row, col));
+    let pixels = label_pixels(masks);
+    let mask_flows: Vec<Vec<[f32; 2]>> = pixels.par_iter().map(|(_, p)| mask_flows(p)).collect();
+    pixels.values().zip(mask_flows.iter()).for_each(|(p, f)| {
+        p.iter().zip(f.iter()).for_each(|(p, f)| {
+            flows[[0, p[0], p[1]]] = f[0];
+            flows[[1, p[0], p[1]]] = f[1];
+        });
+    });
+    flows
+}
+
+/// Follow the flows from each cell pixel.
+///
+/// # Returns
+///
+/// * `Vec<([usize; 2], [f32; 2])>`: The start pixel and `(row, col)` end
+///   position of each cell pixel.
+fn follow_flows(
+    dp: ArrayView3<f32>,
+    cell_mask: ArrayView2<bool>,
+    niter: usize,
+) -> Vec<([usize; 2], [f32; 2])> {
+    let (row, col) = cell_mask.dim();
+    let (max_row, max_col) = ((row - 1) as f32, (col - 1) as f32);
+    let pixels: Vec<[usize; 2]> = cell_mask
+        .indexed_iter()
+        .filter(|&(_, &v)| v)
+        .map(|((r, c), _)| [r, c])
+        .collect();
+    pixels
+        .into_par_iter()
+        .map(|p| {
+            let mut pos = [p[0] as f32, p[1] as f32];
+            (0..niter).for_each(|_| {
+                let d = interp_flow(&dp, &cell_mask, pos);
+                pos[0] = (pos[0] + d[0]).clamp(0.0, max_row);
+                pos[1] = (pos[1] + d[1]).clamp(0.0, max_col);
+            });
+            (p, pos)
+        })
+        .collect()
+}
+
+/// Bilinear interpolation of the masked and scaled flows at a position.
+fn interp_flow(dp: &ArrayView3<f32>, cell_mask: &ArrayView2<bool>, pos: [f32; 2]) -> [f32; 2] {
+    let (row, col) = cell_mask.dim();
+    let r0 = (pos[0].floor() as usize).min(row - 1);
+    let c0 = (pos[1].floor() as usize).min(col - 1);
+    let (r1, c1) = ((r0 + 1).min(row - 1), (c0 + 1).min(col - 1));
+    let (fr, fc) = (pos[0] - r0 as f32, pos[1] - c0 as f32);
+    let mut d = [0.0_f32; 2];
+    [(r0, 1.0 - fr), (r1, fr)].iter().for_each(|&(r, wr)| {
+        [(c0, 1.0 - fc), (c1, fc)].iter().for_each(|&(c, wc)| {
+            if cell_mask[[r, c]] {
+                d[0] += wr * wc * dp[[0, r, c]];
+                d[1] += wr * wc * dp[[1, r, c]];
+            }
+        });
+    });
+    // the network flows are scaled by 5 during training
+    [d[0] / 5.0, d[1] / 5.0]
+}
+
+/// Group the flow end positions into masks.
+///
+/// # Description
+///
+/// Builds a histogram of the flow end positions and seeds a mask at each
+/// local maximum with more than 10 end points. Each seed is expanded into its
+/// neighborhood of histogram bins with more than 2 end points, and each cell
+/// pixel takes the mask of its end position. Masks covering more than 40% of
+/// the image are removed.
+fn get_masks(ends: &[([usize; 2], [f32; 2])], shape: (usize, usize)) -> Array2<u64> {
+    let (row, col) = shape;
+    let (hist_row, hist_col) = (row + 2 * RPAD, col + 2 * RPAD);
+    let bins: Vec<[usize; 2]> = ends
+        .iter()
+        .map(|(_, p)| [p[0] as usize + RPAD, p[1] as usize + RPAD])
+        .collect();
+    let mut hist = Array2::<u32>::zeros((hist_row, hist_col));
+    bins.iter().for_each(|&b| hist[b] += 1);
+    let hmax = max_filter(hist.view(), 2);
+    let mut seeds: Vec<[usize; 2]> = hist
+        .indexed_iter()
+        .filter(|&((r, c), &v)| v > SEED_MIN_COUNT && v == hmax[[r, c]])
+        .map(|((r, c), _)| [r, c])
+        .collect();
+    // largest seeds first, the sort is stable so ties keep their raster order
+    seeds.sort_by(|a, b| hist[*b].cmp(&hist[*a]));
+    let mut hist_masks = Array2::<u64>::zeros((hist_row, hist_col));
+    seeds.iter().enumerate().for_each(|(k, &s)| {
+        let mut pix: BTreeSet<[usize; 2]> = BTreeSet::from([s]);
+        (0..EXPAND_ITERS).for_each(|_| {
+            pix = pix
+                .iter()
+                .flat_map(|p| neighbors(*p, (hist_row, hist_col)))
+                .filter(|&n| hist[n] > EXPAND_MIN_COUNT)
+                .collect();
+        });
+        pix.iter().for_each(|&p| hist_masks[p] = k as u64 + 1);
+    });
+    let mut masks = Array2::<u64>::zeros((row, col));
+    ends.iter()
+        .zip(bins.iter())
+        .for_each(|((p, _), &b)| masks[*p] = hist_masks[b]);
+    // remove masks that cover too much of the image
+    let mut counts: BTreeMap<u64, usize> = BTreeMap::new();
+    masks
+        .iter()
+        .filter(|&&l| l > 0)
+        .for_each(|&l| *counts.entry(l).or_insert(0) += 1);
+    let max_size = MAX_MASK_FRACTION * (row * col) as f64;
+    masks.mapv_inplace(|l| match counts.get(&l) {
+        Some(&n) if n as f64 > max_size => 0,
+        _ => l,
+    });
+    renumber(masks.view())
+}
+
+/// Remove masks whose flows do not match the flows recomputed from the mask.
+fn remove_bad_flow_masks(masks: &mut Array2<u64>, dp: ArrayView3<f32>, threshold: f32) {
+    let mu = masks_to_flows(masks.view());
+    let mut errors: BTreeMap<u64, (f64, usize)> = BTreeMap::new();
+    masks
+        .indexed_iter()
+        .filter(|&(_, &l)| l > 0)
+        .for_each(|((r, c), &l)| {
+            let ey = mu[[0, r, c]] - dp[[0, r, c]] / 5.0;
+            let ex = mu[[1, r, c]] - dp[[1, r, c]] / 5.0;
+            let e = errors.entry(l).or_insert((0.0, 0));
+            e.0 += (ey * ey + ex * ex) as f64;
+            e.1 += 1;
+        });
+    masks.mapv_inplace(|l| match errors.get(&l) {
+        Some(&(e, n)) if e / n as f64 > threshold as f64 => 0,
+        _ => l,
+    });
+}
+
+/// Remove masks smaller than `min_size` pixels and fill the holes of the
+/// remaining masks.
+///
+/// # Returns
+///
+/// * `Array2<u64>`: The masks, sequentially renumbered from `1`. Holes are
+///   only filled with background pixels.
+fn fill_holes_and_remove_small_masks(masks: ArrayView2<u64>, min_size: usize) -> Array2<u64> {
+    let mut filled = Array2::<u64>::zeros(masks.dim());
+    let mut next = 1;
+    label_pixels(masks)
+        .iter()
+        .filter(|(_, p)| p.len() >= min_size)
+        .for_each(|(_, p)| {
+            let (origin, inside) = mask_box(p);
+            let outside = flood_outside(inside.view());
+            // the padding ring is always outside, so enclosed pixels are in
+            // the image
+            inside
+                .indexed_iter()
+                .filter(|&((r, c), _)| !outside[[r, c]])
+                .for_each(|((r, c), &v)| {
+                    let pos = [r + origin[0] - 1, c + origin[1] - 1];
+                    if v || masks[pos] == 0 {
+                        filled[pos] = next;
+                    }
+                });
+            next += 1;
+        });
+    filled
+}
+
+/// Compute the unit flows of a single mask by simulated heat diffusion.
+fn mask_flows(pixels: &[[usize; 2]]) -> Vec<[f32; 2]> {
+    let (origin, inside) = mask_box(pixels);
+    let local: Vec<[usize; 2]> = pixels
+        .iter()
+        .map(|p| [p[0] - origin[0] + 1, p[1] - origin[1] + 1])
+        .collect();
+    // the heat source is the mask pixel nearest the median position
+    let median = |axis: usize| {
+        let mut v: Vec<usize> = local.iter().map(|p| p[axis]).collect();
+        v.sort_unstable();
+        v[v.len() / 2] as f64
+    };
+    let (med_r, med_c) = (median(0), median(1));
+    let center = local
+        .iter()
+        .min_by(|a, b| {
+            let da = (a[0] as f64 - med_r).powi(2) + (a[1] as f64 - med_c).powi(2);
+            let db = (b[0] as f64 - med_r).powi(2) + (b[1] as f64 - med_c).powi(2);
+            da.total_cmp(&db)
+        })
+        .copied()
+        .unwrap();
+    let (box_row, box_col) = inside.dim();
+    // the padded box spans the mask extent plus 3 pixels in each axis
+    let niter = 2 * (box_row + box_col - 6);
+    let mut heat = Array2::<f64>::zeros((box_row, box_col));
+    (0..niter).for_each(|_| {
+        heat[center] += 1.0;
+        let prev = heat.clone();
+        local.iter().for_each(|&p| {
+            let sum: f64 = (p[0] - 1..=p[0] + 1)
+                .flat_map(|r| (p[1] - 1..=p[1] + 1).map(move |c| [r, c]))
+                .filter(|&n| inside[n])
+                .map(|n| prev[n])
+                .sum();
+            heat[p] = sum / 9.0;
+        });
+    });
+    heat.mapv_inplace(|v| v.ln_1p());
+    local
+        .iter()
+        .map(|&[r, c]| {
+            let dy = heat[[r + 1, c]] - heat[[r - 1, c]];
+            let dx = heat[[r, c + 1]] - heat[[r, c - 1]];
+            let norm = (dy * dy + dx * dx).sqrt() + 1e-20;
+            [(dy / norm) as f32, (dx / norm) as f32]
+        })
+        .collect()
+}
+
+/// Get the pixels of each label, in raster order.
+fn label_pixels(masks: ArrayView2<u64>) -> BTreeMap<u64, Vec<[usize; 2]>> {
+    let mut pixels: BTreeMap<u64, Vec<[usize; 2]>> = BTreeMap::new();
+    masks
+        .indexed_iter()
+        .filter(|&(_, &l)| l > 0)
+        .for_each(|((r, c), &l)| pixels.entry(l).or_default().push([r, c]));
+    pixels
+}
+
+/// Get the bounding box origin and the mask of a set of pixels, padded by one
+/// pixel on each side.
+fn mask_box(pixels: &[[usize; 2]]) -> ([usize; 2], Array2<bool>) {
+    let min_r = pixels.iter().map(|p| p[0]).min().unwrap();
+    let max_r = pixels.iter().map(|p| p[0]).max().unwrap();
+    let min_c = pixels.iter().map(|p| p[1]).min().unwrap();
+    let max_c = pixels.iter().map(|p| p[1]).max().unwrap();
+    let mut inside = Array2::<bool>::from_elem((max_r - min_r + 3, max_c - min_c + 3), false);
+    pixels
+        .iter()
+        .for_each(|p| inside[[p[0] - min_r + 1, p[1] - min_c + 1]] = true);
+    ([min_r, min_c], inside)
+}
+
+/// Flood fill the (4-connected) background of a padded mask from its corner.
+fn flood_outside(inside: ArrayView2<bool>) -> Array2<bool> {
+    let (row, col) = inside.dim();
+    let mut outside = Array2::<bool>::from_elem((row, col), false);
+    let mut stack = vec![[0, 0]];
+    outside[[0, 0]] = true;
+    while let Some([r, c]) = stack.pop() {
+        let mut visit = |n: [usize; 2]| {
+            if !inside[n] && !outside[n] {
+                outside[n] = true;
+                stack.push(n);
+            }
+        };
+        if r > 0 {
+            visit([r - 1, c]);
+        }
+        if r + 1 < row {
+            visit([r + 1, c]);
+        }
+        if c > 0 {
+            visit([r, c - 1]);
+        }
+        if c + 1 < col {
+            visit([r, c + 1]);
+        }
+    }
+    outside
+}
+
+/// Get the in bounds 3x3 neighborhood of a pixel (including the pixel).
+fn neighbors(p: [usize; 2], shape: (usize, usize)) -> impl Iterator<Item = [usize; 2]> {
+    let rows = p[0].saturating_sub(1)..=(p[0] + 1).min(shape.0 - 1);
+    rows.flat_map(move |r| {
+        (p[1].saturating_sub(1)..=(p[1] + 1).min(shape.1 - 1)).map(move |c| [r, c])
+    })
+}
+
+/// Square maximum filter with a `radius` pixel window.
+fn max_filter(data: ArrayView2<u32>, radius: usize) -> Array2<u32> {
+    let filter_1d = |data: ArrayView2<u32>, axis: usize| {
+        let mut out = data.to_owned();
+        let len = data.len_of(Axis(axis));
+        out.axis_iter_mut(Axis(1 - axis))
+            .zip(data.axis_iter(Axis(1 - axis)))
+            .for_each(|(mut o, d)| {
+                (0..len).for_each(|i| {
+                    let lo = i.saturating_sub(radius);
+                    let hi = (i + radius).min(len - 1);
+                    o[i] = (lo..=hi).map(|j| d[j]).max().unwrap();
+                });
+            });
+        out
+    };
+    let rows = filter_1d(data, 0);
+    filter_1d(rows.view(), 1)
+}
+
+/// Renumber labels sequentially from `1` in order of first appearance.
+fn renumber(masks: ArrayView2<u64>) -> Array2<u64> {
+    let mut map: BTreeMap<u64, u64> = BTreeMap::new();
+    masks.mapv(|l| {
+        if l == 0 {
+            return 0;
+        }
+        let next = map.len() as u64 + 1;
+        *map.entry(l).or_insert(next)
+    })
+}
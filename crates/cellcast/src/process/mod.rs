@@ -1,6 +1,12 @@
-//! Non-Maximum Suppression functions.
+//! Postprocessing functions.
 //!
-//! This module provides various forms of Non-Maximum Suppression (NMS) used by
-//! supported cell segmentation models.
+//! This module provides the StarDist object candidate gathering, various forms
+//! of Non-Maximum Suppression (NMS), the object filters, the flow dynamics and
+//! the seeded watershed used by supported cell segmentation models to convert
+//! network outputs into instance segmentations.
 
+pub mod candidates;
+pub mod filter;
+pub mod flows;
 pub mod nms;
+pub mod watershed;
@@ -1,12 +1,15 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::f32::consts::PI;
 use std::sync::atomic::{AtomicBool, Ordering};
 
 use imgal::prelude::*;
 use imgal::spatial::KDTree;
 use imgal::statistics::max;
-use ndarray::ArrayView2;
+use ndarray::{ArrayView1, ArrayView2};
 use rayon::prelude::*;
 
-use crate::geometry::polygon::{area_intersection, build_polygons, check_bbox_intersect};
+use crate::geometry::polygon::{PolygonGrid, build_polygons, star_convex_intersection};
 use crate::geometry::polyhedron::{
     bbox_intersect_vol, bounding_inner_radius_iso, bounding_outer_radius_iso,
     convex_hull_intersection_vol, estimate_anisotropy, golden_spiral,
@@ -14,6 +17,145 @@ use crate::geometry::polyhedron::{
     polyhedron_verts, polyhedron_vol, sphere_intersect_volume_iso,
 };
 
+/// The overlap measure of two objects compared to the NMS threshold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverlapCriterion {
+    /// The intersection divided by the smaller object, as in the reference
+    /// StarDist implementation.
+    #[default]
+    Min,
+    /// The intersection over union (IoU).
+    Iou,
+    /// The intersection divided by the candidate object, *i.e.* the lower
+    /// probability object considered for suppression.
+    Candidate,
+}
+
+impl OverlapCriterion {
+    /// Compute the overlap of two objects.
+    ///
+    /// # Arguments
+    ///
+    /// * `inter`: The intersection area/volume of the objects.
+    /// * `size_kept`: The area/volume of the higher probability object.
+    /// * `size_cand`: The area/volume of the candidate object.
+    ///
+    /// # Returns
+    ///
+    /// * `f32`: The overlap of the objects, increasing with `inter`.
+    #[inline]
+    pub fn overlap(&self, inter: f32, size_kept: f32, size_cand: f32) -> f32 {
+        let eps = 1e-10;
+        match self {
+            OverlapCriterion::Min => inter / (size_kept.min(size_cand) + eps),
+            OverlapCriterion::Iou => inter / ((size_kept + size_cand - inter).max(0.0) + eps),
+            OverlapCriterion::Candidate => inter / (size_cand + eps),
+        }
+    }
+
+    /// Compute the intersection area/volume above which the overlap of two
+    /// objects exceeds `threshold`.
+    #[inline]
+    fn min_intersection(&self, threshold: f32, size_kept: f32, size_cand: f32) -> f32 {
+        match self {
+            OverlapCriterion::Min => threshold * size_kept.min(size_cand),
+            OverlapCriterion::Iou => threshold * (size_kept + size_cand) / (1.0 + threshold),
+            OverlapCriterion::Candidate => threshold * size_cand,
+        }
+    }
+}
+
+/// The suppression of overlapping objects.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum NmsMode {
+    /// Objects overlapping a higher probability object by more than the NMS
+    /// threshold are removed.
+    #[default]
+    Hard,
+    /// Linear soft-NMS, the probability of an object overlapping a higher
+    /// probability object by more than the NMS threshold is multiplied by
+    /// `1.0 - overlap`.
+    SoftLinear,
+    /// Gaussian soft-NMS, the probability of an object overlapping a higher
+    /// probability object is multiplied by `exp(-overlap^2 / sigma)`, with a
+    /// positive and finite `sigma`.
+    SoftGaussian { sigma: f32 },
+}
+
+impl NmsMode {
+    /// Decay the probability of a candidate object by its overlap with a
+    /// higher probability object.
+    #[inline]
+    fn decay(&self, prob: f32, overlap: f32, threshold: f32) -> f32 {
+        match self {
+            NmsMode::Hard if overlap > threshold => 0.0,
+            NmsMode::SoftLinear if overlap > threshold => prob * (1.0 - overlap).max(0.0),
+            NmsMode::SoftGaussian { sigma } => prob * (-overlap * overlap / sigma).exp(),
+            _ => prob,
+        }
+    }
+}
+
+/// The accuracy of the polyhedron intersection volumes of 3D NMS.
+///
+/// The polyhedron NMS escalates through increasingly expensive intersection
+/// volume estimates, the cheaper accuracies stop at an earlier estimate. Pairs
+/// decided by the sphere and bounding box bounds are always the same as with
+/// the exact intersection volumes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NmsAccuracy {
+    /// The ambiguous pairs are resolved with the intersection of the polyhedra
+    /// face halfspaces, the intersection of their convex hulls and finally by
+    /// rendering the polyhedra voxels.
+    #[default]
+    Exact,
+    /// Stops at the intersection volume of the polyhedra face halfspaces. This
+    /// volume is a lower bound of the intersection volume and exact for convex
+    /// polyhedra, so polyhedra are never suppressed wrongly but overlapping
+    /// non-convex polyhedra may be kept.
+    Halfspace,
+    /// Stops at the sphere bounds, ambiguous pairs are decided by the
+    /// intersection volume of spheres with the polyhedra volumes. The error
+    /// grows with the deviation of the polyhedra from spheres (after the
+    /// anisotropy correction) and may go in either direction.
+    Spheres,
+}
+
+/// The Non-Maximum Suppression (NMS) options of a prediction.
+///
+/// With soft-NMS, overlapping objects are not removed but their probability
+/// is decayed. Objects are removed once their decayed probability falls below
+/// the probability threshold.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct NmsOptions {
+    /// The overlap measure compared to the NMS threshold. By default the
+    /// intersection divided by the smaller object.
+    pub overlap: OverlapCriterion,
+    /// The suppression of overlapping objects. By default overlapping objects
+    /// are removed.
+    pub mode: NmsMode,
+}
+
+impl NmsOptions {
+    /// Validate the NMS options.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())`: If the options are valid.
+    /// * `Err(ImgalError)`: If the Gaussian soft-NMS `sigma` is not positive
+    ///   and finite.
+    pub fn validate(&self) -> Result<(), ImgalError> {
+        if let NmsMode::SoftGaussian { sigma } = self.mode
+            && !(sigma > 0.0 && sigma.is_finite())
+        {
+            return Err(ImgalError::InvalidGeneric {
+                msg: "The Gaussian soft-NMS sigma must be positive and finite.",
+            });
+        }
+        Ok(())
+    }
+}
+
 /// Perform Non-Maximum Suppression (NMS) on distance representation polygons.
 ///
 /// # Description
@@ -21,7 +163,9 @@ use crate::geometry::polyhedron::{
 /// Performs Non-Maximum Suppression (NMS) on polygons in ray distance
 /// representation. Overlapping polygons are suppressed based on their
 /// intersection area. Polygon distances and positions are expected in
-/// descending order, with the highest probability first.
+/// descending order, with the highest probability first. Polygons are visited
+/// in this order and only the overlap checks with the neighbors of a polygon
+/// run in parallel, so the result does not depend on the thread scheduling.
 ///
 /// # Arguments
 ///
@@ -36,6 +180,7 @@ use crate::geometry::polyhedron::{
 /// * `n_rays`: The number of ray angles.
 /// * `threshold`: The overlap threshold in range `0.0` to `1.0`. Polygons
 ///   exceeding this overlap threshold value are suppressed.
+/// * `overlap`: The overlap measure compared to `threshold`.
 ///
 /// # Returns
 ///
@@ -44,16 +189,16 @@ use crate::geometry::polyhedron::{
 ///   retained).
 pub fn polygon_nms(
     polygon_dist: ArrayView2<f32>,
-    polygon_pnts: ArrayView2<usize>,
+    polygon_pnts: ArrayView2<f32>,
     n_polys: usize,
     n_rays: usize,
     threshold: f32,
+    overlap: OverlapCriterion,
 ) -> Vec<bool> {
     // create 2D polygons vector and perform NMS
     let suppressed: Vec<AtomicBool> = (0..n_polys).map(|_| AtomicBool::new(false)).collect();
     let polygons = build_polygons(polygon_dist.view(), polygon_pnts.view(), n_polys, n_rays);
-    let kdtree = KDTree::build(polygon_pnts.view());
-    let max_dist = polygons.iter().map(|p| p.dist).fold(0.0, f32::max);
+    let grid = PolygonGrid::build(&polygons);
     // iterate through each polygon and skip already suppressed polygons
     // the key here is that each polygon's probability is encoded in it's order
     // as it was sorted in descending order (highest prob first)
@@ -61,20 +206,13 @@ pub fn polygon_nms(
         if suppressed[i].load(Ordering::Relaxed) {
             return;
         }
-        let query = [polygon_pnts[[i, 0]], polygon_pnts[[i, 1]]];
-        let radius = (max_dist + polygons[i].dist) as f64;
-        let neighbors = kdtree.search_for_indices(&query, radius).unwrap();
-        neighbors.par_iter().for_each(|&j| {
+        grid.neighbors(i).par_iter().for_each(|&j| {
             if j <= i || suppressed[j].load(Ordering::Relaxed) {
                 return;
             }
-            if !check_bbox_intersect(&polygons[i].bbox, &polygons[j].bbox) {
-                return;
-            }
-            let poly_area_inter = area_intersection(&polygons[i].vertices, &polygons[j].vertices);
-            let min_area = polygons[i].area.min(polygons[j].area) + 1e-10;
-            let overlap = poly_area_inter / min_area;
-            if overlap > threshold {
+            let poly_area_inter = star_convex_intersection(&polygons[i], &polygons[j]);
+            let poly_overlap = overlap.overlap(poly_area_inter, polygons[i].area, polygons[j].area);
+            if poly_overlap > threshold {
                 suppressed[j].store(true, Ordering::Relaxed);
             }
         });
@@ -92,7 +230,10 @@ pub fn polygon_nms(
 /// Performs Non-Maximum Suppression (NMS) on polyhedra in ray distance
 /// representation. Overlapping polyhedra are suppressed based on their
 /// intersection volume. Polyhedra distances and points (*.i.e.* centers) are
-/// expected in descending order, with the highest probability first.
+/// expected in descending order, with the highest probability first. Polyhedra
+/// are visited in this order and only the overlap checks with the neighbors of
+/// a polyhedron run in parallel, so the result does not depend on the thread
+/// scheduling.
 ///
 /// # Arguments
 ///
@@ -107,6 +248,8 @@ pub fn polygon_nms(
 /// * `n_rays`: The number of ray angles.
 /// * `threshold`: The overlap threshold in range `0.0` to `1.0`. Polyhedra
 ///   exceeding this overlap threshold value are suppressed.
+/// * `overlap`: The overlap measure compared to `threshold`.
+/// * `accuracy`: The accuracy of the polyhedron intersection volumes.
 ///
 /// # Returns
 ///
@@ -121,6 +264,8 @@ pub fn polyhedron_nms(
     n_polys: usize,
     n_rays: usize,
     threshold: f32,
+    overlap: OverlapCriterion,
+    accuracy: NmsAccuracy,
 ) -> Result<Vec<bool>, ImgalError> {
     let eps = 1e-10;
     let gs = golden_spiral(n_rays, Some(anisotropy))?;
@@ -138,7 +283,7 @@ pub fn polyhedron_nms(
             (bbox, vol, ro)
         })
         .collect();
-    let aniso = estimate_anisotropy(&bboxes.as_slice(), n_polys);
+    let aniso = estimate_anisotropy(bboxes.as_slice(), n_polys);
     let (rad_in_iso, rad_out_iso): (Vec<f32>, Vec<f32>) = (0..n_polys)
         .map(|i| {
             let cur_dist = polyhedron_dist.row(i);
@@ -147,113 +292,408 @@ pub fn polyhedron_nms(
             (rii, roi)
         })
         .collect();
+    let rad_vol_iso = volume_radius_iso(&vols, aniso);
     let max_dist = max(&rad_out, None)?;
-    let kdtree = KDTree::build(&polyhedron_pnts);
+    let kdtree = KDTree::build(polyhedron_pnts);
     let suppressed: Vec<AtomicBool> = (0..n_polys).map(|_| AtomicBool::new(false)).collect();
-    (0..n_polys.saturating_sub(1))
-        .into_par_iter()
-        .for_each(|i| {
-            if suppressed[i].load(Ordering::Relaxed) {
-                return;
-            }
-            let cur_dist = polyhedron_dist.row(i);
-            let cur_pnt = polyhedron_pnts.row(i);
-            let cur_bbox = bboxes[i];
-            let cur_poly_verts = polyhedron_verts(cur_dist, cur_pnt, verts);
-            let search_rad = (max_dist + rad_out[i]) as f64;
-            let neighbors = kdtree.search_for_indices(&cur_pnt, search_rad).unwrap();
-            let nz = (cur_bbox[1] - cur_bbox[0] + 1) as usize;
-            let ny = (cur_bbox[3] - cur_bbox[2] + 1) as usize;
-            let nx = (cur_bbox[5] - cur_bbox[4] + 1) as usize;
-            neighbors
-                .iter()
-                .filter(|&&j| j > i && !suppressed[j].load(Ordering::Relaxed))
-                .for_each(|&j| {
-                    let mut iou: f32;
-                    let ngh_dist = polyhedron_dist.row(j);
-                    let ngh_pnt = polyhedron_pnts.row(j);
-                    let vol_min = vols[i].min(vols[j]);
-                    let upper_inter_vol = sphere_intersect_volume_iso(
-                        cur_pnt,
-                        ngh_pnt,
-                        rad_out_iso[i],
-                        rad_out_iso[j],
-                        &aniso,
-                    );
-                    let bbox_inter_vol = bbox_intersect_vol(&cur_bbox, &bboxes[j]);
-                    let upper_inter_vol = upper_inter_vol.min(bbox_inter_vol);
-                    iou = (upper_inter_vol / (vol_min + eps)).min(1.0);
-                    if upper_inter_vol < eps || iou <= threshold {
-                        return;
-                    }
-                    // this checks the lower bound of intersection and IoU
-                    let lower_inter_vol = sphere_intersect_volume_iso(
+    // iterate through each polyhedron in probability order, only the overlap
+    // checks of a polyhedron with its neighbors run in parallel, this keeps
+    // the suppression independent of the thread scheduling
+    (0..n_polys.saturating_sub(1)).for_each(|i| {
+        if suppressed[i].load(Ordering::Relaxed) {
+            return;
+        }
+        let cur_dist = polyhedron_dist.row(i);
+        let cur_pnt = polyhedron_pnts.row(i);
+        let cur_bbox = bboxes[i];
+        let cur_poly_verts = polyhedron_verts(cur_dist, cur_pnt, verts);
+        let search_rad = (max_dist + rad_out[i]) as f64;
+        let neighbors = kdtree.search_for_indices(cur_pnt, search_rad).unwrap();
+        let nz = (cur_bbox[1] - cur_bbox[0] + 1) as usize;
+        let ny = (cur_bbox[3] - cur_bbox[2] + 1) as usize;
+        let nx = (cur_bbox[5] - cur_bbox[4] + 1) as usize;
+        neighbors
+            .par_iter()
+            .filter(|&&j| j > i && !suppressed[j].load(Ordering::Relaxed))
+            .for_each(|&j| {
+                let mut iou: f32;
+                let ngh_dist = polyhedron_dist.row(j);
+                let ngh_pnt = polyhedron_pnts.row(j);
+                let iou_of = |inter: f32| overlap.overlap(inter, vols[i], vols[j]);
+                let upper_inter_vol = sphere_intersect_volume_iso(
+                    cur_pnt,
+                    ngh_pnt,
+                    rad_out_iso[i],
+                    rad_out_iso[j],
+                    &aniso,
+                );
+                let bbox_inter_vol = bbox_intersect_vol(&cur_bbox, &bboxes[j]);
+                let upper_inter_vol = upper_inter_vol.min(bbox_inter_vol);
+                iou = iou_of(upper_inter_vol).min(1.0);
+                if upper_inter_vol < eps || iou <= threshold {
+                    return;
+                }
+                // this checks the lower bound of intersection and IoU
+                let lower_inter_vol = sphere_intersect_volume_iso(
+                    cur_pnt,
+                    ngh_pnt,
+                    rad_in_iso[i],
+                    rad_in_iso[j],
+                    &aniso,
+                );
+                iou = iou_of(lower_inter_vol).max(0.0);
+                if iou > threshold {
+                    suppressed[j].store(true, Ordering::Relaxed);
+                    return;
+                }
+                if accuracy == NmsAccuracy::Spheres {
+                    let sphere_inter_vol = sphere_intersect_volume_iso(
                         cur_pnt,
                         ngh_pnt,
-                        rad_in_iso[i],
-                        rad_in_iso[j],
+                        rad_vol_iso[i],
+                        rad_vol_iso[j],
                         &aniso,
-                    );
-                    iou = (lower_inter_vol / (vol_min + eps)).max(0.0);
-                    if iou > threshold {
-                        suppressed[j].store(true, Ordering::Relaxed);
-                        return;
-                    }
-                    // this computes the polyhedron intersection of the lower bound
-                    let ngh_poly_verts = polyhedron_verts(ngh_dist, ngh_pnt, verts);
-                    let poly_inter_vol = golden_spiral_intersection_vol(
-                        cur_poly_verts.view(),
-                        ngh_poly_verts.view(),
-                        cur_pnt,
-                        ngh_pnt,
-                        faces,
-                    )
-                    .unwrap_or(0.0) as f32;
-                    iou = poly_inter_vol / (vol_min + eps);
-                    if iou > threshold {
-                        suppressed[j].store(true, Ordering::Relaxed);
-                        return;
-                    }
-                    let conv_inter_vol = convex_hull_intersection_vol(
-                        cur_poly_verts.view(),
-                        ngh_poly_verts.view(),
-                        cur_pnt,
-                        ngh_pnt,
                     )
-                    .unwrap_or(1e10) as f32;
-                    iou = conv_inter_vol / (vol_min + eps);
-                    if iou <= threshold {
-                        return;
-                    }
-                    // this computes a polygon rendering check, the final check
-                    let cur_poly_mask = polyhedron_to_mask(
-                        cur_poly_verts.view(),
-                        faces,
-                        cur_pnt,
-                        cur_bbox,
-                        nz,
-                        ny,
-                        nx,
-                    );
-                    let overlap_count = overlap_polyhedron_mask(
-                        ngh_poly_verts.view(),
-                        faces,
-                        ngh_pnt,
-                        &cur_poly_mask,
-                        cur_bbox,
-                        nz,
-                        ny,
-                        nx,
-                        (vol_min + eps) * threshold,
-                    );
-                    iou = overlap_count / (vol_min + eps);
-                    if iou > threshold {
+                    .min(upper_inter_vol)
+                    .max(lower_inter_vol);
+                    if iou_of(sphere_inter_vol) > threshold {
                         suppressed[j].store(true, Ordering::Relaxed);
                     }
-                });
-        });
+                    return;
+                }
+                // this computes the polyhedron intersection of the lower bound
+                let ngh_poly_verts = polyhedron_verts(ngh_dist, ngh_pnt, verts);
+                let poly_inter_vol = golden_spiral_intersection_vol(
+                    cur_poly_verts.view(),
+                    ngh_poly_verts.view(),
+                    cur_pnt,
+                    ngh_pnt,
+                    faces,
+                )
+                .unwrap_or(0.0) as f32;
+                iou = iou_of(poly_inter_vol);
+                if iou > threshold {
+                    suppressed[j].store(true, Ordering::Relaxed);
+                    return;
+                }
+                if accuracy == NmsAccuracy::Halfspace {
+                    return;
+                }
+                let conv_inter_vol = convex_hull_intersection_vol(
+                    cur_poly_verts.view(),
+                    ngh_poly_verts.view(),
+                    cur_pnt,
+                    ngh_pnt,
+                )
+                .unwrap_or(1e10) as f32;
+                iou = iou_of(conv_inter_vol);
+                if iou <= threshold {
+                    return;
+                }
+                // this computes a polygon rendering check, the final check
+                let cur_poly_mask =
+                    polyhedron_to_mask(cur_poly_verts.view(), faces, cur_pnt, cur_bbox, nz, ny, nx);
+                let overlap_count = overlap_polyhedron_mask(
+                    ngh_poly_verts.view(),
+                    faces,
+                    ngh_pnt,
+                    &cur_poly_mask,
+                    cur_bbox,
+                    nz,
+                    ny,
+                    nx,
+                    overlap.min_intersection(threshold, vols[i], vols[j]) + eps,
+                );
+                iou = iou_of(overlap_count);
+                if iou > threshold {
+                    suppressed[j].store(true, Ordering::Relaxed);
+                }
+            });
+    });
     Ok(suppressed
         .iter()
         .map(|v| !v.load(Ordering::Relaxed))
         .collect())
 }
+
+/// Perform soft Non-Maximum Suppression (soft-NMS) on distance representation
+/// polygons.
+///
+/// # Description
+///
+/// Performs soft-NMS on polygons in ray distance representation. Starting
+/// with the highest probability polygon, the probability of each overlapping
+/// polygon is decayed by its overlap instead of suppressing it. Polygons with
+/// a decayed probability below `prob_threshold` are not considered further.
+///
+/// # Arguments
+///
+/// * `polygon_dist`: Input radial distance array with shape `(n_polys, n_rays)`
+///   containing the radial distances from polygon centers to their boundaries
+///   at each ray angle.
+/// * `polygon_pnts`: Input polygon positions array with shape `(n_polys, 2)`
+///   containing the (row, col) coordinates of polygon centers.
+/// * `polygon_prob`: Input polygon probabilities with shape `(n_polys,)`.
+/// * `prob_threshold`: The probability threshold of the polygons.
+/// * `threshold`: The overlap threshold in range `0.0` to `1.0`.
+/// * `overlap`: The overlap measure of the polygons.
+/// * `mode`: The probability decay of overlapping polygons.
+///
+/// # Returns
+///
+/// * `Vec<f32>`: The decayed polygon probabilities of length `n_polys`.
+pub fn polygon_soft_nms(
+    polygon_dist: ArrayView2<f32>,
+    polygon_pnts: ArrayView2<f32>,
+    polygon_prob: ArrayView1<f32>,
+    prob_threshold: f32,
+    threshold: f32,
+    overlap: OverlapCriterion,
+    mode: NmsMode,
+) -> Vec<f32> {
+    let (n_polys, n_rays) = polygon_dist.dim();
+    if n_polys == 0 {
+        return Vec::new();
+    }
+    let polygons = build_polygons(polygon_dist, polygon_pnts, n_polys, n_rays);
+    let grid = PolygonGrid::build(&polygons);
+    soft_nms(
+        polygon_prob,
+        prob_threshold,
+        threshold,
+        mode,
+        |i| grid.neighbors(i),
+        |i, cands| {
+            cands
+                .par_iter()
+                .map(|&j| {
+                    let inter = star_convex_intersection(&polygons[i], &polygons[j]);
+                    overlap.overlap(inter, polygons[i].area, polygons[j].area)
+                })
+                .collect()
+        },
+    )
+}
+
+/// Perform soft Non-Maximum Suppression (soft-NMS) on distance representation
+/// polyhedra.
+///
+/// # Description
+///
+/// Performs soft-NMS on polyhedra in ray distance representation. Starting
+/// with the highest probability polyhedron, the probability of each
+/// overlapping polyhedron is decayed by its overlap instead of suppressing
+/// it. With the exact accuracy, the intersection volumes are computed by
+/// rendering the polyhedra, otherwise they are estimated with the face
+/// halfspaces or the spheres of the polyhedra volumes. Polyhedra with a
+/// decayed probability below `prob_threshold` are not considered further.
+///
+/// # Arguments
+///
+/// * `polyhedron_dist`: Input radial distance array with shape
+///   `(n_polys, n_rays)` containing the radial distances from polygon centers
+///   to their boundaries at each ray angle.
+/// * `polyhedron_pnts`: Input polyhedron points array with shape `(n_polys, 3)`
+///   containing the (pln, row, col) coordinates of polyhedron centers.
+/// * `polyhedron_prob`: Input polyhedron probabilities with shape `(n_polys,)`.
+/// * `anisotropy`: The anisotropy of the polyhedron rays.
+/// * `prob_threshold`: The probability threshold of the polyhedra.
+/// * `threshold`: The overlap threshold in range `0.0` to `1.0`.
+/// * `overlap`: The overlap measure of the polyhedra.
+/// * `mode`: The probability decay of overlapping polyhedra.
+/// * `accuracy`: The accuracy of the polyhedron intersection volumes.
+///
+/// # Returns
+///
+/// * `Ok(Vec<f32>)`: The decayed polyhedron probabilities of length `n_polys`.
+/// * `Err(ImgalError)`: If the golden spiral can not be constructed.
+pub fn polyhedron_soft_nms(
+    polyhedron_dist: ArrayView2<f32>,
+    polyhedron_pnts: ArrayView2<f32>,
+    polyhedron_prob: ArrayView1<f32>,
+    anisotropy: [f32; 3],
+    prob_threshold: f32,
+    threshold: f32,
+    overlap: OverlapCriterion,
+    mode: NmsMode,
+    accuracy: NmsAccuracy,
+) -> Result<Vec<f32>, ImgalError> {
+    let (n_polys, n_rays) = polyhedron_dist.dim();
+    if n_polys == 0 {
+        return Ok(Vec::new());
+    }
+    let gs = golden_spiral(n_rays, Some(anisotropy))?;
+    let verts = gs.0.view();
+    let faces = gs.1.view();
+    let (bboxes, vols, rad_out): (Vec<[i32; 6]>, Vec<f32>, Vec<f32>) = (0..n_polys)
+        .map(|i| {
+            let cur_dist = polyhedron_dist.row(i);
+            let bbox = polyhedron_bbox(cur_dist, polyhedron_pnts.row(i), verts);
+            // SAFE: this unwrap is safe because we know that the parameters are
+            // valid lengths here
+            let vol = polyhedron_vol(cur_dist, verts, faces).unwrap();
+            let ro = max(cur_dist, Some(1)).unwrap();
+            (bbox, vol, ro)
+        })
+        .collect();
+    let max_dist = max(&rad_out, None)?;
+    let aniso = estimate_anisotropy(bboxes.as_slice(), n_polys);
+    let rad_vol_iso = volume_radius_iso(&vols, aniso);
+    let kdtree = KDTree::build(polyhedron_pnts);
+    Ok(soft_nms(
+        polyhedron_prob,
+        prob_threshold,
+        threshold,
+        mode,
+        |i| {
+            let search_rad = (max_dist + rad_out[i]) as f64;
+            kdtree
+                .search_for_indices(polyhedron_pnts.row(i), search_rad)
+                .unwrap()
+                .to_vec()
+        },
+        |i, cands| {
+            let cur_pnt = polyhedron_pnts.row(i);
+            let cur_bbox = bboxes[i];
+            let cur_poly_verts = polyhedron_verts(polyhedron_dist.row(i), cur_pnt, verts);
+            let nz = (cur_bbox[1] - cur_bbox[0] + 1) as usize;
+            let ny = (cur_bbox[3] - cur_bbox[2] + 1) as usize;
+            let nx = (cur_bbox[5] - cur_bbox[4] + 1) as usize;
+            let cur_poly_mask = (accuracy == NmsAccuracy::Exact).then(|| {
+                polyhedron_to_mask(cur_poly_verts.view(), faces, cur_pnt, cur_bbox, nz, ny, nx)
+            });
+            cands
+                .par_iter()
+                .map(|&j| {
+                    let bbox_inter_vol = bbox_intersect_vol(&cur_bbox, &bboxes[j]);
+                    if bbox_inter_vol <= 0.0 {
+                        return 0.0;
+                    }
+                    let ngh_pnt = polyhedron_pnts.row(j);
+                    let ngh_poly_verts = polyhedron_verts(polyhedron_dist.row(j), ngh_pnt, verts);
+                    let inter = match &cur_poly_mask {
+                        Some(cur_poly_mask) => overlap_polyhedron_mask(
+                            ngh_poly_verts.view(),
+                            faces,
+                            ngh_pnt,
+                            cur_poly_mask,
+                            cur_bbox,
+                            nz,
+                            ny,
+                            nx,
+                            f32::MAX,
+                        ),
+                        None if accuracy == NmsAccuracy::Halfspace => {
+                            golden_spiral_intersection_vol(
+                                cur_poly_verts.view(),
+                                ngh_poly_verts.view(),
+                                cur_pnt,
+                                ngh_pnt,
+                                faces,
+                            )
+                            .unwrap_or(0.0) as f32
+                        }
+                        None => sphere_intersect_volume_iso(
+                            cur_pnt,
+                            ngh_pnt,
+                            rad_vol_iso[i],
+                            rad_vol_iso[j],
+                            &aniso,
+                        )
+                        .min(bbox_inter_vol),
+                    };
+                    overlap.overlap(inter, vols[i], vols[j])
+                })
+                .collect()
+        },
+    ))
+}
+
+/// Compute the radii of the spheres with the polyhedra volumes, after the
+/// anisotropy correction.
+fn volume_radius_iso(vols: &[f32], anisotropy: [f32; 3]) -> Vec<f32> {
+    let scale = anisotropy[0] * anisotropy[1] * anisotropy[2];
+    vols.iter()
+        .map(|v| (3.0 * v * scale / (4.0 * PI)).cbrt())
+        .collect()
+}
+
+/// An object score ordered by `f32::total_cmp`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Score(f32);
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// Decay the probabilities of overlapping objects, starting with the highest
+/// probability object.
+///
+/// # Arguments
+///
+/// * `prob`: The object probabilities.
+/// * `prob_threshold`: The probability threshold of the objects.
+/// * `threshold`: The overlap threshold.
+/// * `mode`: The probability decay of overlapping objects.
+/// * `neighbors`: The indices of the objects near an object.
+/// * `overlaps`: The overlaps of candidate objects with an object.
+///
+/// # Returns
+///
+/// * `Vec<f32>`: The decayed object probabilities.
+fn soft_nms<N, O>(
+    prob: ArrayView1<f32>,
+    prob_threshold: f32,
+    threshold: f32,
+    mode: NmsMode,
+    neighbors: N,
+    overlaps: O,
+) -> Vec<f32>
+where
+    N: Fn(usize) -> Vec<usize>,
+    O: Fn(usize, &[usize]) -> Vec<f32>,
+{
+    let n_objs = prob.len();
+    let mut scores = prob.to_vec();
+    let mut done = vec![false; n_objs];
+    // the remaining object with the highest probability (the first one on
+    // ties) decays the probability of its overlapping neighbors, decayed
+    // objects are pushed again and their previous entries are skipped
+    let mut heap: BinaryHeap<(Score, Reverse<usize>)> = (0..n_objs)
+        .filter(|&i| scores[i] >= prob_threshold)
+        .map(|i| (Score(scores[i]), Reverse(i)))
+        .collect();
+    while let Some((Score(score), Reverse(i))) = heap.pop() {
+        if done[i] || score.to_bits() != scores[i].to_bits() {
+            continue;
+        }
+        done[i] = true;
+        let cands: Vec<usize> = neighbors(i)
+            .into_iter()
+            .filter(|&j| !done[j] && scores[j] >= prob_threshold)
+            .collect();
+        overlaps(i, &cands)
+            .into_iter()
+            .zip(cands)
+            .for_each(|(o, j)| {
+                let decayed = mode.decay(scores[j], o, threshold);
+                if decayed.to_bits() != scores[j].to_bits() {
+                    scores[j] = decayed;
+                    if decayed >= prob_threshold {
+                        heap.push((Score(decayed), Reverse(j)));
+                    }
+                }
+            });
+    }
+    scores
+}
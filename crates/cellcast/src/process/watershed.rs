@@ -0,0 +1,225 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BinaryHeap, VecDeque};
+
+use imgal::prelude::*;
+use ndarray::{ArrayD, ArrayViewD, Axis, IxDyn};
+
+use crate::CellcastError;
+
+/// A pixel waiting to be flooded, ordered by elevation and then by the order
+/// it was queued in.
+struct FloodNode {
+    elevation: f32,
+    age: usize,
+    idx: usize,
+    label: u64,
+}
+
+impl PartialEq for FloodNode {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for FloodNode {}
+
+impl PartialOrd for FloodNode {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for FloodNode {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // reversed for a min-heap, lowest elevation and oldest first
+        other
+            .elevation
+            .total_cmp(&self.elevation)
+            .then(other.age.cmp(&self.age))
+    }
+}
+
+/// Compute instance segmentation labels from semantic class probabilities.
+///
+/// # Description
+///
+/// Computes instance labels from the class probabilities of a semantic
+/// segmentation network with a seeded watershed. Pixels whose foreground
+/// probability (*i.e.* one minus the background probability) exceeds
+/// `fg_threshold` are segmented. Seeds are the connected regions of
+/// foreground pixels whose interior probability exceeds `seed_threshold`,
+/// and the seeds are flooded over one minus the interior probability, so
+/// that boundary pixels are reached last. A single class is treated as both
+/// the foreground and interior probability, otherwise class `0` is the
+/// background and class `1` the object interior (*e.g.* with class `2` as the
+/// object boundary).
+///
+/// # Arguments
+///
+/// * `probs`: The class probabilities with the class axis first, *i.e.* with
+///   shape `(n_classes, row, col)` or `(n_classes, pln, row, col)`.
+/// * `fg_threshold`: The foreground probability threshold.
+/// * `seed_threshold`: The interior probability threshold of the seeds.
+/// * `min_size`: The minimum number of pixels of an object.
+///
+/// # Returns
+///
+/// * `Ok(ArrayD<u64>)`: The instance segmentation labels, numbered from `1`.
+/// * `Err(CellcastError)`: If `probs` has no spatial axes or no classes.
+pub fn boundary_watershed(
+    probs: ArrayViewD<f32>,
+    fg_threshold: f32,
+    seed_threshold: f32,
+    min_size: usize,
+) -> Result<ArrayD<u64>, CellcastError> {
+    if probs.ndim() < 2 || probs.shape()[0] == 0 {
+        return Err(CellcastError::Imgal(ImgalError::InvalidGeneric {
+            msg: "The class probabilities must have a class axis and spatial axes.",
+        }));
+    }
+    let (foreground, interior) = if probs.shape()[0] == 1 {
+        let p = probs.index_axis(Axis(0), 0).to_owned();
+        (p.clone(), p)
+    } else {
+        (
+            probs.index_axis(Axis(0), 0).mapv(|v| 1.0 - v),
+            probs.index_axis(Axis(0), 1).to_owned(),
+        )
+    };
+    let mask = foreground.mapv(|v| v > fg_threshold);
+    let seeds = ndarray::Zip::from(&interior)
+        .and(&mask)
+        .map_collect(|&v, &m| m && v > seed_threshold);
+    let markers = connected_components(seeds.view());
+    let elevation = interior.mapv(|v| 1.0 - v);
+    let labels = seeded_watershed(elevation.view(), markers.view(), mask.view());
+    Ok(remove_small_objects(labels, min_size))
+}
+
+/// Flood labeled markers over an elevation map.
+///
+/// # Description
+///
+/// Performs a seeded watershed: starting from the labeled markers, unlabeled
+/// pixels within `mask` are flooded in order of increasing elevation by the
+/// label of their (face-connected) neighbor that reached them first. Pixels
+/// that are not connected to a marker within `mask` stay unlabeled.
+///
+/// # Arguments
+///
+/// * `elevation`: The elevation map.
+/// * `markers`: The marker labels, with `0` as unlabeled.
+/// * `mask`: The pixels that can be flooded.
+///
+/// # Returns
+///
+/// * `ArrayD<u64>`: The flooded labels.
+pub fn seeded_watershed(
+    elevation: ArrayViewD<f32>,
+    markers: ArrayViewD<u64>,
+    mask: ArrayViewD<bool>,
+) -> ArrayD<u64> {
+    let shape = elevation.shape().to_vec();
+    let strides = row_major_strides(&shape);
+    let elevation: Vec<f32> = elevation.iter().copied().collect();
+    let mask: Vec<bool> = mask.iter().copied().collect();
+    let mut labels: Vec<u64> = markers.iter().copied().collect();
+    let mut heap = BinaryHeap::new();
+    let mut age = 0;
+    let mut push = |heap: &mut BinaryHeap<FloodNode>, idx: usize, label: u64| {
+        heap.push(FloodNode {
+            elevation: elevation[idx],
+            age,
+            idx,
+            label,
+        });
+        age += 1;
+    };
+    (0..labels.len()).filter(|&i| labels[i] > 0).for_each(|i| {
+        face_neighbors(i, &shape, &strides)
+            .filter(|&n| labels[n] == 0 && mask[n])
+            .for_each(|n| push(&mut heap, n, labels[i]));
+    });
+    while let Some(node) = heap.pop() {
+        if labels[node.idx] != 0 {
+            continue;
+        }
+        labels[node.idx] = node.label;
+        face_neighbors(node.idx, &shape, &strides)
+            .filter(|&n| labels[n] == 0 && mask[n])
+            .for_each(|n| push(&mut heap, n, node.label));
+    }
+    ArrayD::from_shape_vec(IxDyn(&shape), labels).unwrap()
+}
+
+/// Label the face-connected components of a mask, numbered from `1` in
+/// raster order.
+fn connected_components(mask: ArrayViewD<bool>) -> ArrayD<u64> {
+    let shape = mask.shape().to_vec();
+    let strides = row_major_strides(&shape);
+    let mask: Vec<bool> = mask.iter().copied().collect();
+    let mut labels = vec![0_u64; mask.len()];
+    let mut next = 0;
+    let mut queue = VecDeque::new();
+    (0..mask.len()).for_each(|i| {
+        if !mask[i] || labels[i] != 0 {
+            return;
+        }
+        next += 1;
+        labels[i] = next;
+        queue.push_back(i);
+        while let Some(p) = queue.pop_front() {
+            face_neighbors(p, &shape, &strides).for_each(|n| {
+                if mask[n] && labels[n] == 0 {
+                    labels[n] = next;
+                    queue.push_back(n);
+                }
+            });
+        }
+    });
+    ArrayD::from_shape_vec(IxDyn(&shape), labels).unwrap()
+}
+
+/// Remove objects smaller than `min_size` pixels and renumber the remaining
+/// objects sequentially, in label order.
+fn remove_small_objects(mut labels: ArrayD<u64>, min_size: usize) -> ArrayD<u64> {
+    let mut sizes: BTreeMap<u64, usize> = BTreeMap::new();
+    labels
+        .iter()
+        .filter(|&&l| l > 0)
+        .for_each(|&l| *sizes.entry(l).or_insert(0) += 1);
+    let mut next = 0;
+    let map: BTreeMap<u64, u64> = sizes
+        .into_iter()
+        .filter(|&(_, n)| n >= min_size)
+        .map(|(l, _)| {
+            next += 1;
+            (l, next)
+        })
+        .collect();
+    labels.mapv_inplace(|l| map.get(&l).copied().unwrap_or(0));
+    labels
+}
+
+/// Get the row-major strides of a shape.
+fn row_major_strides(shape: &[usize]) -> Vec<usize> {
+    let mut strides = vec![1; shape.len()];
+    (0..shape.len().saturating_sub(1))
+        .rev()
+        .for_each(|axis| strides[axis] = strides[axis + 1] * shape[axis + 1]);
+    strides
+}
+
+/// Get the face-connected neighbors of a flat (row-major) index.
+fn face_neighbors<'a>(
+    idx: usize,
+    shape: &'a [usize],
+    strides: &'a [usize],
+) -> impl Iterator<Item = usize> + 'a {
+    (0..shape.len()).flat_map(move |axis| {
+        let pos = (idx / strides[axis]) % shape[axis];
+        let before = (pos > 0).then(|| idx - strides[axis]);
+        let after = (pos + 1 < shape[axis]).then(|| idx + strides[axis]);
+        before.into_iter().chain(after)
+    })
+}
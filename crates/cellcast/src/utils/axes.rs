@@ -17,3 +17,39 @@
 pub fn divisible_pad(axis_len: usize, div: usize) -> usize {
     (div - axis_len % div) % div
 }
+
+/// Get the length of an axis resampled by a scale factor.
+///
+/// # Arguments
+///
+/// * `axis_len`: The length of a given axis.
+/// * `scale`: The scale factor of the axis.
+///
+/// # Returns
+///
+/// * `usize`: The rounded scaled axis length, at least `1`.
+#[inline]
+pub fn scaled_len(axis_len: usize, scale: f32) -> usize {
+    ((axis_len as f32 * scale).round() as usize).max(1)
+}
+
+/// Map a coordinate into an axis resampled by a scale factor.
+///
+/// # Description
+///
+/// Maps a pixel coordinate into the coordinates of the axis resampled by
+/// `scale`, aligning the pixel edges of both axes (*i.e.* pixel `0` spans
+/// `-0.5..0.5` in both axes).
+///
+/// # Arguments
+///
+/// * `coord`: The pixel coordinate.
+/// * `scale`: The scale factor of the axis.
+///
+/// # Returns
+///
+/// * `f32`: The coordinate in the resampled axis.
+#[inline]
+pub fn scale_coord(coord: f32, scale: f32) -> f32 {
+    (coord + 0.5) * scale - 0.5
+}
@@ -0,0 +1,124 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use ciborium::Value;
+
+const MAGIC_NUMBER: u32 = 0x4255524E;
+const HEADER_SIZE: usize = 10;
+const TENSOR_ALIGNMENT: usize = 256;
+const METADATA_KEY: &str = "metadata";
+
+/// Read the key-value metadata of a burnpack (`.bpk`) file.
+///
+/// # Arguments
+///
+/// * `path`: The path to the burnpack file.
+///
+/// # Returns
+///
+/// * `Some(BTreeMap<String, String>)`: The file metadata (empty if the file
+///   has none).
+/// * `None`: If the file can not be read or is not a burnpack file.
+pub fn read_metadata(path: &Path) -> Option<BTreeMap<String, String>> {
+    let bytes = fs::read(path).ok()?;
+    let (header, _) = read_header(&bytes)?;
+    let mut metadata = BTreeMap::new();
+    if let Some(Value::Map(entries)) = map_get(&header, METADATA_KEY) {
+        for (k, v) in entries {
+            if let (Value::Text(k), Value::Text(v)) = (k, v) {
+                metadata.insert(k.clone(), v.clone());
+            }
+        }
+    }
+    Some(metadata)
+}
+
+/// Write a key-value metadata entry to a burnpack (`.bpk`) file.
+///
+/// # Description
+///
+/// Inserts (or replaces) a metadata entry in the CBOR header of an existing
+/// burnpack file. The tensor descriptors are kept as is and the tensor data
+/// section is moved to the next aligned position after the new header.
+///
+/// # Arguments
+///
+/// * `path`: The path to the burnpack file.
+/// * `key`: The metadata key.
+/// * `value`: The metadata value.
+///
+/// # Returns
+///
+/// * `Some(())`: If the metadata was written.
+/// * `None`: If the file can not be read or written or is not a burnpack
+///   file.
+pub fn write_metadata(path: &Path, key: &str, value: &str) -> Option<()> {
+    let bytes = fs::read(path).ok()?;
+    let (mut header, data_start) = read_header(&bytes)?;
+    let Value::Map(entries) = &mut header else {
+        return None;
+    };
+    let metadata = match entries
+        .iter()
+        .position(|(k, _)| k.as_text() == Some(METADATA_KEY))
+    {
+        Some(i) => &mut entries[i].1,
+        None => {
+            entries.push((Value::Text(METADATA_KEY.into()), Value::Map(Vec::new())));
+            &mut entries.last_mut()?.1
+        }
+    };
+    let Value::Map(metadata) = metadata else {
+        return None;
+    };
+    metadata.retain(|(k, _)| k.as_text() != Some(key));
+    metadata.push((Value::Text(key.into()), Value::Text(value.into())));
+    let mut cbor = Vec::new();
+    ciborium::ser::into_writer(&header, &mut cbor).ok()?;
+    let new_data_start = aligned_data_start(cbor.len());
+    let mut out = Vec::with_capacity(new_data_start + bytes.len() - data_start);
+    out.extend_from_slice(&MAGIC_NUMBER.to_le_bytes());
+    out.extend_from_slice(&bytes[4..6]);
+    out.extend_from_slice(&(cbor.len() as u32).to_le_bytes());
+    out.extend_from_slice(&cbor);
+    out.resize(new_data_start, 0);
+    out.extend_from_slice(&bytes[data_start..]);
+    fs::write(path, out).ok()
+}
+
+/// Parse the CBOR header of a burnpack file.
+///
+/// # Returns
+///
+/// * `Some((Value, usize))`: The header and the start of the tensor data
+///   section.
+/// * `None`: If the bytes are not a burnpack file.
+fn read_header(bytes: &[u8]) -> Option<(Value, usize)> {
+    let magic = u32::from_le_bytes(bytes.get(0..4)?.try_into().ok()?);
+    if magic != MAGIC_NUMBER {
+        return None;
+    }
+    let size = u32::from_le_bytes(bytes.get(6..HEADER_SIZE)?.try_into().ok()?) as usize;
+    let cbor = bytes.get(HEADER_SIZE..HEADER_SIZE + size)?;
+    let header: Value = ciborium::de::from_reader(cbor).ok()?;
+    let data_start = aligned_data_start(size);
+    if !header.is_map() || data_start > bytes.len() {
+        return None;
+    }
+    Some((header, data_start))
+}
+
+/// Get the start of the tensor data section, aligned to 256 bytes after the
+/// header.
+fn aligned_data_start(metadata_size: usize) -> usize {
+    (HEADER_SIZE + metadata_size).div_ceil(TENSOR_ALIGNMENT) * TENSOR_ALIGNMENT
+}
+
+/// Get the value of a text key of a CBOR map.
+fn map_get<'a>(map: &'a Value, key: &str) -> Option<&'a Value> {
+    map.as_map()?
+        .iter()
+        .find(|(k, _)| k.as_text() == Some(key))
+        .map(|(_, v)| v)
+}
@@ -5,4 +5,7 @@
 
 pub mod axes;
 pub mod border;
+pub mod burnpack;
 pub mod fetch;
+pub mod npy;
+pub mod resample;
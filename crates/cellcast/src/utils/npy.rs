@@ -0,0 +1,92 @@
+use ndarray::{ArrayD, IxDyn};
+
+const MAGIC: &[u8] = b"\x93NUMPY";
+
+/// Read a NumPy `.npy` array as `f32`.
+///
+/// # Description
+///
+/// Parses a NumPy `.npy` file (format version 1, 2 or 3) holding a C-order,
+/// little-endian numeric array. Supported data types are `f4`, `f8`, `u1`,
+/// `i1`, `u2`, `i2`, `u4` and `i4`, all of which are converted to `f32`.
+///
+/// # Arguments
+///
+/// * `bytes`: The contents of the `.npy` file.
+///
+/// # Returns
+///
+/// * `Some(ArrayD<f32>)`: The array.
+/// * `None`: If the file is not a supported `.npy` array.
+pub fn read_npy(bytes: &[u8]) -> Option<ArrayD<f32>> {
+    if bytes.len() < 10 || &bytes[..6] != MAGIC {
+        return None;
+    }
+    // version 1 headers have a 2 byte length, versions 2 and 3 a 4 byte length
+    let (header_len, offset) = match bytes[6] {
+        1 => (u16::from_le_bytes([bytes[8], bytes[9]]) as usize, 10),
+        2 | 3 => (
+            u32::from_le_bytes(bytes.get(8..12)?.try_into().ok()?) as usize,
+            12,
+        ),
+        _ => return None,
+    };
+    let header = std::str::from_utf8(bytes.get(offset..offset + header_len)?).ok()?;
+    let descr = header_value(header, "descr")?.trim_matches(['\'', '"']);
+    if header_value(header, "fortran_order")? != "False" {
+        return None;
+    }
+    let shape = header_value(header, "shape")?;
+    let shape: Vec<usize> = shape
+        .trim_matches(['(', ')'])
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse().ok())
+        .collect::<Option<Vec<usize>>>()?;
+    let data = &bytes[offset + header_len..];
+    let values: Vec<f32> = match descr {
+        "<f4" => data
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect(),
+        "<f8" => data
+            .chunks_exact(8)
+            .map(|b| f64::from_le_bytes(b.try_into().unwrap()) as f32)
+            .collect(),
+        "|u1" => data.iter().map(|&b| b as f32).collect(),
+        "|i1" => data.iter().map(|&b| b as i8 as f32).collect(),
+        "<u2" => data
+            .chunks_exact(2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]) as f32)
+            .collect(),
+        "<i2" => data
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32)
+            .collect(),
+        "<u4" => data
+            .chunks_exact(4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32)
+            .collect(),
+        "<i4" => data
+            .chunks_exact(4)
+            .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32)
+            .collect(),
+        _ => return None,
+    };
+    let len: usize = shape.iter().product();
+    ArrayD::from_shape_vec(IxDyn(&shape), values.get(..len)?.to_vec()).ok()
+}
+
+/// Get the raw value of a key in a `.npy` header dictionary.
+fn header_value<'a>(header: &'a str, key: &str) -> Option<&'a str> {
+    let start = header.find(&format!("'{}'", key))? + key.len() + 2;
+    let rest = header[start..].trim_start().strip_prefix(':')?.trim_start();
+    // tuples contain commas, so they end at the closing parenthesis
+    let end = if rest.starts_with('(') {
+        rest.find(')')? + 1
+    } else {
+        rest.find([',', '}'])?
+    };
+    Some(rest[..end].trim())
+}
@@ -0,0 +1,54 @@
+use imgal::prelude::*;
+use ndarray::{Array, ArrayBase, AsArray, Axis, Dimension, RemoveAxis, ViewRepr, Zip};
+
+/// Resize an n-dimensional array with linear interpolation.
+///
+/// # Description
+///
+/// Resizes each axis of the input array in turn, sampling the input at the
+/// output pixel centers with linear interpolation. The pixel edges of the
+/// input and output axes are aligned (*i.e.* the same convention as
+/// `axes::scale_coord`) and samples beyond the first/last pixel centers are
+/// clamped to the edge pixels.
+///
+/// # Arguments
+///
+/// * `data`: The input n-dimensional array.
+/// * `shape`: The output shape, with one non-zero length per axis of `data`.
+///
+/// # Returns
+///
+/// * `Array<f64, D>`: The resized array.
+pub fn resize_linear<'a, T, A, D>(data: A, shape: &[usize]) -> Array<f64, D>
+where
+    A: AsArray<'a, T, D>,
+    D: Dimension + RemoveAxis,
+    T: 'a + AsNumeric,
+{
+    let data: ArrayBase<ViewRepr<&'a T>, D> = data.into();
+    let mut out = data.mapv(|v| v.to_f64());
+    shape.iter().enumerate().for_each(|(axis, &len)| {
+        let ax = Axis(axis);
+        let src_len = out.len_of(ax);
+        if len == src_len {
+            return;
+        }
+        let scale = len as f64 / src_len as f64;
+        let max = (src_len - 1) as f64;
+        let mut dim = out.raw_dim();
+        dim[axis] = len;
+        let mut res = Array::<f64, D>::zeros(dim);
+        res.axis_iter_mut(ax).enumerate().for_each(|(i, lane)| {
+            let src = ((i as f64 + 0.5) / scale - 0.5).clamp(0.0, max);
+            let lo = src.floor() as usize;
+            let hi = (lo + 1).min(src_len - 1);
+            let frac = src - lo as f64;
+            Zip::from(lane)
+                .and(out.index_axis(ax, lo))
+                .and(out.index_axis(ax, hi))
+                .for_each(|o, &a, &b| *o = a + (b - a) * frac);
+        });
+        out = res;
+    });
+    out
+}
@@ -0,0 +1,165 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use burn::data::dataset::{Dataset, InMemDataset};
+use ndarray::{ArrayD, IxDyn};
+
+use cellcast::CellcastError;
+use cellcast::augment::{
+    AugmentDataset, AugmentPipeline, AugmentSample, ElasticDeform, GaussianBlur, GaussianNoise,
+    IntensityScale, RandomFlip, RandomRot90, StainJitter,
+};
+
+/// A dataset counting the loaded items.
+struct CountingDataset<'a> {
+    dataset: InMemDataset<AugmentSample>,
+    loads: &'a AtomicUsize,
+}
+
+impl Dataset<AugmentSample> for CountingDataset<'_> {
+    fn get(&self, index: usize) -> Option<AugmentSample> {
+        self.loads.fetch_add(1, Ordering::Relaxed);
+        self.dataset.get(index)
+    }
+
+    fn len(&self) -> usize {
+        self.dataset.len()
+    }
+}
+
+/// Create a sample where the image intensity equals the label id.
+fn labeled_sample(shape: &[usize]) -> AugmentSample {
+    let label = ArrayD::from_shape_fn(IxDyn(shape), |idx| {
+        (0..shape.len()).fold(0_u64, |acc, d| acc * 7 + (idx[d] / 4) as u64)
+    });
+    let image = label.mapv(|v| v as f32);
+    AugmentSample::new(image, label, None).unwrap()
+}
+
+/// Tests that geometric augmentations transform the image and label
+/// identically in 2D and 3D.
+#[test]
+fn augment_geometric_image_label_consistent() -> Result<(), CellcastError> {
+    let pipeline = AugmentPipeline::new(42)
+        .with(RandomFlip::new(None))
+        .with(RandomRot90::new(None))
+        .with(ElasticDeform::new(0.0, None, None));
+    for shape in [vec![16, 24], vec![6, 16, 24]] {
+        let sample = labeled_sample(&shape);
+        for index in 0..8 {
+            let aug = pipeline.augment(&sample, 0, index)?;
+            assert_eq!(aug.image, aug.label.mapv(|v| v as f32));
+        }
+    }
+    Ok(())
+}
+
+/// Tests that the same seed, epoch and index produce identical results, and
+/// that `AugmentDataset` yields reproducible items per epoch.
+#[test]
+fn augment_pipeline_reproducible() -> Result<(), CellcastError> {
+    let make_pipeline = || {
+        AugmentPipeline::new(7)
+            .with(RandomFlip::new(None))
+            .with_prob(ElasticDeform::new(2.0, Some(4), None), 0.5)
+            .with(IntensityScale::new((0.8, 1.2), Some((-0.1, 0.1))))
+            .with(GaussianBlur::new((0.0, 1.0)))
+            .with(GaussianNoise::new((0.0, 0.1)))
+    };
+    let sample = labeled_sample(&[32, 32]);
+    let a = make_pipeline().augment(&sample, 3, 5)?;
+    let b = make_pipeline().augment(&sample, 3, 5)?;
+    assert_eq!(a, b);
+    let ds = AugmentDataset::new(
+        InMemDataset::new(vec![sample.clone(), sample]),
+        make_pipeline(),
+    )?;
+    let first = ds.get(1).unwrap();
+    assert_eq!(first, ds.get(1).unwrap());
+    ds.set_epoch(1);
+    assert_ne!(first, ds.get(1).unwrap());
+    // the random stream of a seed does not change between releases
+    let ones = AugmentSample::new(
+        ArrayD::ones(IxDyn(&[2, 2])),
+        ArrayD::zeros(IxDyn(&[2, 2])),
+        None,
+    )?;
+    let scaled = AugmentPipeline::new(7)
+        .with(IntensityScale::new((0.0, 1.0), None))
+        .augment(&ones, 0, 0)?;
+    assert_eq!(scaled.image[[0, 0]], 0.7822962);
+    Ok(())
+}
+
+/// Tests that color augmentations require an RGB channel axis.
+#[test]
+fn augment_color_requires_rgb() -> Result<(), CellcastError> {
+    let label = ArrayD::<u64>::zeros(IxDyn(&[8, 8]));
+    let rgb = ArrayD::<f32>::from_elem(IxDyn(&[8, 8, 3]), 0.5);
+    let rgb_sample = AugmentSample::new(rgb, label.clone(), Some(2))?;
+    let aug = AugmentPipeline::new(0)
+        .with(StainJitter::new(None, None))
+        .augment(&rgb_sample, 0, 0)?;
+    assert!(aug.image.iter().all(|&v| (0.0..=1.0).contains(&v)));
+    let gray = AugmentSample::new(label.mapv(|v| v as f32), label, None)?;
+    assert!(
+        AugmentPipeline::new(0)
+            .with(StainJitter::new(None, None))
+            .augment(&gray, 0, 0)
+            .is_err()
+    );
+    Ok(())
+}
+
+/// Tests that an augmented dataset is checked against its pipeline when it is
+/// created, instead of failing when an item is loaded.
+#[test]
+fn augment_dataset_checks_pipeline() -> Result<(), CellcastError> {
+    let label = ArrayD::<u64>::zeros(IxDyn(&[8, 8]));
+    let rgb = ArrayD::<f32>::from_elem(IxDyn(&[8, 8, 3]), 0.5);
+    let rgb_sample = AugmentSample::new(rgb, label.clone(), Some(2))?;
+    let gray = AugmentSample::new(label.mapv(|v| v as f32), label, None)?;
+    let pipeline = || {
+        AugmentPipeline::new(0)
+            .with(RandomFlip::new(None))
+            .with_prob(StainJitter::new(None, None), 0.0)
+    };
+    // the pipeline is checked against the first item, regardless of the step
+    // probabilities
+    assert!(
+        AugmentDataset::new(
+            InMemDataset::new(vec![gray.clone(), rgb_sample.clone()]),
+            pipeline()
+        )
+        .is_err()
+    );
+    assert!(
+        AugmentDataset::new(
+            InMemDataset::new(vec![gray.clone()]),
+            AugmentPipeline::new(0).with(RandomRot90::new(Some((0, 2))))
+        )
+        .is_err()
+    );
+    // only the first item is loaded to check the pipeline
+    let loads = AtomicUsize::new(0);
+    let ds = AugmentDataset::new(
+        CountingDataset {
+            dataset: InMemDataset::new(vec![rgb_sample.clone(); 4]),
+            loads: &loads,
+        },
+        pipeline(),
+    )?;
+    assert_eq!(loads.load(Ordering::Relaxed), 1);
+    assert_eq!(ds.get(0).unwrap().image.shape(), &[8, 8, 3]);
+    assert!(ds.take_error().is_none());
+    // a later item that fails to augment is not augmented and its error is
+    // kept
+    let ds = AugmentDataset::new(
+        InMemDataset::new(vec![rgb_sample, gray.clone()]),
+        AugmentPipeline::new(0).with(StainJitter::new(None, None)),
+    )?;
+    assert!(ds.get(0).is_some() && ds.take_error().is_none());
+    assert_eq!(ds.get(1), Some(gray));
+    assert_eq!(ds.take_error().map(|(i, _)| i), Some(1));
+    assert!(ds.take_error().is_none());
+    Ok(())
+}
@@ -0,0 +1,127 @@
+use burn::backend::Flex;
+use burn::prelude::*;
+use ndarray::{Array2, ArrayD, Dimension, IxDyn};
+
+use cellcast::process::candidates::{GridCandidates, gather_candidates, gather_candidates_host};
+
+type TestBackend = Flex<f32, i32>;
+
+/// Create pseudo-random object probabilities with a grid shape, with
+/// probabilities exactly at `0.5` in an inner and a border cell, and
+/// pseudo-random ray distances (including negative distances) with shape
+/// `(n_cells, n_rays)`.
+fn network_output(shape: &[usize], n_rays: usize) -> (ArrayD<f32>, Array2<f32>) {
+    let noise = |i: usize| ((i * 31 + 7) as f32 * 0.731).sin().abs();
+    let mut prob = ArrayD::from_shape_fn(IxDyn(shape), |idx| {
+        let flat = idx
+            .slice()
+            .iter()
+            .zip(shape)
+            .fold(0, |acc, (&i, &s)| acc * s + i);
+        noise(flat)
+    });
+    let inner: Vec<usize> = shape.iter().map(|&s| s / 2).collect();
+    let border: Vec<usize> = shape.iter().map(|_| 1).collect();
+    prob[IxDyn(&inner)] = 0.5;
+    prob[IxDyn(&border)] = 0.5;
+    let n_cells = prob.len();
+    let dist = Array2::from_shape_fn((n_cells, n_rays), |(i, r)| {
+        4.0 * noise(i * n_rays + r + 1000) - 0.5
+    });
+    (prob, dist)
+}
+
+/// Assert that the device and host candidates are identical.
+fn assert_same_candidates(device: &GridCandidates, host: &GridCandidates) {
+    assert_eq!(device.pos, host.pos);
+    assert_eq!(device.prob, host.prob);
+    assert_eq!(device.dist, host.dist);
+}
+
+/// Tests that the 2D candidates gathered on the device match the candidates
+/// gathered on the host, including probabilities at the threshold, the
+/// clipped border and clamped distances.
+#[test]
+fn gather_candidates_2d_matches_host() {
+    let device = Default::default();
+    let (n_row, n_col, n_rays) = (12, 14, 8);
+    let (prob, dist) = network_output(&[n_row, n_col], n_rays);
+    let gather = |threshold: f32, border: usize| {
+        let prob_t = Tensor::<TestBackend, 2>::from_data(
+            TensorData::new(prob.iter().copied().collect(), [n_row, n_col]),
+            &device,
+        );
+        let dist_t = Tensor::<TestBackend, 2>::from_data(
+            TensorData::new(dist.iter().copied().collect(), [n_row * n_col, n_rays]),
+            &device,
+        );
+        (
+            gather_candidates(prob_t, dist_t, threshold, border),
+            gather_candidates_host(prob.view(), dist.view(), threshold, border),
+        )
+    };
+    let (cands, host) = gather(0.5, 2);
+    assert_same_candidates(&cands, &host);
+    assert!(cands.prob.iter().all(|&p| p >= 0.5));
+    assert!(cands.dist.iter().all(|&d| d >= 1e-3));
+    assert!(cands.dist.iter().any(|&d| d == 1e-3));
+    // the inner cell at the threshold is a candidate, the border cell is not
+    let pos: Vec<(usize, usize)> = cands.pos.rows().into_iter().map(|p| (p[0], p[1])).collect();
+    assert!(pos.contains(&(n_row / 2, n_col / 2)));
+    assert!(
+        pos.iter()
+            .all(|&(r, c)| (2..n_row - 2).contains(&r) && (2..n_col - 2).contains(&c))
+    );
+    // the row-major grid order
+    assert!(pos.windows(2).all(|w| w[0] < w[1]));
+    let (cands, host) = gather(0.0, 0);
+    assert_same_candidates(&cands, &host);
+    assert_eq!(cands.pos.nrows(), n_row * n_col);
+    // no candidates above the threshold or in a grid smaller than the border
+    for (threshold, border) in [(1.5, 2), (0.0, 6), (0.0, 7)] {
+        let (cands, host) = gather(threshold, border);
+        assert_same_candidates(&cands, &host);
+        assert_eq!(cands.pos.dim(), (0, 2));
+        assert_eq!(cands.dist.dim(), (0, n_rays));
+    }
+}
+
+/// Tests that the 3D candidates gathered on the device from channels first
+/// ray distances (*i.e.* `(n_rays, n_cells)` swapped to `(n_cells, n_rays)`)
+/// match the candidates gathered on the host.
+#[test]
+fn gather_candidates_3d_swapped_layout_matches_host() {
+    let device = Default::default();
+    let (shape, n_rays) = ([6, 7, 8], 16);
+    let (prob, dist) = network_output(&shape, n_rays);
+    let n_cells: usize = shape.iter().product();
+    let gather = |threshold: f32| {
+        let prob_t = Tensor::<TestBackend, 3>::from_data(
+            TensorData::new(prob.iter().copied().collect(), shape),
+            &device,
+        );
+        // the network ray distances are channels first
+        let dist_t = Tensor::<TestBackend, 2>::from_data(
+            TensorData::new(dist.t().iter().copied().collect(), [n_rays, n_cells]),
+            &device,
+        )
+        .swap_dims(0, 1);
+        (
+            gather_candidates(prob_t, dist_t, threshold, 2),
+            gather_candidates_host(prob.view(), dist.view(), threshold, 2),
+        )
+    };
+    let (cands, host) = gather(0.5);
+    assert_same_candidates(&cands, &host);
+    assert!(cands.pos.nrows() > 0);
+    assert!(
+        cands
+            .pos
+            .rows()
+            .into_iter()
+            .any(|p| p.to_vec() == [3, 3, 4])
+    );
+    let (cands, host) = gather(1.5);
+    assert_same_candidates(&cands, &host);
+    assert_eq!(cands.pos.dim(), (0, 3));
+}
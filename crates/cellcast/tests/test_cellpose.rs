@@ -0,0 +1,235 @@
+use std::fs;
+use std::path::Path;
+
+use burn::module::ParamId;
+use burn::prelude::*;
+use burn_store::{BurnpackWriter, TensorSnapshot};
+use ndarray::{Array2, Array3};
+
+use cellcast::CellcastError;
+use cellcast::models::{Cellpose, CellposeNetworkConfig};
+
+/// The network configuration of a tiny Cellpose U-Net (2 levels of 4 and 8
+/// filters).
+fn tiny_network() -> CellposeNetworkConfig {
+    CellposeNetworkConfig {
+        n_filters: vec![4, 8],
+        ..CellposeNetworkConfig::cyto()
+    }
+}
+
+/// Add the PyTorch parameters of a Cellpose batch convolution, *i.e.* a batch
+/// norm at `0` and a convolution at `1` (without a ReLU) or `2` (with a ReLU).
+fn batch_conv(
+    params: &mut Vec<(String, Vec<usize>)>,
+    prefix: &str,
+    c_in: usize,
+    c_out: usize,
+    kernel: usize,
+    relu: bool,
+) {
+    ["weight", "bias", "running_mean", "running_var"]
+        .iter()
+        .for_each(|p| params.push((format!("{}.0.{}", prefix, p), vec![c_in])));
+    let conv = if relu { 2 } else { 1 };
+    params.push((
+        format!("{}.{}.weight", prefix, conv),
+        vec![c_out, c_in, kernel, kernel],
+    ));
+    params.push((format!("{}.{}.bias", prefix, conv), vec![c_out]));
+}
+
+/// Write a burnpack file with the PyTorch parameter names of a tiny Cellpose
+/// network and deterministic weights.
+fn write_tiny_cellpose(path: &Path) {
+    let network = tiny_network();
+    let k = network.kernel_size;
+    let mut params = Vec::new();
+    let n_base = [network.n_channel_in, 4, 8];
+    (0..2).for_each(|i| {
+        let down = format!("downsample.down.res_down_{}", i);
+        batch_conv(
+            &mut params,
+            &format!("{}.proj", down),
+            n_base[i],
+            n_base[i + 1],
+            1,
+            false,
+        );
+        (0..4).for_each(|t| {
+            let c_in = if t == 0 { n_base[i] } else { n_base[i + 1] };
+            let prefix = format!("{}.conv.conv_{}", down, t);
+            batch_conv(&mut params, &prefix, c_in, n_base[i + 1], k, true);
+        });
+    });
+    let n_base_up = [4, 8, 8];
+    (0..2).for_each(|i| {
+        let up = format!("upsample.up.res_up_{}", i);
+        let (c_in, c_out) = (n_base_up[i + 1], n_base_up[i]);
+        batch_conv(&mut params, &format!("{}.proj", up), c_in, c_out, 1, false);
+        batch_conv(
+            &mut params,
+            &format!("{}.conv.conv_0", up),
+            c_in,
+            c_out,
+            k,
+            true,
+        );
+        (1..4).for_each(|t| {
+            let prefix = format!("{}.conv.conv_{}", up, t);
+            params.push((format!("{}.full.weight", prefix), vec![c_out, 8]));
+            params.push((format!("{}.full.bias", prefix), vec![c_out]));
+            batch_conv(
+                &mut params,
+                &format!("{}.conv", prefix),
+                c_out,
+                c_out,
+                k,
+                true,
+            );
+        });
+    });
+    batch_conv(&mut params, "output", 4, 3, 1, true);
+    let snapshots: Vec<TensorSnapshot> = params
+        .iter()
+        .enumerate()
+        .map(|(n, (name, shape))| {
+            let len: usize = shape.iter().product();
+            let values: Vec<f32> = (0..len)
+                .map(|i| {
+                    let v = ((i * 7 + n * 13) as f32 * 0.61).sin() * 0.3;
+                    if name.ends_with("running_var") {
+                        1.0 + v.abs()
+                    } else {
+                        v
+                    }
+                })
+                .collect();
+            TensorSnapshot::from_data(
+                TensorData::new(values, shape.clone()),
+                name.split('.').map(String::from).collect(),
+                vec!["Struct:Model".to_string()],
+                ParamId::new(),
+            )
+        })
+        .collect();
+    BurnpackWriter::new(snapshots).write_to_file(path).unwrap();
+}
+
+/// Create a label image of two discs with a radius of 8 pixels.
+fn two_discs() -> Array2<u64> {
+    Array2::from_shape_fn((48, 64), |(r, c)| {
+        let d = |cr: f64, cc: f64| ((r as f64 - cr).powi(2) + (c as f64 - cc).powi(2)).sqrt();
+        if d(20.0, 18.0) <= 8.0 {
+            1
+        } else if d(26.0, 44.0) <= 8.0 {
+            2
+        } else {
+            0
+        }
+    })
+}
+
+/// Tests that a Cellpose model loads weights with the PyTorch parameter names
+/// and predicts labels matching the input shape with and without rescaling.
+#[test]
+fn cellpose_init_and_predict() -> Result<(), CellcastError> {
+    let dir = std::env::temp_dir().join("cellcast_test_cellpose");
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("weights.bpk");
+    write_tiny_cellpose(&path);
+    let path = path.to_str().unwrap();
+    let cp = Cellpose::init(path, Some(tiny_network()), false)?;
+    assert_eq!(cp.config(), &tiny_network());
+    assert_eq!(cp.thresholds(), (0.4, 0.0));
+    let data = Array2::<u16>::from_shape_fn((37, 50), |(r, c)| ((r * 3 + c * 5) % 11) as u16);
+    assert_eq!(cp.predict(&data, None, None, None)?.dim(), (37, 50));
+    assert_eq!(cp.predict(&data, Some(45.0), None, None)?.dim(), (37, 50));
+    let channels =
+        Array3::<u16>::from_shape_fn((37, 50, 2), |(r, c, ch)| ((r * 3 + c * 5 + ch) % 11) as u16);
+    let labels = cp.predict_channels(&channels, None, Some(20.0), Some(0.0), Some(-1.0))?;
+    assert_eq!(labels.dim(), (37, 50));
+    // the channels first layout gives the same result
+    let first = channels.clone().permuted_axes([2, 0, 1]);
+    let labels_first = cp.predict_channels(&first, Some(0), Some(20.0), Some(0.0), Some(-1.0))?;
+    assert_eq!(labels, labels_first);
+    // too many channels and invalid diameters are rejected
+    let rgb = Array3::<u16>::zeros((37, 50, 3));
+    assert!(cp.predict_channels(&rgb, None, None, None, None).is_err());
+    assert!(cp.predict(&data, Some(0.0), None, None).is_err());
+    // the weights do not match a deeper network
+    let deeper = CellposeNetworkConfig {
+        n_filters: vec![4, 8, 16],
+        ..tiny_network()
+    };
+    assert!(Cellpose::init(path, Some(deeper), false).is_err());
+    assert!(Cellpose::init("missing.bpk", None, false).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+    Ok(())
+}
+
+/// Tests that following the flows computed from masks recovers the masks.
+#[test]
+fn cellpose_masks_from_flows_round_trip() -> Result<(), CellcastError> {
+    let masks = two_discs();
+    let flows = Cellpose::flows_from_masks(masks.view());
+    assert_eq!(flows.dim(), (2, 48, 64));
+    // flows are unit vectors inside the masks (except at the symmetric disc
+    // centers) and zero outside
+    let mut n_unit = 0;
+    masks.indexed_iter().for_each(|((r, c), &l)| {
+        let norm = (flows[[0, r, c]].powi(2) + flows[[1, r, c]].powi(2)).sqrt();
+        if l == 0 {
+            assert_eq!(norm, 0.0);
+        } else {
+            assert!(norm < 1.0 + 1e-3);
+            n_unit += usize::from((norm - 1.0).abs() < 1e-3);
+        }
+    });
+    assert!(n_unit >= masks.iter().filter(|&&l| l > 0).count() - 2);
+    // the network flows are scaled by 5
+    let dp = flows.mapv(|v| v * 5.0);
+    let cellprob = masks.mapv(|l| if l > 0 { 5.0_f32 } else { -5.0 });
+    let labels = Cellpose::masks_from_flows(dp.view(), cellprob.view(), 0.4, 0.0, 200)?;
+    assert_eq!(labels, masks);
+    // no cell pixels give no labels
+    let labels = Cellpose::masks_from_flows(dp.view(), cellprob.view(), 0.4, 10.0, 200)?;
+    assert!(labels.iter().all(|&l| l == 0));
+    // mismatched shapes are rejected
+    let small = Array2::<f32>::zeros((10, 10));
+    assert!(Cellpose::masks_from_flows(dp.view(), small.view(), 0.4, 0.0, 200).is_err());
+    Ok(())
+}
+
+/// Tests the Cellpose network configuration presets and validation.
+#[test]
+fn cellpose_network_config_validation() {
+    let cyto = CellposeNetworkConfig::cyto();
+    assert_eq!(cyto.n_filters, vec![32, 64, 128, 256]);
+    assert_eq!(cyto.div(), 8);
+    assert!(cyto.validate().is_ok());
+    assert_eq!(CellposeNetworkConfig::nuclei().diam_mean, 17.0);
+    let invalid = [
+        CellposeNetworkConfig {
+            n_channel_in: 0,
+            ..CellposeNetworkConfig::cyto()
+        },
+        CellposeNetworkConfig {
+            n_filters: vec![],
+            ..CellposeNetworkConfig::cyto()
+        },
+        CellposeNetworkConfig {
+            n_filters: vec![32, 0],
+            ..CellposeNetworkConfig::cyto()
+        },
+        CellposeNetworkConfig {
+            kernel_size: 4,
+            ..CellposeNetworkConfig::cyto()
+        },
+        CellposeNetworkConfig {
+            diam_mean: 0.0,
+            ..CellposeNetworkConfig::cyto()
+        },
+    ];
+    invalid.iter().for_each(|c| assert!(c.validate().is_err()));
+}
@@ -0,0 +1,346 @@
+use std::fs;
+use std::path::Path;
+
+use burn::nn::PaddingConfig2d;
+use burn::nn::conv::{Conv2d, Conv2dConfig};
+use burn::prelude::*;
+use burn_store::{BurnpackStore, ModuleSnapshot};
+use hdf5_pure::{AttrValue, File};
+use ndarray::{Array2, ArrayD, Axis, IxDyn};
+
+use cellcast::CellcastError;
+use cellcast::convert::{KerasReference, convert_keras_weights};
+use cellcast::models::{StarDist2D, StarDist2DConfig, StarDistMetadata, StarDistNetworkConfig};
+
+/// A stand-in for a tiny converted StarDist2D network with random weights.
+#[derive(Module, Debug)]
+struct TinyStarDist2D<B: Backend> {
+    convs: Vec<Conv2d<B>>,
+}
+
+/// The Keras layer names of the tiny StarDist2D network, in model order, with
+/// the index of their convolution.
+const KERAS_LAYERS: [(&str, Option<usize>); 13] = [
+    ("input", None),
+    ("down_level_0_no_0", Some(0)),
+    ("down_level_0_no_1", Some(1)),
+    ("max_pooling2d", None),
+    ("middle_0", Some(2)),
+    ("middle_1", Some(3)),
+    ("up_sampling2d", None),
+    ("concatenate", None),
+    ("up_level_0_no_0", Some(4)),
+    ("up_level_0_no_1", Some(5)),
+    ("features", Some(6)),
+    ("prob", Some(8)),
+    ("dist", Some(7)),
+];
+
+/// Create a tiny StarDist2D U-Net (8 rays, grid of 1, depth of 1, 2 filters)
+/// with random weights and its network configuration.
+fn tiny_stardist_2d() -> (TinyStarDist2D<burn::backend::Flex>, StarDistNetworkConfig) {
+    // (c_in, c_out, kernel) of the U-Net levels, feature layer and heads
+    let layers = [
+        (1, 2, 3),
+        (2, 2, 3),
+        (2, 4, 3),
+        (4, 2, 3),
+        (4, 2, 3),
+        (2, 2, 3),
+        (2, 4, 3),
+        (4, 8, 1),
+        (4, 1, 1),
+    ];
+    let device = Default::default();
+    let net = TinyStarDist2D {
+        convs: layers
+            .iter()
+            .map(|&(c_in, c_out, k)| {
+                Conv2dConfig::new([c_in, c_out], [k, k])
+                    .with_padding(PaddingConfig2d::Explicit(k / 2, k / 2, k / 2, k / 2))
+                    .init(&device)
+            })
+            .collect(),
+    };
+    let network = StarDistNetworkConfig {
+        n_rays: 8,
+        grid: vec![1, 1],
+        unet_n_depth: 1,
+        unet_n_filter_base: 2,
+        unet_n_conv_per_depth: 2,
+        net_conv_after_unet: 4,
+        ..StarDistNetworkConfig::fluo_2d()
+    };
+    (net, network)
+}
+
+/// Run the tiny StarDist2D U-Net on a `(1, 1, row, col)` input, returning the
+/// `(1, row, col, 1 + n_rays)` object probabilities and ray distances.
+fn tiny_stardist_2d_forward(
+    net: &TinyStarDist2D<burn::backend::Flex>,
+    x: Tensor<burn::backend::Flex, 4>,
+) -> Tensor<burn::backend::Flex, 4> {
+    let conv = |i: usize, x| burn::tensor::activation::relu(net.convs[i].forward(x));
+    let skip = conv(1, conv(0, x));
+    let x = burn::tensor::module::max_pool2d(skip.clone(), [2, 2], [2, 2], [0, 0], [1, 1], false);
+    let x = conv(3, conv(2, x));
+    let [_, _, h, w] = x.dims();
+    let x = burn::tensor::module::interpolate(
+        x,
+        [2 * h, 2 * w],
+        burn::tensor::ops::InterpolateOptions::new(burn::tensor::ops::InterpolateMode::Nearest),
+    );
+    let features = conv(6, conv(5, conv(4, Tensor::cat(vec![x, skip], 1))));
+    let dist = net.convs[7].forward(features.clone());
+    let prob = burn::tensor::activation::sigmoid(net.convs[8].forward(features));
+    Tensor::cat(vec![prob, dist], 1).permute([0, 2, 3, 1])
+}
+
+/// Encode an `f32` array as a NumPy `.npy` file.
+fn npy(shape: &[usize], values: &[f32]) -> Vec<u8> {
+    let shape: Vec<String> = shape.iter().map(|d| d.to_string()).collect();
+    let mut header = format!(
+        "{{'descr': '<f4', 'fortran_order': False, 'shape': ({}), }}",
+        shape.join(", ")
+    );
+    while (header.len() + 11) % 64 != 0 {
+        header.push(' ');
+    }
+    header.push('\n');
+    let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+    bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(header.as_bytes());
+    values
+        .iter()
+        .for_each(|v| bytes.extend_from_slice(&v.to_le_bytes()));
+    bytes
+}
+
+/// Save a tiny StarDist2D network as Keras HDF5 weights, with kernels
+/// permuted from the `(c_out, c_in, kh, kw)` layout by `axes` (*i.e.*
+/// `[2, 3, 1, 0]` for the Keras `(kh, kw, c_in, c_out)` layout).
+fn write_keras_weights(net: &TinyStarDist2D<burn::backend::Flex>, path: &Path, axes: [usize; 4]) {
+    let file = File::create(path).unwrap();
+    let root = file.root();
+    let names: Vec<String> = KERAS_LAYERS.iter().map(|(n, _)| n.to_string()).collect();
+    root.set_attr("layer_names", AttrValue::StringArray(names))
+        .unwrap();
+    for (name, conv) in KERAS_LAYERS {
+        let layer = root.create_group(name).unwrap();
+        let Some(idx) = conv else {
+            continue;
+        };
+        let conv = &net.convs[idx];
+        let weight = conv.weight.val();
+        let dims = weight.dims().to_vec();
+        let weight: Vec<f32> = weight.into_data().to_vec().unwrap();
+        let kernel = ArrayD::from_shape_vec(IxDyn(&dims), weight)
+            .unwrap()
+            .permuted_axes(IxDyn(&axes));
+        let kernel_shape: Vec<u64> = kernel.shape().iter().map(|&d| d as u64).collect();
+        let kernel: Vec<f32> = kernel.iter().copied().collect();
+        let bias: Vec<f32> = conv
+            .bias
+            .as_ref()
+            .unwrap()
+            .val()
+            .into_data()
+            .to_vec()
+            .unwrap();
+        let weights = layer.create_group(name).unwrap();
+        weights
+            .create_dataset("kernel:0", |b| {
+                b.with_f32_data(&kernel).with_shape(&kernel_shape);
+            })
+            .unwrap();
+        weights
+            .create_dataset("bias:0", |b| {
+                b.with_f32_data(&bias).with_shape(&[bias.len() as u64]);
+            })
+            .unwrap();
+        layer
+            .set_attr(
+                "weight_names",
+                AttrValue::StringArray(vec![
+                    format!("{}/kernel:0", name),
+                    format!("{}/bias:0", name),
+                ]),
+            )
+            .unwrap();
+    }
+    file.close().unwrap();
+}
+
+/// Tests that Keras HDF5 StarDist2D weights are converted to a burnpack file
+/// that reproduces the original network.
+#[test]
+fn convert_keras_weights_2d() -> Result<(), CellcastError> {
+    let dir = std::env::temp_dir().join("cellcast_test_convert_keras_2d");
+    fs::create_dir_all(&dir).unwrap();
+    let (net, network) = tiny_stardist_2d();
+    let h5_path = dir.join("weights_best.h5");
+    write_keras_weights(&net, &h5_path, [2, 3, 1, 0]);
+    let bpk_path = dir.join("weights_best.bpk");
+    let conversion = convert_keras_weights(
+        h5_path.to_str().unwrap(),
+        bpk_path.to_str().unwrap(),
+        &network,
+        None,
+    )?;
+    assert_eq!(conversion.n_convs, 9);
+    assert_eq!(conversion.round_trip_diff, 0.0);
+    assert_eq!(conversion.reference_diff, None);
+    // the converted weights describe their network
+    let metadata = StarDistMetadata::from_file(bpk_path.to_str().unwrap())?.unwrap();
+    assert_eq!(metadata.network, network);
+    // the converted weights match the original network weights
+    let ref_path = dir.join("reference.bpk");
+    let mut store = BurnpackStore::from_file(&ref_path).overwrite(true);
+    net.save_into(&mut store).unwrap();
+    let config = StarDist2DConfig::from_network(network.clone())?;
+    let sd = StarDist2D::init_fluo(bpk_path.to_str(), Some(config.clone()), false)?;
+    let sd_ref = StarDist2D::init_fluo(ref_path.to_str(), Some(config), false)?;
+    let data = Array2::<f32>::from_shape_fn((20, 30), |(r, c)| ((r * c) % 7) as f32);
+    let expected = sd_ref.predict_fluo(&data, None, None, Some(0.3), None)?;
+    assert_eq!(
+        sd.predict_fluo(&data, None, None, Some(0.3), None)?,
+        expected
+    );
+    // the non-default network of the weights metadata is used without a
+    // configuration, a configuration contradicting the metadata fails
+    let sd = StarDist2D::init_fluo(bpk_path.to_str(), None, false)?;
+    assert_eq!(sd.config().network(), &network);
+    assert_eq!(
+        sd.predict_fluo(&data, None, None, Some(0.3), None)?,
+        expected
+    );
+    assert!(
+        StarDist2D::init_fluo(
+            bpk_path.to_str(),
+            Some(StarDist2DConfig::new(Some(8), Some((2, 2)))?),
+            false
+        )
+        .is_err()
+    );
+    // mismatched configurations and missing files fail
+    let wrong = StarDistNetworkConfig {
+        n_rays: 16,
+        ..network.clone()
+    };
+    assert!(
+        convert_keras_weights(
+            h5_path.to_str().unwrap(),
+            bpk_path.to_str().unwrap(),
+            &wrong,
+            None
+        )
+        .is_err()
+    );
+    // a failed conversion keeps the previous weights and leaves no files
+    // behind
+    let metadata = StarDistMetadata::from_file(bpk_path.to_str().unwrap())?.unwrap();
+    assert_eq!(metadata.network, network);
+    let wrong_path = dir.join("wrong.bpk");
+    assert!(
+        convert_keras_weights(
+            h5_path.to_str().unwrap(),
+            wrong_path.to_str().unwrap(),
+            &wrong,
+            None
+        )
+        .is_err()
+    );
+    assert!(!wrong_path.exists() && !dir.join("wrong.tmp.bpk").exists());
+    assert!(
+        convert_keras_weights("missing.h5", bpk_path.to_str().unwrap(), &network, None).is_err()
+    );
+    fs::remove_dir_all(&dir).unwrap();
+    Ok(())
+}
+
+/// Tests that a Keras weights conversion is verified against the output of
+/// the original network on a test input, failing for kernels in the wrong
+/// layout.
+#[test]
+fn convert_keras_weights_2d_reference() -> Result<(), CellcastError> {
+    let dir = std::env::temp_dir().join("cellcast_test_convert_keras_2d_reference");
+    fs::create_dir_all(&dir).unwrap();
+    let (net, network) = tiny_stardist_2d();
+    let device = Default::default();
+    let (n_row, n_col) = (16, 24);
+    let input: Vec<f32> = (0..n_row * n_col)
+        .map(|i| ((i * 13) % 17) as f32 / 16.0)
+        .collect();
+    let output = tiny_stardist_2d_forward(
+        &net,
+        Tensor::from_data(
+            TensorData::new(input.clone(), [1, 1, n_row, n_col]),
+            &device,
+        ),
+    );
+    let output: Vec<f32> = output.into_data().to_vec().unwrap();
+    // bioimage.io test tensors with a batch axis
+    let input_path = dir.join("test_input.npy");
+    let output_path = dir.join("test_output.npy");
+    fs::write(&input_path, npy(&[1, n_row, n_col, 1], &input)).unwrap();
+    fs::write(&output_path, npy(&[1, n_row, n_col, 9], &output)).unwrap();
+    let reference = KerasReference::from_npy_files(
+        input_path.to_str().unwrap(),
+        output_path.to_str().unwrap(),
+        None,
+    )?;
+    let h5_path = dir.join("weights_best.h5");
+    let bpk_path = dir.join("weights_best.bpk");
+    write_keras_weights(&net, &h5_path, [2, 3, 1, 0]);
+    let conversion = convert_keras_weights(
+        h5_path.to_str().unwrap(),
+        bpk_path.to_str().unwrap(),
+        &network,
+        Some(&reference),
+    )?;
+    assert!(conversion.reference_diff.unwrap() <= 1e-5);
+    // kernels with swapped spatial axes pass the round trip check but not
+    // the reference, and are not written
+    fs::remove_file(&bpk_path).unwrap();
+    write_keras_weights(&net, &h5_path, [3, 2, 1, 0]);
+    let convert = |reference: &KerasReference| {
+        convert_keras_weights(
+            h5_path.to_str().unwrap(),
+            bpk_path.to_str().unwrap(),
+            &network,
+            Some(reference),
+        )
+    };
+    assert!(convert(&reference).is_err());
+    assert!(!bpk_path.exists());
+    assert!(
+        convert_keras_weights(
+            h5_path.to_str().unwrap(),
+            bpk_path.to_str().unwrap(),
+            &network,
+            None
+        )
+        .is_ok()
+    );
+    // reference inputs and outputs that do not match the network
+    write_keras_weights(&net, &h5_path, [2, 3, 1, 0]);
+    let odd_input = KerasReference {
+        input: reference
+            .input
+            .slice_axis(Axis(1), (0..15).into())
+            .to_owned(),
+        ..reference.clone()
+    };
+    assert!(convert(&odd_input).is_err());
+    let short_output = KerasReference {
+        output: reference
+            .output
+            .slice_axis(Axis(3), (0..5).into())
+            .to_owned(),
+        ..reference.clone()
+    };
+    assert!(convert(&short_output).is_err());
+    assert!(KerasReference::from_npy_files("missing.npy", "missing.npy", None).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+    Ok(())
+}
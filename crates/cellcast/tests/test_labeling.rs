@@ -0,0 +1,253 @@
+use std::f32::consts::TAU;
+
+use imgal::spatial::geometry::inside_polyhedron;
+use ndarray::{Array1, Array2, Array3, ArrayView1, ArrayView2, arr1, arr2};
+
+use cellcast::geometry::polyhedron::{
+    golden_spiral, polyhedron_bbox, polyhedron_to_mask, polyhedron_verts,
+};
+use cellcast::labeling::{distance_polygon_to_label, distance_polyhedron_to_label};
+
+/// Create pseudo-random star-convex polygon ray distances with shape
+/// `(n_polys, n_rays)`.
+fn star_polygons(radii: &[f32], n_rays: usize) -> Array2<f32> {
+    Array2::from_shape_fn((radii.len(), n_rays), |(p, r)| {
+        radii[p] * (0.6 + 0.4 * ((p * 13 + r * 7) as f32 * 0.917).sin().abs())
+    })
+}
+
+/// Check if a `(y, x)` point is inside of a polygon with the even-odd rule.
+fn inside_polygon(y: f32, x: f32, vertices: &[(f32, f32)]) -> bool {
+    let mut inside = false;
+    let mut j = vertices.len() - 1;
+    (0..vertices.len()).for_each(|i| {
+        let (yi, xi) = vertices[i];
+        let (yj, xj) = vertices[j];
+        if (yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+            inside = !inside;
+        }
+        j = i;
+    });
+    inside
+}
+
+/// Render a label image pixel by pixel, drawing the polygons in ascending
+/// probability order with their probability rank as label.
+fn reference_polygon_labels(
+    dist: &Array2<f32>,
+    prob: &Array1<f32>,
+    pos: &Array2<f32>,
+    shape: (usize, usize),
+    scale: Option<(f32, f32)>,
+) -> Array2<u64> {
+    let (n_polys, n_rays) = dist.dim();
+    let mut order: Vec<usize> = (0..n_polys).collect();
+    order.sort_by(|&a, &b| prob[a].partial_cmp(&prob[b]).unwrap());
+    let mut labels = Array2::<u64>::zeros(shape);
+    order.iter().enumerate().for_each(|(rank, &p)| {
+        let (cy, cx, s) = match scale {
+            Some(s) => (
+                (pos[[p, 0]] + 0.5) * s.0 - 0.5,
+                (pos[[p, 1]] + 0.5) * s.1 - 0.5,
+                s,
+            ),
+            None => (pos[[p, 0]], pos[[p, 1]], (1.0, 1.0)),
+        };
+        let vertices: Vec<(f32, f32)> = (0..n_rays)
+            .map(|r| {
+                let a = (r as f32) * TAU / (n_rays as f32);
+                let d = dist[[p, r]];
+                (cy + d * a.sin() * s.0, cx + d * a.cos() * s.1)
+            })
+            .collect();
+        labels.indexed_iter_mut().for_each(|((y, x), l)| {
+            if inside_polygon(y as f32, x as f32, &vertices) {
+                *l = (rank + 1) as u64;
+            }
+        });
+    });
+    labels
+}
+
+/// Tests that the scanline polygon labels match a per-pixel point in polygon
+/// reference, including overlapping polygons, polygons clipped by the image
+/// edges and scaled polygons.
+#[test]
+fn distance_polygon_to_label_matches_reference() {
+    let n_rays = 32;
+    // overlapping polygons out of probability order and polygons crossing
+    // the image edges, one centered outside of the image
+    let dist = star_polygons(&[9.0, 7.0, 8.0, 6.0, 10.0, 5.0], n_rays);
+    let pos = arr2(&[
+        [20.0, 20.0],
+        [24.0, 26.0],
+        [16.5, 25.3],
+        [1.0, 40.0],
+        [35.7, 2.2],
+        [-3.0, 12.0],
+    ]);
+    let prob = arr1(&[0.6, 0.9, 0.7, 0.8, 0.5, 0.65]);
+    let shape = (40, 48);
+    let labels = distance_polygon_to_label(dist.view(), prob.view(), pos.view(), shape, None);
+    assert_eq!(
+        labels,
+        reference_polygon_labels(&dist, &prob, &pos, shape, None)
+    );
+    // every polygon is drawn, the highest probability polygon overwrites the
+    // polygons it overlaps
+    (1..=6).for_each(|l| assert!(labels.iter().any(|&v| v == l)));
+    assert_eq!(labels[[24, 26]], 6);
+    assert_eq!(labels[[20, 20]], 2);
+    // the polygons drawn at a higher resolution
+    let scale = (2.0, 1.5);
+    let shape = (80, 72);
+    let labels =
+        distance_polygon_to_label(dist.view(), prob.view(), pos.view(), shape, Some(scale));
+    assert_eq!(
+        labels,
+        reference_polygon_labels(&dist, &prob, &pos, shape, Some(scale))
+    );
+    assert_eq!(labels[[49, 39]], 6);
+    // no polygons or an empty image
+    let empty = distance_polygon_to_label(
+        Array2::<f32>::zeros((0, n_rays)).view(),
+        Array1::<f32>::zeros(0).view(),
+        Array2::<f32>::zeros((0, 2)).view(),
+        (10, 12),
+        None,
+    );
+    assert!(empty.iter().all(|&v| v == 0));
+    assert_eq!(
+        distance_polygon_to_label(dist.view(), prob.view(), pos.view(), (0, 12), None).dim(),
+        (0, 12)
+    );
+}
+
+/// Create pseudo-random star-convex polyhedron ray distances with shape
+/// `(n_polys, n_rays)`.
+fn star_polyhedra(radii: &[f32], n_rays: usize) -> Array2<f32> {
+    Array2::from_shape_fn((radii.len(), n_rays), |(p, r)| {
+        radii[p] * (0.7 + 0.3 * ((p * 11 + r * 5) as f32 * 0.613).sin().abs())
+    })
+}
+
+/// Check if the voxel `(z, y, x)` is inside of a polyhedron with the
+/// per-voxel `inside_polyhedron` reference.
+fn inside_voxel(
+    verts: ArrayView2<f32>,
+    faces: ArrayView2<usize>,
+    center: ArrayView1<f32>,
+    voxel: [i32; 3],
+) -> bool {
+    let query = voxel.map(|v| v as f32);
+    inside_polyhedron(verts, faces, center, ArrayView1::from(&query), None).unwrap()
+}
+
+/// Tests that the polyhedron voxel spans match the per-voxel
+/// `inside_polyhedron` reference for isotropic, anisotropic and bounding box
+/// clipped polyhedra.
+#[test]
+fn polyhedron_to_mask_matches_inside_polyhedron() {
+    let cases: [(usize, [f32; 3], [f32; 3], f32); 4] = [
+        (32, [1.0, 1.0, 1.0], [10.3, 11.7, 9.2], 6.0),
+        (64, [1.0, 1.0, 1.0], [12.0, 12.0, 12.0], 8.0),
+        (64, [2.0, 1.0, 1.0], [9.5, 14.2, 13.8], 7.0),
+        (96, [1.0, 1.5, 0.8], [15.1, 12.6, 14.4], 9.0),
+    ];
+    cases
+        .iter()
+        .for_each(|&(n_rays, anisotropy, center, radius)| {
+            let (gs_verts, faces) = golden_spiral(n_rays, Some(anisotropy)).unwrap();
+            let dist = star_polyhedra(&[radius], n_rays);
+            let center = ArrayView1::from(&center);
+            let verts = polyhedron_verts(dist.row(0), center, gs_verts.view());
+            let full = polyhedron_bbox(dist.row(0), center, gs_verts.view());
+            // the polyhedron clipped to the upper z half and the lower x half
+            let clipped = [
+                center[0] as i32,
+                full[1],
+                full[2],
+                full[3],
+                full[4],
+                center[2] as i32,
+            ];
+            [full, clipped].iter().for_each(|&bbox| {
+                let nz = (bbox[1] - bbox[0] + 1) as usize;
+                let ny = (bbox[3] - bbox[2] + 1) as usize;
+                let nx = (bbox[5] - bbox[4] + 1) as usize;
+                let mask = polyhedron_to_mask(verts.view(), faces.view(), center, bbox, nz, ny, nx);
+                assert_eq!(mask.len(), nz * ny * nx);
+                let mut n_inside = 0;
+                let mut n_diff = 0;
+                mask.iter().enumerate().for_each(|(i, &m)| {
+                    let voxel = [
+                        bbox[0] + (i / (ny * nx)) as i32,
+                        bbox[2] + (i / nx % ny) as i32,
+                        bbox[4] + (i % nx) as i32,
+                    ];
+                    let inside = inside_voxel(verts.view(), faces.view(), center, voxel);
+                    n_inside += inside as usize;
+                    n_diff += (inside != m) as usize;
+                });
+                // only a few voxels on the polyhedron surface may differ
+                assert!(n_inside > 100);
+                assert!(n_diff <= 3, "{} of {} voxels differ", n_diff, n_inside);
+            });
+        });
+}
+
+/// Tests that the polyhedron labels match the per-voxel `inside_polyhedron`
+/// reference, drawing the polyhedra above the probability threshold in
+/// descending probability order.
+#[test]
+fn distance_polyhedron_to_label_matches_reference() {
+    let n_rays = 64;
+    let anisotropy = [1.5, 1.0, 1.0];
+    let shape = [20, 26, 28];
+    // overlapping polyhedra out of probability order, a polyhedron crossing
+    // the volume edges and a polyhedron below the probability threshold
+    let dist = star_polyhedra(&[6.0, 7.0, 5.0, 6.0, 4.0], n_rays);
+    let pnts = arr2(&[
+        [10.0, 12.0, 12.0],
+        [11.0, 15.0, 16.0],
+        [9.0, 13.0, 17.0],
+        [1.0, 2.0, 25.0],
+        [10.0, 20.0, 5.0],
+    ]);
+    let prob = arr1(&[0.8, 0.95, 0.6, 0.7, 0.3]);
+    let labels = distance_polyhedron_to_label(
+        dist.view(),
+        pnts.view(),
+        prob.view(),
+        0.5,
+        anisotropy,
+        shape,
+        None,
+    )
+    .unwrap();
+    // the reference labels are drawn in descending probability order, lower
+    // probability polyhedra overwriting higher probability ones
+    let (gs_verts, faces) = golden_spiral(n_rays, Some(anisotropy)).unwrap();
+    let mut reference = Array3::<u64>::zeros(shape);
+    [1, 0, 3, 2].iter().for_each(|&p| {
+        let verts = polyhedron_verts(dist.row(p), pnts.row(p), gs_verts.view());
+        let bbox = polyhedron_bbox(dist.row(p), pnts.row(p), gs_verts.view());
+        reference.indexed_iter_mut().for_each(|((z, y, x), l)| {
+            let voxel = [z as i32, y as i32, x as i32];
+            let in_bbox = (0..3).all(|d| bbox[2 * d] <= voxel[d] && voxel[d] <= bbox[2 * d + 1]);
+            if in_bbox && inside_voxel(verts.view(), faces.view(), pnts.row(p), voxel) {
+                *l = (p + 1) as u64;
+            }
+        });
+    });
+    let n_diff = labels
+        .iter()
+        .zip(reference.iter())
+        .filter(|(a, b)| a != b)
+        .count();
+    assert!(n_diff <= 3, "{} voxels differ", n_diff);
+    (1..=4).for_each(|l| assert!(labels.iter().any(|&v| v == l)));
+    assert!(labels.iter().all(|&v| v != 5));
+    // the lowest probability polyhedron overwrites the polyhedra it overlaps
+    assert_eq!(labels[[9, 13, 17]], 3);
+}
@@ -0,0 +1,380 @@
+use ndarray::{Array1, Array2, arr1, arr2};
+
+use cellcast::process::nms::{
+    NmsAccuracy, NmsMode, OverlapCriterion, polygon_nms, polygon_soft_nms, polyhedron_nms,
+    polyhedron_soft_nms,
+};
+
+/// Create star-convex objects with constant ray distances (*i.e.* circles or
+/// spheres).
+fn round_objects(radii: &[f32], n_rays: usize) -> Array2<f32> {
+    Array2::from_shape_fn((radii.len(), n_rays), |(p, _)| radii[p])
+}
+
+/// Tests that the polygon overlap criteria suppress a small polygon inside of
+/// a large polygon depending on the polygon sizes and order.
+#[test]
+fn polygon_nms_overlap_criteria() {
+    let n_rays = 32;
+    let pnts = arr2(&[[30.0, 30.0], [31.0, 30.0]]);
+    // a large polygon first, the small polygon is the candidate
+    let large_first = round_objects(&[10.0, 4.0], n_rays);
+    // a small polygon first, the large polygon is the candidate
+    let small_first = round_objects(&[4.0, 10.0], n_rays);
+    let nms = |dist: &Array2<f32>, overlap| {
+        polygon_nms(dist.view(), pnts.view(), 2, n_rays, 0.3, overlap)
+    };
+    assert_eq!(nms(&large_first, OverlapCriterion::Min), [true, false]);
+    assert_eq!(nms(&small_first, OverlapCriterion::Min), [true, false]);
+    // the intersection over union of the polygons is about 0.16
+    assert_eq!(nms(&large_first, OverlapCriterion::Iou), [true, true]);
+    assert_eq!(nms(&small_first, OverlapCriterion::Iou), [true, true]);
+    assert_eq!(
+        nms(&large_first, OverlapCriterion::Candidate),
+        [true, false]
+    );
+    assert_eq!(nms(&small_first, OverlapCriterion::Candidate), [true, true]);
+}
+
+/// Tests that a few small polygons far apart are suppressed like nearby
+/// polygons, without a grid cell for every polygon size step between them.
+#[test]
+fn polygon_nms_sparse_polygons() {
+    let n_rays = 32;
+    // an overlapping pair and two distant polygons, about 2e5 polygon sizes
+    // apart
+    let dist = round_objects(&[2.0, 2.0, 2.0, 2.0], n_rays);
+    let pnts = arr2(&[[0.0, 0.0], [0.5, 0.0], [1.0e6, 0.0], [1.0e6, 1.0e6]]);
+    let valid = polygon_nms(
+        dist.view(),
+        pnts.view(),
+        4,
+        n_rays,
+        0.3,
+        OverlapCriterion::Iou,
+    );
+    assert_eq!(valid, [true, false, true, true]);
+}
+
+/// Tests that polygon soft-NMS decays the probability of overlapping polygons
+/// instead of removing them.
+#[test]
+fn polygon_soft_nms_decay() {
+    let n_rays = 32;
+    // two overlapping polygons and a distant polygon
+    let dist = round_objects(&[8.0, 8.0, 8.0], n_rays);
+    let pnts = arr2(&[[30.0, 30.0], [30.0, 36.0], [30.0, 80.0]]);
+    let prob = arr1(&[0.9, 0.8, 0.7]);
+    let soft = |mode| {
+        polygon_soft_nms(
+            dist.view(),
+            pnts.view(),
+            prob.view(),
+            0.1,
+            0.3,
+            OverlapCriterion::Iou,
+            mode,
+        )
+    };
+    let gaussian = soft(NmsMode::SoftGaussian { sigma: 0.5 });
+    assert_eq!(gaussian[0], 0.9);
+    assert!(gaussian[1] < 0.8 && gaussian[1] > 0.1);
+    assert_eq!(gaussian[2], 0.7);
+    let linear = soft(NmsMode::SoftLinear);
+    assert_eq!(linear[0], 0.9);
+    assert!(linear[1] < 0.8 && linear[1] > 0.1);
+    assert_eq!(linear[2], 0.7);
+    // the hard mode removes the overlapping polygon
+    let hard = soft(NmsMode::Hard);
+    assert_eq!(hard, [0.9, 0.0, 0.7]);
+    assert_eq!(
+        polygon_nms(
+            dist.view(),
+            pnts.view(),
+            3,
+            n_rays,
+            0.3,
+            OverlapCriterion::Iou
+        ),
+        [true, false, true]
+    );
+    // a weak overlap below the threshold is not decayed linearly
+    let linear = polygon_soft_nms(
+        dist.view(),
+        pnts.view(),
+        prob.view(),
+        0.1,
+        0.9,
+        OverlapCriterion::Iou,
+        NmsMode::SoftLinear,
+    );
+    assert_eq!(linear, [0.9, 0.8, 0.7]);
+    assert!(
+        polygon_soft_nms(
+            Array2::<f32>::zeros((0, n_rays)).view(),
+            Array2::<f32>::zeros((0, 2)).view(),
+            Array1::<f32>::zeros(0).view(),
+            0.1,
+            0.3,
+            OverlapCriterion::Min,
+            NmsMode::SoftLinear,
+        )
+        .is_empty()
+    );
+}
+
+/// Tests that the polyhedron overlap criteria and soft-NMS match the polygon
+/// behavior.
+#[test]
+fn polyhedron_nms_overlap_criteria() {
+    let n_rays = 32;
+    let aniso = [1.0, 1.0, 1.0];
+    let pnts = arr2(&[[20.0, 20.0, 20.0], [20.0, 21.0, 20.0]]);
+    let large_first = round_objects(&[8.0, 3.0], n_rays);
+    let small_first = round_objects(&[3.0, 8.0], n_rays);
+    let nms = |dist: &Array2<f32>, overlap| {
+        polyhedron_nms(
+            dist.view(),
+            pnts.view(),
+            aniso,
+            2,
+            n_rays,
+            0.3,
+            overlap,
+            NmsAccuracy::Exact,
+        )
+        .unwrap()
+    };
+    assert_eq!(nms(&large_first, OverlapCriterion::Min), [true, false]);
+    assert_eq!(nms(&large_first, OverlapCriterion::Iou), [true, true]);
+    assert_eq!(
+        nms(&large_first, OverlapCriterion::Candidate),
+        [true, false]
+    );
+    assert_eq!(nms(&small_first, OverlapCriterion::Candidate), [true, true]);
+    let prob = arr1(&[0.9, 0.8]);
+    let soft = |mode| {
+        polyhedron_soft_nms(
+            large_first.view(),
+            pnts.view(),
+            prob.view(),
+            aniso,
+            0.1,
+            0.3,
+            OverlapCriterion::Min,
+            mode,
+            NmsAccuracy::Exact,
+        )
+        .unwrap()
+    };
+    // the small polyhedron is inside of the large polyhedron, up to the
+    // voxel rendering of the intersection
+    let linear = soft(NmsMode::SoftLinear);
+    assert_eq!(linear[0], 0.9);
+    assert!(linear[1] < 0.8 * 0.2);
+    let gaussian = soft(NmsMode::SoftGaussian { sigma: 0.5 });
+    assert_eq!(gaussian[0], 0.9);
+    assert!(gaussian[1] < 0.8 * 0.2);
+}
+
+/// Tests that repeated NMS runs on many overlapping objects give identical
+/// results.
+#[test]
+fn nms_repeated_runs_are_identical() {
+    let n_rays = 32;
+    // pseudo-random clustered objects, sorted by descending probability
+    let noise = |i: usize, k: usize| ((i * 31 + k * 17) as f32 * 0.731).sin().abs();
+    let n_polys = 400;
+    let dist_2d = Array2::from_shape_fn((n_polys, n_rays), |(i, r)| 3.0 + 6.0 * noise(i, r));
+    let pnts_2d = Array2::from_shape_fn((n_polys, 2), |(i, d)| 10.0 + 40.0 * noise(i, d + 40));
+    let expected = polygon_nms(
+        dist_2d.view(),
+        pnts_2d.view(),
+        n_polys,
+        n_rays,
+        0.3,
+        OverlapCriterion::Min,
+    );
+    assert!(expected.iter().any(|&v| v) && expected.iter().any(|&v| !v));
+    (0..10).for_each(|_| {
+        let valid = polygon_nms(
+            dist_2d.view(),
+            pnts_2d.view(),
+            n_polys,
+            n_rays,
+            0.3,
+            OverlapCriterion::Min,
+        );
+        assert_eq!(valid, expected);
+    });
+    let n_polys = 20;
+    let dist_3d = Array2::from_shape_fn((n_polys, n_rays), |(i, r)| 1.5 + 1.5 * noise(i, r));
+    let pnts_3d = Array2::from_shape_fn((n_polys, 3), |(i, d)| 5.0 + 10.0 * noise(i, d + 40));
+    let nms_3d = || {
+        polyhedron_nms(
+            dist_3d.view(),
+            pnts_3d.view(),
+            [1.0, 1.0, 1.0],
+            n_polys,
+            n_rays,
+            0.3,
+            OverlapCriterion::Min,
+            NmsAccuracy::Exact,
+        )
+        .unwrap()
+    };
+    let expected = nms_3d();
+    assert!(expected.iter().any(|&v| v) && expected.iter().any(|&v| !v));
+    (0..5).for_each(|_| assert_eq!(nms_3d(), expected));
+}
+
+/// Tests that the cheaper polyhedron NMS accuracies agree with the exact
+/// intersection volumes on round objects, and that the halfspace accuracy
+/// never suppresses polyhedra kept by the exact intersection volumes.
+#[test]
+fn polyhedron_nms_accuracy() {
+    let n_rays = 32;
+    let aniso = [1.0, 1.0, 1.0];
+    let nms = |dist: &Array2<f32>, pnts: &Array2<f32>, accuracy| {
+        polyhedron_nms(
+            dist.view(),
+            pnts.view(),
+            aniso,
+            dist.nrows(),
+            n_rays,
+            0.3,
+            OverlapCriterion::Min,
+            accuracy,
+        )
+        .unwrap()
+    };
+    let accuracies = [
+        NmsAccuracy::Exact,
+        NmsAccuracy::Halfspace,
+        NmsAccuracy::Spheres,
+    ];
+    // a small sphere overlapping a large sphere by more than the threshold,
+    // and barely touching it
+    let round = round_objects(&[6.0, 4.0], n_rays);
+    let pnts = arr2(&[[20.0, 20.0, 20.0], [20.0, 20.0, 26.0]]);
+    accuracies
+        .iter()
+        .for_each(|&a| assert_eq!(nms(&round, &pnts, a), [true, false]));
+    let far = arr2(&[[20.0, 20.0, 20.0], [20.0, 20.0, 29.5]]);
+    accuracies
+        .iter()
+        .for_each(|&a| assert_eq!(nms(&round, &far, a), [true, true]));
+    // pseudo-random non-convex polyhedra
+    let noise = |i: usize, k: usize| ((i * 31 + k * 17) as f32 * 0.731).sin().abs();
+    let n_polys = 20;
+    let dist = Array2::from_shape_fn((n_polys, n_rays), |(i, r)| 1.5 + 1.5 * noise(i, r));
+    let pnts = Array2::from_shape_fn((n_polys, 3), |(i, d)| 5.0 + 10.0 * noise(i, d + 40));
+    let exact = nms(&dist, &pnts, NmsAccuracy::Exact);
+    let halfspace = nms(&dist, &pnts, NmsAccuracy::Halfspace);
+    assert!(exact.iter().zip(&halfspace).all(|(&e, &h)| h || !e));
+    assert_eq!(nms(&dist, &pnts, NmsAccuracy::Spheres).len(), n_polys);
+    let prob = Array1::from_shape_fn(n_polys, |i| 1.0 - i as f32 / n_polys as f32);
+    accuracies.iter().for_each(|&a| {
+        let soft = polyhedron_soft_nms(
+            dist.view(),
+            pnts.view(),
+            prob.view(),
+            aniso,
+            0.1,
+            0.3,
+            OverlapCriterion::Min,
+            NmsMode::SoftLinear,
+            a,
+        )
+        .unwrap();
+        assert!(soft.iter().zip(prob.iter()).all(|(s, p)| s <= p));
+    });
+}
+
+/// Check if a `(y, x)` point is inside a polygon using ray casting.
+fn point_in_polygon(y: f32, x: f32, vertices: &[(f32, f32)]) -> bool {
+    let n = vertices.len();
+    (0..n).fold(false, |inside, i| {
+        let (yi, xi) = vertices[i];
+        let (yj, xj) = vertices[(i + 1) % n];
+        if (yi > y) != (yj > y) && x < xi + (y - yi) / (yj - yi) * (xj - xi) {
+            !inside
+        } else {
+            inside
+        }
+    })
+}
+
+/// Tests that the star-convex polygon intersection matches exact and sampled
+/// intersection areas.
+#[test]
+fn polygon_nms_star_convex_intersection() {
+    // two diamonds with 4 rays overlapping in a diamond of a quarter area
+    let dist = round_objects(&[2.0, 2.0], 4);
+    let pnts = arr2(&[[10.0, 10.0], [10.0, 12.0]]);
+    let nms = |pnts: &Array2<f32>, threshold| {
+        polygon_nms(
+            dist.view(),
+            pnts.view(),
+            2,
+            4,
+            threshold,
+            OverlapCriterion::Min,
+        )
+    };
+    assert_eq!(nms(&pnts, 0.24), [true, false]);
+    assert_eq!(nms(&pnts, 0.26), [true, true]);
+    // identical polygons share their whole boundary
+    let same = arr2(&[[10.0, 10.0], [10.0, 10.0]]);
+    assert_eq!(nms(&same, 0.99), [true, false]);
+    // touching polygons do not overlap
+    let touching = arr2(&[[10.0, 10.0], [10.0, 14.0]]);
+    assert_eq!(nms(&touching, 0.0), [true, true]);
+    // non-convex stars, the overlap is recovered from the gaussian soft-NMS
+    // decay and compared to a sampled intersection area
+    let n_rays = 16;
+    let angle_step = 2.0 * std::f32::consts::PI / n_rays as f32;
+    let dist = Array2::from_shape_fn((2, n_rays), |(p, r)| match (p + r) % 3 {
+        0 => 2.0,
+        1 => 6.0,
+        _ => 4.0,
+    });
+    let pnts = arr2(&[[20.0, 20.0], [22.5, 23.0]]);
+    let vertices: Vec<Vec<(f32, f32)>> = (0..2)
+        .map(|p| {
+            (0..n_rays)
+                .map(|r| {
+                    let angle = angle_step * r as f32;
+                    (
+                        pnts[[p, 0]] + dist[[p, r]] * angle.sin(),
+                        pnts[[p, 1]] + dist[[p, r]] * angle.cos(),
+                    )
+                })
+                .collect()
+        })
+        .collect();
+    let step = 0.02;
+    let (mut inter, mut area) = (0usize, 0usize);
+    (0..1500).for_each(|r| {
+        (0..1500).for_each(|c| {
+            let (y, x) = (5.0 + r as f32 * step, 5.0 + c as f32 * step);
+            let in_b = point_in_polygon(y, x, &vertices[1]);
+            area += in_b as usize;
+            inter += (in_b && point_in_polygon(y, x, &vertices[0])) as usize;
+        });
+    });
+    let expected = inter as f32 / area as f32;
+    let sigma = 1.0;
+    let scores = polygon_soft_nms(
+        dist.view(),
+        pnts.view(),
+        arr1(&[0.9, 0.8]).view(),
+        0.0,
+        0.5,
+        OverlapCriterion::Candidate,
+        NmsMode::SoftGaussian { sigma },
+    );
+    let overlap = (-sigma * (scores[1] / 0.8).ln()).sqrt();
+    assert!(expected > 0.1 && expected < 0.9);
+    assert!((overlap - expected).abs() < 0.01);
+}
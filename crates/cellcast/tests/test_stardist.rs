@@ -1,9 +1,19 @@
+use std::fs;
+
+use burn::module::Param;
+use burn::nn::PaddingConfig2d;
+use burn::nn::conv::{Conv2d, Conv2dConfig};
+use burn::prelude::*;
+use burn_store::{BurnpackStore, ModuleSnapshot};
 use imgal::simulation::blob::logistic_metaballs;
 use imgal::spatial::roi::roi_cloud_map;
-use ndarray::{Ix2, Ix3, arr2};
+use ndarray::{Array2, Ix2, Ix3, arr2};
 
 use cellcast::CellcastError;
-use cellcast::models::{StarDist2D, StarDist3D};
+use cellcast::models::{
+    EnsembleMode, StarDist2D, StarDist2DConfig, StarDist2DEnsemble, StarDist3D, StarDist3DConfig,
+    StarDistBackbone, StarDistMetadata, StarDistNetworkConfig,
+};
 
 const CENTERS_2D: [[f64; 2]; 20] = [
     [45.0, 57.5],
@@ -66,7 +76,7 @@ fn stardist_2d_predict_fluo_expected_results() -> Result<(), CellcastError> {
         None,
     )?;
     let data = data.into_dimensionality::<Ix2>().unwrap();
-    let sd = StarDist2D::init_fluo(None, false)?;
+    let sd = StarDist2D::init_fluo(None, None, false)?;
     let labels = sd.predict_fluo(&data, None, None, None, None)?;
     let rcm = roi_cloud_map(&labels, None);
     assert_eq!(rcm.len(), 20);
@@ -93,6 +103,25 @@ fn stardist_2d_predict_fluo_expected_results() -> Result<(), CellcastError> {
     Ok(())
 }
 
+/// Tests that `StarDist2DConfig` validates the ray count and grid and computes
+/// the expected input divisibility.
+#[test]
+fn stardist_2d_config_ray_count_and_grid() -> Result<(), CellcastError> {
+    let default = StarDist2DConfig::default();
+    assert_eq!(default, StarDist2DConfig::new(None, None)?);
+    assert_eq!(default.n_rays(), 32);
+    assert_eq!(default.div(), (16, 16));
+    let config = StarDist2DConfig::new(Some(64), Some((1, 4)))?;
+    assert_eq!(config.n_rays(), 64);
+    assert_eq!(config.grid(), (1, 4));
+    assert_eq!(config.div(), (8, 32));
+    assert!(StarDist2DConfig::new(Some(2), None).is_err());
+    assert!(StarDist2DConfig::new(None, Some((3, 3))).is_err());
+    // pretrained weights only support the default configuration
+    assert!(StarDist2D::init_fluo(None, Some(config), false).is_err());
+    Ok(())
+}
+
 /// Tests that `predict_fluo` returns the expected results with the "3D_demo"
 /// pretrained weights for a simulated dataset of 9 blobs in 3D. This test
 /// asserts the number of blobs found and their size.
@@ -108,7 +137,7 @@ fn stardist_3d_predict_fluo_expected_results() -> Result<(), CellcastError> {
         None,
     )?;
     let data = data.into_dimensionality::<Ix3>().unwrap();
-    let sd = StarDist3D::init_fluo(None, None, false)?;
+    let sd = StarDist3D::init_fluo(None, None, None, false)?;
     let labels = sd.predict_fluo(&data, None, None, None, None, None)?;
     let rcm = roi_cloud_map(&labels, None);
     assert_eq!(rcm.len(), 9);
@@ -123,3 +152,721 @@ fn stardist_3d_predict_fluo_expected_results() -> Result<(), CellcastError> {
     assert_eq!(rcm.get(&9).expect("ROI 9 not found.").dim().0, 304);
     Ok(())
 }
+
+/// Tests that `StarDist3DConfig` validates the ray count and grid and computes
+/// the expected input divisibility for anisotropic grids.
+#[test]
+fn stardist_3d_config_ray_count_and_grid() -> Result<(), CellcastError> {
+    let default = StarDist3DConfig::default();
+    assert_eq!(default, StarDist3DConfig::new(None, None)?);
+    assert_eq!(default.n_rays(), 96);
+    assert_eq!(default.div(), (1, 16, 16));
+    let config = StarDist3DConfig::new(Some(64), Some((2, 4, 4)))?;
+    assert_eq!(config.n_rays(), 64);
+    assert_eq!(config.grid(), (2, 4, 4));
+    assert_eq!(config.div(), (2, 16, 16));
+    assert!(StarDist3DConfig::new(Some(3), None).is_err());
+    assert!(StarDist3DConfig::new(None, Some((1, 3, 2))).is_err());
+    // pretrained weights only support the default configuration
+    assert!(StarDist3D::init_fluo(None, None, Some(config), false).is_err());
+    Ok(())
+}
+
+/// Tests that the StarDist network presets match the default model
+/// configurations and that network configurations are validated per model.
+#[test]
+fn stardist_network_config_presets() -> Result<(), CellcastError> {
+    assert_eq!(
+        StarDist2DConfig::default().network(),
+        &StarDistNetworkConfig::fluo_2d()
+    );
+    assert_eq!(
+        StarDist3DConfig::default().network(),
+        &StarDistNetworkConfig::fluo_3d()
+    );
+    assert_eq!(StarDistNetworkConfig::he_2d().n_channel_in, 3);
+    // a deeper 2D U-Net with anisotropic pooling
+    let network = StarDistNetworkConfig {
+        unet_n_depth: 4,
+        unet_pool: vec![2, 1],
+        ..StarDistNetworkConfig::fluo_2d()
+    };
+    assert_eq!(StarDist2DConfig::from_network(network)?.div(), (32, 2));
+    // a 3D U-Net backbone
+    let network = StarDistNetworkConfig {
+        backbone: StarDistBackbone::Unet,
+        grid: vec![2, 4, 4],
+        ..StarDistNetworkConfig::fluo_3d()
+    };
+    assert_eq!(StarDist3DConfig::from_network(network)?.div(), (8, 16, 16));
+    // invalid and mismatched configurations
+    assert!(StarDist2DConfig::from_network(StarDistNetworkConfig::fluo_3d()).is_err());
+    assert!(StarDist3DConfig::from_network(StarDistNetworkConfig::fluo_2d()).is_err());
+    let network = StarDistNetworkConfig {
+        backbone: StarDistBackbone::Resnet,
+        ..StarDistNetworkConfig::fluo_2d()
+    };
+    assert!(network.validate().is_err());
+    let network = StarDistNetworkConfig {
+        unet_kernel_size: vec![3, 3, 3],
+        ..StarDistNetworkConfig::fluo_2d()
+    };
+    assert!(network.validate().is_err());
+    Ok(())
+}
+
+/// A stand-in for a tiny converted StarDist2D network with random weights.
+#[derive(Module, Debug)]
+struct TinyStarDist2D<B: Backend> {
+    convs: Vec<Conv2d<B>>,
+}
+
+/// Create a tiny StarDist2D U-Net (8 rays, grid of 1, depth of 1, 2 filters)
+/// with random weights.
+fn tiny_stardist_2d() -> TinyStarDist2D<burn::backend::Flex> {
+    // (c_in, c_out, kernel) of the U-Net levels, feature layer and heads
+    let layers = [
+        (1, 2, 3),
+        (2, 2, 3),
+        (2, 4, 3),
+        (4, 2, 3),
+        (4, 2, 3),
+        (2, 2, 3),
+        (2, 4, 3),
+        (4, 8, 1),
+        (4, 1, 1),
+    ];
+    let device = Default::default();
+    TinyStarDist2D {
+        convs: layers
+            .iter()
+            .map(|&(c_in, c_out, k)| {
+                Conv2dConfig::new([c_in, c_out], [k, k])
+                    .with_padding(PaddingConfig2d::Explicit(k / 2, k / 2, k / 2, k / 2))
+                    .init(&device)
+            })
+            .collect(),
+    }
+}
+
+/// Tests that a StarDist2D model can be initialized from a StarDist model
+/// folder, picking up the configuration and thresholds.
+#[test]
+fn stardist_2d_init_from_folder() -> Result<(), CellcastError> {
+    let dir = std::env::temp_dir().join("cellcast_test_stardist_2d_folder");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(
+        dir.join("config.json"),
+        r#"{"n_dim": 2, "axes": "YXC", "n_channel_in": 1, "n_rays": 8, "grid": [1, 1],
+        "backbone": "unet", "unet_n_depth": 1, "unet_kernel_size": [3, 3],
+        "unet_n_filter_base": 2, "unet_n_conv_per_depth": 2, "unet_pool": [2, 2],
+        "net_conv_after_unet": 4, "train_epochs": 400}"#,
+    )
+    .unwrap();
+    fs::write(dir.join("thresholds.json"), r#"{"prob": 0.6, "nms": 0.4}"#).unwrap();
+    let net = tiny_stardist_2d();
+    let mut store = BurnpackStore::from_file(dir.join("weights_best.bpk")).overwrite(true);
+    net.save_into(&mut store).unwrap();
+    let sd = StarDist2D::init_from_folder(dir.to_str().unwrap(), false)?;
+    assert_eq!(sd.config().n_rays(), 8);
+    assert_eq!(sd.config().grid(), (1, 1));
+    assert_eq!(sd.thresholds(), (0.6, 0.4));
+    let data = Array2::<f32>::from_shape_fn((20, 30), |(r, c)| ((r * c) % 7) as f32);
+    let labels = sd.predict_fluo(&data, None, None, None, None)?;
+    assert_eq!(labels.dim(), (20, 30));
+    // the folder does not contain a 3D model
+    assert!(StarDist3D::init_from_folder(dir.to_str().unwrap(), false).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+    Ok(())
+}
+
+/// Tests that StarDist metadata round-trips through a burnpack weights file
+/// and is applied as the model defaults when the weights are loaded.
+#[test]
+fn stardist_2d_init_with_weights_metadata() -> Result<(), CellcastError> {
+    let dir = std::env::temp_dir().join("cellcast_test_stardist_2d_metadata");
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("weights.bpk");
+    let net = tiny_stardist_2d();
+    let mut store = BurnpackStore::from_file(&path).overwrite(true);
+    net.save_into(&mut store).unwrap();
+    let path = path.to_str().unwrap();
+    // weights without metadata
+    assert_eq!(StarDistMetadata::from_file(path)?, None);
+    let data = Array2::<f32>::from_shape_fn((20, 30), |(r, c)| ((r * c) % 7) as f32);
+    let network = StarDistNetworkConfig {
+        n_rays: 8,
+        grid: vec![1, 1],
+        unet_n_depth: 1,
+        unet_n_filter_base: 2,
+        unet_n_conv_per_depth: 2,
+        net_conv_after_unet: 4,
+        ..StarDistNetworkConfig::fluo_2d()
+    };
+    let sd_ref = StarDist2D::init_fluo(
+        Some(path),
+        Some(StarDist2DConfig::from_network(network.clone())?),
+        false,
+    )?;
+    let expected = sd_ref.predict_fluo(&data, None, None, Some(0.6), Some(0.4))?;
+    let metadata = StarDistMetadata {
+        version: Some("0.1.0".to_string()),
+        prob_threshold: Some(0.6),
+        nms_threshold: Some(0.4),
+        percentiles: Some((2.0, 99.0)),
+        description: Some("A tiny test model.".to_string()),
+        license: Some("MIT".to_string()),
+        ..StarDistMetadata::new(network)
+    };
+    metadata.write_to_file(path)?;
+    assert_eq!(StarDistMetadata::from_file(path)?, Some(metadata.clone()));
+    // rewriting the metadata replaces it
+    metadata.write_to_file(path)?;
+    assert_eq!(StarDistMetadata::from_file(path)?, Some(metadata));
+    // the metadata sets the configuration, thresholds and percentiles
+    let sd = StarDist2D::init_fluo(Some(path), None, false)?;
+    assert_eq!(sd.config().n_rays(), 8);
+    assert_eq!(sd.thresholds(), (0.6, 0.4));
+    assert_eq!(sd.percentiles(), (2.0, 99.0));
+    // the weights are unchanged
+    let labels = sd.predict_fluo(&data, Some(1.0), Some(99.8), None, None)?;
+    assert_eq!(labels, expected);
+    // the metadata does not describe a 3D or H&E model
+    assert!(StarDist3D::init_fluo(Some(path), None, None, false).is_err());
+    assert!(StarDist2D::init_he(Some(path), None, false).is_err());
+    assert!(StarDistMetadata::from_file("missing.bpk").is_err());
+    fs::remove_dir_all(&dir).unwrap();
+    Ok(())
+}
+
+/// Tests that a multi-class StarDist2D model assigns each object the class
+/// with the highest mean probability over its pixels.
+#[test]
+fn stardist_2d_predict_fluo_classes() -> Result<(), CellcastError> {
+    let dir = std::env::temp_dir().join("cellcast_test_stardist_2d_classes");
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("weights.bpk");
+    // the class feature layer and a class head that always predicts class 2
+    let device = Default::default();
+    let mut net = tiny_stardist_2d();
+    net.convs.push(
+        Conv2dConfig::new([2, 4], [3, 3])
+            .with_padding(PaddingConfig2d::Explicit(1, 1, 1, 1))
+            .init(&device),
+    );
+    let mut class_head = Conv2dConfig::new([4, 4], [1, 1]).init(&device);
+    class_head.weight = Param::from_tensor(Tensor::zeros([4, 4, 1, 1], &device));
+    class_head.bias = Some(Param::from_tensor(Tensor::from_floats(
+        [0.0, 0.0, 5.0, 0.0],
+        &device,
+    )));
+    net.convs.push(class_head);
+    let mut store = BurnpackStore::from_file(&path).overwrite(true);
+    net.save_into(&mut store).unwrap();
+    let network = StarDistNetworkConfig {
+        n_rays: 8,
+        grid: vec![1, 1],
+        unet_n_depth: 1,
+        unet_n_filter_base: 2,
+        unet_n_conv_per_depth: 2,
+        net_conv_after_unet: 4,
+        n_classes: Some(3),
+        ..StarDistNetworkConfig::fluo_2d()
+    };
+    // the class count is stored with the weights metadata
+    StarDistMetadata::new(network.clone()).write_to_file(path.to_str().unwrap())?;
+    let sd = StarDist2D::init_fluo(path.to_str(), None, false)?;
+    assert_eq!(sd.config().n_classes(), Some(3));
+    let data = Array2::<f32>::from_shape_fn((20, 30), |(r, c)| ((r * c) % 7) as f32);
+    let labels = sd.predict_fluo(&data, None, None, Some(0.0), None)?;
+    let classes = sd.predict_fluo_classes(&data, None, None, Some(0.0), None)?;
+    assert_eq!(classes.labels, labels);
+    assert!(!classes.classes.is_empty());
+    assert!(classes.classes.values().all(|&c| c == 2));
+    assert!(
+        classes
+            .class_probs
+            .values()
+            .all(|p| { p.len() == 4 && (p.iter().sum::<f32>() - 1.0).abs() < 1e-4 && p[2] > 0.9 })
+    );
+    assert_eq!(
+        classes.class_image,
+        labels.mapv(|l| if l > 0 { 2 } else { 0 })
+    );
+    // single class models and invalid class counts fail
+    let tiny_path = dir.join("single.bpk");
+    let mut store = BurnpackStore::from_file(&tiny_path).overwrite(true);
+    tiny_stardist_2d().save_into(&mut store).unwrap();
+    let sd = StarDist2D::init_fluo(
+        tiny_path.to_str(),
+        Some(StarDist2DConfig::from_network(StarDistNetworkConfig {
+            n_classes: None,
+            ..network.clone()
+        })?),
+        false,
+    )?;
+    assert!(
+        sd.predict_fluo_classes(&data, None, None, None, None)
+            .is_err()
+    );
+    let zero = StarDistNetworkConfig {
+        n_classes: Some(0),
+        ..network
+    };
+    assert!(zero.validate().is_err());
+    let classes_3d = StarDistNetworkConfig {
+        n_classes: Some(2),
+        ..StarDistNetworkConfig::fluo_3d()
+    };
+    assert!(classes_3d.validate().is_err());
+    fs::remove_dir_all(&dir).unwrap();
+    Ok(())
+}
+
+/// Tests that a StarDist2D ensemble averages or merges its model predictions
+/// into consensus labels with per-object agreement counts.
+#[test]
+fn stardist_2d_ensemble_consensus() -> Result<(), CellcastError> {
+    let dir = std::env::temp_dir().join("cellcast_test_stardist_2d_ensemble");
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("weights.bpk");
+    let mut store = BurnpackStore::from_file(&path).overwrite(true);
+    tiny_stardist_2d().save_into(&mut store).unwrap();
+    let network = StarDistNetworkConfig {
+        n_rays: 8,
+        grid: vec![1, 1],
+        unet_n_depth: 1,
+        unet_n_filter_base: 2,
+        unet_n_conv_per_depth: 2,
+        net_conv_after_unet: 4,
+        ..StarDistNetworkConfig::fluo_2d()
+    };
+    let init = || {
+        StarDist2D::init_fluo(
+            path.to_str(),
+            Some(StarDist2DConfig::from_network(network.clone())?),
+            false,
+        )
+    };
+    let data = Array2::<f32>::from_shape_fn((20, 30), |(r, c)| ((r * c) % 7) as f32);
+    let expected = init()?.predict_fluo(&data, None, None, Some(0.0), Some(0.4))?;
+    let n_objects = expected.iter().max().copied().unwrap_or(0);
+    assert!(n_objects > 0);
+    // identical models average to the single model prediction and agree on
+    // every object
+    let ensemble = StarDist2DEnsemble::new(vec![init()?, init()?])?;
+    assert_eq!(ensemble.models().len(), 2);
+    let consensus = ensemble.predict_fluo(
+        &data,
+        None,
+        None,
+        Some(0.0),
+        Some(0.4),
+        EnsembleMode::Average,
+    )?;
+    assert_eq!(consensus.labels, expected);
+    assert_eq!(consensus.agreement.len() as u64, n_objects);
+    assert!(consensus.agreement.values().all(|&n| n <= 2));
+    // merged duplicate candidates suppress each other
+    let consensus =
+        ensemble.predict_fluo(&data, None, None, Some(0.0), Some(0.4), EnsembleMode::Merge)?;
+    assert_eq!(consensus.labels.dim(), (20, 30));
+    assert_eq!(consensus.labels.iter().max().copied(), Some(n_objects));
+    assert!(consensus.agreement.values().all(|&n| n == 0 || n == 2));
+    // ensembles must be non-empty and have a common ray count
+    assert!(StarDist2DEnsemble::new(Vec::new()).is_err());
+    let path_16 = dir.join("weights_16.bpk");
+    let mut net = tiny_stardist_2d();
+    net.convs[7] = Conv2dConfig::new([4, 16], [1, 1]).init(&Default::default());
+    let mut store = BurnpackStore::from_file(&path_16).overwrite(true);
+    net.save_into(&mut store).unwrap();
+    let sd_16 = StarDist2D::init_fluo(
+        path_16.to_str(),
+        Some(StarDist2DConfig::from_network(StarDistNetworkConfig {
+            n_rays: 16,
+            ..network.clone()
+        })?),
+        false,
+    )?;
+    assert!(StarDist2DEnsemble::new(vec![init()?, sd_16]).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+    Ok(())
+}
+
+/// A minimal protobuf message writer for building ONNX files.
+#[derive(Default)]
+struct Proto(Vec<u8>);
+
+impl Proto {
+    fn varint(mut self, field: u64, v: u64) -> Self {
+        self.key(field, 0);
+        self.raw_varint(v);
+        self
+    }
+
+    fn bytes(mut self, field: u64, b: &[u8]) -> Self {
+        self.key(field, 2);
+        self.raw_varint(b.len() as u64);
+        self.0.extend_from_slice(b);
+        self
+    }
+
+    fn str(self, field: u64, s: &str) -> Self {
+        self.bytes(field, s.as_bytes())
+    }
+
+    fn msg(self, field: u64, m: Proto) -> Self {
+        self.bytes(field, &m.0)
+    }
+
+    fn key(&mut self, field: u64, wire: u64) {
+        self.raw_varint(field << 3 | wire);
+    }
+
+    fn raw_varint(&mut self, mut v: u64) {
+        while v >= 0x80 {
+            self.0.push((v as u8) | 0x80);
+            v >>= 7;
+        }
+        self.0.push(v as u8);
+    }
+}
+
+/// Create an ONNX node with integer list attributes.
+fn onnx_node(op: &str, inputs: &[&str], outputs: &[&str], attrs: &[(&str, &[u64])]) -> Proto {
+    let mut node = Proto::default();
+    for i in inputs {
+        node = node.str(1, i);
+    }
+    for o in outputs {
+        node = node.str(2, o);
+    }
+    node = node.str(3, &format!("{}_{}", op, outputs[0])).str(4, op);
+    for (name, ints) in attrs {
+        let mut attr = Proto::default().str(1, name);
+        for &i in ints.iter() {
+            attr = attr.varint(8, i);
+        }
+        node = node.msg(5, attr.varint(20, 7));
+    }
+    node
+}
+
+/// Create an ONNX float tensor initializer.
+fn onnx_tensor(name: &str, dims: &[usize], values: &[f32]) -> Proto {
+    let mut tensor = Proto::default();
+    for &d in dims {
+        tensor = tensor.varint(1, d as u64);
+    }
+    let raw: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+    tensor.varint(2, 1).str(8, name).bytes(9, &raw)
+}
+
+/// Create an ONNX float tensor value info.
+fn onnx_value_info(name: &str, dims: &[usize]) -> Proto {
+    let mut shape = Proto::default();
+    for &d in dims {
+        shape = shape.msg(1, Proto::default().varint(1, d as u64));
+    }
+    let tensor_type = Proto::default().varint(1, 1).msg(2, shape);
+    Proto::default()
+        .str(1, name)
+        .msg(2, Proto::default().msg(1, tensor_type))
+}
+
+/// Export a tiny StarDist2D U-Net to an ONNX file.
+fn write_tiny_stardist_2d_onnx(net: &TinyStarDist2D<burn::backend::Flex>, path: &std::path::Path) {
+    let mut graph = Proto::default().str(2, "stardist");
+    for (i, conv) in net.convs.iter().enumerate() {
+        let weight = conv.weight.val();
+        let dims = weight.dims();
+        let weight: Vec<f32> = weight.into_data().to_vec().unwrap();
+        let bias: Vec<f32> = conv
+            .bias
+            .as_ref()
+            .unwrap()
+            .val()
+            .into_data()
+            .to_vec()
+            .unwrap();
+        graph = graph
+            .msg(5, onnx_tensor(&format!("w{}", i), &dims, &weight))
+            .msg(5, onnx_tensor(&format!("b{}", i), &[dims[0]], &bias));
+    }
+    graph = graph.msg(5, onnx_tensor("scales", &[4], &[1.0, 1.0, 2.0, 2.0]));
+    let conv = |i: usize, input: &str, output: &str| {
+        let k = if i < 7 { 3 } else { 1 };
+        let p = k / 2;
+        onnx_node(
+            "Conv",
+            &[input, &format!("w{}", i), &format!("b{}", i)],
+            &[output],
+            &[("kernel_shape", &[k, k]), ("pads", &[p, p, p, p])],
+        )
+    };
+    let relu = |input: &str, output: &str| onnx_node("Relu", &[input], &[output], &[]);
+    let nodes = [
+        conv(0, "input", "a0"),
+        relu("a0", "r0"),
+        conv(1, "r0", "a1"),
+        relu("a1", "r1"),
+        onnx_node(
+            "MaxPool",
+            &["r1"],
+            &["p1"],
+            &[("kernel_shape", &[2, 2]), ("strides", &[2, 2])],
+        ),
+        conv(2, "p1", "a2"),
+        relu("a2", "r2"),
+        conv(3, "r2", "a3"),
+        relu("a3", "r3"),
+        onnx_node("Resize", &["r3", "", "scales"], &["u3"], &[]).msg(
+            5,
+            Proto::default()
+                .str(1, "mode")
+                .str(4, "nearest")
+                .varint(20, 3),
+        ),
+        onnx_node("Concat", &["u3", "r1"], &["k3"], &[]).msg(
+            5,
+            Proto::default().str(1, "axis").varint(3, 1).varint(20, 2),
+        ),
+        conv(4, "k3", "a4"),
+        relu("a4", "r4"),
+        conv(5, "r4", "a5"),
+        relu("a5", "r5"),
+        conv(6, "r5", "a6"),
+        relu("a6", "r6"),
+        conv(7, "r6", "dist"),
+        conv(8, "r6", "a8"),
+        onnx_node("Sigmoid", &["a8"], &["prob"], &[]),
+    ];
+    for node in nodes {
+        graph = graph.msg(1, node);
+    }
+    graph = graph
+        .msg(11, onnx_value_info("input", &[1, 1, 32, 32]))
+        .msg(12, onnx_value_info("prob", &[1, 1, 32, 32]))
+        .msg(12, onnx_value_info("dist", &[1, 8, 32, 32]));
+    let model = Proto::default()
+        .varint(1, 8)
+        .msg(8, Proto::default().str(1, "").varint(2, 13))
+        .msg(7, graph);
+    fs::write(path, model.0).unwrap();
+}
+
+/// Tests that a StarDist2D model can be loaded from an ONNX file at runtime,
+/// inferring its configuration and matching the same weights loaded from a
+/// burnpack file.
+#[test]
+fn stardist_2d_init_from_onnx() -> Result<(), CellcastError> {
+    let dir = std::env::temp_dir().join("cellcast_test_stardist_2d_onnx");
+    fs::create_dir_all(&dir).unwrap();
+    let net = tiny_stardist_2d();
+    let onnx_path = dir.join("stardist.onnx");
+    write_tiny_stardist_2d_onnx(&net, &onnx_path);
+    let bpk_path = dir.join("stardist.bpk");
+    let mut store = BurnpackStore::from_file(&bpk_path).overwrite(true);
+    net.save_into(&mut store).unwrap();
+    let sd_onnx = StarDist2D::init_from_onnx(onnx_path.to_str().unwrap(), None, false)?;
+    let network = StarDistNetworkConfig {
+        n_rays: 8,
+        grid: vec![1, 1],
+        unet_n_depth: 1,
+        unet_n_filter_base: 2,
+        unet_n_conv_per_depth: 2,
+        net_conv_after_unet: 4,
+        ..StarDistNetworkConfig::fluo_2d()
+    };
+    assert_eq!(sd_onnx.config().network(), &network);
+    let config = StarDist2DConfig::from_network(network)?;
+    let sd_bpk = StarDist2D::init_fluo(bpk_path.to_str(), Some(config), false)?;
+    let data = Array2::<f32>::from_shape_fn((20, 30), |(r, c)| ((r * c) % 7) as f32);
+    assert_eq!(
+        sd_onnx.predict_fluo(&data, None, None, Some(0.3), None)?,
+        sd_bpk.predict_fluo(&data, None, None, Some(0.3), None)?
+    );
+    // a StarDist2D network is not a StarDist3D network
+    assert!(StarDist3D::init_from_onnx(onnx_path.to_str().unwrap(), None, None, false).is_err());
+    // missing or invalid ONNX files
+    assert!(StarDist2D::init_from_onnx(bpk_path.to_str().unwrap(), None, false).is_err());
+    assert!(StarDist2D::init_from_onnx("missing.onnx", None, false).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+    Ok(())
+}
+
+/// Run the tiny StarDist2D U-Net on a `(1, 1, row, col)` input, returning the
+/// `(1, row, col, 1 + n_rays)` object probabilities and ray distances.
+fn tiny_stardist_2d_forward(
+    net: &TinyStarDist2D<burn::backend::Flex>,
+    x: Tensor<burn::backend::Flex, 4>,
+) -> Tensor<burn::backend::Flex, 4> {
+    let conv = |i: usize, x| burn::tensor::activation::relu(net.convs[i].forward(x));
+    let skip = conv(1, conv(0, x));
+    let x = burn::tensor::module::max_pool2d(skip.clone(), [2, 2], [2, 2], [0, 0], [1, 1], false);
+    let x = conv(3, conv(2, x));
+    let [_, _, h, w] = x.dims();
+    let x = burn::tensor::module::interpolate(
+        x,
+        [2 * h, 2 * w],
+        burn::tensor::ops::InterpolateOptions::new(burn::tensor::ops::InterpolateMode::Nearest),
+    );
+    let features = conv(6, conv(5, conv(4, Tensor::cat(vec![x, skip], 1))));
+    let dist = net.convs[7].forward(features.clone());
+    let prob = burn::tensor::activation::sigmoid(net.convs[8].forward(features));
+    Tensor::cat(vec![prob, dist], 1).permute([0, 2, 3, 1])
+}
+
+/// Encode an `f32` array as a NumPy `.npy` file.
+fn npy(shape: &[usize], values: &[f32]) -> Vec<u8> {
+    let shape: Vec<String> = shape.iter().map(|d| d.to_string()).collect();
+    let mut header = format!(
+        "{{'descr': '<f4', 'fortran_order': False, 'shape': ({}), }}",
+        shape.join(", ")
+    );
+    while (header.len() + 11) % 64 != 0 {
+        header.push(' ');
+    }
+    header.push('\n');
+    let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+    bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(header.as_bytes());
+    values
+        .iter()
+        .for_each(|v| bytes.extend_from_slice(&v.to_le_bytes()));
+    bytes
+}
+
+/// The resource description of a tiny StarDist2D bioimage.io model package
+/// (format version 0.4).
+const TINY_RDF_2D: &str = r#"
+format_version: 0.4.10
+type: model
+name: tiny-stardist-2d
+inputs:
+  - name: input
+    axes: byxc
+    data_type: float32
+    preprocessing:
+      - name: scale_range
+        kwargs: {mode: per_sample, axes: yx, min_percentile: 0.0, max_percentile: 100.0}
+outputs:
+  - name: output
+    axes: byxc
+    data_type: float32
+test_inputs: [test_input.npy]
+test_outputs: [test_output.npy]
+weights:
+  onnx:
+    source: ./stardist.onnx
+config:
+  stardist:
+    stardist_version: 0.9.1
+    thresholds: {prob: 0.55, nms: 0.35}
+    config:
+      n_dim: 2
+      axes: YXC
+      n_channel_in: 1
+      n_rays: 8
+      grid: [1, 1]
+      backbone: unet
+      unet_n_depth: 1
+      unet_n_filter_base: 2
+      unet_n_conv_per_depth: 2
+      net_conv_after_unet: 4
+"#;
+
+/// Tests that a StarDist2D model can be initialized from a zipped and an
+/// unpacked bioimage.io model package and validated with its test tensors.
+#[test]
+fn stardist_2d_init_from_bioimageio() -> Result<(), CellcastError> {
+    let dir = std::env::temp_dir().join("cellcast_test_stardist_2d_bioimageio");
+    let unpacked = dir.join("unpacked");
+    fs::create_dir_all(&unpacked).unwrap();
+    let net = tiny_stardist_2d();
+    write_tiny_stardist_2d_onnx(&net, &unpacked.join("stardist.onnx"));
+    // the package test output is the network output of the min-max normalized
+    // test input
+    let (row, col) = (16, 24);
+    let input: Vec<f32> = (0..row * col).map(|i| ((i * 7) % 23) as f32).collect();
+    let norm: Vec<f32> = input.iter().map(|v| v / 22.0).collect();
+    let x = Tensor::from_data(TensorData::new(norm, [1, 1, row, col]), &Default::default());
+    let output: Vec<f32> = tiny_stardist_2d_forward(&net, x)
+        .into_data()
+        .to_vec()
+        .unwrap();
+    fs::write(
+        unpacked.join("test_input.npy"),
+        npy(&[1, row, col, 1], &input),
+    )
+    .unwrap();
+    fs::write(
+        unpacked.join("test_output.npy"),
+        npy(&[1, row, col, 9], &output),
+    )
+    .unwrap();
+    fs::write(unpacked.join("rdf.yaml"), TINY_RDF_2D).unwrap();
+    // zip the package
+    let zip_path = dir.join("tiny_stardist_2d.zip");
+    let mut zip = zip::ZipWriter::new(fs::File::create(&zip_path).unwrap());
+    for name in [
+        "rdf.yaml",
+        "stardist.onnx",
+        "test_input.npy",
+        "test_output.npy",
+    ] {
+        zip.start_file(name, zip::write::SimpleFileOptions::default())
+            .unwrap();
+        std::io::Write::write_all(&mut zip, &fs::read(unpacked.join(name)).unwrap()).unwrap();
+    }
+    zip.finish().unwrap();
+    let sd = StarDist2D::init_from_bioimageio(zip_path.to_str().unwrap(), true, false)?;
+    assert_eq!(sd.config().n_rays(), 8);
+    assert_eq!(sd.config().grid(), (1, 1));
+    assert_eq!(sd.thresholds(), (0.55, 0.35));
+    assert_eq!(sd.percentiles(), (0.0, 100.0));
+    StarDist2D::init_from_bioimageio(unpacked.to_str().unwrap(), true, false)?;
+    // format version 0.5 axes and test tensors
+    let rdf_05 = TINY_RDF_2D
+        .replace("format_version: 0.4.10", "format_version: 0.5.3")
+        .replace(
+            "axes: byxc",
+            "axes: [{type: batch}, {type: space, id: y}, {type: space, id: x}, {type: channel}]",
+        )
+        .replace("- name: scale_range", "- id: scale_range")
+        .replace(
+            "    data_type: float32\n    preprocessing",
+            "    test_tensor: {source: test_input.npy}\n    preprocessing",
+        )
+        .replace(
+            "    data_type: float32\ntest_inputs",
+            "    test_tensor: {source: test_output.npy}\ntest_inputs",
+        )
+        .replace(
+            "test_inputs: [test_input.npy]\ntest_outputs: [test_output.npy]\n",
+            "",
+        );
+    fs::write(unpacked.join("rdf.yaml"), &rdf_05).unwrap();
+    StarDist2D::init_from_bioimageio(unpacked.to_str().unwrap(), true, false)?;
+    // a test output that does not match the model fails validation
+    let wrong: Vec<f32> = output.iter().map(|v| v + 0.1).collect();
+    fs::write(
+        unpacked.join("test_output.npy"),
+        npy(&[1, row, col, 9], &wrong),
+    )
+    .unwrap();
+    assert!(StarDist2D::init_from_bioimageio(unpacked.to_str().unwrap(), true, false).is_err());
+    StarDist2D::init_from_bioimageio(unpacked.to_str().unwrap(), false, false)?;
+    // unsupported preprocessing and non-StarDist packages
+    let rdf = rdf_05.replace("id: scale_range", "id: zero_mean_unit_variance");
+    fs::write(unpacked.join("rdf.yaml"), rdf).unwrap();
+    assert!(StarDist2D::init_from_bioimageio(unpacked.to_str().unwrap(), false, false).is_err());
+    let rdf = rdf_05.replace("  stardist:", "  other:");
+    fs::write(unpacked.join("rdf.yaml"), rdf).unwrap();
+    assert!(StarDist2D::init_from_bioimageio(unpacked.to_str().unwrap(), false, false).is_err());
+    // the package does not contain a 3D model
+    assert!(StarDist3D::init_from_bioimageio(zip_path.to_str().unwrap(), false, false).is_err());
+    assert!(StarDist2D::init_from_bioimageio("missing.zip", false, false).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+    Ok(())
+}
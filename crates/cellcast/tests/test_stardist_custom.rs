@@ -0,0 +1,479 @@
+use burn::backend::Flex;
+use burn::prelude::*;
+use ndarray::{Array2, Array3};
+
+use cellcast::CellcastError;
+use cellcast::models::{
+    NmsMode, NmsOptions, ObjectFilter, OverlapCriterion, StarDist2DConfig, StarDist2DCustom,
+    StarDist3DConfig, StarDist3DCustom, StarDistNetwork2D, StarDistNetwork3D,
+};
+
+type TestBackend = Flex<f32, i32>;
+
+/// A network predicting the normalized first input channel as the object
+/// probability and a constant ray distance.
+struct BlobNetwork {
+    n_rays: usize,
+    radius: f32,
+}
+
+impl<B: Backend> StarDistNetwork2D<B> for BlobNetwork {
+    fn forward_prob_dist(&self, input: Tensor<B, 4>) -> (Tensor<B, 4>, Tensor<B, 4>) {
+        let [_, _, h, w] = input.dims();
+        let dist = Tensor::ones([1, self.n_rays, h, w], &input.device()).mul_scalar(self.radius);
+        (input.slice([0..1, 0..1, 0..h, 0..w]), dist)
+    }
+}
+
+impl<B: Backend> StarDistNetwork3D<B> for BlobNetwork {
+    fn forward_prob_dist(&self, input: Tensor<B, 5>) -> (Tensor<B, 5>, Tensor<B, 5>) {
+        let [_, _, d, h, w] = input.dims();
+        let dist = Tensor::ones([1, self.n_rays, d, h, w], &input.device()).mul_scalar(self.radius);
+        (input, dist)
+    }
+}
+
+/// A network predicting the normalized first input channel averaged over
+/// `2 x 2` grid cells as the object probability and a constant ray distance.
+struct GridBlobNetwork {
+    n_rays: usize,
+    radius: f32,
+}
+
+impl<B: Backend> StarDistNetwork2D<B> for GridBlobNetwork {
+    fn forward_prob_dist(&self, input: Tensor<B, 4>) -> (Tensor<B, 4>, Tensor<B, 4>) {
+        let [_, _, h, w] = input.dims();
+        let device = input.device();
+        let prob = input
+            .slice([0..1, 0..1, 0..h, 0..w])
+            .reshape([1, 1, h / 2, 2, w / 2, 2])
+            .mean_dim(5)
+            .mean_dim(3)
+            .reshape([1, 1, h / 2, w / 2]);
+        let dist = Tensor::ones([1, self.n_rays, h / 2, w / 2], &device).mul_scalar(self.radius);
+        (prob, dist)
+    }
+}
+
+/// Create a 2D image of two Gaussian blobs.
+fn two_blobs_2d() -> Array2<f32> {
+    Array2::from_shape_fn((40, 60), |(r, c)| {
+        let g =
+            |cr: f32, cc: f32| (-((r as f32 - cr).powi(2) + (c as f32 - cc).powi(2)) / 18.0).exp();
+        g(15.0, 15.0) + g(24.0, 42.0)
+    })
+}
+
+/// Tests that a user-defined 2D network runs through the StarDist2D
+/// postprocessing, with one label per blob.
+#[test]
+fn stardist_2d_custom_network_predict() -> Result<(), CellcastError> {
+    let config = StarDist2DConfig::new(Some(16), Some((1, 1)))?;
+    let network = BlobNetwork {
+        n_rays: 16,
+        radius: 5.0,
+    };
+    let model = StarDist2DCustom::<TestBackend, _>::new(network, config, Default::default())
+        .with_thresholds(0.8, 0.3)
+        .with_percentiles(0.0, 100.0);
+    assert_eq!(model.thresholds(), (0.8, 0.3));
+    assert_eq!(model.percentiles(), (0.0, 100.0));
+    let data = two_blobs_2d();
+    let labels = model.predict(&data, None, None, None, None)?;
+    assert_eq!(labels.dim(), (40, 60));
+    assert_eq!(labels.iter().max(), Some(&2));
+    assert!(labels[[15, 15]] > 0 && labels[[24, 42]] > 0);
+    assert_ne!(labels[[15, 15]], labels[[24, 42]]);
+    assert_eq!(labels[[0, 0]], 0);
+    // a higher probability threshold keeps no more objects
+    assert!(
+        model
+            .predict(&data, None, None, Some(0.999), None)?
+            .iter()
+            .max()
+            <= Some(&2)
+    );
+    // the distant blobs do not overlap, soft-NMS keeps both
+    let soft = NmsOptions {
+        overlap: OverlapCriterion::Iou,
+        mode: NmsMode::SoftGaussian { sigma: 0.5 },
+    };
+    let blob_model = || -> Result<_, CellcastError> {
+        Ok(StarDist2DCustom::<TestBackend, _>::new(
+            BlobNetwork {
+                n_rays: 16,
+                radius: 5.0,
+            },
+            StarDist2DConfig::new(Some(16), Some((1, 1)))?,
+            Default::default(),
+        )
+        .with_thresholds(0.8, 0.3)
+        .with_percentiles(0.0, 100.0))
+    };
+    let soft_model = blob_model()?.with_nms_options(soft)?;
+    assert_eq!(soft_model.nms_options(), &soft);
+    // the Gaussian soft-NMS sigma must be positive and finite
+    for sigma in [0.0, -0.5, f32::NAN, f32::INFINITY] {
+        let invalid = NmsOptions {
+            mode: NmsMode::SoftGaussian { sigma },
+            ..soft
+        };
+        assert!(invalid.validate().is_err());
+        assert!(blob_model()?.with_nms_options(invalid).is_err());
+    }
+    let soft_labels = soft_model.predict(&data, None, None, None, None)?;
+    assert_eq!(soft_labels.iter().max(), Some(&2));
+    assert_ne!(soft_labels[[15, 15]], soft_labels[[24, 42]]);
+    // multichannel images need a multichannel network
+    let channels = Array3::from_shape_fn((40, 60, 2), |(r, c, _)| data[[r, c]]);
+    assert!(
+        model
+            .predict_channels(&channels, None, None, None, None, None)
+            .is_err()
+    );
+    Ok(())
+}
+
+/// Tests that a user-defined 3D network runs through the StarDist3D
+/// postprocessing, with one label per blob in the axis order of the input.
+#[test]
+fn stardist_3d_custom_network_predict() -> Result<(), CellcastError> {
+    let config = StarDist3DConfig::new(Some(32), Some((1, 1, 1)))?;
+    let network = BlobNetwork {
+        n_rays: 32,
+        radius: 3.0,
+    };
+    let model = StarDist3DCustom::<TestBackend, _>::new(
+        network,
+        config,
+        Some(&[1.0, 1.0, 1.0]),
+        Default::default(),
+    )?
+    .with_thresholds(0.8, 0.3)
+    .with_percentiles(0.0, 100.0);
+    assert_eq!(model.anisotropy(), [1.0, 1.0, 1.0]);
+    let data = Array3::from_shape_fn((16, 24, 32), |(p, r, c)| {
+        let g = |cp: f32, cr: f32, cc: f32| {
+            (-((p as f32 - cp).powi(2) + (r as f32 - cr).powi(2) + (c as f32 - cc).powi(2)) / 8.0)
+                .exp()
+        };
+        g(7.0, 8.0, 8.0) + g(8.0, 15.0, 22.0)
+    });
+    let labels = model.predict(&data, None, None, None, None, None)?;
+    assert_eq!(labels.dim(), (16, 24, 32));
+    assert_eq!(labels.iter().max(), Some(&2));
+    assert_ne!(labels[[7, 8, 8]], labels[[8, 15, 22]]);
+    assert!(labels[[7, 8, 8]] > 0 && labels[[8, 15, 22]] > 0);
+    // the planes axis last gives labels in the input axis order
+    let last = data.clone().permuted_axes([1, 2, 0]);
+    let labels_last = model.predict(&last, None, None, None, None, Some(2))?;
+    assert_eq!(labels_last.dim(), (24, 32, 16));
+    assert_eq!(labels_last.iter().max(), Some(&2));
+    assert_ne!(labels_last[[8, 8, 7]], labels_last[[15, 22, 8]]);
+    assert!(labels_last[[8, 8, 7]] > 0 && labels_last[[15, 22, 8]] > 0);
+    assert!(
+        StarDist3DCustom::<TestBackend, _>::new(
+            BlobNetwork {
+                n_rays: 32,
+                radius: 3.0,
+            },
+            StarDist3DConfig::default(),
+            Some(&[1.0, 1.0]),
+            Default::default(),
+        )
+        .is_err()
+    );
+    Ok(())
+}
+
+/// Tests that objects crossing the image border or outside of the size range
+/// are removed before labeling, keeping the label ids contiguous.
+#[test]
+fn stardist_custom_object_filter() -> Result<(), CellcastError> {
+    // a third blob crossing the top image border
+    let data = two_blobs_2d()
+        + Array2::from_shape_fn((40, 60), |(r, c)| {
+            (-((r as f32 - 4.0).powi(2) + (c as f32 - 30.0).powi(2)) / 18.0).exp()
+        });
+    let model = |filter: ObjectFilter| {
+        StarDist2DCustom::<TestBackend, _>::new(
+            BlobNetwork {
+                n_rays: 16,
+                radius: 5.0,
+            },
+            StarDist2DConfig::new(Some(16), Some((1, 1))).unwrap(),
+            Default::default(),
+        )
+        .with_thresholds(0.8, 0.3)
+        .with_percentiles(0.0, 100.0)
+        .with_object_filter(filter)
+    };
+    let labels = model(ObjectFilter::default()).predict(&data, None, None, None, None)?;
+    assert_eq!(labels.iter().max(), Some(&3));
+    assert!(labels[[4, 30]] > 0);
+    let filter = ObjectFilter {
+        exclude_border: true,
+        ..Default::default()
+    };
+    let sd = model(filter);
+    assert_eq!(sd.object_filter(), &filter);
+    let labels = sd.predict(&data, None, None, None, None)?;
+    assert_eq!(labels.iter().max(), Some(&2));
+    assert_eq!(labels[[4, 30]], 0);
+    assert!(labels[[15, 15]] > 0 && labels[[24, 42]] > 0);
+    // the 16 ray polygons of radius 5 have an area of about 76.5 pixels
+    let sizes = [
+        (Some(50.0), Some(100.0), 3),
+        (Some(100.0), None, 0),
+        (None, Some(50.0), 0),
+    ];
+    sizes.iter().for_each(|&(min_size, max_size, n)| {
+        let filter = ObjectFilter {
+            min_size,
+            max_size,
+            ..Default::default()
+        };
+        let labels = model(filter)
+            .predict(&data, None, None, None, None)
+            .unwrap();
+        assert_eq!(labels.iter().max().copied().unwrap_or(0), n);
+    });
+
+    // a 3D blob crossing the first plane
+    let volume = Array3::from_shape_fn((16, 24, 32), |(p, r, c)| {
+        let g = |cp: f32, cr: f32, cc: f32| {
+            (-((p as f32 - cp).powi(2) + (r as f32 - cr).powi(2) + (c as f32 - cc).powi(2)) / 8.0)
+                .exp()
+        };
+        g(7.0, 8.0, 8.0) + g(8.0, 15.0, 22.0) + g(2.0, 16.0, 8.0)
+    });
+    let model_3d = |filter: ObjectFilter| {
+        StarDist3DCustom::<TestBackend, _>::new(
+            BlobNetwork {
+                n_rays: 32,
+                radius: 3.0,
+            },
+            StarDist3DConfig::new(Some(32), Some((1, 1, 1))).unwrap(),
+            Some(&[1.0, 1.0, 1.0]),
+            Default::default(),
+        )
+        .unwrap()
+        .with_thresholds(0.8, 0.3)
+        .with_percentiles(0.0, 100.0)
+        .with_object_filter(filter)
+    };
+    let labels =
+        model_3d(ObjectFilter::default()).predict(&volume, None, None, None, None, None)?;
+    assert_eq!(labels.iter().max(), Some(&3));
+    let labels = model_3d(ObjectFilter {
+        exclude_border: true,
+        ..Default::default()
+    })
+    .predict(&volume, None, None, None, None, None)?;
+    assert_eq!(labels.iter().max(), Some(&2));
+    assert_eq!(labels[[2, 16, 8]], 0);
+    // the polyhedra of radius 3 have a volume below 4/3 * pi * 27
+    let labels = model_3d(ObjectFilter {
+        min_size: Some(120.0),
+        ..Default::default()
+    })
+    .predict(&volume, None, None, None, None, None)?;
+    assert_eq!(labels.iter().max(), Some(&0));
+    Ok(())
+}
+
+/// Tests that sub-pixel centers place the objects at the center of their grid
+/// cell, moving the label centroids closer to the blob centers.
+#[test]
+fn stardist_2d_custom_subpixel_centers() -> Result<(), CellcastError> {
+    // blobs centered on odd pixels, between the grid cell corners
+    let data = Array2::from_shape_fn((40, 60), |(r, c)| {
+        let g =
+            |cr: f32, cc: f32| (-((r as f32 - cr).powi(2) + (c as f32 - cc).powi(2)) / 18.0).exp();
+        g(15.0, 15.0) + g(25.0, 41.0)
+    });
+    let model = |subpixel: bool| {
+        StarDist2DCustom::<TestBackend, _>::new(
+            GridBlobNetwork {
+                n_rays: 32,
+                radius: 5.0,
+            },
+            StarDist2DConfig::new(Some(32), Some((2, 2))).unwrap(),
+            Default::default(),
+        )
+        .with_thresholds(0.8, 0.3)
+        .with_percentiles(0.0, 100.0)
+        .with_subpixel_centers(subpixel)
+    };
+    assert!(!model(false).subpixel_centers());
+    assert!(model(true).subpixel_centers());
+    // the mean absolute distance of the label centroids to the blob centers
+    let centroid_error = |labels: &Array2<u64>| {
+        [(15.0, 15.0), (25.0, 41.0)]
+            .iter()
+            .map(|&(cr, cc): &(f32, f32)| {
+                let l = labels[[cr as usize, cc as usize]];
+                assert!(l > 0);
+                let pixels: Vec<(f32, f32)> = labels
+                    .indexed_iter()
+                    .filter(|&(_, &v)| v == l)
+                    .map(|((r, c), _)| (r as f32, c as f32))
+                    .collect();
+                let n = pixels.len() as f32;
+                let mr = pixels.iter().map(|p| p.0).sum::<f32>() / n;
+                let mc = pixels.iter().map(|p| p.1).sum::<f32>() / n;
+                (mr - cr).abs() + (mc - cc).abs()
+            })
+            .sum::<f32>()
+    };
+    let labels = model(false).predict(&data, None, None, None, None)?;
+    let labels_subpixel = model(true).predict(&data, None, None, None, None)?;
+    assert_eq!(labels.iter().max(), Some(&2));
+    assert_eq!(labels_subpixel.iter().max(), Some(&2));
+    assert!(centroid_error(&labels_subpixel) < centroid_error(&labels));
+    Ok(())
+}
+
+/// Tests that the labels are rendered at the output scale, with the objects
+/// at the scaled positions and sizes.
+#[test]
+fn stardist_custom_output_scale() -> Result<(), CellcastError> {
+    let model_2d = || {
+        StarDist2DCustom::<TestBackend, _>::new(
+            BlobNetwork {
+                n_rays: 32,
+                radius: 5.0,
+            },
+            StarDist2DConfig::new(Some(32), Some((1, 1))).unwrap(),
+            Default::default(),
+        )
+        .with_thresholds(0.8, 0.3)
+        .with_percentiles(0.0, 100.0)
+    };
+    assert!(model_2d().with_output_scale((0.0, 1.0)).is_err());
+    assert!(model_2d().with_output_scale((2.0, f32::NAN)).is_err());
+    let data = two_blobs_2d();
+    let labels = model_2d().predict(&data, None, None, None, None)?;
+    let scaled_model = model_2d().with_output_scale((2.0, 2.0))?;
+    assert_eq!(scaled_model.output_scale(), (2.0, 2.0));
+    let scaled = scaled_model.predict(&data, None, None, None, None)?;
+    assert_eq!(scaled.dim(), (80, 120));
+    assert_eq!(scaled.iter().max(), Some(&2));
+    // the blob centers (15, 15) and (24, 42) map to (30.5, 30.5) and (48.5, 84.5)
+    assert!(scaled[[30, 30]] > 0 && scaled[[48, 84]] > 0);
+    assert_ne!(scaled[[30, 30]], scaled[[48, 84]]);
+    let area = |labels: &Array2<u64>| labels.iter().filter(|&&l| l > 0).count() as f32;
+    let ratio = area(&scaled) / area(&labels);
+    assert!((ratio - 4.0).abs() < 0.8, "area ratio {ratio}");
+    // a downscaled output
+    let half = model_2d()
+        .with_output_scale((0.5, 1.0))?
+        .predict(&data, None, None, None, None)?;
+    assert_eq!(half.dim(), (20, 60));
+    assert_eq!(half.iter().max(), Some(&2));
+    // an isotropic output volume of a volume with a plane spacing of twice the
+    // pixel size
+    let data = Array3::from_shape_fn((8, 24, 32), |(p, r, c)| {
+        let g = |cp: f32, cr: f32, cc: f32| {
+            (-((2.0 * p as f32 - cp).powi(2) + (r as f32 - cr).powi(2) + (c as f32 - cc).powi(2))
+                / 8.0)
+                .exp()
+        };
+        g(8.0, 8.0, 8.0) + g(8.0, 15.0, 22.0)
+    });
+    let model_3d = StarDist3DCustom::<TestBackend, _>::new(
+        BlobNetwork {
+            n_rays: 32,
+            radius: 3.0,
+        },
+        StarDist3DConfig::new(Some(32), Some((1, 1, 1)))?,
+        Some(&[2.0, 1.0, 1.0]),
+        Default::default(),
+    )?
+    .with_thresholds(0.8, 0.3)
+    .with_percentiles(0.0, 100.0);
+    assert!(model_3d.output_scale() == [1.0; 3]);
+    let model_3d = model_3d.with_output_scale([2.0, 1.0, 1.0])?;
+    let labels = model_3d.predict(&data, None, None, None, None, None)?;
+    assert_eq!(labels.dim(), (16, 24, 32));
+    assert_eq!(labels.iter().max(), Some(&2));
+    assert!(labels[[8, 8, 8]] > 0 && labels[[8, 15, 22]] > 0);
+    assert_ne!(labels[[8, 8, 8]], labels[[8, 15, 22]]);
+    Ok(())
+}
+
+/// Tests that resized input images are labeled on the input image grid, with
+/// the network ray distances scaled back to the input image.
+#[test]
+fn stardist_custom_input_scale() -> Result<(), CellcastError> {
+    let model_2d = || {
+        StarDist2DCustom::<TestBackend, _>::new(
+            BlobNetwork {
+                n_rays: 32,
+                radius: 5.0,
+            },
+            StarDist2DConfig::new(Some(32), Some((1, 1))).unwrap(),
+            Default::default(),
+        )
+        .with_thresholds(0.8, 0.3)
+        .with_percentiles(0.0, 100.0)
+    };
+    assert!(model_2d().with_input_scale((-1.0, 1.0)).is_err());
+    let data = two_blobs_2d();
+    let area = |labels: &Array2<u64>| labels.iter().filter(|&&l| l > 0).count() as f32;
+    let labels = model_2d().predict(&data, None, None, None, None)?;
+    // the network predicts the same ray distances at half the resolution,
+    // i.e. objects of twice the size in the input image
+    let scaled_model = model_2d().with_input_scale((0.5, 0.5))?;
+    assert_eq!(scaled_model.input_scale(), (0.5, 0.5));
+    let scaled = scaled_model.predict(&data, None, None, None, None)?;
+    assert_eq!(scaled.dim(), (40, 60));
+    assert_eq!(scaled.iter().max(), Some(&2));
+    assert!(scaled[[15, 15]] > 0 && scaled[[24, 42]] > 0);
+    assert_ne!(scaled[[15, 15]], scaled[[24, 42]]);
+    let ratio = area(&scaled) / area(&labels);
+    assert!((ratio - 4.0).abs() < 0.8, "area ratio {ratio}");
+    // the object filter sizes are in input image pixels
+    let filtered = model_2d()
+        .with_input_scale((0.5, 0.5))?
+        .with_object_filter(ObjectFilter {
+            min_size: Some(150.0),
+            ..Default::default()
+        })
+        .predict(&data, None, None, None, None)?;
+    assert_eq!(filtered, scaled);
+    // the output scale is relative to the input image
+    let output = model_2d()
+        .with_input_scale((0.5, 0.5))?
+        .with_output_scale((0.5, 0.5))?
+        .predict(&data, None, None, None, None)?;
+    assert_eq!(output.dim(), (20, 30));
+    assert_eq!(output.iter().max(), Some(&2));
+    let ratio = area(&output) / area(&labels);
+    assert!((ratio - 1.0).abs() < 0.2, "area ratio {ratio}");
+    let data = Array3::from_shape_fn((16, 24, 32), |(p, r, c)| {
+        let g = |cp: f32, cr: f32, cc: f32| {
+            (-((p as f32 - cp).powi(2) + (r as f32 - cr).powi(2) + (c as f32 - cc).powi(2)) / 8.0)
+                .exp()
+        };
+        g(7.0, 8.0, 8.0) + g(8.0, 15.0, 22.0)
+    });
+    let labels = StarDist3DCustom::<TestBackend, _>::new(
+        BlobNetwork {
+            n_rays: 32,
+            radius: 2.0,
+        },
+        StarDist3DConfig::new(Some(32), Some((1, 1, 1)))?,
+        Some(&[1.0, 1.0, 1.0]),
+        Default::default(),
+    )?
+    .with_thresholds(0.8, 0.3)
+    .with_percentiles(0.0, 100.0)
+    .with_input_scale([1.0, 0.5, 0.5])?
+    .predict(&data, None, None, None, None, None)?;
+    assert_eq!(labels.dim(), (16, 24, 32));
+    assert_eq!(labels.iter().max(), Some(&2));
+    assert!(labels[[7, 8, 8]] > 0 && labels[[8, 15, 22]] > 0);
+    assert_ne!(labels[[7, 8, 8]], labels[[8, 15, 22]]);
+    Ok(())
+}
@@ -0,0 +1,211 @@
+use std::fs;
+use std::path::Path;
+
+use burn::module::ParamId;
+use burn::prelude::*;
+use burn_store::{BurnpackWriter, TensorSnapshot};
+use ndarray::{Array2, Array3, Axis, Ix2, s};
+
+use cellcast::CellcastError;
+use cellcast::models::{UNet, UNetConfig};
+
+/// The network configuration of a tiny U-Net (1 level of 4 filters).
+fn tiny_network(n_dim: usize) -> UNetConfig {
+    UNetConfig {
+        n_dim,
+        n_depth: 1,
+        n_filter_base: 4,
+        ..UNetConfig::semantic_2d()
+    }
+}
+
+/// Write a burnpack file with the ONNX layer names of a tiny U-Net and
+/// deterministic weights.
+fn write_tiny_unet(path: &Path, config: &UNetConfig) {
+    let f = |level: usize| config.n_filter_base * 2_usize.pow(level as u32);
+    // down sampling levels, the middle level and the concatenated up sampling
+    // levels
+    let mut channels = Vec::new();
+    let mut c_in = config.n_channel_in;
+    (0..=config.n_depth).for_each(|n| {
+        (0..config.n_conv_per_depth).for_each(|_| {
+            channels.push((c_in, f(n), config.kernel_size));
+            c_in = f(n);
+        });
+    });
+    (0..config.n_depth).rev().for_each(|n| {
+        c_in += f(n);
+        (0..config.n_conv_per_depth).for_each(|_| {
+            channels.push((c_in, f(n), config.kernel_size));
+            c_in = f(n);
+        });
+    });
+    channels.push((config.n_filter_base, config.n_classes, 1));
+    let layer = if config.n_dim == 2 {
+        "conv2d"
+    } else {
+        "conv3d"
+    };
+    let snapshots: Vec<TensorSnapshot> = channels
+        .iter()
+        .enumerate()
+        .flat_map(|(n, &(c_in, c_out, k))| {
+            let mut weight_shape = vec![c_out, c_in];
+            weight_shape.extend(std::iter::repeat_n(k, config.n_dim));
+            [("weight", weight_shape), ("bias", vec![c_out])]
+                .into_iter()
+                .map(move |(param, shape)| {
+                    let len: usize = shape.iter().product();
+                    let values: Vec<f32> = (0..len)
+                        .map(|i| ((i * 7 + n * 13) as f32 * 0.61).sin() * 0.3)
+                        .collect();
+                    TensorSnapshot::from_data(
+                        TensorData::new(values, shape),
+                        vec![format!("{}{}", layer, n + 1), param.to_string()],
+                        vec!["Struct:Model".to_string()],
+                        ParamId::new(),
+                    )
+                })
+        })
+        .collect();
+    BurnpackWriter::new(snapshots).write_to_file(path).unwrap();
+}
+
+/// Tests that 2D and 3D U-Net models load weights with the ONNX layer names
+/// and predict class probabilities and labels matching the input shape.
+#[test]
+fn unet_init_and_predict() -> Result<(), CellcastError> {
+    let dir = std::env::temp_dir().join("cellcast_test_unet");
+    fs::create_dir_all(&dir).unwrap();
+    let path_2d = dir.join("weights_2d.bpk");
+    write_tiny_unet(&path_2d, &tiny_network(2));
+    let path_2d = path_2d.to_str().unwrap();
+    let unet = UNet::init(path_2d, tiny_network(2), false)?;
+    assert_eq!(unet.config(), &tiny_network(2));
+    let data = Array2::<u16>::from_shape_fn((37, 50), |(r, c)| ((r * 3 + c * 5) % 11) as u16);
+    let probs = unet.predict_2d(&data, None, None)?;
+    assert_eq!(probs.dim(), (3, 37, 50));
+    // the softmax class probabilities sum to one
+    probs
+        .sum_axis(Axis(0))
+        .iter()
+        .for_each(|&s| assert!((s - 1.0).abs() < 1e-4));
+    let labels = unet.segment_2d(&data, None, None, None, None, Some(4))?;
+    assert_eq!(labels.dim(), (37, 50));
+    // a 2D model does not accept 3D or multichannel images
+    let volume =
+        Array3::<u16>::from_shape_fn((9, 20, 22), |(p, r, c)| ((p + r * 3 + c) % 7) as u16);
+    assert!(unet.predict_3d(&volume, None, None).is_err());
+    assert!(unet.predict_2d_channels(&volume, None, None, None).is_err());
+    // the weights do not match a deeper network
+    let deeper = UNetConfig {
+        n_depth: 2,
+        ..tiny_network(2)
+    };
+    assert!(UNet::init(path_2d, deeper, false).is_err());
+    assert!(UNet::init("missing.bpk", tiny_network(2), false).is_err());
+
+    let path_3d = dir.join("weights_3d.bpk");
+    write_tiny_unet(&path_3d, &tiny_network(3));
+    let unet = UNet::init(path_3d.to_str().unwrap(), tiny_network(3), false)?;
+    assert_eq!(unet.predict_3d(&volume, None, None)?.dim(), (3, 9, 20, 22));
+    assert_eq!(
+        unet.segment_3d(&volume, None, None, None, None, None)?
+            .dim(),
+        (9, 20, 22)
+    );
+    assert!(unet.predict_2d(&data, None, None).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+    Ok(())
+}
+
+/// Tests that the seeded watershed splits two touching objects along their
+/// boundary class.
+#[test]
+fn unet_labels_from_probs_splits_touching_objects() -> Result<(), CellcastError> {
+    // two touching squares separated by a boundary column, plus a small object
+    let shape = (20, 30);
+    let mut probs = Array3::<f32>::zeros((3, shape.0, shape.1));
+    (0..shape.0).for_each(|r| {
+        (0..shape.1).for_each(|c| {
+            let (bg, interior, boundary) = if (4..16).contains(&r) && (4..26).contains(&c) {
+                if c == 15 {
+                    (0.1, 0.2, 0.7)
+                } else {
+                    (0.0, 0.9, 0.1)
+                }
+            } else if r == 18 && c == 2 {
+                (0.0, 1.0, 0.0)
+            } else {
+                (1.0, 0.0, 0.0)
+            };
+            probs[[0, r, c]] = bg;
+            probs[[1, r, c]] = interior;
+            probs[[2, r, c]] = boundary;
+        });
+    });
+    let labels = UNet::labels_from_probs(probs.view().into_dyn(), 0.5, 0.5, 0)?;
+    let labels = labels.into_dimensionality::<Ix2>().unwrap();
+    assert_eq!(labels.iter().max(), Some(&3));
+    // the boundary column is assigned to one of the neighboring objects
+    assert!(labels.slice(s![4..16, 4..26]).iter().all(|&l| l > 0));
+    assert_ne!(labels[[10, 10]], labels[[10, 20]]);
+    assert!(
+        labels
+            .slice(s![4..16, 4..15])
+            .iter()
+            .all(|&l| l == labels[[10, 10]])
+    );
+    assert!(
+        labels
+            .slice(s![4..16, 16..26])
+            .iter()
+            .all(|&l| l == labels[[10, 20]])
+    );
+    assert_eq!(labels[[0, 0]], 0);
+    // the small object is removed and the labels renumbered
+    let labels = UNet::labels_from_probs(probs.view().into_dyn(), 0.5, 0.5, 2)?;
+    assert_eq!(labels.iter().max(), Some(&2));
+    assert_eq!(labels[[18, 2]], 0);
+    // a single class is both the foreground and the interior, the low
+    // interior boundary column is background
+    let single = Array2::from_shape_fn(shape, |(r, c)| probs[[1, r, c]]).insert_axis(Axis(0));
+    let labels = UNet::labels_from_probs(single.view().into_dyn(), 0.5, 0.5, 0)?;
+    assert_eq!(labels.iter().max(), Some(&3));
+    assert_eq!(labels[[10, 15]], 0);
+    assert!(
+        UNet::labels_from_probs(Array2::<f32>::zeros((0, 4)).view().into_dyn(), 0.5, 0.5, 0)
+            .is_err()
+    );
+    Ok(())
+}
+
+/// Tests the U-Net network configuration presets and validation.
+#[test]
+fn unet_config_validation() {
+    let semantic = UNetConfig::semantic_2d();
+    assert_eq!(semantic.n_classes, 3);
+    assert_eq!(semantic.div(), 8);
+    assert!(semantic.validate().is_ok());
+    assert_eq!(UNetConfig::semantic_3d().n_dim, 3);
+    assert!(UNetConfig::semantic_3d().validate().is_ok());
+    let invalid = [
+        UNetConfig {
+            n_dim: 4,
+            ..UNetConfig::semantic_2d()
+        },
+        UNetConfig {
+            n_classes: 0,
+            ..UNetConfig::semantic_2d()
+        },
+        UNetConfig {
+            n_channel_in: 0,
+            ..UNetConfig::semantic_2d()
+        },
+        UNetConfig {
+            kernel_size: 2,
+            ..UNetConfig::semantic_2d()
+        },
+    ];
+    invalid.iter().for_each(|c| assert!(c.validate().is_err()));
+}
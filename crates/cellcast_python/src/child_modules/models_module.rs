@@ -1,15 +1,21 @@
 use pyo3::prelude::*;
 
+use crate::classes::cellpose_classes::PyCellpose;
 use crate::classes::stardist_classes::{PyStarDist2D, PyStarDist3D};
+use crate::classes::unet_classes::PyUNet;
 use crate::utils::py_import_module;
 
 /// Registration function for the "models" module and their submodules.
 pub fn register_models_module(parent_module: &Bound<'_, PyModule>) -> PyResult<()> {
     let models_module = PyModule::new(parent_module.py(), "models")?;
     py_import_module("models");
+    py_import_module("models.Cellpose");
     py_import_module("models.StarDist2D");
     py_import_module("models.StarDist3D");
+    py_import_module("models.UNet");
+    models_module.add_class::<PyCellpose>()?;
     models_module.add_class::<PyStarDist2D>()?;
     models_module.add_class::<PyStarDist3D>()?;
+    models_module.add_class::<PyUNet>()?;
     parent_module.add_submodule(&models_module)
 }
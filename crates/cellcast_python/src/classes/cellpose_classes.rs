@@ -0,0 +1,210 @@
+use numpy::{IntoPyArray, PyArray2, PyReadonlyArray2, PyReadonlyArray3};
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::prelude::*;
+
+use crate::error::cellcast_error_to_pyerr;
+use cellcast::models::{Cellpose, CellposeNetworkConfig};
+
+#[pyclass(name = "Cellpose")]
+pub struct PyCellpose(Cellpose);
+
+#[pymethods]
+impl PyCellpose {
+    /// Initialize a Cellpose model.
+    ///
+    /// Initializes a Cellpose model with weights in burnpack format, converted
+    /// from the reference PyTorch weights (e.g. with the `cellpose_to_bpk`
+    /// binary). A Cellpose model can be initialized on either the GPU or CPU,
+    /// but not both concurrently. The model is pre-warmed as part of the
+    /// initialization process.
+    ///
+    /// Args:
+    ///     weights_path: The path to the Cellpose weights in burnpack (`.bpk`)
+    ///         format.
+    ///     preset: The network of the weights, "cyto" or "nuclei". If `None`
+    ///         then `preset = "cyto"`.
+    ///     gpu: If `True`, the configured GPU backend is used. If `False` then the
+    ///         configured CPU backend is used.
+    ///
+    /// Returns:
+    ///     An initialized Cellpose model.
+    ///
+    /// Errors:
+    ///     If `preset` is unknown. If the weights can not be loaded or do not
+    ///     match the network.
+    #[staticmethod]
+    #[pyo3(signature = (weights_path, preset=None, gpu=None))]
+    pub fn init(weights_path: &str, preset: Option<&str>, gpu: Option<bool>) -> PyResult<Self> {
+        let config = match preset.unwrap_or("cyto") {
+            "cyto" => CellposeNetworkConfig::cyto(),
+            "nuclei" => CellposeNetworkConfig::nuclei(),
+            _ => {
+                return Err(PyErr::new::<PyValueError, _>(
+                    "Unknown Cellpose preset, supported presets are \"cyto\" and \"nuclei\".",
+                ));
+            }
+        };
+        Ok(Self(
+            Cellpose::init(weights_path, Some(config), gpu.unwrap_or(true))
+                .map_err(cellcast_error_to_pyerr)?,
+        ))
+    }
+
+    /// Predict instance segmentation labels of a single channel image with the
+    /// Cellpose model.
+    ///
+    /// Performs model inference with the Cellpose model on a grayscale image,
+    /// used as the first (i.e. cytoplasm) input channel with any other input
+    /// channels left empty.
+    ///
+    /// Args:
+    ///     data: The input 2D image.
+    ///     diameter: The expected cell diameter in pixels. If `None`, then the
+    ///         image is not rescaled.
+    ///     flow_threshold: The maximum flow error of a cell. If `None`, then
+    ///         `flow_threshold = 0.4`.
+    ///     cellprob_threshold: The cell probability logit threshold. If `None`,
+    ///         then `cellprob_threshold = 0.0`.
+    ///
+    /// Returns:
+    ///     The Cellpose instance segmentation label image.
+    ///
+    /// Errors:
+    ///     If `diameter` is not positive.
+    #[pyo3(signature = (data, diameter=None, flow_threshold=None, cellprob_threshold=None))]
+    pub fn predict<'py>(
+        &self,
+        py: Python<'py>,
+        data: Bound<'py, PyAny>,
+        diameter: Option<f64>,
+        flow_threshold: Option<f64>,
+        cellprob_threshold: Option<f64>,
+    ) -> PyResult<Bound<'py, PyArray2<u64>>> {
+        if let Ok(arr) = data.extract::<PyReadonlyArray2<u8>>() {
+            self.0
+                .predict(arr.as_array(), diameter, flow_threshold, cellprob_threshold)
+                .map(|output| output.into_pyarray(py))
+                .map_err(cellcast_error_to_pyerr)
+        } else if let Ok(arr) = data.extract::<PyReadonlyArray2<u16>>() {
+            self.0
+                .predict(arr.as_array(), diameter, flow_threshold, cellprob_threshold)
+                .map(|output| output.into_pyarray(py))
+                .map_err(cellcast_error_to_pyerr)
+        } else if let Ok(arr) = data.extract::<PyReadonlyArray2<u64>>() {
+            self.0
+                .predict(arr.as_array(), diameter, flow_threshold, cellprob_threshold)
+                .map(|output| output.into_pyarray(py))
+                .map_err(cellcast_error_to_pyerr)
+        } else if let Ok(arr) = data.extract::<PyReadonlyArray2<f32>>() {
+            self.0
+                .predict(arr.as_array(), diameter, flow_threshold, cellprob_threshold)
+                .map(|output| output.into_pyarray(py))
+                .map_err(cellcast_error_to_pyerr)
+        } else if let Ok(arr) = data.extract::<PyReadonlyArray2<f64>>() {
+            self.0
+                .predict(arr.as_array(), diameter, flow_threshold, cellprob_threshold)
+                .map(|output| output.into_pyarray(py))
+                .map_err(cellcast_error_to_pyerr)
+        } else {
+            Err(PyErr::new::<PyTypeError, _>(
+                "Unsupported array dtype, supported array dtypes are u8, u16, u64, f32, and f64.",
+            ))
+        }
+    }
+
+    /// Predict instance segmentation labels of a multichannel image with the
+    /// Cellpose model.
+    ///
+    /// Performs model inference with the Cellpose model, returning instance
+    /// segmentations of cells of any shape. The image is rescaled by the ratio
+    /// of the network's mean cell diameter to `diameter`.
+    ///
+    /// Args:
+    ///     data: The input 3D image, e.g. with a cytoplasm and a nucleus
+    ///         channel.
+    ///     axis: The channel axis. If `None` then `axis == 2`.
+    ///     diameter: The expected cell diameter in pixels. If `None`, then the
+    ///         image is not rescaled.
+    ///     flow_threshold: The maximum flow error of a cell. If `None`, then
+    ///         `flow_threshold = 0.4`.
+    ///     cellprob_threshold: The cell probability logit threshold. If `None`,
+    ///         then `cellprob_threshold = 0.0`.
+    ///
+    /// Returns:
+    ///     The Cellpose instance segmentation label image.
+    ///
+    /// Errors:
+    ///     If the image has more channels than the network. If `diameter` is
+    ///     not positive.
+    #[pyo3(signature = (data, axis=None, diameter=None, flow_threshold=None, cellprob_threshold=None))]
+    pub fn predict_channels<'py>(
+        &self,
+        py: Python<'py>,
+        data: Bound<'py, PyAny>,
+        axis: Option<usize>,
+        diameter: Option<f64>,
+        flow_threshold: Option<f64>,
+        cellprob_threshold: Option<f64>,
+    ) -> PyResult<Bound<'py, PyArray2<u64>>> {
+        if let Ok(arr) = data.extract::<PyReadonlyArray3<u8>>() {
+            self.0
+                .predict_channels(
+                    arr.as_array(),
+                    axis,
+                    diameter,
+                    flow_threshold,
+                    cellprob_threshold,
+                )
+                .map(|output| output.into_pyarray(py))
+                .map_err(cellcast_error_to_pyerr)
+        } else if let Ok(arr) = data.extract::<PyReadonlyArray3<u16>>() {
+            self.0
+                .predict_channels(
+                    arr.as_array(),
+                    axis,
+                    diameter,
+                    flow_threshold,
+                    cellprob_threshold,
+                )
+                .map(|output| output.into_pyarray(py))
+                .map_err(cellcast_error_to_pyerr)
+        } else if let Ok(arr) = data.extract::<PyReadonlyArray3<u64>>() {
+            self.0
+                .predict_channels(
+                    arr.as_array(),
+                    axis,
+                    diameter,
+                    flow_threshold,
+                    cellprob_threshold,
+                )
+                .map(|output| output.into_pyarray(py))
+                .map_err(cellcast_error_to_pyerr)
+        } else if let Ok(arr) = data.extract::<PyReadonlyArray3<f32>>() {
+            self.0
+                .predict_channels(
+                    arr.as_array(),
+                    axis,
+                    diameter,
+                    flow_threshold,
+                    cellprob_threshold,
+                )
+                .map(|output| output.into_pyarray(py))
+                .map_err(cellcast_error_to_pyerr)
+        } else if let Ok(arr) = data.extract::<PyReadonlyArray3<f64>>() {
+            self.0
+                .predict_channels(
+                    arr.as_array(),
+                    axis,
+                    diameter,
+                    flow_threshold,
+                    cellprob_threshold,
+                )
+                .map(|output| output.into_pyarray(py))
+                .map_err(cellcast_error_to_pyerr)
+        } else {
+            Err(PyErr::new::<PyTypeError, _>(
+                "Unsupported array dtype, supported array dtypes are u8, u16, u64, f32, and f64.",
+            ))
+        }
+    }
+}
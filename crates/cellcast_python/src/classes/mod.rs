@@ -1 +1,3 @@
+pub mod cellpose_classes;
 pub mod stardist_classes;
+pub mod unet_classes;
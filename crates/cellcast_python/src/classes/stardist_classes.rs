@@ -1,9 +1,13 @@
+use std::collections::BTreeMap;
+
 use numpy::{IntoPyArray, PyArray2, PyArray3, PyReadonlyArray2, PyReadonlyArray3};
 use pyo3::exceptions::PyTypeError;
 use pyo3::prelude::*;
 
 use crate::error::cellcast_error_to_pyerr;
-use cellcast::models::{StarDist2D, StarDist3D};
+use cellcast::models::{
+    StarDist2D, StarDist2DClasses, StarDist2DConfig, StarDist3D, StarDist3DConfig,
+};
 
 #[pyclass(name = "StarDist2D")]
 pub struct PyStarDist2D(StarDist2D);
@@ -21,6 +25,12 @@ impl PyStarDist2D {
     ///     weights_path: The path to custom StarDist2D weights in burnpack (`.bpk`)
     ///         format. If `None` then the versatile fluo pretrained weights are
     ///         used.
+    ///     n_rays: The number of rays of the custom weights. If `n_rays` and
+    ///         `grid` are `None` then the network of the weights metadata is
+    ///         used, otherwise if `None` then `n_rays = 32`.
+    ///     grid: The `(row, col)` grid of the custom weights. If `n_rays` and
+    ///         `grid` are `None` then the network of the weights metadata is
+    ///         used, otherwise if `None` then `grid = (2, 2)`.
     ///     gpu: If `True`, the configured GPU backend is used. If `False` then the
     ///         configured CPU backend is used.
     ///
@@ -28,12 +38,20 @@ impl PyStarDist2D {
     ///     An initialized StarDist2D fluo model.
     ///
     /// Errors:
-    ///     If the requested model can not be initialized.
+    ///     If the requested model can not be initialized. If `n_rays` or `grid`
+    ///     are set without custom weights. If `n_rays` or `grid` do not match
+    ///     the network of the weights metadata.
     #[staticmethod]
-    #[pyo3(signature = (weights_path=None, gpu=None))]
-    pub fn init_fluo(weights_path: Option<&str>, gpu: Option<bool>) -> PyResult<Self> {
+    #[pyo3(signature = (weights_path=None, n_rays=None, grid=None, gpu=None))]
+    pub fn init_fluo(
+        weights_path: Option<&str>,
+        n_rays: Option<usize>,
+        grid: Option<(usize, usize)>,
+        gpu: Option<bool>,
+    ) -> PyResult<Self> {
+        let config = config_2d(n_rays, grid)?;
         Ok(Self(
-            StarDist2D::init_fluo(weights_path, gpu.unwrap_or(true))
+            StarDist2D::init_fluo(weights_path, config, gpu.unwrap_or(true))
                 .map_err(cellcast_error_to_pyerr)?,
         ))
     }
@@ -48,6 +66,12 @@ impl PyStarDist2D {
     /// Args:
     ///     weights_path: The path to custom StarDist2D weights in burnpack (`.bpk`)
     ///         format. If `None` then the versatile HE pretrained weights are used.
+    ///     n_rays: The number of rays of the custom weights. If `n_rays` and
+    ///         `grid` are `None` then the network of the weights metadata is
+    ///         used, otherwise if `None` then `n_rays = 32`.
+    ///     grid: The `(row, col)` grid of the custom weights. If `n_rays` and
+    ///         `grid` are `None` then the network of the weights metadata is
+    ///         used, otherwise if `None` then `grid = (2, 2)`.
     ///     gpu: If `True`, the configured GPU backend is used. If `False` then the
     ///         configured CPU backend is used.
     ///
@@ -55,12 +79,114 @@ impl PyStarDist2D {
     ///     An initialized StarDist2D HE model.
     ///
     /// Errors:
-    ///     If the requested model can not be initialized.
+    ///     If the requested model can not be initialized. If `n_rays` or `grid`
+    ///     are set without custom weights. If `n_rays` or `grid` do not match
+    ///     the network of the weights metadata.
+    #[staticmethod]
+    #[pyo3(signature = (weights_path=None, n_rays=None, grid=None, gpu=None))]
+    pub fn init_he(
+        weights_path: Option<&str>,
+        n_rays: Option<usize>,
+        grid: Option<(usize, usize)>,
+        gpu: Option<bool>,
+    ) -> PyResult<Self> {
+        let config = config_2d(n_rays, grid)?;
+        Ok(Self(
+            StarDist2D::init_he(weights_path, config, gpu.unwrap_or(true))
+                .map_err(cellcast_error_to_pyerr)?,
+        ))
+    }
+
+    /// Initialize a StarDist2D model from a StarDist model folder.
+    ///
+    /// Initializes a StarDist2D model from a model folder exported by the
+    /// reference StarDist, with the weights converted to burnpack (`.bpk`)
+    /// format. The ray count, grid, network architecture and number of input
+    /// channels are read from `config.json` and the default probability and
+    /// NMS thresholds from `thresholds.json` (if present). Single channel
+    /// models are used with `predict_fluo` and three channel (RGB) models with
+    /// `predict_he`.
+    ///
+    /// Args:
+    ///     path: The path to the model folder.
+    ///     gpu: If `True`, the configured GPU backend is used. If `False` then the
+    ///         configured CPU backend is used.
+    ///
+    /// Returns:
+    ///     An initialized StarDist2D model.
+    ///
+    /// Errors:
+    ///     If the model folder can not be read. If the model is not a 2D model
+    ///     with 1 or 3 input channels. If the requested model can not be
+    ///     initialized.
+    #[staticmethod]
+    #[pyo3(signature = (path, gpu=None))]
+    pub fn init_from_folder(path: &str, gpu: Option<bool>) -> PyResult<Self> {
+        Ok(Self(
+            StarDist2D::init_from_folder(path, gpu.unwrap_or(true))
+                .map_err(cellcast_error_to_pyerr)?,
+        ))
+    }
+
+    /// Initialize a StarDist2D model from an ONNX file.
+    ///
+    /// Initializes a StarDist2D model from a StarDist2D network exported to
+    /// ONNX, loaded at runtime. The number of input channels, ray count, grid
+    /// and network architecture are inferred from the ONNX graph. Single
+    /// channel models are used with `predict_fluo` and three channel (RGB)
+    /// models with `predict_he`.
+    ///
+    /// Args:
+    ///     path: The path to the ONNX (`.onnx`) file.
+    ///     gpu: If `True`, the configured GPU backend is used. If `False` then the
+    ///         configured CPU backend is used.
+    ///
+    /// Returns:
+    ///     An initialized StarDist2D model.
+    ///
+    /// Errors:
+    ///     If the ONNX file can not be read or is not a StarDist2D model with 1
+    ///     or 3 input channels. If the requested model can not be initialized.
+    #[staticmethod]
+    #[pyo3(signature = (path, gpu=None))]
+    pub fn init_from_onnx(path: &str, gpu: Option<bool>) -> PyResult<Self> {
+        Ok(Self(
+            StarDist2D::init_from_onnx(path, None, gpu.unwrap_or(true))
+                .map_err(cellcast_error_to_pyerr)?,
+        ))
+    }
+
+    /// Initialize a StarDist2D model from a bioimage.io model package.
+    ///
+    /// Initializes a StarDist2D model from a bioimage.io model zoo package
+    /// exported by the reference StarDist (a zip file with an `rdf.yaml`). The
+    /// network configuration, thresholds and normalization percentiles are
+    /// read from the package, and the weights from its `keras_hdf5` or `onnx`
+    /// weights.
+    ///
+    /// Args:
+    ///     path: The path to the model package zip file or unpacked folder.
+    ///     validate: If `True`, the import is validated with the package test
+    ///         input and output. If `None` then `validate = True`.
+    ///     gpu: If `True`, the configured GPU backend is used. If `False` then the
+    ///         configured CPU backend is used.
+    ///
+    /// Returns:
+    ///     An initialized StarDist2D model.
+    ///
+    /// Errors:
+    ///     If the package can not be read or is not a StarDist2D model with 1
+    ///     or 3 input channels. If the validation fails. If the requested model
+    ///     can not be initialized.
     #[staticmethod]
-    #[pyo3(signature = (weights_path=None, gpu=None))]
-    pub fn init_he(weights_path: Option<&str>, gpu: Option<bool>) -> PyResult<Self> {
+    #[pyo3(signature = (path, validate=None, gpu=None))]
+    pub fn init_from_bioimageio(
+        path: &str,
+        validate: Option<bool>,
+        gpu: Option<bool>,
+    ) -> PyResult<Self> {
         Ok(Self(
-            StarDist2D::init_he(weights_path, gpu.unwrap_or(true))
+            StarDist2D::init_from_bioimageio(path, validate.unwrap_or(true), gpu.unwrap_or(true))
                 .map_err(cellcast_error_to_pyerr)?,
         ))
     }
@@ -233,6 +359,211 @@ impl PyStarDist2D {
             ))
         }
     }
+
+    /// Predict multi-class instance segmentation labels with the StarDist2D
+    /// fluo model.
+    ///
+    /// Performs model inference with a multi-class StarDist2D fluo model,
+    /// returning instance segmentations of star-convex shapes and the class of
+    /// each object. The class of an object is the class with the highest mean
+    /// probability over the object's pixels.
+    ///
+    /// Args:
+    ///     data: The input 2D image.
+    ///     pmin: The minimum percentage to linear percentile normalize the input
+    ///         image. If `None`, then the model's default percentile is used.
+    ///     pmax: The maximum percentage to linear percentile normalize the input
+    ///         image. If `None`, then the model's default percentile is used.
+    ///     prob_threshold: The object/polygon probability threshold. If `None`,
+    ///         then the model's default threshold is used.
+    ///     nms_threshold: The non-maximum suppression (NMS) threshold. If `None`,
+    ///         then the model's default threshold is used.
+    ///
+    /// Returns:
+    ///     A tuple of the instance segmentation label image, the semantic class
+    ///     image and a dict mapping each object label to its class.
+    ///
+    /// Errors:
+    ///     If the model has no object class head. If `pmin` and/or `pmax` are
+    ///     outside of range `0.0` to `1.0.`
+    #[pyo3(signature = (data, pmin=None, pmax=None, prob_threshold=None, nms_threshold=None))]
+    pub fn predict_fluo_classes<'py>(
+        &self,
+        py: Python<'py>,
+        data: Bound<'py, PyAny>,
+        pmin: Option<f64>,
+        pmax: Option<f64>,
+        prob_threshold: Option<f64>,
+        nms_threshold: Option<f64>,
+    ) -> PyResult<PyClasses<'py>> {
+        if let Ok(arr) = data.extract::<PyReadonlyArray2<u8>>() {
+            self.0
+                .predict_fluo_classes(arr.as_array(), pmin, pmax, prob_threshold, nms_threshold)
+                .map(|output| classes_to_py(py, output))
+                .map_err(cellcast_error_to_pyerr)
+        } else if let Ok(arr) = data.extract::<PyReadonlyArray2<u16>>() {
+            self.0
+                .predict_fluo_classes(arr.as_array(), pmin, pmax, prob_threshold, nms_threshold)
+                .map(|output| classes_to_py(py, output))
+                .map_err(cellcast_error_to_pyerr)
+        } else if let Ok(arr) = data.extract::<PyReadonlyArray2<u64>>() {
+            self.0
+                .predict_fluo_classes(arr.as_array(), pmin, pmax, prob_threshold, nms_threshold)
+                .map(|output| classes_to_py(py, output))
+                .map_err(cellcast_error_to_pyerr)
+        } else if let Ok(arr) = data.extract::<PyReadonlyArray2<f32>>() {
+            self.0
+                .predict_fluo_classes(arr.as_array(), pmin, pmax, prob_threshold, nms_threshold)
+                .map(|output| classes_to_py(py, output))
+                .map_err(cellcast_error_to_pyerr)
+        } else if let Ok(arr) = data.extract::<PyReadonlyArray2<f64>>() {
+            self.0
+                .predict_fluo_classes(arr.as_array(), pmin, pmax, prob_threshold, nms_threshold)
+                .map(|output| classes_to_py(py, output))
+                .map_err(cellcast_error_to_pyerr)
+        } else {
+            Err(PyErr::new::<PyTypeError, _>(
+                "Unsupported array dtype, supported array dtypes are u8, u16, u64, f32, and f64.",
+            ))
+        }
+    }
+
+    /// Predict multi-class instance segmentation labels with the StarDist2D HE
+    /// model.
+    ///
+    /// Performs model inference with a multi-class StarDist2D HE model,
+    /// returning instance segmentations of star-convex shapes and the class of
+    /// each object (*e.g.* tumor, lymphocyte or stroma nuclei). The class of an
+    /// object is the class with the highest mean probability over the object's
+    /// pixels.
+    ///
+    /// Args:
+    ///     data: The input 3D image, where the third dimension is the channel axis.
+    ///     pmin: The minimum percentage to linear percentile normalize the input
+    ///         image. If `None`, then the model's default percentile is used.
+    ///     pmax: The maximum percentage to linear percentile normalize the input
+    ///         image. If `None`, then the model's default percentile is used.
+    ///     prob_threshold: The object/polygon probability threshold. If `None`,
+    ///         then the model's default threshold is used.
+    ///     nms_threshold: The non-maximum suppression (NMS) threshold. If `None`,
+    ///         then the model's default threshold is used.
+    ///     axis: The channel axis. If `None` then `axis == 2`.
+    ///
+    /// Returns:
+    ///     A tuple of the instance segmentation label image, the semantic class
+    ///     image and a dict mapping each object label to its class.
+    ///
+    /// Errors:
+    ///     If the model has no object class head. If `pmin` and/or `pmax` are
+    ///     outside of range `0.0` to `1.0.`
+    #[pyo3(signature = (data, pmin=None, pmax=None, prob_threshold=None, nms_threshold=None, axis=None))]
+    pub fn predict_he_classes<'py>(
+        &self,
+        py: Python<'py>,
+        data: Bound<'py, PyAny>,
+        pmin: Option<f64>,
+        pmax: Option<f64>,
+        prob_threshold: Option<f64>,
+        nms_threshold: Option<f64>,
+        axis: Option<usize>,
+    ) -> PyResult<PyClasses<'py>> {
+        if let Ok(arr) = data.extract::<PyReadonlyArray3<u8>>() {
+            self.0
+                .predict_he_classes(
+                    arr.as_array(),
+                    pmin,
+                    pmax,
+                    prob_threshold,
+                    nms_threshold,
+                    axis,
+                )
+                .map(|output| classes_to_py(py, output))
+                .map_err(cellcast_error_to_pyerr)
+        } else if let Ok(arr) = data.extract::<PyReadonlyArray3<u16>>() {
+            self.0
+                .predict_he_classes(
+                    arr.as_array(),
+                    pmin,
+                    pmax,
+                    prob_threshold,
+                    nms_threshold,
+                    axis,
+                )
+                .map(|output| classes_to_py(py, output))
+                .map_err(cellcast_error_to_pyerr)
+        } else if let Ok(arr) = data.extract::<PyReadonlyArray3<u64>>() {
+            self.0
+                .predict_he_classes(
+                    arr.as_array(),
+                    pmin,
+                    pmax,
+                    prob_threshold,
+                    nms_threshold,
+                    axis,
+                )
+                .map(|output| classes_to_py(py, output))
+                .map_err(cellcast_error_to_pyerr)
+        } else if let Ok(arr) = data.extract::<PyReadonlyArray3<f32>>() {
+            self.0
+                .predict_he_classes(
+                    arr.as_array(),
+                    pmin,
+                    pmax,
+                    prob_threshold,
+                    nms_threshold,
+                    axis,
+                )
+                .map(|output| classes_to_py(py, output))
+                .map_err(cellcast_error_to_pyerr)
+        } else if let Ok(arr) = data.extract::<PyReadonlyArray3<f64>>() {
+            self.0
+                .predict_he_classes(
+                    arr.as_array(),
+                    pmin,
+                    pmax,
+                    prob_threshold,
+                    nms_threshold,
+                    axis,
+                )
+                .map(|output| classes_to_py(py, output))
+                .map_err(cellcast_error_to_pyerr)
+        } else {
+            Err(PyErr::new::<PyTypeError, _>(
+                "Unsupported array dtype, supported array dtypes are u8, u16, u64, f32, and f64.",
+            ))
+        }
+    }
+}
+
+/// The labels, class image and object classes of a multi-class prediction.
+type PyClasses<'py> = (
+    Bound<'py, PyArray2<u64>>,
+    Bound<'py, PyArray2<usize>>,
+    BTreeMap<u64, usize>,
+);
+
+/// Convert a multi-class StarDist2D prediction to Python objects.
+fn classes_to_py(py: Python<'_>, classes: StarDist2DClasses) -> PyClasses<'_> {
+    (
+        classes.labels.into_pyarray(py),
+        classes.class_image.into_pyarray(py),
+        classes.classes,
+    )
+}
+
+/// Create the StarDist2D configuration of a ray count and grid. If both are
+/// `None` then no configuration is given, using the network of the weights
+/// metadata (if any).
+fn config_2d(
+    n_rays: Option<usize>,
+    grid: Option<(usize, usize)>,
+) -> PyResult<Option<StarDist2DConfig>> {
+    if n_rays.is_none() && grid.is_none() {
+        return Ok(None);
+    }
+    StarDist2DConfig::new(n_rays, grid)
+        .map(Some)
+        .map_err(cellcast_error_to_pyerr)
 }
 
 #[pyclass(name = "StarDist3D")]
@@ -253,6 +584,12 @@ impl PyStarDist3D {
     ///         used.
     ///     anisotropy: The anisotropy the model was trained with for all three
     ///         axes. If `None` then anisotropy of `[2.0, 1.0, 1.0]` is used.
+    ///     n_rays: The number of rays of the custom weights. If `n_rays` and
+    ///         `grid` are `None` then the network of the weights metadata is
+    ///         used, otherwise if `None` then `n_rays = 96`.
+    ///     grid: The `(pln, row, col)` grid of the custom weights. If `n_rays`
+    ///         and `grid` are `None` then the network of the weights metadata is
+    ///         used, otherwise if `None` then `grid = (1, 2, 2)`.
     ///     gpu: If `True`, the configured GPU backend is used. If `false` then the
     ///         configured CPU backend is used.
     ///
@@ -261,18 +598,120 @@ impl PyStarDist3D {
     ///
     /// Errors:
     ///     If the requested model can not be initialized. If
-    ///     `anisotropy.len() != 3`.
+    ///     `anisotropy.len() != 3`. If `n_rays` or `grid` are set without custom
+    ///     weights. If `n_rays` or `grid` do not match the network of the
+    ///     weights metadata.
     #[staticmethod]
-    #[pyo3(signature = (weights_path=None, anisotropy=None, gpu=None))]
+    #[pyo3(signature = (weights_path=None, anisotropy=None, n_rays=None, grid=None, gpu=None))]
     pub fn init_fluo(
         weights_path: Option<&str>,
         anisotropy: Option<Vec<f32>>,
+        n_rays: Option<usize>,
+        grid: Option<(usize, usize, usize)>,
         gpu: Option<bool>,
     ) -> PyResult<Self> {
         let anisotropy = anisotropy.as_deref();
+        let config = config_3d(n_rays, grid)?;
+        Ok(Self(
+            StarDist3D::init_fluo(weights_path, anisotropy, config, gpu.unwrap_or(true))
+                .map_err(cellcast_error_to_pyerr)?,
+        ))
+    }
+
+    /// Initialize a StarDist3D model from a StarDist model folder.
+    ///
+    /// Initializes a StarDist3D model from a model folder exported by the
+    /// reference StarDist, with the weights converted to burnpack (`.bpk`)
+    /// format. The ray count, grid, network architecture and anisotropy are
+    /// read from `config.json` and the default probability and NMS thresholds
+    /// from `thresholds.json` (if present). The model is used with
+    /// `predict_fluo`.
+    ///
+    /// Args:
+    ///     path: The path to the model folder.
+    ///     gpu: If `True`, the configured GPU backend is used. If `False` then the
+    ///         configured CPU backend is used.
+    ///
+    /// Returns:
+    ///     An initialized StarDist3D model.
+    ///
+    /// Errors:
+    ///     If the model folder can not be read. If the model is not a single
+    ///     channel 3D model. If the requested model can not be initialized.
+    #[staticmethod]
+    #[pyo3(signature = (path, gpu=None))]
+    pub fn init_from_folder(path: &str, gpu: Option<bool>) -> PyResult<Self> {
         Ok(Self(
-            StarDist3D::init_fluo(weights_path, anisotropy, gpu.unwrap_or(true))
-                .map(|output| output)
+            StarDist3D::init_from_folder(path, gpu.unwrap_or(true))
+                .map_err(cellcast_error_to_pyerr)?,
+        ))
+    }
+
+    /// Initialize a StarDist3D model from an ONNX file.
+    ///
+    /// Initializes a StarDist3D model from a StarDist3D network exported to
+    /// ONNX, loaded at runtime. The ray count, grid and network architecture
+    /// are inferred from the ONNX graph. The model is used with
+    /// `predict_fluo`.
+    ///
+    /// Args:
+    ///     path: The path to the ONNX (`.onnx`) file.
+    ///     anisotropy: The anisotropy the model was trained with for all three
+    ///         axes. If `None` then anisotropy of `[2.0, 1.0, 1.0]` is used.
+    ///     gpu: If `True`, the configured GPU backend is used. If `False` then the
+    ///         configured CPU backend is used.
+    ///
+    /// Returns:
+    ///     An initialized StarDist3D model.
+    ///
+    /// Errors:
+    ///     If the ONNX file can not be read or is not a single channel
+    ///     StarDist3D model. If the requested model can not be initialized.
+    #[staticmethod]
+    #[pyo3(signature = (path, anisotropy=None, gpu=None))]
+    pub fn init_from_onnx(
+        path: &str,
+        anisotropy: Option<Vec<f32>>,
+        gpu: Option<bool>,
+    ) -> PyResult<Self> {
+        let anisotropy = anisotropy.as_deref();
+        Ok(Self(
+            StarDist3D::init_from_onnx(path, anisotropy, None, gpu.unwrap_or(true))
+                .map_err(cellcast_error_to_pyerr)?,
+        ))
+    }
+
+    /// Initialize a StarDist3D model from a bioimage.io model package.
+    ///
+    /// Initializes a StarDist3D model from a bioimage.io model zoo package
+    /// exported by the reference StarDist (a zip file with an `rdf.yaml`). The
+    /// network configuration, anisotropy, thresholds and normalization
+    /// percentiles are read from the package, and the weights from its
+    /// `keras_hdf5` or `onnx` weights.
+    ///
+    /// Args:
+    ///     path: The path to the model package zip file or unpacked folder.
+    ///     validate: If `True`, the import is validated with the package test
+    ///         input and output. If `None` then `validate = True`.
+    ///     gpu: If `True`, the configured GPU backend is used. If `False` then the
+    ///         configured CPU backend is used.
+    ///
+    /// Returns:
+    ///     An initialized StarDist3D model.
+    ///
+    /// Errors:
+    ///     If the package can not be read or is not a single channel StarDist3D
+    ///     model. If the validation fails. If the requested model can not be
+    ///     initialized.
+    #[staticmethod]
+    #[pyo3(signature = (path, validate=None, gpu=None))]
+    pub fn init_from_bioimageio(
+        path: &str,
+        validate: Option<bool>,
+        gpu: Option<bool>,
+    ) -> PyResult<Self> {
+        Ok(Self(
+            StarDist3D::init_from_bioimageio(path, validate.unwrap_or(true), gpu.unwrap_or(true))
                 .map_err(cellcast_error_to_pyerr)?,
         ))
     }
@@ -381,3 +820,18 @@ impl PyStarDist3D {
         }
     }
 }
+
+/// Create the StarDist3D configuration of a ray count and grid. If both are
+/// `None` then no configuration is given, using the network of the weights
+/// metadata (if any).
+fn config_3d(
+    n_rays: Option<usize>,
+    grid: Option<(usize, usize, usize)>,
+) -> PyResult<Option<StarDist3DConfig>> {
+    if n_rays.is_none() && grid.is_none() {
+        return Ok(None);
+    }
+    StarDist3DConfig::new(n_rays, grid)
+        .map(Some)
+        .map_err(cellcast_error_to_pyerr)
+}
@@ -0,0 +1,383 @@
+use numpy::{IntoPyArray, PyArray2, PyArray3, PyArray4, PyReadonlyArray2, PyReadonlyArray3};
+use pyo3::exceptions::PyTypeError;
+use pyo3::prelude::*;
+
+use crate::error::cellcast_error_to_pyerr;
+use cellcast::models::{UNet, UNetConfig};
+
+#[pyclass(name = "UNet")]
+pub struct PyUNet(UNet);
+
+#[pymethods]
+impl PyUNet {
+    /// Initialize a U-Net semantic segmentation model.
+    ///
+    /// Initializes a 2D or 3D U-Net model with custom weights in burnpack
+    /// format. The network follows the semantic segmentation presets (i.e.
+    /// depth, filters and convolutions), with a configurable number of input
+    /// channels and output classes. A U-Net model can be initialized on either
+    /// the GPU or CPU, but not both concurrently. The model is pre-warmed as
+    /// part of the initialization process.
+    ///
+    /// Args:
+    ///     weights_path: The path to the U-Net weights in burnpack (`.bpk`)
+    ///         format.
+    ///     n_dim: The number of spatial dimensions, 2 or 3. If `None` then
+    ///         `n_dim = 2`.
+    ///     n_classes: The number of output classes. If `None` then
+    ///         `n_classes = 3` (i.e. background, interior and boundary).
+    ///     n_channel_in: The number of input channels. If `None` then
+    ///         `n_channel_in = 1`.
+    ///     gpu: If `True`, the configured GPU backend is used. If `False` then the
+    ///         configured CPU backend is used.
+    ///
+    /// Returns:
+    ///     An initialized U-Net model.
+    ///
+    /// Errors:
+    ///     If the network configuration is invalid. If the weights can not be
+    ///     loaded or do not match the network.
+    #[staticmethod]
+    #[pyo3(signature = (weights_path, n_dim=None, n_classes=None, n_channel_in=None, gpu=None))]
+    pub fn init(
+        weights_path: &str,
+        n_dim: Option<usize>,
+        n_classes: Option<usize>,
+        n_channel_in: Option<usize>,
+        gpu: Option<bool>,
+    ) -> PyResult<Self> {
+        let preset = if n_dim == Some(3) {
+            UNetConfig::semantic_3d()
+        } else {
+            UNetConfig::semantic_2d()
+        };
+        let config = UNetConfig {
+            n_dim: n_dim.unwrap_or(2),
+            n_classes: n_classes.unwrap_or(preset.n_classes),
+            n_channel_in: n_channel_in.unwrap_or(preset.n_channel_in),
+            ..preset
+        };
+        Ok(Self(
+            UNet::init(weights_path, config, gpu.unwrap_or(true))
+                .map_err(cellcast_error_to_pyerr)?,
+        ))
+    }
+
+    /// Predict class probabilities of a 2D image with the U-Net model.
+    ///
+    /// Args:
+    ///     data: The input 2D image.
+    ///     pmin: The minimum percentage to linear percentile normalize the
+    ///         input image. If `None`, then `pmin = 1.0`.
+    ///     pmax: The maximum percentage to linear percentile normalize the
+    ///         input image. If `None`, then `pmax = 99.8`.
+    ///
+    /// Returns:
+    ///     The class probabilities with shape `(n_classes, row, col)`.
+    ///
+    /// Errors:
+    ///     If the model is not a single channel 2D U-Net. If the
+    ///     percentiles are out of range.
+    #[pyo3(signature = (data, pmin=None, pmax=None))]
+    pub fn predict_2d<'py>(
+        &self,
+        py: Python<'py>,
+        data: Bound<'py, PyAny>,
+        pmin: Option<f64>,
+        pmax: Option<f64>,
+    ) -> PyResult<Bound<'py, PyArray3<f32>>> {
+        if let Ok(arr) = data.extract::<PyReadonlyArray2<u8>>() {
+            self.0
+                .predict_2d(arr.as_array(), pmin, pmax)
+                .map(|output| output.into_pyarray(py))
+                .map_err(cellcast_error_to_pyerr)
+        } else if let Ok(arr) = data.extract::<PyReadonlyArray2<u16>>() {
+            self.0
+                .predict_2d(arr.as_array(), pmin, pmax)
+                .map(|output| output.into_pyarray(py))
+                .map_err(cellcast_error_to_pyerr)
+        } else if let Ok(arr) = data.extract::<PyReadonlyArray2<u64>>() {
+            self.0
+                .predict_2d(arr.as_array(), pmin, pmax)
+                .map(|output| output.into_pyarray(py))
+                .map_err(cellcast_error_to_pyerr)
+        } else if let Ok(arr) = data.extract::<PyReadonlyArray2<f32>>() {
+            self.0
+                .predict_2d(arr.as_array(), pmin, pmax)
+                .map(|output| output.into_pyarray(py))
+                .map_err(cellcast_error_to_pyerr)
+        } else if let Ok(arr) = data.extract::<PyReadonlyArray2<f64>>() {
+            self.0
+                .predict_2d(arr.as_array(), pmin, pmax)
+                .map(|output| output.into_pyarray(py))
+                .map_err(cellcast_error_to_pyerr)
+        } else {
+            Err(PyErr::new::<PyTypeError, _>(
+                "Unsupported array dtype, supported array dtypes are u8, u16, u64, f32, and f64.",
+            ))
+        }
+    }
+
+    /// Predict class probabilities of a 3D image with the U-Net model.
+    ///
+    /// Args:
+    ///     data: The input 3D image.
+    ///     pmin: The minimum percentage to linear percentile normalize the
+    ///         input image. If `None`, then `pmin = 1.0`.
+    ///     pmax: The maximum percentage to linear percentile normalize the
+    ///         input image. If `None`, then `pmax = 99.8`.
+    ///
+    /// Returns:
+    ///     The class probabilities with shape `(n_classes, pln, row, col)`.
+    ///
+    /// Errors:
+    ///     If the model is not a single channel 3D U-Net. If the
+    ///     percentiles are out of range.
+    #[pyo3(signature = (data, pmin=None, pmax=None))]
+    pub fn predict_3d<'py>(
+        &self,
+        py: Python<'py>,
+        data: Bound<'py, PyAny>,
+        pmin: Option<f64>,
+        pmax: Option<f64>,
+    ) -> PyResult<Bound<'py, PyArray4<f32>>> {
+        if let Ok(arr) = data.extract::<PyReadonlyArray3<u8>>() {
+            self.0
+                .predict_3d(arr.as_array(), pmin, pmax)
+                .map(|output| output.into_pyarray(py))
+                .map_err(cellcast_error_to_pyerr)
+        } else if let Ok(arr) = data.extract::<PyReadonlyArray3<u16>>() {
+            self.0
+                .predict_3d(arr.as_array(), pmin, pmax)
+                .map(|output| output.into_pyarray(py))
+                .map_err(cellcast_error_to_pyerr)
+        } else if let Ok(arr) = data.extract::<PyReadonlyArray3<u64>>() {
+            self.0
+                .predict_3d(arr.as_array(), pmin, pmax)
+                .map(|output| output.into_pyarray(py))
+                .map_err(cellcast_error_to_pyerr)
+        } else if let Ok(arr) = data.extract::<PyReadonlyArray3<f32>>() {
+            self.0
+                .predict_3d(arr.as_array(), pmin, pmax)
+                .map(|output| output.into_pyarray(py))
+                .map_err(cellcast_error_to_pyerr)
+        } else if let Ok(arr) = data.extract::<PyReadonlyArray3<f64>>() {
+            self.0
+                .predict_3d(arr.as_array(), pmin, pmax)
+                .map(|output| output.into_pyarray(py))
+                .map_err(cellcast_error_to_pyerr)
+        } else {
+            Err(PyErr::new::<PyTypeError, _>(
+                "Unsupported array dtype, supported array dtypes are u8, u16, u64, f32, and f64.",
+            ))
+        }
+    }
+
+    /// Predict instance segmentation labels of a 2D image with the U-Net
+    /// model.
+    ///
+    /// Predicts the class probabilities of the image and computes instance
+    /// labels with a seeded watershed, seeded by the regions of high interior
+    /// probability and split along the object boundaries.
+    ///
+    /// Args:
+    ///     data: The input 2D image.
+    ///     pmin: The minimum percentage to linear percentile normalize the
+    ///         input image. If `None`, then `pmin = 1.0`.
+    ///     pmax: The maximum percentage to linear percentile normalize the
+    ///         input image. If `None`, then `pmax = 99.8`.
+    ///     fg_threshold: The foreground probability threshold. If `None`, then
+    ///         `fg_threshold = 0.5`.
+    ///     seed_threshold: The interior probability threshold of the watershed
+    ///         seeds. If `None`, then `seed_threshold = 0.5`.
+    ///     min_size: The minimum number of pixels of an object. If `None`, then
+    ///         `min_size = 0`.
+    ///
+    /// Returns:
+    ///     The U-Net instance segmentation label image.
+    ///
+    /// Errors:
+    ///     If the model is not a single channel 2D U-Net. If the
+    ///     percentiles are out of range.
+    #[pyo3(signature = (data, pmin=None, pmax=None, fg_threshold=None, seed_threshold=None, min_size=None))]
+    pub fn segment_2d<'py>(
+        &self,
+        py: Python<'py>,
+        data: Bound<'py, PyAny>,
+        pmin: Option<f64>,
+        pmax: Option<f64>,
+        fg_threshold: Option<f64>,
+        seed_threshold: Option<f64>,
+        min_size: Option<usize>,
+    ) -> PyResult<Bound<'py, PyArray2<u64>>> {
+        if let Ok(arr) = data.extract::<PyReadonlyArray2<u8>>() {
+            self.0
+                .segment_2d(
+                    arr.as_array(),
+                    pmin,
+                    pmax,
+                    fg_threshold,
+                    seed_threshold,
+                    min_size,
+                )
+                .map(|output| output.into_pyarray(py))
+                .map_err(cellcast_error_to_pyerr)
+        } else if let Ok(arr) = data.extract::<PyReadonlyArray2<u16>>() {
+            self.0
+                .segment_2d(
+                    arr.as_array(),
+                    pmin,
+                    pmax,
+                    fg_threshold,
+                    seed_threshold,
+                    min_size,
+                )
+                .map(|output| output.into_pyarray(py))
+                .map_err(cellcast_error_to_pyerr)
+        } else if let Ok(arr) = data.extract::<PyReadonlyArray2<u64>>() {
+            self.0
+                .segment_2d(
+                    arr.as_array(),
+                    pmin,
+                    pmax,
+                    fg_threshold,
+                    seed_threshold,
+                    min_size,
+                )
+                .map(|output| output.into_pyarray(py))
+                .map_err(cellcast_error_to_pyerr)
+        } else if let Ok(arr) = data.extract::<PyReadonlyArray2<f32>>() {
+            self.0
+                .segment_2d(
+                    arr.as_array(),
+                    pmin,
+                    pmax,
+                    fg_threshold,
+                    seed_threshold,
+                    min_size,
+                )
+                .map(|output| output.into_pyarray(py))
+                .map_err(cellcast_error_to_pyerr)
+        } else if let Ok(arr) = data.extract::<PyReadonlyArray2<f64>>() {
+            self.0
+                .segment_2d(
+                    arr.as_array(),
+                    pmin,
+                    pmax,
+                    fg_threshold,
+                    seed_threshold,
+                    min_size,
+                )
+                .map(|output| output.into_pyarray(py))
+                .map_err(cellcast_error_to_pyerr)
+        } else {
+            Err(PyErr::new::<PyTypeError, _>(
+                "Unsupported array dtype, supported array dtypes are u8, u16, u64, f32, and f64.",
+            ))
+        }
+    }
+
+    /// Predict instance segmentation labels of a 3D image with the U-Net
+    /// model.
+    ///
+    /// Predicts the class probabilities of the image and computes instance
+    /// labels with a seeded watershed, seeded by the regions of high interior
+    /// probability and split along the object boundaries.
+    ///
+    /// Args:
+    ///     data: The input 3D image.
+    ///     pmin: The minimum percentage to linear percentile normalize the
+    ///         input image. If `None`, then `pmin = 1.0`.
+    ///     pmax: The maximum percentage to linear percentile normalize the
+    ///         input image. If `None`, then `pmax = 99.8`.
+    ///     fg_threshold: The foreground probability threshold. If `None`, then
+    ///         `fg_threshold = 0.5`.
+    ///     seed_threshold: The interior probability threshold of the watershed
+    ///         seeds. If `None`, then `seed_threshold = 0.5`.
+    ///     min_size: The minimum number of pixels of an object. If `None`, then
+    ///         `min_size = 0`.
+    ///
+    /// Returns:
+    ///     The U-Net instance segmentation label image.
+    ///
+    /// Errors:
+    ///     If the model is not a single channel 3D U-Net. If the
+    ///     percentiles are out of range.
+    #[pyo3(signature = (data, pmin=None, pmax=None, fg_threshold=None, seed_threshold=None, min_size=None))]
+    pub fn segment_3d<'py>(
+        &self,
+        py: Python<'py>,
+        data: Bound<'py, PyAny>,
+        pmin: Option<f64>,
+        pmax: Option<f64>,
+        fg_threshold: Option<f64>,
+        seed_threshold: Option<f64>,
+        min_size: Option<usize>,
+    ) -> PyResult<Bound<'py, PyArray3<u64>>> {
+        if let Ok(arr) = data.extract::<PyReadonlyArray3<u8>>() {
+            self.0
+                .segment_3d(
+                    arr.as_array(),
+                    pmin,
+                    pmax,
+                    fg_threshold,
+                    seed_threshold,
+                    min_size,
+                )
+                .map(|output| output.into_pyarray(py))
+                .map_err(cellcast_error_to_pyerr)
+        } else if let Ok(arr) = data.extract::<PyReadonlyArray3<u16>>() {
+            self.0
+                .segment_3d(
+                    arr.as_array(),
+                    pmin,
+                    pmax,
+                    fg_threshold,
+                    seed_threshold,
+                    min_size,
+                )
+                .map(|output| output.into_pyarray(py))
+                .map_err(cellcast_error_to_pyerr)
+        } else if let Ok(arr) = data.extract::<PyReadonlyArray3<u64>>() {
+            self.0
+                .segment_3d(
+                    arr.as_array(),
+                    pmin,
+                    pmax,
+                    fg_threshold,
+                    seed_threshold,
+                    min_size,
+                )
+                .map(|output| output.into_pyarray(py))
+                .map_err(cellcast_error_to_pyerr)
+        } else if let Ok(arr) = data.extract::<PyReadonlyArray3<f32>>() {
+            self.0
+                .segment_3d(
+                    arr.as_array(),
+                    pmin,
+                    pmax,
+                    fg_threshold,
+                    seed_threshold,
+                    min_size,
+                )
+                .map(|output| output.into_pyarray(py))
+                .map_err(cellcast_error_to_pyerr)
+        } else if let Ok(arr) = data.extract::<PyReadonlyArray3<f64>>() {
+            self.0
+                .segment_3d(
+                    arr.as_array(),
+                    pmin,
+                    pmax,
+                    fg_threshold,
+                    seed_threshold,
+                    min_size,
+                )
+                .map(|output| output.into_pyarray(py))
+                .map_err(cellcast_error_to_pyerr)
+        } else {
+            Err(PyErr::new::<PyTypeError, _>(
+                "Unsupported array dtype, supported array dtypes are u8, u16, u64, f32, and f64.",
+            ))
+        }
+    }
+}